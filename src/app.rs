@@ -1,5 +1,8 @@
 use eframe::App;
 
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
 #[cfg(not(target_arch = "wasm32"))]
 use std::fs;
 #[cfg(not(target_arch = "wasm32"))]
@@ -8,9 +11,80 @@ use std::fs::File;
 use std::io::{Read, Write};
 
 #[cfg(target_arch = "wasm32")]
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+// What to do with a file once the browser has finished reading it
+#[cfg(target_arch = "wasm32")]
+#[derive(Clone, Copy)]
+enum FileAction {
+    Load,
+    Merge,
+}
+
+// The browser reads files asynchronously. Every pick gets its own channel, which receives the file
+// contents (or None when the dialog was cancelled) exactly once
+#[cfg(target_arch = "wasm32")]
+#[derive(Default)]
+enum FileLoadState {
+    #[default]
+    Idle,
+    Loading(FileAction, Receiver<Option<String>>),
+    Loaded(f64), // egui time the file arrived, the notice clears itself after LOADED_NOTICE_SECONDS
+    Error(String),
+}
 
 use crate::efficiency_fitter::measurements::MeasurementHandler;
+use crate::efficiency_fitter::source_library::{SourceLibrary, SOURCE_LIBRARY_KEY};
+
+#[cfg(target_arch = "wasm32")]
+const LOADED_NOTICE_SECONDS: f64 = 3.0;
+
+const DIRTY_CHECK_INTERVAL: f64 = 0.5; // seconds between fingerprints while the user is editing
+
+// curves and bands the fits regenerate, left out of the fingerprint. The Monte Carlo bands are
+// resampled in the background and would otherwise count as an edit
+const DERIVED_KEYS: [&str; 8] = [
+    "points",
+    "upper_uncertainity_points",
+    "lower_uncertainity_points",
+    "upper_prediction_points",
+    "lower_prediction_points",
+    "monte_carlo_band",
+    "uncertainty_lower_points",
+    "uncertainty_upper_points",
+];
+
+// removes the arrays stored under DERIVED_KEYS anywhere in `value`. Settings with the same names,
+// like the number of curve points, are numbers and stay
+fn strip_derived(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|key, value| !(value.is_array() && DERIVED_KEYS.contains(&key.as_str())));
+            map.values_mut().for_each(strip_derived);
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(strip_derived),
+        _ => {}
+    }
+}
+
+// what replaces the session once the unsaved changes are dealt with
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy, PartialEq)]
+enum Replacement {
+    Sample,
+    File,
+}
+
+// input that can change the project. Hovering, moving the pointer and scrolling cannot, a click or
+// drag is done when the button is released
+fn is_edit_event(event: &egui::Event) -> bool {
+    match event {
+        egui::Event::Text(_) | egui::Event::Paste(_) | egui::Event::Cut => true,
+        egui::Event::Key { pressed, .. } => *pressed,
+        egui::Event::PointerButton { pressed, .. } => !*pressed,
+        _ => false,
+    }
+}
 
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct CeBrAEfficiencyApp {
@@ -20,10 +94,31 @@ pub struct CeBrAEfficiencyApp {
     show_bottom_panel: bool,
     #[cfg(target_arch = "wasm32")]
     #[serde(skip)]
-    file_channel: Option<(Sender<String>, Receiver<String>)>,
+    file_load: FileLoadState,
     #[cfg(target_arch = "wasm32")]
     #[serde(skip)]
     filename: String,
+    #[serde(skip)]
+    saved_fingerprint: Option<u64>,
+    #[serde(skip)]
+    dirty: bool,
+    #[serde(skip)]
+    dirty_check_pending: bool, // something may have been edited since the last fingerprint
+    #[serde(skip)]
+    last_dirty_check: f64, // egui time of the last fingerprint, in seconds
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    confirm_replace: Option<Replacement>,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    confirm_close: bool,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    allow_close: bool,
+    #[serde(skip)]
+    pending_merge: Option<MeasurementHandler>,
+    #[serde(skip)]
+    source_library: SourceLibrary, // stored under its own key, not in the project
 }
 
 impl Default for CeBrAEfficiencyApp {
@@ -34,9 +129,21 @@ impl Default for CeBrAEfficiencyApp {
             show_left_panel: true,
             show_bottom_panel: true,
             #[cfg(target_arch = "wasm32")]
-            file_channel: None,
+            file_load: FileLoadState::Idle,
             #[cfg(target_arch = "wasm32")]
             filename: String::new(),
+            saved_fingerprint: None,
+            dirty: false,
+            dirty_check_pending: false,
+            last_dirty_check: 0.0,
+            #[cfg(not(target_arch = "wasm32"))]
+            confirm_replace: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            confirm_close: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            allow_close: false,
+            pending_merge: None,
+            source_library: SourceLibrary::default(),
         }
     }
 }
@@ -49,23 +156,94 @@ impl CeBrAEfficiencyApp {
             show_left_panel: true,
             show_bottom_panel: true,
             #[cfg(target_arch = "wasm32")]
-            file_channel: None,
+            file_load: FileLoadState::Idle,
             #[cfg(target_arch = "wasm32")]
             filename: String::new(),
+            saved_fingerprint: None,
+            dirty: false,
+            dirty_check_pending: false,
+            last_dirty_check: 0.0,
+            #[cfg(not(target_arch = "wasm32"))]
+            confirm_replace: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            confirm_close: false,
+            #[cfg(not(target_arch = "wasm32"))]
+            allow_close: false,
+            pending_merge: None,
+            source_library: SourceLibrary::default(),
         };
 
         if let Some(storage) = cc.storage {
             app = eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+            app.source_library = eframe::get_value(storage, SOURCE_LIBRARY_KEY).unwrap_or_default();
         }
 
-        #[cfg(target_arch = "wasm32")]
-        if app.file_channel.is_none() {
-            app.file_channel = Some(channel());
-        }
+        app.mark_clean();
 
         app
     }
 
+    // Hash of everything the project saves but the regenerated curves, used to detect unsaved
+    // changes. The JSON objects keep their keys sorted, so the order of the detector maps does
+    // not matter
+    fn fingerprint(&self) -> u64 {
+        let mut project = serde_json::to_value(&self.measurment_handler).unwrap_or_default();
+        strip_derived(&mut project);
+
+        let mut hasher = DefaultHasher::new();
+        project.to_string().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn mark_clean(&mut self) {
+        self.saved_fingerprint = Some(self.fingerprint());
+        self.dirty = false;
+        self.dirty_check_pending = false;
+    }
+
+    // Re-hashes the project after input that can edit it, at most once per DIRTY_CHECK_INTERVAL.
+    // Pointer movement and scrolling never edit anything, and a burst of typing is hashed once
+    // when it settles instead of on every key
+    fn update_dirty(&mut self, ctx: &egui::Context) {
+        let (edited, now) = ctx.input(|i| (i.events.iter().any(is_edit_event), i.time));
+        self.dirty_check_pending |= edited;
+
+        if !self.dirty_check_pending {
+            return;
+        }
+
+        let elapsed = now - self.last_dirty_check;
+        if elapsed < DIRTY_CHECK_INTERVAL {
+            // make sure the check runs once the user stops
+            ctx.request_repaint_after(std::time::Duration::from_secs_f64(
+                DIRTY_CHECK_INTERVAL - elapsed,
+            ));
+            return;
+        }
+
+        self.last_dirty_check = now;
+        self.check_dirty();
+    }
+
+    fn check_dirty(&mut self) {
+        self.dirty_check_pending = false;
+
+        let fingerprint = self.fingerprint();
+        match self.saved_fingerprint {
+            Some(saved_fingerprint) => self.dirty = fingerprint != saved_fingerprint,
+            None => self.saved_fingerprint = Some(fingerprint),
+        }
+    }
+
+    // the throttled flag can lag an edit, decisions that discard work check it first
+    #[cfg(not(target_arch = "wasm32"))]
+    fn dirty_now(&mut self) -> bool {
+        if self.dirty_check_pending {
+            self.check_dirty();
+        }
+        self.dirty
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     fn load_previous_measurements() -> Self {
         if let Ok(data) = fs::read_to_string("etc/REU_2023.yaml") {
@@ -81,64 +259,288 @@ impl CeBrAEfficiencyApp {
         }
     }
 
+    // replaces the session with a loaded project, keeping the source library
+    fn replace_project(&mut self, project: Self) {
+        let source_library = std::mem::take(&mut self.source_library);
+        *self = project;
+        self.source_library = source_library;
+    }
+
+    // Replaces the session, a cancelled or unreadable file dialog keeps it
     #[cfg(not(target_arch = "wasm32"))]
-    fn load_from_file() -> Self {
-        if let Some(path) = rfd::FileDialog::new()
-            .set_title("Open")
-            .add_filter("YAML", &["yaml", "yml"])
-            .pick_file()
-        {
-            match File::open(path) {
-                Ok(mut file) => {
-                    let mut data = String::new();
-                    if let Err(err) = file.read_to_string(&mut data) {
-                        eprintln!("Failed to read data from file: {}", err);
-                        return Self::default();
+    fn load_replacement(&mut self, replacement: Replacement) {
+        let project = match replacement {
+            Replacement::Sample => Self::load_previous_measurements(),
+            Replacement::File => match Self::read_from_file_dialog("Open") {
+                Some(project) => project,
+                None => return,
+            },
+        };
+
+        self.replace_project(project);
+        self.mark_clean();
+    }
+
+    // replaces the session right away, or asks first when it has unsaved changes
+    #[cfg(not(target_arch = "wasm32"))]
+    fn request_replace(&mut self, replacement: Replacement) {
+        if self.dirty_now() {
+            self.confirm_replace = Some(replacement);
+        } else {
+            self.load_replacement(replacement);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn confirm_replace_window(&mut self, ctx: &egui::Context) {
+        let replacement = match self.confirm_replace {
+            Some(replacement) => replacement,
+            None => return,
+        };
+
+        egui::Window::new("Unsaved Changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("You have unsaved changes.");
+                ui.label(match replacement {
+                    Replacement::Sample => {
+                        "Loading the REU 2023 measurements will replace the current session."
+                    }
+                    Replacement::File => "Loading a project will replace the current session.",
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        self.save_to_file();
+                        if !self.dirty {
+                            self.confirm_replace = None;
+                            self.load_replacement(replacement);
+                        }
+                    }
+
+                    if ui.button("Discard and Load").clicked() {
+                        self.confirm_replace = None;
+                        self.load_replacement(replacement);
                     }
-                    match serde_yaml::from_str(&data) {
-                        Ok(result) => result,
-                        Err(err) => {
-                            eprintln!("Failed to deserialize data: {}", err);
-                            Self::default()
+
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_replace = None;
+                    }
+                });
+            });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn handle_close_request(&mut self, ctx: &egui::Context) {
+        if ctx.input(|i| i.viewport().close_requested()) && !self.allow_close && self.dirty_now() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.confirm_close = true;
+        }
+
+        if !self.confirm_close {
+            return;
+        }
+
+        egui::Window::new("Quit")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("You have unsaved changes.");
+                ui.label("Do you want to save them before quitting?");
+
+                ui.horizontal(|ui| {
+                    if ui.button("Save and Quit").clicked() {
+                        self.save_to_file();
+                        if !self.dirty {
+                            self.confirm_close = false;
+                            self.allow_close = true;
+                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                         }
                     }
+
+                    if ui.button("Quit Without Saving").clicked() {
+                        self.confirm_close = false;
+                        self.allow_close = true;
+                        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        self.confirm_close = false;
+                    }
+                });
+            });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_from_file_dialog(title: &str) -> Option<Self> {
+        let path = rfd::FileDialog::new()
+            .set_title(title)
+            .add_filter("YAML", &["yaml", "yml"])
+            .pick_file()?;
+
+        match File::open(path) {
+            Ok(mut file) => {
+                let mut data = String::new();
+                if let Err(err) = file.read_to_string(&mut data) {
+                    eprintln!("Failed to read data from file: {}", err);
+                    return None;
                 }
-                Err(e) => {
-                    eprintln!("Failed to load file: {}", e);
-                    Self::default() // Return default if loading fails
+                match serde_yaml::from_str(&data) {
+                    Ok(result) => Some(result),
+                    Err(err) => {
+                        eprintln!("Failed to deserialize data: {}", err);
+                        None
+                    }
                 }
             }
+            Err(e) => {
+                eprintln!("Failed to load file: {}", e);
+                None
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn merge_from_file(&mut self) {
+        if let Some(other) = Self::read_from_file_dialog("Merge") {
+            self.start_merge(other.measurment_handler);
+        }
+    }
+
+    fn start_merge(&mut self, other: MeasurementHandler) {
+        if self
+            .measurment_handler
+            .colliding_detector_names(&other)
+            .is_empty()
+        {
+            self.measurment_handler.merge(other, false);
         } else {
-            Self::default() // Return default if no file is picked
+            self.pending_merge = Some(other);
         }
     }
 
-    #[cfg(target_arch = "wasm32")]
-    fn load_from_file_wasm(&self, ui: &mut egui::Ui) {
-        if ui.button("Load").clicked() {
-            if let Some((sender, _)) = &self.file_channel {
-                let sender = sender.clone();
-                let task = rfd::AsyncFileDialog::new()
-                    .set_title("Open")
-                    .add_filter("YAML", &["yaml", "yml"])
-                    .pick_file();
-
-                let ctx = ui.ctx().clone();
-                wasm_bindgen_futures::spawn_local(async move {
-                    if let Some(file) = task.await {
-                        let data = file.read().await;
-                        let _ = sender.send(String::from_utf8_lossy(&data).to_string());
-                        ctx.request_repaint();
-                    } else {
-                        eprintln!("No file selected");
+    fn confirm_merge_window(&mut self, ctx: &egui::Context) {
+        let collisions = match &self.pending_merge {
+            Some(other) => self.measurment_handler.colliding_detector_names(other),
+            None => return,
+        };
+
+        let mut keep_both = None;
+        let mut cancel = false;
+
+        egui::Window::new("Merge Detectors")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("These detectors exist in both sessions:");
+                ui.label(collisions.join(", "));
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Same Detectors")
+                        .on_hover_text(
+                            "Pool the imported lines with the existing detectors of the same name",
+                        )
+                        .clicked()
+                    {
+                        keep_both = Some(false);
+                    }
+
+                    if ui
+                        .button("Keep Both")
+                        .on_hover_text("Rename the imported detectors so they are fit separately")
+                        .clicked()
+                    {
+                        keep_both = Some(true);
+                    }
+
+                    if ui.button("Cancel").clicked() {
+                        cancel = true;
                     }
                 });
+            });
+
+        if let Some(keep_both) = keep_both {
+            if let Some(other) = self.pending_merge.take() {
+                self.measurment_handler.merge(other, keep_both);
             }
+        } else if cancel {
+            self.pending_merge = None;
         }
     }
 
+    #[cfg(target_arch = "wasm32")]
+    fn load_from_file_wasm(&mut self, ui: &mut egui::Ui) {
+        let idle = !matches!(self.file_load, FileLoadState::Loading(..));
+
+        if ui.add_enabled(idle, egui::Button::new("Load")).clicked() {
+            self.pick_file_wasm(ui, FileAction::Load);
+        }
+
+        if ui
+            .add_enabled(idle, egui::Button::new("Merge"))
+            .on_hover_text("Append the measurements from another file to the current session")
+            .clicked()
+        {
+            self.pick_file_wasm(ui, FileAction::Merge);
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn pick_file_wasm(&mut self, ui: &mut egui::Ui, action: FileAction) {
+        let (sender, receiver) = channel();
+        self.file_load = FileLoadState::Loading(action, receiver);
+
+        let task = rfd::AsyncFileDialog::new()
+            .set_title(match action {
+                FileAction::Load => "Open",
+                FileAction::Merge => "Merge",
+            })
+            .add_filter("YAML", &["yaml", "yml"])
+            .pick_file();
+
+        let ctx = ui.ctx().clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let data = match task.await {
+                Some(file) => Some(String::from_utf8_lossy(&file.read().await).to_string()),
+                None => None,
+            };
+
+            let _ = sender.send(data);
+            ctx.request_repaint();
+        });
+    }
+
+    // Loads `project`, refits every detector without opening a window and writes the results to
+    // `out` as JSON. Returns how many fits finished and how many failed
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn fit_headless(
+        project: &std::path::Path,
+        out: &std::path::Path,
+    ) -> Result<(usize, usize), String> {
+        let data = fs::read_to_string(project)
+            .map_err(|err| format!("Failed to read {}: {}", project.display(), err))?;
+        let mut app: Self = serde_yaml::from_str(&data)
+            .map_err(|err| format!("Failed to deserialize {}: {}", project.display(), err))?;
+
+        let report = app
+            .measurment_handler
+            .refit_all_blocking(&project.display().to_string());
+
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|err| format!("Failed to serialize the results: {}", err))?;
+        fs::write(out, json)
+            .map_err(|err| format!("Failed to write {}: {}", out.display(), err))?;
+
+        Ok((report.fits.len(), report.failures.len()))
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
-    fn save_to_file(&self) {
+    fn save_to_file(&mut self) {
         if let Some(path) = rfd::FileDialog::new()
             .set_title("Save As")
             .add_filter("YAML", &["yaml", "yml"])
@@ -149,6 +551,7 @@ impl CeBrAEfficiencyApp {
                     let data = serde_yaml::to_string(self).expect("Failed to serialize data.");
                     file.write_all(data.as_bytes())
                         .expect("Failed to write data to file.");
+                    self.mark_clean();
                 }
                 Err(e) => {
                     eprintln!("Failed to save file: {}", e);
@@ -180,6 +583,8 @@ impl CeBrAEfficiencyApp {
                     .add_filter("YAML", &["yaml", "yml"])
                     .save_file();
 
+                self.mark_clean();
+
                 spawn_local(async move {
                     if let Some(file_handle) = task.await {
                         if let Err(e) = file_handle.write(serialized_data.as_bytes()).await {
@@ -197,19 +602,65 @@ impl CeBrAEfficiencyApp {
         });
     }
 
+    // Polled every frame. A file that fails to parse leaves the current session untouched
     #[cfg(target_arch = "wasm32")]
-    fn handle_loaded_file(&mut self, ui: &mut egui::Ui) {
-        // need this otherwise, you can only load something once
-        if self.file_channel.is_none() {
-            self.file_channel = Some(channel());
-        }
+    fn handle_loaded_file(&mut self, ctx: &egui::Context) {
+        let (action, received) = match &self.file_load {
+            FileLoadState::Loading(action, receiver) => (*action, receiver.try_recv()),
+            _ => return,
+        };
+
+        self.file_load = match received {
+            Ok(Some(data)) => match serde_yaml::from_str::<Self>(&data) {
+                Ok(result) => {
+                    match action {
+                        FileAction::Load => {
+                            self.replace_with(result);
+                            self.mark_clean();
+                        }
+                        FileAction::Merge => self.start_merge(result.measurment_handler),
+                    }
+                    FileLoadState::Loaded(ctx.input(|i| i.time))
+                }
+                Err(e) => FileLoadState::Error(format!("Failed to read file: {}", e)),
+            },
+            Ok(None) => FileLoadState::Idle,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                FileLoadState::Error("File reading was interrupted".to_string())
+            }
+        };
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn file_load_ui(&mut self, ui: &mut egui::Ui) {
+        match &self.file_load {
+            FileLoadState::Idle => {}
+            FileLoadState::Loading(..) => {
+                ui.separator();
+                ui.spinner();
+                ui.label("Loading…");
+            }
+            &FileLoadState::Loaded(loaded_at) => {
+                let shown_for = ui.input(|i| i.time) - loaded_at;
+                if shown_for >= LOADED_NOTICE_SECONDS {
+                    self.file_load = FileLoadState::Idle;
+                    return;
+                }
+
+                ui.separator();
+                ui.weak("✔ Loaded");
+                ui.ctx()
+                    .request_repaint_after(std::time::Duration::from_secs_f64(
+                        LOADED_NOTICE_SECONDS - shown_for,
+                    ));
+            }
+            FileLoadState::Error(error) => {
+                ui.separator();
+                ui.colored_label(ui.visuals().error_fg_color, format!("✖ {}", error));
 
-        if let Some((_, receiver)) = &self.file_channel {
-            if let Ok(data) = receiver.try_recv() {
-                if let Ok(result) = serde_yaml::from_str(&data) {
-                    self.replace_with(result);
-                } else {
-                    ui.label("Failed to deserialize data");
+                if ui.small_button("Dismiss").clicked() {
+                    self.file_load = FileLoadState::Idle;
                 }
             }
         }
@@ -224,13 +675,22 @@ impl CeBrAEfficiencyApp {
             }
 
             if ui.button("Load").clicked() {
-                *self = Self::load_from_file();
+                self.request_replace(Replacement::File);
+                ui.close_menu();
+            }
+
+            if ui
+                .button("Merge")
+                .on_hover_text("Append the measurements from another file to the current session")
+                .clicked()
+            {
+                self.merge_from_file();
+                ui.close_menu();
             }
         }
 
         #[cfg(target_arch = "wasm32")]
         {
-            self.handle_loaded_file(ui);
             self.load_from_file_wasm(ui);
             self.save_to_file_wasm(ui);
         }
@@ -253,6 +713,15 @@ impl CeBrAEfficiencyApp {
                     ui.checkbox(&mut self.show_left_panel, "Measurement Panel");
                     ui.checkbox(&mut self.show_bottom_panel, "Fitting Panel");
                 });
+
+                if self.dirty {
+                    ui.separator();
+                    ui.label("● Unsaved changes")
+                        .on_hover_text("Changes since the last save or load");
+                }
+
+                #[cfg(target_arch = "wasm32")]
+                self.file_load_ui(ui);
             });
         });
 
@@ -261,12 +730,16 @@ impl CeBrAEfficiencyApp {
             ui.horizontal(|ui| {
                 ui.label("Previous Measurements");
                 if ui.button("REU 2023").clicked() {
-                    *self = Self::load_previous_measurements();
+                    self.request_replace(Replacement::Sample);
                 }
             });
 
-            self.measurment_handler
-                .ui(ui, self.show_bottom_panel, self.show_left_panel);
+            self.measurment_handler.ui(
+                ui,
+                self.show_bottom_panel,
+                self.show_left_panel,
+                &mut self.source_library,
+            );
         });
     }
 }
@@ -274,9 +747,13 @@ impl CeBrAEfficiencyApp {
 impl App for CeBrAEfficiencyApp {
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
         eframe::set_value(storage, eframe::APP_KEY, self);
+        eframe::set_value(storage, SOURCE_LIBRARY_KEY, &self.source_library);
     }
 
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        #[cfg(target_arch = "wasm32")]
+        self.handle_loaded_file(ctx);
+
         if self.window {
             egui::Window::new("CeBrA Efficiency").show(ctx, |ui| {
                 self.ui(ui, ctx);
@@ -286,6 +763,15 @@ impl App for CeBrAEfficiencyApp {
                 self.ui(ui, ctx);
             });
         }
+
+        self.update_dirty(ctx);
+        self.confirm_merge_window(ctx);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.confirm_replace_window(ctx);
+            self.handle_close_request(ctx);
+        }
     }
 }
 
@@ -297,6 +783,79 @@ trait ReplaceWith {
 #[cfg(target_arch = "wasm32")]
 impl ReplaceWith for CeBrAEfficiencyApp {
     fn replace_with(&mut self, other: Self) {
-        *self = other;
+        self.replace_project(other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::efficiency_fitter::exp_fitter::ExpFitter;
+
+    #[test]
+    fn only_editing_input_triggers_a_dirty_check() {
+        let click = |pressed| egui::Event::PointerButton {
+            pos: egui::Pos2::ZERO,
+            button: egui::PointerButton::Primary,
+            pressed,
+            modifiers: egui::Modifiers::NONE,
+        };
+
+        assert!(is_edit_event(&egui::Event::Text("a".to_string())));
+        assert!(is_edit_event(&click(false)));
+        assert!(!is_edit_event(&click(true)));
+        assert!(!is_edit_event(&egui::Event::PointerMoved(egui::Pos2::ZERO)));
+        assert!(!is_edit_event(&egui::Event::MouseWheel {
+            unit: egui::MouseWheelUnit::Point,
+            delta: egui::Vec2::ZERO,
+            modifiers: egui::Modifiers::NONE,
+        }));
+    }
+
+    #[test]
+    fn pending_edits_are_hashed_before_discarding_work() {
+        let mut app = CeBrAEfficiencyApp::default();
+        app.mark_clean();
+
+        app.measurment_handler
+            .measurement_exp_fits
+            .entry("A".to_string())
+            .or_default()
+            .initial_b_guess = 123.0;
+        assert!(!app.dirty);
+
+        app.dirty_check_pending = true;
+        assert!(app.dirty_now());
+        assert!(!app.dirty_check_pending);
+    }
+
+    #[test]
+    fn fit_settings_are_edits_but_regenerated_curves_are_not() {
+        let mut app = CeBrAEfficiencyApp::default();
+        app.measurment_handler
+            .measurement_exp_fits
+            .entry("A".to_string())
+            .or_default()
+            .insert_fit("Single", ExpFitter::new(vec![1.0], vec![1.0], vec![1.0]));
+        app.mark_clean();
+
+        let fitter = app
+            .measurment_handler
+            .measurement_exp_fits
+            .get_mut("A")
+            .unwrap();
+        let fit = fitter.fits.get_mut("Single").unwrap();
+        fit.fit_line.points.push([1.0, 2.0]);
+        fit.upper_uncertainity_points.push([1.0, 2.5]);
+        app.check_dirty();
+        assert!(!app.dirty);
+
+        app.measurment_handler
+            .measurement_exp_fits
+            .get_mut("A")
+            .unwrap()
+            .error_scale = 2.0;
+        app.check_dirty();
+        assert!(app.dirty);
     }
 }
@@ -0,0 +1,646 @@
+// Fitted efficiency curves without any of the GUI types, so other crates can read a saved project
+// and query efficiencies. Build with `default-features = false` to leave egui and eframe out
+
+use evalexpr::{ContextWithMutableVariables, HashMapContext, Node, Operator, Value};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// math functions that can be written without the `math::` prefix evalexpr expects
+const MATH_FUNCTIONS: [&str; 12] = [
+    "exp", "ln", "log", "log2", "log10", "sqrt", "pow", "abs", "sin", "cos", "tan", "tanh",
+];
+
+// Parses a formula in x the way the fit panel does: bare math functions get their `math::` prefix
+// and integer constants become floats, since evalexpr divides integers as integers
+pub fn parse_formula(expression: &str) -> Result<Node, String> {
+    let mut tree = evalexpr::build_operator_tree(expression).map_err(|err| err.to_string())?;
+
+    for identifier in tree.iter_function_identifiers_mut() {
+        if MATH_FUNCTIONS.contains(&identifier.as_str()) {
+            *identifier = format!("math::{}", identifier);
+        }
+    }
+
+    promote_integers(&mut tree);
+
+    if !tree.iter_variable_identifiers().any(|name| name == "x") {
+        return Err("The formula does not depend on x".to_string());
+    }
+
+    Ok(tree)
+}
+
+fn promote_integers(node: &mut Node) {
+    if let Operator::Const {
+        value: Value::Int(integer),
+    } = node.operator()
+    {
+        *node.operator_mut() = Operator::Const {
+            value: Value::Float(*integer as f64),
+        };
+    }
+
+    for child in node.children_mut() {
+        promote_integers(child);
+    }
+}
+
+// every identifier other than x, in the order the fit stores the parameters
+pub fn formula_parameter_names(tree: &Node) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for name in tree.iter_variable_identifiers() {
+        if name != "x" && !names.iter().any(|known| known == name) {
+            names.push(name.to_string());
+        }
+    }
+
+    names
+}
+
+// A formula parsed once, with its parameter names in the order the fit stores them
+#[derive(Debug, Clone)]
+pub struct ParsedFormula {
+    tree: Node,
+    names: Vec<String>,
+}
+
+impl ParsedFormula {
+    pub fn new(expression: &str) -> Result<Self, String> {
+        let tree = parse_formula(expression)?;
+        let names = formula_parameter_names(&tree);
+        Ok(Self { tree, names })
+    }
+
+    pub fn tree(&self) -> &Node {
+        &self.tree
+    }
+
+    pub fn parameter_names(&self) -> &[String] {
+        &self.names
+    }
+
+    // NaN when a parameter is missing or the expression cannot be evaluated
+    pub fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        if parameters.len() < self.names.len() {
+            return f64::NAN;
+        }
+
+        let mut context = HashMapContext::new();
+        let values = std::iter::once(("x", x)).chain(
+            self.names
+                .iter()
+                .map(String::as_str)
+                .zip(parameters.iter().cloned()),
+        );
+        for (name, value) in values {
+            if context
+                .set_value(name.to_string(), Value::Float(value))
+                .is_err()
+            {
+                return f64::NAN;
+            }
+        }
+
+        self.tree
+            .eval_number_with_context(&context)
+            .unwrap_or(f64::NAN)
+    }
+}
+
+// y = a exp(-x/b) + c exp(-x/d) + ..., the amplitudes first, then the decay constants
+pub fn exponential(x: f64, parameters: &[f64]) -> f64 {
+    let (amplitudes, decays) = parameters.split_at(parameters.len() / 2);
+
+    amplitudes
+        .iter()
+        .zip(decays.iter())
+        .map(|(a, b)| a * (-x / b).exp())
+        .sum()
+}
+
+// ln(y) = sum a_k ln(x/E0)^k, zero at and below zero energy
+pub fn log_polynomial(x: f64, reference_energy: f64, coefficients: &[f64]) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+
+    let log_x = (x / reference_energy).ln();
+    coefficients
+        .iter()
+        .enumerate()
+        .map(|(power, coefficient)| coefficient * log_x.powi(power as i32))
+        .sum::<f64>()
+        .exp()
+}
+
+// ln(y) as a natural cubic spline in ln(x) with knots at `log_knots`, zero at and below zero energy
+pub fn spline(x: f64, log_knots: &[f64], coefficients: &[f64]) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+
+    natural_spline_basis(x.ln(), log_knots)
+        .iter()
+        .zip(coefficients.iter())
+        .map(|(basis, coefficient)| basis * coefficient)
+        .sum::<f64>()
+        .exp()
+}
+
+// Natural cubic spline basis at `log_x` for knots at `log_knots` (ascending), one function per
+// knot: 1, L and the truncated cubics that keep the spline linear outside the outer knots
+pub fn natural_spline_basis(log_x: f64, log_knots: &[f64]) -> Vec<f64> {
+    let count = log_knots.len();
+    if count < 2 {
+        return vec![1.0; count];
+    }
+
+    let last = log_knots[count - 1];
+    let cube = |knot: f64| (log_x - knot).max(0.0).powi(3);
+    let d = |k: usize| (cube(log_knots[k]) - cube(last)) / (last - log_knots[k]);
+
+    let mut basis = vec![1.0, log_x];
+    basis.extend((0..count - 2).map(|k| d(k) - d(count - 2)));
+    basis
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum CurveModel {
+    Exponential { terms: usize }, // a exp(-x/b) + c exp(-x/d) + ...
+    LogPolynomial { order: usize, reference_energy: f64 }, // ln(y) = sum a_k ln(x/E0)^k
+    Formula { expression: String },
+    Spline { knots: Vec<f64> }, // ln(y) as a natural cubic spline in ln(x), knots in keV
+}
+
+// Relative fits divide every efficiency by the one of a reference line before fitting, so the model
+// is the relative efficiency and the absolute scale is this separate parameter
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Normalization {
+    pub energy: f64,      // keV, the reference line
+    pub value: f64,       // percent, efficiency of the reference line
+    pub uncertainty: f64, // percent
+}
+
+impl Normalization {
+    pub fn relative_uncertainty(&self) -> f64 {
+        if self.value != 0.0 {
+            self.uncertainty / self.value.abs()
+        } else {
+            0.0
+        }
+    }
+}
+
+// The model is private so the formula parsed from it on the first evaluation can not go stale
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct EfficiencyCurve {
+    pub detector: String,
+    pub fit: String,
+    model: CurveModel,
+    pub parameters: Vec<f64>, // linear then nonlinear, the order of the covariance
+    pub covariance: Vec<f64>, // row major
+    #[serde(default)]
+    pub energy_times_efficiency: bool, // the model is E * eff(E)
+    pub valid_range: (f64, f64), // keV
+    #[serde(default)]
+    pub normalization: Option<Normalization>, // the model is relative to this line
+    #[serde(default)]
+    pub low_energy_cutoff: Option<f64>, // keV, the detector threshold, zero efficiency below
+    #[serde(skip)]
+    formula: OnceLock<Result<ParsedFormula, String>>,
+}
+
+// the parsed formula is derived from the model, so it is left out of the comparison
+impl PartialEq for EfficiencyCurve {
+    fn eq(&self, other: &Self) -> bool {
+        self.detector == other.detector
+            && self.fit == other.fit
+            && self.model == other.model
+            && self.parameters == other.parameters
+            && self.covariance == other.covariance
+            && self.energy_times_efficiency == other.energy_times_efficiency
+            && self.valid_range == other.valid_range
+            && self.normalization == other.normalization
+            && self.low_energy_cutoff == other.low_energy_cutoff
+    }
+}
+
+impl EfficiencyCurve {
+    pub fn new(
+        detector: &str,
+        fit: &str,
+        model: CurveModel,
+        parameters: Vec<f64>,
+        covariance: Vec<f64>,
+        valid_range: (f64, f64),
+    ) -> Self {
+        Self {
+            detector: detector.to_string(),
+            fit: fit.to_string(),
+            model,
+            parameters,
+            covariance,
+            energy_times_efficiency: false,
+            valid_range,
+            normalization: None,
+            low_energy_cutoff: None,
+            formula: OnceLock::new(),
+        }
+    }
+
+    pub fn model(&self) -> &CurveModel {
+        &self.model
+    }
+
+    // (efficiency, 1σ standard error) in percent at `energy` in keV. The error is propagated from
+    // the covariance to first order, without the Student-t factor of the band drawn in the app.
+    // Both are zero below the detector threshold, like in the app
+    pub fn evaluate(&self, energy: f64) -> (f64, f64) {
+        if self.low_energy_cutoff.is_some_and(|cutoff| energy < cutoff) {
+            return (0.0, 0.0);
+        }
+
+        let efficiency = self.evaluate_with(energy, &self.parameters);
+
+        // the normalization is independent of the shape parameters, added in quadrature
+        let normalization_variance = self.normalization.map_or(0.0, |normalization| {
+            (efficiency * normalization.relative_uncertainty()).powi(2)
+        });
+
+        let n_parameters = self.parameters.len();
+        if self.covariance.len() != n_parameters * n_parameters {
+            return (efficiency, normalization_variance.sqrt());
+        }
+
+        let mut shifted = self.parameters.clone();
+        let gradient: Vec<f64> = (0..n_parameters)
+            .map(|k| {
+                let h = 1e-6 * self.parameters[k].abs().max(1e-6);
+
+                shifted[k] = self.parameters[k] + h;
+                let upper = self.evaluate_with(energy, &shifted);
+                shifted[k] = self.parameters[k] - h;
+                let lower = self.evaluate_with(energy, &shifted);
+                shifted[k] = self.parameters[k];
+
+                (upper - lower) / (2.0 * h)
+            })
+            .collect();
+
+        let variance: f64 = (0..n_parameters)
+            .flat_map(|i| (0..n_parameters).map(move |j| (i, j)))
+            .map(|(i, j)| gradient[i] * gradient[j] * self.covariance[i * n_parameters + j])
+            .sum();
+
+        (
+            efficiency,
+            (variance.max(0.0) + normalization_variance).sqrt(),
+        )
+    }
+
+    fn evaluate_with(&self, energy: f64, parameters: &[f64]) -> f64 {
+        let y = match &self.model {
+            // a parameter list that does not fit the terms is a malformed curve, not a panic
+            CurveModel::Exponential { terms } => {
+                if parameters.len() != 2 * terms {
+                    return f64::NAN;
+                }
+                exponential(energy, parameters)
+            }
+            CurveModel::LogPolynomial {
+                reference_energy, ..
+            } => log_polynomial(energy, *reference_energy, parameters),
+            CurveModel::Formula { expression } => {
+                match self.formula.get_or_init(|| ParsedFormula::new(expression)) {
+                    Ok(formula) => formula.evaluate(energy, parameters),
+                    Err(_) => f64::NAN,
+                }
+            }
+            CurveModel::Spline { knots } => {
+                let log_knots: Vec<f64> = knots.iter().map(|knot| knot.ln()).collect();
+                spline(energy, &log_knots, parameters)
+            }
+        };
+
+        let y = y * self
+            .normalization
+            .map_or(1.0, |normalization| normalization.value);
+
+        if self.energy_times_efficiency {
+            // eff(E) = f(E) / E is undefined at zero energy
+            if energy > 0.0 {
+                y / energy
+            } else {
+                0.0
+            }
+        } else {
+            y
+        }
+    }
+
+    // The active fit of every detector in a project saved by the app, sorted by detector name.
+    // Detectors without a fit are skipped
+    pub fn from_project_yaml(yaml: &str) -> Result<Vec<EfficiencyCurve>, String> {
+        let project: SavedProject = serde_yaml::from_str(yaml).map_err(|err| err.to_string())?;
+        let handler = project.measurment_handler;
+
+        // a detector name can appear in several measurements, the app uses the highest threshold
+        let mut cutoffs: HashMap<String, f64> = HashMap::new();
+        for detector in handler.measurements.iter().flat_map(|m| m.detectors.iter()) {
+            if let Some(cutoff) = detector.low_energy_cutoff {
+                let entry = cutoffs.entry(detector.name.clone()).or_insert(cutoff);
+                *entry = entry.max(cutoff);
+            }
+        }
+
+        let mut curves: Vec<EfficiencyCurve> = handler
+            .measurement_exp_fits
+            .into_iter()
+            .filter_map(|(detector, fitter)| {
+                let (fit_name, fit) = match fitter.fits.get(&fitter.active_fit) {
+                    Some(fit) => (fitter.active_fit.clone(), fit),
+                    // saved before named fits existed
+                    None => ("Fit".to_string(), fitter.exp_fitter.as_ref()?),
+                };
+                let mut curve = fit.curve(&detector, &fit_name)?;
+                curve.low_energy_cutoff = cutoffs.get(&detector).copied();
+                Some(curve)
+            })
+            .collect();
+
+        curves.sort_by(|a, b| a.detector.cmp(&b.detector));
+        Ok(curves)
+    }
+}
+
+// The parts of the saved project the curves are built from. Everything else in the file is
+// ignored, so these do not depend on the GUI types
+
+#[derive(serde::Deserialize)]
+struct SavedProject {
+    measurment_handler: SavedHandler,
+}
+
+#[derive(serde::Deserialize)]
+struct SavedHandler {
+    #[serde(default)]
+    measurements: Vec<SavedMeasurement>,
+    #[serde(default)]
+    measurement_exp_fits: HashMap<String, SavedFitter>,
+}
+
+#[derive(serde::Deserialize)]
+struct SavedMeasurement {
+    #[serde(default)]
+    detectors: Vec<SavedDetector>,
+}
+
+#[derive(serde::Deserialize)]
+struct SavedDetector {
+    name: String,
+    #[serde(default)]
+    low_energy_cutoff: Option<f64>,
+}
+
+#[derive(serde::Deserialize)]
+struct SavedFitter {
+    #[serde(default)]
+    fits: HashMap<String, SavedFit>,
+    #[serde(default)]
+    active_fit: String,
+    #[serde(default)]
+    exp_fitter: Option<SavedFit>,
+}
+
+#[derive(serde::Deserialize)]
+enum SavedModel {
+    Exponential { initial_decays: Vec<f64> },
+    LogPolynomial { order: usize, reference_energy: f64 },
+    Formula { expression: String },
+    Spline { knots: Vec<f64> },
+}
+
+#[derive(serde::Deserialize)]
+struct SavedResult {
+    linear_parameters: Vec<f64>,
+    nonlinear_parameters: Vec<f64>,
+    covariance_matrix: Vec<f64>,
+    reduced_chi_squared: f64,
+}
+
+fn default_scale_covariance() -> bool {
+    true
+}
+
+#[derive(serde::Deserialize)]
+struct SavedFit {
+    #[serde(default)]
+    x: Vec<f64>,
+    fit_result: Option<SavedResult>,
+    #[serde(default)]
+    model: Option<SavedModel>,
+    #[serde(default)]
+    energy_times_efficiency: bool,
+    #[serde(default = "default_scale_covariance")]
+    scale_covariance: bool,
+    #[serde(default)]
+    valid_min: f64,
+    #[serde(default)]
+    valid_max: f64,
+    #[serde(default)]
+    normalization: Option<Normalization>,
+}
+
+impl SavedFit {
+    fn curve(&self, detector: &str, fit_name: &str) -> Option<EfficiencyCurve> {
+        let result = self.fit_result.as_ref()?;
+
+        let model = match &self.model {
+            Some(SavedModel::Exponential { initial_decays }) => CurveModel::Exponential {
+                terms: initial_decays.len(),
+            },
+            Some(SavedModel::LogPolynomial {
+                order,
+                reference_energy,
+            }) => CurveModel::LogPolynomial {
+                order: *order,
+                reference_energy: *reference_energy,
+            },
+            Some(SavedModel::Formula { expression }) => CurveModel::Formula {
+                expression: expression.clone(),
+            },
+            Some(SavedModel::Spline { knots }) => CurveModel::Spline {
+                knots: knots.clone(),
+            },
+            // fits saved before the model was stored are double exponentials
+            None => CurveModel::Exponential {
+                terms: result.nonlinear_parameters.len(),
+            },
+        };
+
+        // the stored covariance is scaled by the reduced χ², undone when the fit trusts the data errors
+        let factor = if !self.scale_covariance
+            && result.reduced_chi_squared.is_finite()
+            && result.reduced_chi_squared > 0.0
+        {
+            1.0 / result.reduced_chi_squared
+        } else {
+            1.0
+        };
+
+        let valid_range = if self.valid_max > self.valid_min {
+            (self.valid_min, self.valid_max)
+        } else {
+            (
+                self.x.iter().fold(f64::INFINITY, |a, &b| a.min(b)),
+                self.x.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b)),
+            )
+        };
+
+        let parameters = result
+            .linear_parameters
+            .iter()
+            .chain(result.nonlinear_parameters.iter())
+            .cloned()
+            .collect();
+        let covariance = result
+            .covariance_matrix
+            .iter()
+            .map(|value| value * factor)
+            .collect();
+
+        let mut curve = EfficiencyCurve::new(
+            detector,
+            fit_name,
+            model,
+            parameters,
+            covariance,
+            valid_range,
+        );
+        curve.energy_times_efficiency = self.energy_times_efficiency;
+        curve.normalization = self.normalization;
+        Some(curve)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(model: CurveModel, parameters: Vec<f64>) -> EfficiencyCurve {
+        EfficiencyCurve::new("A", "Fit", model, parameters, Vec::new(), (100.0, 1500.0))
+    }
+
+    #[test]
+    fn exponential() {
+        let curve = curve(
+            CurveModel::Exponential { terms: 2 },
+            vec![10.0, 2.0, 200.0, 2000.0],
+        );
+
+        let expected = 10.0 * (-0.5f64).exp() + 2.0 * (-0.05f64).exp();
+        assert!((curve.evaluate(100.0).0 - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn log_polynomial() {
+        let curve = curve(
+            CurveModel::LogPolynomial {
+                order: 1,
+                reference_energy: 1000.0,
+            },
+            vec![0.5, -0.7],
+        );
+
+        assert!((curve.evaluate(1000.0).0 - 0.5f64.exp()).abs() < 1e-12);
+        assert_eq!(curve.evaluate(0.0).0, 0.0);
+    }
+
+    #[test]
+    fn formula_matches_the_exponential() {
+        let formula = curve(
+            CurveModel::Formula {
+                expression: "a * exp(-x / b) + 1".to_string(),
+            },
+            vec![10.0, 200.0],
+        );
+
+        for energy in [100.0, 500.0, 1000.0] {
+            let expected = 10.0 * (-energy / 200.0f64).exp() + 1.0;
+            assert!((formula.evaluate(energy).0 - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn error_from_the_covariance() {
+        // y = a exp(-x/b) with only a uncertain, σy = σa exp(-x/b)
+        let mut curve = curve(CurveModel::Exponential { terms: 1 }, vec![10.0, 200.0]);
+        curve.covariance = vec![0.25, 0.0, 0.0, 0.0];
+
+        let (_, error) = curve.evaluate(200.0);
+        assert!((error - 0.5 * (-1.0f64).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn malformed_curves_are_nan() {
+        // too few parameters for the terms
+        let short = curve(CurveModel::Exponential { terms: 2 }, vec![10.0, 200.0]);
+        assert!(short.evaluate(100.0).0.is_nan());
+
+        let unparsable = curve(
+            CurveModel::Formula {
+                expression: "a * exp(-x / ".to_string(),
+            },
+            vec![10.0],
+        );
+        assert!(unparsable.evaluate(100.0).0.is_nan());
+
+        let missing = curve(
+            CurveModel::Formula {
+                expression: "a * exp(-x / b)".to_string(),
+            },
+            vec![10.0],
+        );
+        assert!(missing.evaluate(100.0).0.is_nan());
+    }
+
+    #[test]
+    fn parse_formula_rejects_formulas_without_x() {
+        assert!(parse_formula("a * b").is_err());
+        assert!(parse_formula("a * (").is_err());
+
+        let tree = parse_formula("a * x^2 + b / 2").unwrap();
+        assert_eq!(formula_parameter_names(&tree), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn nothing_below_the_detector_threshold() {
+        let yaml = r#"
+measurment_handler:
+  measurements:
+    - detectors:
+        - name: A
+          low_energy_cutoff: 80.0
+        - name: A
+          low_energy_cutoff: 100.0
+  measurement_exp_fits:
+    A:
+      active_fit: Single
+      fits:
+        Single:
+          x: [121.8, 1408.0]
+          model: !Exponential
+            initial_decays: [200.0]
+          fit_result:
+            linear_parameters: [10.0]
+            nonlinear_parameters: [200.0]
+            covariance_matrix: [0.25, 0.0, 0.0, 0.0]
+            reduced_chi_squared: 1.0
+"#;
+        let curves = EfficiencyCurve::from_project_yaml(yaml).unwrap();
+        assert_eq!(curves[0].low_energy_cutoff, Some(100.0));
+        assert_eq!(curves[0].evaluate(90.0), (0.0, 0.0));
+        assert!((curves[0].evaluate(100.0).0 - 10.0 * (-0.5f64).exp()).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,590 @@
+use super::exp_fitter::ExpFitter;
+use super::fit_model::Model;
+
+use evalexpr::{Node, Operator, Value};
+
+// The fitted efficiency as a function to paste into an analysis, with a companion uncertainty
+// function that propagates the parameter covariance through central differences so it works the
+// same way for every model
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum CodeLanguage {
+    #[default]
+    Python,
+    Cpp, // plain C++, also runs as a ROOT macro
+    Rust,
+}
+
+impl CodeLanguage {
+    const ALL: [CodeLanguage; 3] = [CodeLanguage::Python, CodeLanguage::Cpp, CodeLanguage::Rust];
+
+    fn label(self) -> &'static str {
+        match self {
+            CodeLanguage::Python => "Python",
+            CodeLanguage::Cpp => "C++/ROOT",
+            CodeLanguage::Rust => "Rust",
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_id_source(ui.id().with("code_language"))
+            .selected_text(self.label())
+            .show_ui(ui, |ui| {
+                for language in Self::ALL {
+                    ui.selectable_value(self, language, language.label());
+                }
+            });
+    }
+
+    fn comment(self) -> &'static str {
+        match self {
+            CodeLanguage::Python => "#",
+            CodeLanguage::Cpp | CodeLanguage::Rust => "//",
+        }
+    }
+
+    fn parameter(self, index: usize) -> String {
+        format!("p[{}]", index)
+    }
+
+    fn function(self, name: &str, arguments: &[String]) -> Result<String, String> {
+        let argument = |index: usize| {
+            arguments
+                .get(index)
+                .cloned()
+                .ok_or_else(|| format!("{} needs {} arguments", name, index + 1))
+        };
+
+        let call = |python: &str, cpp: &str, rust: &str| -> Result<String, String> {
+            let arguments = arguments.join(", ");
+            Ok(match self {
+                CodeLanguage::Python => format!("math.{}({})", python, arguments),
+                CodeLanguage::Cpp => format!("std::{}({})", cpp, arguments),
+                CodeLanguage::Rust => format!("f64::{}({})", rust, arguments),
+            })
+        };
+
+        match name {
+            "exp" | "sqrt" | "sin" | "cos" | "tan" | "tanh" | "log2" | "log10" => {
+                call(name, name, name)
+            }
+            "ln" => call("log", "log", "ln"),
+            "abs" => match self {
+                CodeLanguage::Python => Ok(format!("abs({})", argument(0)?)),
+                _ => call("fabs", "abs", "abs"),
+            },
+            "pow" => self.power(argument(0)?, argument(1)?),
+            "max" => match self {
+                CodeLanguage::Python => Ok(format!("max({})", arguments.join(", "))),
+                _ => call("max", "max", "max"),
+            },
+            // evalexpr's log takes the base as the second argument
+            "log" => match self {
+                CodeLanguage::Cpp => Ok(format!(
+                    "(std::log({}) / std::log({}))",
+                    argument(0)?,
+                    argument(1)?
+                )),
+                _ => call("log", "log", "log"),
+            },
+            _ => Err(format!("{} has no equivalent in {}", name, self.label())),
+        }
+    }
+
+    fn power(self, base: String, exponent: String) -> Result<String, String> {
+        Ok(match self {
+            CodeLanguage::Python => format!("({} ** {})", base, exponent),
+            CodeLanguage::Cpp => format!("std::pow({}, {})", base, exponent),
+            CodeLanguage::Rust => format!("f64::powf({}, {})", base, exponent),
+        })
+    }
+
+    // literals in exponent notation are valid and round trip in all three languages
+    fn number(self, value: f64) -> String {
+        format!("{:e}", value)
+    }
+
+    // f(E) of the fitted model with the parameters read from `p`
+    fn model_expression(self, model: &Model) -> Result<String, String> {
+        match model {
+            Model::Exponential(exponential) => {
+                let terms = exponential.terms();
+                Ok((0..terms)
+                    .map(|term| {
+                        self.function(
+                            "exp",
+                            &[format!("-energy / {}", self.parameter(terms + term))],
+                        )
+                        .map(|exp| format!("{} * {}", self.parameter(term), exp))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(" + "))
+            }
+            Model::LogPolynomial(polynomial) => {
+                let log_energy = self.function(
+                    "ln",
+                    &[format!(
+                        "energy / {}",
+                        self.number(polynomial.reference_energy)
+                    )],
+                )?;
+
+                let terms = (0..=polynomial.order)
+                    .map(|power| match power {
+                        0 => Ok(self.parameter(0)),
+                        1 => Ok(format!("{} * {}", self.parameter(1), log_energy)),
+                        _ => self
+                            .power(log_energy.clone(), self.number(power as f64))
+                            .map(|term| format!("{} * {}", self.parameter(power), term)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.function("exp", &[terms.join(" + ")])
+            }
+            // the truncated cubics of the natural spline basis, see `curve::natural_spline_basis`
+            Model::Spline(spline) => {
+                let log_energy = self.function("ln", &["energy".to_string()])?;
+                let log_knots: Vec<String> = spline
+                    .knots
+                    .iter()
+                    .map(|knot| self.number(knot.ln()))
+                    .collect();
+                let count = log_knots.len();
+                if count < 3 {
+                    return Err("The spline needs at least 3 knots".to_string());
+                }
+
+                let cube = |knot: &String| {
+                    let positive = self.function(
+                        "max",
+                        &[format!("{} - {}", log_energy, knot), self.number(0.0)],
+                    )?;
+                    self.power(positive, self.number(3.0))
+                };
+                let last = &log_knots[count - 1];
+                let d = |k: usize| -> Result<String, String> {
+                    Ok(format!(
+                        "({} - {}) / ({} - {})",
+                        cube(&log_knots[k])?,
+                        cube(last)?,
+                        last,
+                        log_knots[k]
+                    ))
+                };
+
+                let mut terms = vec![
+                    self.parameter(0),
+                    format!("{} * {}", self.parameter(1), log_energy),
+                ];
+                for k in 0..count - 2 {
+                    terms.push(format!(
+                        "{} * ({} - {})",
+                        self.parameter(k + 2),
+                        d(k)?,
+                        d(count - 2)?
+                    ));
+                }
+
+                self.function("exp", &[terms.join(" + ")])
+            }
+            Model::Formula(formula) => {
+                let tree = formula.tree().map_err(|err| err.clone())?;
+                let names = model.fit_model().parameter_names();
+                self.node(tree, &names)
+            }
+        }
+    }
+
+    // evalexpr tree to source, parenthesized everywhere so precedence never differs
+    fn node(self, node: &Node, names: &[String]) -> Result<String, String> {
+        let children = node
+            .children()
+            .iter()
+            .map(|child| self.node(child, names))
+            .collect::<Result<Vec<String>, String>>()?;
+
+        let binary = |symbol: &str| -> Result<String, String> {
+            match children.as_slice() {
+                [left, right] => Ok(format!("({} {} {})", left, symbol, right)),
+                _ => Err(format!("{} needs two operands", symbol)),
+            }
+        };
+
+        match node.operator() {
+            Operator::RootNode => match children.as_slice() {
+                [child] => Ok(child.clone()),
+                _ => Err("Empty expression".to_string()),
+            },
+            Operator::Add => binary("+"),
+            Operator::Sub => binary("-"),
+            Operator::Mul => binary("*"),
+            Operator::Div => binary("/"),
+            Operator::Neg => match children.as_slice() {
+                [child] => Ok(format!("(-{})", child)),
+                _ => Err("- needs one operand".to_string()),
+            },
+            Operator::Exp => match children.as_slice() {
+                [base, exponent] => self.power(base.clone(), exponent.clone()),
+                _ => Err("^ needs two operands".to_string()),
+            },
+            Operator::Tuple => Ok(children.join(", ")),
+            Operator::Const {
+                value: Value::Float(value),
+            } => Ok(self.number(*value)),
+            Operator::Const {
+                value: Value::Int(value),
+            } => Ok(self.number(*value as f64)),
+            Operator::VariableIdentifierRead { identifier } => {
+                if identifier == "x" {
+                    return Ok("energy".to_string());
+                }
+
+                names
+                    .iter()
+                    .position(|name| name == identifier)
+                    .map(|index| self.parameter(index))
+                    .ok_or_else(|| format!("Unknown parameter {}", identifier))
+            }
+            Operator::FunctionIdentifier { identifier } => {
+                // the arguments are a tuple, usually inside the root node of the parentheses
+                let mut argument = node;
+                while let [child] = argument.children() {
+                    argument = child;
+                    if !matches!(argument.operator(), Operator::RootNode) {
+                        break;
+                    }
+                }
+
+                let arguments: Vec<String> = if matches!(argument.operator(), Operator::Tuple) {
+                    argument
+                        .children()
+                        .iter()
+                        .map(|child| self.node(child, names))
+                        .collect::<Result<_, _>>()?
+                } else {
+                    children.clone()
+                };
+
+                self.function(identifier.trim_start_matches("math::"), &arguments)
+            }
+            operator => Err(format!("{:?} is not supported in code export", operator)),
+        }
+    }
+}
+
+// Source for eff(E) and sigma(E) of `fit`, or why it could not be written
+pub fn code_snippet(
+    fit: &ExpFitter,
+    title: &str,
+    language: CodeLanguage,
+) -> Result<String, String> {
+    let (model, result) = match (&fit.model, &fit.fit_result) {
+        (Some(model), Some(result)) => (model, result),
+        _ => return Err("Nothing has been fit".to_string()),
+    };
+
+    let mut parameters = result.parameters();
+    let mut n_parameters = parameters.len();
+    let mut covariance = fit.covariance();
+    if covariance.len() != n_parameters * n_parameters {
+        return Err("The fit has no covariance matrix".to_string());
+    }
+
+    let mut expression = language.model_expression(model)?;
+    let mut names = model.fit_model().parameter_names();
+
+    // the normalization of a relative fit goes last, uncorrelated with the shape
+    if let Some(normalization) = &fit.normalization {
+        expression = format!("{} * ({})", language.parameter(n_parameters), expression);
+        names.push("N".to_string());
+        parameters.push(normalization.value);
+
+        let size = n_parameters + 1;
+        covariance = (0..size * size)
+            .map(|index| {
+                let (i, j) = (index / size, index % size);
+                if i < n_parameters && j < n_parameters {
+                    covariance[i * n_parameters + j]
+                } else if i == j {
+                    normalization.uncertainty.powi(2)
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        n_parameters = size;
+    }
+    let mut guarded = matches!(model, Model::LogPolynomial(_) | Model::Spline(_));
+    if fit.energy_times_efficiency {
+        expression = format!("({}) / energy", expression);
+        guarded = true;
+    }
+
+    let (valid_min, valid_max) = fit.valid_range();
+    let comment = language.comment();
+    let mut header = vec![
+        format!("{} {}", comment, title),
+        format!("{} {}", comment, model.fit_model().formula()),
+        format!(
+            "{} Efficiency in percent, energy in keV. Valid from {:.1} to {:.1} keV",
+            comment, valid_min, valid_max
+        ),
+        format!("{} Parameters: {}", comment, names.join(", ")),
+    ];
+    if fit.energy_times_efficiency {
+        header.push(format!("{} The formula is fit to E * eff(E)", comment));
+    }
+    if let Some(normalization) = &fit.normalization {
+        header.push(format!(
+            "{} The formula is relative to the {:.1} keV line, N is the efficiency of that line",
+            comment, normalization.energy
+        ));
+    }
+    header.push(format!(
+        "{} The uncertainty is the 1 sigma standard error, without the Student-t factor of the plotted band",
+        comment
+    ));
+    let header = header.join("\n");
+
+    let numbers = |values: &[f64]| {
+        values
+            .iter()
+            .map(|value| language.number(*value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let covariance_rows: Vec<String> = covariance.chunks(n_parameters).map(&numbers).collect();
+
+    Ok(match language {
+        CodeLanguage::Python => {
+            let guard = if guarded {
+                "    if energy <= 0:\n        return 0.0\n"
+            } else {
+                ""
+            };
+            let covariance = covariance_rows
+                .iter()
+                .map(|row| format!("    [{}],", row))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            format!(
+                r#"import math
+
+{header}
+PARAMETERS = [{parameters}]
+COVARIANCE = [
+{covariance}
+]
+
+
+def efficiency(energy, p=PARAMETERS):
+{guard}    return {expression}
+
+
+def efficiency_uncertainty(energy):
+    gradient = []
+    for k in range(len(PARAMETERS)):
+        h = 1e-6 * max(abs(PARAMETERS[k]), 1e-6)
+        upper = list(PARAMETERS)
+        upper[k] += h
+        lower = list(PARAMETERS)
+        lower[k] -= h
+        gradient.append((efficiency(energy, upper) - efficiency(energy, lower)) / (2 * h))
+    variance = sum(
+        gradient[i] * COVARIANCE[i][j] * gradient[j]
+        for i in range(len(PARAMETERS))
+        for j in range(len(PARAMETERS))
+    )
+    return math.sqrt(max(variance, 0.0))
+"#,
+                parameters = numbers(&parameters),
+            )
+        }
+        CodeLanguage::Cpp => {
+            let guard = if guarded {
+                "    if (energy <= 0.0) return 0.0;\n"
+            } else {
+                ""
+            };
+            let covariance = covariance_rows
+                .iter()
+                .map(|row| format!("    {{{}}},", row))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            format!(
+                r#"#include <algorithm>
+#include <cmath>
+#include <vector>
+
+{header}
+const std::vector<double> kParameters = {{{parameters}}};
+const double kCovariance[{n}][{n}] = {{
+{covariance}
+}};
+
+double efficiency(double energy, const std::vector<double>& p = kParameters) {{
+{guard}    return {expression};
+}}
+
+double efficiency_uncertainty(double energy) {{
+    std::vector<double> gradient({n});
+    for (int k = 0; k < {n}; ++k) {{
+        double h = 1e-6 * std::max(std::abs(kParameters[k]), 1e-6);
+        std::vector<double> upper = kParameters;
+        std::vector<double> lower = kParameters;
+        upper[k] += h;
+        lower[k] -= h;
+        gradient[k] = (efficiency(energy, upper) - efficiency(energy, lower)) / (2.0 * h);
+    }}
+    double variance = 0.0;
+    for (int i = 0; i < {n}; ++i)
+        for (int j = 0; j < {n}; ++j)
+            variance += gradient[i] * kCovariance[i][j] * gradient[j];
+    return std::sqrt(std::max(variance, 0.0));
+}}
+"#,
+                n = n_parameters,
+                parameters = numbers(&parameters),
+            )
+        }
+        CodeLanguage::Rust => {
+            let guard = if guarded {
+                "    if energy <= 0.0 {\n        return 0.0;\n    }\n"
+            } else {
+                ""
+            };
+            let covariance = covariance_rows
+                .iter()
+                .map(|row| format!("    [{}],", row))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            format!(
+                r#"{header}
+const PARAMETERS: [f64; {n}] = [{parameters}];
+const COVARIANCE: [[f64; {n}]; {n}] = [
+{covariance}
+];
+
+#[allow(unused_parens)]
+fn efficiency(energy: f64, p: &[f64; {n}]) -> f64 {{
+{guard}    {expression}
+}}
+
+fn efficiency_uncertainty(energy: f64) -> f64 {{
+    let mut gradient = [0.0; {n}];
+    for k in 0..{n} {{
+        let h = 1e-6 * PARAMETERS[k].abs().max(1e-6);
+        let mut upper = PARAMETERS;
+        let mut lower = PARAMETERS;
+        upper[k] += h;
+        lower[k] -= h;
+        gradient[k] = (efficiency(energy, &upper) - efficiency(energy, &lower)) / (2.0 * h);
+    }}
+    let mut variance = 0.0;
+    for i in 0..{n} {{
+        for j in 0..{n} {{
+            variance += gradient[i] * COVARIANCE[i][j] * gradient[j];
+        }}
+    }}
+    f64::sqrt(f64::max(variance, 0.0))
+}}
+"#,
+                n = n_parameters,
+                parameters = numbers(&parameters),
+            )
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::efficiency_fitter::exp_fitter::FitResult;
+    use crate::efficiency_fitter::fit_model::{Exponential, Formula};
+
+    // a fit of `model` with the given parameters in covariance order and a diagonal covariance
+    fn fitted(model: Model, linear: Vec<f64>, nonlinear: Vec<f64>) -> ExpFitter {
+        let n = linear.len() + nonlinear.len();
+        let mut fit = ExpFitter::new(vec![100.0, 1400.0], vec![1.0, 1.0], vec![1.0, 1.0]);
+        fit.model = Some(model);
+        fit.fit_result = Some(FitResult {
+            covariance_matrix: (0..n * n)
+                .map(|index| if index % (n + 1) == 0 { 0.01 } else { 0.0 })
+                .collect(),
+            linear_parameters: linear,
+            nonlinear_parameters: nonlinear,
+            reduced_chi_squared: 1.0,
+            ..Default::default()
+        });
+        fit
+    }
+
+    fn exponential() -> ExpFitter {
+        fitted(
+            Model::Exponential(Exponential::new(vec![400.0])),
+            vec![10.0],
+            vec![400.0],
+        )
+    }
+
+    fn formula(expression: &str) -> ExpFitter {
+        fitted(
+            Model::Formula(Formula::new(expression)),
+            Vec::new(),
+            vec![10.0, 400.0, 0.5],
+        )
+    }
+
+    const FORMULA: &str = "a * exp(-x / b) + c ^ 2";
+
+    #[test]
+    fn python_export() {
+        let code = code_snippet(&exponential(), "A", CodeLanguage::Python).unwrap();
+        assert!(code.contains("PARAMETERS = [1e1, 4e2]\n"));
+        assert!(code.contains("    return p[0] * math.exp(-energy / p[1])\n"));
+
+        let code = code_snippet(&formula(FORMULA), "A", CodeLanguage::Python).unwrap();
+        assert!(code.contains("PARAMETERS = [1e1, 4e2, 5e-1]\n"));
+        assert!(
+            code.contains("    return ((p[0] * math.exp(((-energy) / p[1]))) + (p[2] ** 2e0))\n")
+        );
+    }
+
+    #[test]
+    fn cpp_export() {
+        let code = code_snippet(&exponential(), "A", CodeLanguage::Cpp).unwrap();
+        assert!(code.contains("kParameters = {1e1, 4e2};\n"));
+        assert!(code.contains("    return p[0] * std::exp(-energy / p[1]);\n"));
+
+        let code = code_snippet(&formula(FORMULA), "A", CodeLanguage::Cpp).unwrap();
+        assert!(code.contains("const double kCovariance[3][3] = {\n"));
+        assert!(code.contains(
+            "    return ((p[0] * std::exp(((-energy) / p[1]))) + std::pow(p[2], 2e0));\n"
+        ));
+    }
+
+    #[test]
+    fn rust_export() {
+        let code = code_snippet(&exponential(), "A", CodeLanguage::Rust).unwrap();
+        assert!(code.contains("const PARAMETERS: [f64; 2] = [1e1, 4e2];\n"));
+        assert!(code.contains("    p[0] * f64::exp(-energy / p[1])\n}"));
+
+        let code = code_snippet(&formula(FORMULA), "A", CodeLanguage::Rust).unwrap();
+        assert!(
+            code.contains("    ((p[0] * f64::exp(((-energy) / p[1]))) + f64::powf(p[2], 2e0))\n}")
+        );
+    }
+
+    #[test]
+    fn unsupported_functions_are_an_error() {
+        for language in CodeLanguage::ALL {
+            let error = code_snippet(&formula("a * floor(x / b) + c"), "A", language).unwrap_err();
+            assert_eq!(
+                error,
+                format!("floor has no equivalent in {}", language.label())
+            );
+        }
+    }
+}
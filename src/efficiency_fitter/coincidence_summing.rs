@@ -0,0 +1,215 @@
+// True coincidence summing correction of one detector line. The measured efficiency is multiplied
+// by `factor` (true over apparent full-energy peak rate), so lines that lose counts to summing out
+// have factors above one. These come from a simulation or a far-geometry measurement, the
+// uncertainty adds in quadrature with the others
+#[derive(Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SummingCorrection {
+    pub factor: f64,
+    pub uncertainty: f64,
+}
+
+impl Default for SummingCorrection {
+    fn default() -> Self {
+        Self {
+            factor: 1.0,
+            uncertainty: 0.0,
+        }
+    }
+}
+
+impl SummingCorrection {
+    pub fn is_identity(&self) -> bool {
+        self.factor == 1.0 && self.uncertainty == 0.0
+    }
+
+    pub fn relative_uncertainty(&self) -> f64 {
+        if self.factor > 0.0 {
+            self.uncertainty / self.factor
+        } else {
+            0.0
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if ui
+            .add(
+                egui::DragValue::new(&mut self.factor)
+                    .speed(0.001)
+                    .clamp_range(0.01..=100.0)
+                    .prefix("×"),
+            )
+            .on_hover_text("Coincidence summing correction factor. Double click to reset")
+            .double_clicked()
+        {
+            *self = Self::default();
+        }
+
+        ui.add(
+            egui::DragValue::new(&mut self.uncertainty)
+                .speed(0.001)
+                .clamp_range(0.0..=f64::INFINITY)
+                .prefix("± "),
+        );
+    }
+}
+
+// (energy [keV], correction) from lines of "energy, factor, uncertainty". Commas, tabs or spaces
+// separate the columns, the uncertainty may be left out and lines that do not start with a
+// number (headers, comments) are skipped
+pub fn parse_corrections(text: &str) -> Result<Vec<(f64, SummingCorrection)>, String> {
+    let mut corrections = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let cells: Vec<&str> = line
+            .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+            .filter(|cell| !cell.is_empty())
+            .collect();
+
+        let energy: f64 = match cells.first().and_then(|cell| cell.parse().ok()) {
+            Some(energy) => energy,
+            None => continue,
+        };
+
+        let factor: f64 = cells
+            .get(1)
+            .and_then(|cell| cell.parse().ok())
+            .filter(|factor: &f64| *factor > 0.0)
+            .ok_or_else(|| format!("line {}: no correction factor after {}", index + 1, energy))?;
+
+        let uncertainty: f64 = match cells.get(2) {
+            Some(cell) => cell
+                .parse()
+                .map_err(|_| format!("line {}: bad uncertainty '{}'", index + 1, cell))?,
+            None => 0.0,
+        };
+
+        corrections.push((
+            energy,
+            SummingCorrection {
+                factor,
+                uncertainty: uncertainty.abs(),
+            },
+        ));
+    }
+
+    if corrections.is_empty() {
+        return Err("No correction factors found".to_string());
+    }
+
+    Ok(corrections)
+}
+
+// State of the import window of one detector
+#[derive(Default, Clone)]
+pub struct SummingImport {
+    pub text: String,
+    pub error: Option<String>,
+}
+
+impl SummingImport {
+    // the parsed corrections once "Import" is clicked. `open` is cleared when the window is closed
+    pub fn window(
+        &mut self,
+        ctx: &egui::Context,
+        id: egui::Id,
+        title: &str,
+        open: &mut bool,
+    ) -> Option<Vec<(f64, SummingCorrection)>> {
+        let mut imported = None;
+
+        egui::Window::new(title)
+            .id(id)
+            .open(open)
+            .default_width(400.0)
+            .show(ctx, |ui| {
+                ui.label(
+                    "Paste one line per gamma line: energy [keV], correction factor, uncertainty",
+                );
+
+                #[cfg(not(target_arch = "wasm32"))]
+                if ui.button("Open File").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .set_title("Open Summing Corrections")
+                        .add_filter("CSV or text", &["csv", "txt", "dat"])
+                        .pick_file()
+                    {
+                        match std::fs::read_to_string(&path) {
+                            Ok(text) => self.text = text,
+                            Err(err) => {
+                                self.error =
+                                    Some(format!("Failed to read {}: {}", path.display(), err))
+                            }
+                        }
+                    }
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.add(
+                            egui::TextEdit::multiline(&mut self.text)
+                                .font(egui::TextStyle::Monospace)
+                                .desired_rows(12)
+                                .desired_width(f32::INFINITY),
+                        );
+                    });
+
+                if ui.button("Import").clicked() {
+                    match parse_corrections(&self.text) {
+                        Ok(corrections) => {
+                            self.error = None;
+                            imported = Some(corrections);
+                        }
+                        Err(err) => {
+                            log::error!("Summing correction import failed: {}", err);
+                            self.error = Some(err);
+                        }
+                    }
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+
+        imported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mixed_separators() {
+        let text = "energy, factor, uncertainty\n121.78, 1.08, 0.02\n344.28\t1.03\n# comment\n778.9 1.1 -0.01\n";
+        let corrections = parse_corrections(text).unwrap();
+
+        assert_eq!(corrections.len(), 3);
+        assert_eq!(corrections[0].0, 121.78);
+        assert_eq!(corrections[0].1.factor, 1.08);
+        assert_eq!(corrections[0].1.uncertainty, 0.02);
+        assert_eq!(corrections[1].1.uncertainty, 0.0);
+        assert_eq!(corrections[2].1.uncertainty, 0.01);
+    }
+
+    #[test]
+    fn rejects_missing_factor_and_empty_text() {
+        assert!(parse_corrections("121.78\n").is_err());
+        assert!(parse_corrections("121.78, 0\n").is_err());
+        assert!(parse_corrections("121.78, 1.0, x\n").is_err());
+        assert!(parse_corrections("energy, factor\n").is_err());
+    }
+
+    #[test]
+    fn relative_uncertainty() {
+        let correction = SummingCorrection {
+            factor: 1.25,
+            uncertainty: 0.05,
+        };
+
+        assert!((correction.relative_uncertainty() - 0.04).abs() < 1e-12);
+        assert!(!correction.is_identity());
+        assert!(SummingCorrection::default().is_identity());
+    }
+}
@@ -0,0 +1,516 @@
+use super::gamma_source::GammaLine;
+
+// Gamma lines and half-life of one nuclide read from an ENSDF decay dataset or an NNDC "decay
+// radiation" CSV export. Intensities are photons per 100 decays of the parent, like the presets
+#[derive(Default, Clone)]
+pub struct DecayData {
+    pub nuclide: String,            // e.g. "152Eu"
+    pub half_life: Option<f64>,     // years
+    pub half_life_uncertainty: f64, // years, zero when not given
+    pub gamma_lines: Vec<GammaLine>,
+}
+
+// Reads `text` as ENSDF when it has no commas and as CSV otherwise. `nuclide` picks the parent
+// ("152Eu", "152EU", ...), empty takes the first one in the file
+pub fn parse(text: &str, nuclide: &str) -> Result<DecayData, String> {
+    let first_line = text.lines().find(|line| !line.trim().is_empty());
+
+    let mut data = match first_line {
+        None => return Err("Nothing to import".to_string()),
+        Some(line) if line.contains(',') => parse_csv(text, nuclide)?,
+        Some(_) => parse_ensdf(text, nuclide)?,
+    };
+
+    if data.gamma_lines.is_empty() {
+        return Err(format!(
+            "No gamma lines found{}",
+            if nuclide.trim().is_empty() {
+                String::new()
+            } else {
+                format!(" for {}", nuclide.trim())
+            }
+        ));
+    }
+
+    data.gamma_lines
+        .sort_by(|a, b| a.energy.total_cmp(&b.energy));
+
+    Ok(data)
+}
+
+// common calibration nuclides shipped with the app, in the CSV format `parse` reads
+const EMBEDDED_TABLE: &str = include_str!("../../etc/decay_data.csv");
+
+// the nuclides in the embedded table, in table order
+pub fn embedded_nuclides() -> Vec<String> {
+    let mut nuclides: Vec<String> = Vec::new();
+
+    for line in EMBEDDED_TABLE.lines().skip(1) {
+        if let Some(nuclide) = line.split(',').next() {
+            if !nuclide.is_empty() && !nuclides.iter().any(|known| known == nuclide) {
+                nuclides.push(nuclide.to_string());
+            }
+        }
+    }
+
+    nuclides
+}
+
+// embedded nuclides matching `query`, either way round: "eu", "152", "152Eu" and "Eu-152" all find 152Eu
+pub fn search_embedded(query: &str) -> Vec<String> {
+    let query: String = query
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    embedded_nuclides()
+        .into_iter()
+        .filter(|nuclide| {
+            let name = nuclide.to_ascii_lowercase();
+            let split = name
+                .find(|c: char| c.is_ascii_alphabetic())
+                .unwrap_or(name.len());
+            let reversed = format!("{}{}", &name[split..], &name[..split]);
+
+            name.contains(&query) || reversed.contains(&query)
+        })
+        .collect()
+}
+
+pub fn embedded(nuclide: &str) -> Option<DecayData> {
+    match parse(EMBEDDED_TABLE, nuclide) {
+        Ok(data) => Some(data),
+        Err(err) => {
+            log::error!("{}", err);
+            None
+        }
+    }
+}
+
+// "152EU", " 152Eu" and "152eu" all name the same nuclide
+fn same_nuclide(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
+// "152EU" -> "152Eu"
+fn nuclide_name(nucid: &str) -> String {
+    let nucid = nucid.trim();
+    let split = nucid
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(nucid.len());
+    let (mass, element) = nucid.split_at(split);
+
+    let mut element = element.to_ascii_lowercase();
+    if let Some(first) = element.get_mut(0..1) {
+        first.make_ascii_uppercase();
+    }
+
+    format!("{}{}", mass, element)
+}
+
+// "13.517 Y" -> 13.517, None for stable or unknown units
+fn half_life_years(text: &str) -> Option<f64> {
+    let mut parts = text.split_whitespace();
+    let value: f64 = parts.next()?.parse().ok()?;
+
+    let seconds_per_unit = match parts.next()?.to_ascii_uppercase().as_str() {
+        "Y" => 365.25 * 86400.0,
+        "D" => 86400.0,
+        "H" => 3600.0,
+        "M" => 60.0,
+        "S" => 1.0,
+        "MS" => 1e-3,
+        "US" => 1e-6,
+        "NS" => 1e-9,
+        _ => return None,
+    };
+
+    Some(value * seconds_per_unit / (365.25 * 86400.0))
+}
+
+// `uncertainty` given in the unit of the half-life `text` ("13.517 Y", "77.236 D") in years
+fn half_life_uncertainty_years(text: &str, uncertainty: f64) -> f64 {
+    let value: f64 = text
+        .split_whitespace()
+        .next()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0.0);
+
+    match half_life_years(text) {
+        Some(years) if value > 0.0 => uncertainty * years / value,
+        _ => 0.0,
+    }
+}
+
+// 1-based inclusive ENSDF columns, short lines read as blank
+fn field(line: &str, first: usize, last: usize) -> &str {
+    line.get(first - 1..last.min(line.len()))
+        .unwrap_or("")
+        .trim()
+}
+
+// ENSDF value and its uncertainty in units of the last digits: ("121.7817", "3") is 121.7817(3).
+// Limits and approximate values ("LT", "AP", ...) have no uncertainty
+fn ensdf_value(value: &str, uncertainty: &str) -> Option<(f64, f64)> {
+    let number: f64 = value.parse().ok()?;
+
+    let (mantissa, exponent) = match value.to_ascii_uppercase().split_once('E') {
+        Some((mantissa, exponent)) => (mantissa.to_string(), exponent.parse::<i32>().ok()?),
+        None => (value.to_string(), 0),
+    };
+    let decimals = mantissa
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len()) as i32;
+
+    let uncertainty = uncertainty
+        .parse::<f64>()
+        .map_or(0.0, |digits| digits * 10f64.powi(exponent - decimals));
+
+    Some((number, uncertainty))
+}
+
+fn relative(value: f64, uncertainty: f64) -> f64 {
+    if value != 0.0 {
+        uncertainty / value
+    } else {
+        0.0
+    }
+}
+
+// Every decay dataset of the parent is read, so a nuclide with several decay modes (152Eu EC and
+// β-) gets the lines of all of them. Intensities are RI * NR * BR from the normalization record
+fn parse_ensdf(text: &str, nuclide: &str) -> Result<DecayData, String> {
+    let mut data = DecayData::default();
+
+    // datasets are separated by blank lines
+    let mut datasets: Vec<Vec<&str>> = vec![Vec::new()];
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            datasets.push(Vec::new());
+        } else if let Some(dataset) = datasets.last_mut() {
+            dataset.push(line);
+        }
+    }
+
+    for dataset in datasets.iter().filter(|dataset| !dataset.is_empty()) {
+        // only primary records, continuation and comment records are skipped
+        let record = |line: &&str, kind: char| {
+            let bytes = line.as_bytes();
+            bytes.len() > 8
+                && (bytes[5] == b' ' || bytes[5] == b'1')
+                && bytes[6] == b' '
+                && bytes[7] == kind as u8
+                && bytes[8] == b' '
+        };
+
+        if !field(dataset[0], 10, 39)
+            .to_ascii_uppercase()
+            .contains("DECAY")
+        {
+            continue;
+        }
+
+        let parent = match dataset.iter().find(|line| record(line, 'P')) {
+            Some(parent) => parent,
+            None => continue,
+        };
+        let parent_name = nuclide_name(field(parent, 1, 5));
+
+        if nuclide.trim().is_empty() {
+            if !data.nuclide.is_empty() && data.nuclide != parent_name {
+                continue;
+            }
+        } else if !same_nuclide(&parent_name, nuclide) {
+            continue;
+        }
+
+        data.nuclide.clone_from(&parent_name);
+        if data.half_life.is_none() {
+            let half_life = field(parent, 40, 49);
+            let digits = half_life.split_whitespace().next().unwrap_or("");
+
+            data.half_life = half_life_years(half_life);
+            data.half_life_uncertainty = half_life_uncertainty_years(
+                half_life,
+                ensdf_value(digits, field(parent, 50, 55)).map_or(0.0, |(_, dt)| dt),
+            );
+        }
+
+        // (value, uncertainty) of NR and BR, blank fields are 1
+        let (nr, br) = match dataset.iter().find(|line| record(line, 'N')) {
+            Some(normalization) => (
+                ensdf_value(field(normalization, 10, 19), field(normalization, 20, 21))
+                    .unwrap_or((1.0, 0.0)),
+                ensdf_value(field(normalization, 32, 39), field(normalization, 40, 41))
+                    .unwrap_or((1.0, 0.0)),
+            ),
+            None => {
+                log::warn!(
+                    "{}: no normalization record, the intensities are relative",
+                    parent_name
+                );
+                ((1.0, 0.0), (1.0, 0.0))
+            }
+        };
+        let scale = nr.0 * br.0;
+
+        for gamma in dataset.iter().filter(|line| record(line, 'G')) {
+            let (energy, _) = match ensdf_value(field(gamma, 10, 19), field(gamma, 20, 21)) {
+                Some(energy) => energy,
+                None => continue,
+            };
+            let (intensity, intensity_uncertainty) =
+                match ensdf_value(field(gamma, 22, 29), field(gamma, 30, 31)) {
+                    Some(intensity) => intensity,
+                    None => continue,
+                };
+
+            let intensity_relative_uncertainty = relative(intensity, intensity_uncertainty)
+                .hypot(relative(nr.0, nr.1))
+                .hypot(relative(br.0, br.1));
+
+            data.gamma_lines.push(GammaLine {
+                energy,
+                intensity: intensity * scale,
+                intensity_uncertainty: intensity * scale * intensity_relative_uncertainty,
+                ..Default::default()
+            });
+        }
+    }
+
+    if data.nuclide.is_empty() {
+        return Err(if nuclide.trim().is_empty() {
+            "No decay dataset found".to_string()
+        } else {
+            format!("No decay dataset for {}", nuclide.trim())
+        });
+    }
+
+    Ok(data)
+}
+
+// The columns are found by their headers: the energy and intensity columns, each followed by its
+// absolute uncertainty, and optionally the radiation type, the parent and its half-life
+fn parse_csv(text: &str, nuclide: &str) -> Result<DecayData, String> {
+    let split = |line: &str| -> Vec<String> {
+        line.split(',')
+            .map(|cell| cell.trim().trim_matches('"').trim().to_string())
+            .collect()
+    };
+
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let headers: Vec<String> = match lines.next() {
+        Some(header) => split(header)
+            .into_iter()
+            .map(|header| header.to_ascii_lowercase())
+            .collect(),
+        None => return Err("Nothing to import".to_string()),
+    };
+
+    let column = |matches: &dyn Fn(&str) -> bool| headers.iter().position(|header| matches(header));
+
+    let energy = column(&|header| {
+        (header.contains("ene") || header.contains("energy"))
+            && !header.contains("unc")
+            && !header.contains("parent")
+            && !header.contains("level")
+            && !header.starts_with("ep")
+    })
+    .ok_or("No energy column in the CSV header")?;
+    let intensity = column(&|header| header.contains("int") && !header.contains("unc"))
+        .ok_or("No intensity column in the CSV header")?;
+    let radiation = column(&|header| {
+        header.contains("radiation") || header == "type" || header.contains("rad type")
+    });
+    let parent = column(&|header| header == "parent" || header.contains("parent nuc"));
+    let half_life = column(&|header| header.contains("half"));
+
+    let number = |cells: &[String], index: usize| -> Option<f64> {
+        cells.get(index).and_then(|cell| cell.parse::<f64>().ok())
+    };
+    // the uncertainty sits right after its value
+    let uncertainty = |cells: &[String], index: usize| -> f64 {
+        let is_uncertainty = headers
+            .get(index + 1)
+            .is_some_and(|header| header.contains("unc") || header.starts_with('d'));
+        if is_uncertainty {
+            number(cells, index + 1).unwrap_or(0.0)
+        } else {
+            0.0
+        }
+    };
+
+    let mut data = DecayData::default();
+
+    for line in lines {
+        let cells = split(line);
+
+        if let Some(radiation) = radiation {
+            let kind = cells
+                .get(radiation)
+                .map(|cell| cell.to_ascii_lowercase())
+                .unwrap_or_default();
+            if !(kind == "g" || kind.starts_with("gamma")) {
+                continue;
+            }
+        }
+
+        if let Some(parent) = parent {
+            let parent_name = cells.get(parent).cloned().unwrap_or_default();
+            // without a nuclide the first parent in the file is taken
+            let wanted = if nuclide.trim().is_empty() {
+                data.nuclide.as_str()
+            } else {
+                nuclide
+            };
+            if !wanted.is_empty() && !same_nuclide(&parent_name, wanted) {
+                continue;
+            }
+            if data.nuclide.is_empty() {
+                data.nuclide = nuclide_name(&parent_name);
+            }
+        }
+
+        if data.half_life.is_none() {
+            data.half_life = half_life.and_then(|index| {
+                let cell = cells.get(index)?;
+                let cell_uncertainty = uncertainty(&cells, index);
+
+                if headers[index].contains("sec") {
+                    data.half_life_uncertainty = cell_uncertainty / (365.25 * 86400.0);
+                    Some(cell.parse::<f64>().ok()? / (365.25 * 86400.0))
+                } else {
+                    data.half_life_uncertainty =
+                        half_life_uncertainty_years(cell, cell_uncertainty);
+                    half_life_years(cell)
+                }
+            });
+        }
+
+        let (energy_value, intensity_value) =
+            match (number(&cells, energy), number(&cells, intensity)) {
+                (Some(energy), Some(intensity)) => (energy, intensity),
+                _ => continue,
+            };
+
+        data.gamma_lines.push(GammaLine {
+            energy: energy_value,
+            intensity: intensity_value,
+            intensity_uncertainty: uncertainty(&cells, intensity),
+            ..Default::default()
+        });
+    }
+
+    if data.nuclide.is_empty() {
+        data.nuclide = nuclide.trim().to_string();
+    }
+
+    Ok(data)
+}
+
+// State of the import window of one source, the pasted or opened file and what to take from it
+#[derive(Default, Clone)]
+pub struct DecayImport {
+    pub text: String,
+    pub nuclide: String,
+    pub min_intensity: f64, // percent, weaker lines are left out
+    pub error: Option<String>,
+}
+
+impl DecayImport {
+    pub fn new(nuclide: &str) -> Self {
+        Self {
+            nuclide: nuclide.to_string(),
+            min_intensity: 0.1,
+            ..Default::default()
+        }
+    }
+
+    // the parsed lines above the intensity cut once "Import" is clicked. `open` is cleared when
+    // the window is closed
+    pub fn window(
+        &mut self,
+        ctx: &egui::Context,
+        id: egui::Id,
+        open: &mut bool,
+    ) -> Option<DecayData> {
+        let mut imported = None;
+
+        egui::Window::new("Import Decay Data")
+            .id(id)
+            .open(open)
+            .default_width(500.0)
+            .show(ctx, |ui| {
+                ui.label("Paste an ENSDF decay dataset or an NNDC decay radiation CSV export");
+
+                ui.horizontal(|ui| {
+                    ui.label("Nuclide:");
+                    ui.add(egui::TextEdit::singleline(&mut self.nuclide).desired_width(80.0))
+                        .on_hover_text("Parent nuclide to read, e.g. 152Eu. Leave empty for the first one in the file");
+
+                    ui.label("Min Intensity:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.min_intensity)
+                            .speed(0.01)
+                            .clamp_range(0.0..=100.0)
+                            .suffix("%"),
+                    );
+
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if ui.button("Open File").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .set_title("Open Decay Data")
+                            .add_filter("ENSDF or CSV", &["ens", "ensdf", "txt", "csv"])
+                            .pick_file()
+                        {
+                            match std::fs::read_to_string(&path) {
+                                Ok(text) => self.text = text,
+                                Err(err) => {
+                                    self.error =
+                                        Some(format!("Failed to read {}: {}", path.display(), err))
+                                }
+                            }
+                        }
+                    }
+                });
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.add(
+                            egui::TextEdit::multiline(&mut self.text)
+                                .font(egui::TextStyle::Monospace)
+                                .desired_rows(12)
+                                .desired_width(f32::INFINITY),
+                        );
+                    });
+
+                if ui.button("Import").clicked() {
+                    match parse(&self.text, &self.nuclide) {
+                        Ok(mut data) => {
+                            data.gamma_lines
+                                .retain(|line| line.intensity >= self.min_intensity);
+                            log::info!(
+                                "Imported {} gamma lines of {}",
+                                data.gamma_lines.len(),
+                                data.nuclide
+                            );
+                            self.error = None;
+                            imported = Some(data);
+                        }
+                        Err(err) => {
+                            log::error!("Decay data import failed: {}", err);
+                            self.error = Some(err);
+                        }
+                    }
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+            });
+
+        imported
+    }
+}
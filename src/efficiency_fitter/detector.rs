@@ -1,7 +1,103 @@
-use super::gamma_source::GammaSource;
+use super::coincidence_summing::{SummingCorrection, SummingImport};
+use super::gamma_source::{GammaSource, LINE_MATCH_TOLERANCE};
+use super::pile_up::PileUpCorrection;
+use super::units::{EnergyUnit, UncertaintyDisplay};
 
 use crate::egui_plot_stuff::egui_points::EguiPoints;
 
+// Contributions to the efficiency uncertainty (in % efficiency) that add in quadrature
+#[derive(Default, Clone, serde::Deserialize, serde::Serialize)]
+pub struct UncertaintyBreakdown {
+    pub counting: f64,
+    pub intensity: f64,
+    pub activity: f64,
+    #[serde(default)]
+    pub summing: f64,
+    #[serde(default)]
+    pub pile_up: f64,
+}
+
+impl UncertaintyBreakdown {
+    const COLORS: [egui::Color32; 5] = [
+        egui::Color32::from_rgb(70, 130, 180),
+        egui::Color32::from_rgb(218, 165, 32),
+        egui::Color32::from_rgb(178, 34, 34),
+        egui::Color32::from_rgb(46, 139, 87),
+        egui::Color32::from_rgb(128, 0, 128),
+    ];
+
+    // stacked bar where each segment is the fraction of the total variance
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        let variances = [
+            self.counting.powi(2),
+            self.intensity.powi(2),
+            self.activity.powi(2),
+            self.summing.powi(2),
+            self.pile_up.powi(2),
+        ];
+        let total: f64 = variances.iter().sum();
+
+        let (rect, response) = ui.allocate_exact_size(egui::vec2(80.0, 12.0), egui::Sense::hover());
+
+        if !total.is_finite() || total <= 0.0 {
+            return;
+        }
+
+        let mut left = rect.left();
+        for (variance, color) in variances.iter().zip(Self::COLORS.iter()) {
+            let width = (variance / total) as f32 * rect.width();
+            let segment = egui::Rect::from_min_max(
+                egui::pos2(left, rect.top()),
+                egui::pos2(left + width, rect.bottom()),
+            );
+            ui.painter().rect_filled(segment, 0.0, *color);
+            left += width;
+        }
+
+        response.on_hover_text(format!(
+            "Counting: ± {:.3}% ({:.0}%)\nIntensity: ± {:.3}% ({:.0}%)\nActivity: ± {:.3}% ({:.0}%)\nSumming: ± {:.3}% ({:.0}%)\nPile-up: ± {:.3}% ({:.0}%)\n(share of the variance)",
+            self.counting,
+            variances[0] / total * 100.0,
+            self.intensity,
+            variances[1] / total * 100.0,
+            self.activity,
+            variances[2] / total * 100.0,
+            self.summing,
+            variances[3] / total * 100.0,
+            self.pile_up,
+            variances[4] / total * 100.0,
+        ));
+    }
+}
+
+fn dead_time_percent(live_time: f64, real_time: f64) -> f64 {
+    if real_time > 0.0 {
+        100.0 * (1.0 - live_time / real_time)
+    } else {
+        0.0
+    }
+}
+
+// text drawn next to each plotted efficiency point
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum PointLabel {
+    #[default]
+    Off,
+    Energy,
+    EnergyAndSource,
+}
+
+impl PointLabel {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Point Labels:");
+            ui.radio_value(self, PointLabel::Off, "Off");
+            ui.radio_value(self, PointLabel::Energy, "Energy");
+            ui.radio_value(self, PointLabel::EnergyAndSource, "Energy + Source");
+        });
+    }
+}
+
 #[derive(Default, Clone, serde::Deserialize, serde::Serialize)]
 pub struct DetectorLine {
     pub energy: f64,
@@ -11,10 +107,51 @@ pub struct DetectorLine {
     pub intensity_uncertainty: f64,
     pub efficiency: f64,
     pub efficiency_uncertainty: f64,
+    #[serde(default)]
+    pub background: f64,
+    #[serde(default)]
+    pub background_uncertainty: f64,
+    #[serde(default)]
+    pub exclude_from_fit: bool,
+    #[serde(default)]
+    pub energy_uncertainty: f64, // keV, centroid uncertainty used by the ODR fit option
+    #[serde(default)]
+    pub weight_multiplier: Option<f64>, // manual factor on the fit weight, e.g. to down-weight a line with summing
+    #[serde(default)]
+    pub summing_correction: SummingCorrection,
+    #[serde(default)]
+    pub uncertainty_breakdown: UncertaintyBreakdown,
+    #[serde(skip)]
+    pub chi_squared: Option<f64>, // contribution to the chi-square of the last fit
+    #[serde(skip)]
+    pub studentized_residual: Option<f64>, // of the last fit
+    #[serde(skip)]
+    pub outlier: bool, // studentized residual above the outlier threshold
 }
 
 impl DetectorLine {
-    fn ui(&mut self, ui: &mut egui::Ui) {
+    // names the physical gamma line, e.g. "152Eu 121.8 keV"
+    pub fn line_label(&self, source_name: &str, energy_unit: EnergyUnit) -> String {
+        format!("{} {}", source_name, energy_unit.format(self.energy))
+    }
+
+    // counts in the peak after subtracting the ambient background
+    pub fn net_counts(&self) -> f64 {
+        self.count - self.background
+    }
+
+    // a background at or above the peak counts leaves nothing to compute an efficiency from
+    pub fn background_exceeds_counts(&self) -> bool {
+        self.background > 0.0 && self.net_counts() <= 0.0
+    }
+
+    fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        show_background: bool,
+        show_summing: bool,
+        uncertainty_display: UncertaintyDisplay,
+    ) {
         ui.add(
             egui::DragValue::new(&mut self.count)
                 .speed(1.0)
@@ -26,10 +163,110 @@ impl DetectorLine {
                 .clamp_range(0.0..=f64::INFINITY),
         );
 
-        ui.label(format!(
-            "{:.3} ± {:.3}%",
-            self.efficiency, self.efficiency_uncertainty
-        ));
+        if show_background {
+            ui.add(
+                egui::DragValue::new(&mut self.background)
+                    .speed(1.0)
+                    .clamp_range(0.0..=f64::INFINITY),
+            );
+            ui.add(
+                egui::DragValue::new(&mut self.background_uncertainty)
+                    .speed(1.0)
+                    .clamp_range(0.0..=f64::INFINITY),
+            );
+            if self.background_exceeds_counts() {
+                ui.colored_label(ui.visuals().warn_fg_color, "⚠")
+                    .on_hover_text(
+                        "Background is not below the counts, the line is left out of the fit",
+                    );
+            }
+        }
+
+        if show_summing {
+            self.summing_correction.ui(ui);
+        }
+
+        ui.label(uncertainty_display.format(self.efficiency, self.efficiency_uncertainty));
+
+        self.uncertainty_breakdown.ui(ui);
+    }
+
+    // lines without a usable uncertainty would get an infinite or NaN weight in the fit
+    pub fn is_fittable(&self) -> bool {
+        !self.exclude_from_fit
+            && self.efficiency.is_finite()
+            && self.efficiency_uncertainty.is_finite()
+            && self.efficiency_uncertainty > 0.0
+    }
+
+    // efficiencies computed elsewhere are entered directly, bypassing the counts/activity calculation
+    fn direct_efficiency_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::DragValue::new(&mut self.efficiency)
+                .speed(0.01)
+                .clamp_range(0.0..=100.0)
+                .suffix("%"),
+        );
+        ui.add(
+            egui::DragValue::new(&mut self.efficiency_uncertainty)
+                .speed(0.001)
+                .clamp_range(0.0..=100.0)
+                .prefix("± ")
+                .suffix("%"),
+        );
+    }
+
+    fn weight_multiplier_ui(&mut self, ui: &mut egui::Ui) {
+        let mut multiplier = self.weight_multiplier.unwrap_or(1.0);
+
+        if ui
+            .add(
+                egui::DragValue::new(&mut multiplier)
+                    .speed(0.01)
+                    .clamp_range(0.01..=100.0)
+                    .prefix("×"),
+            )
+            .on_hover_text("Multiplies the fit weight of this line on top of the weighting scheme. Double click to reset")
+            .double_clicked()
+        {
+            multiplier = 1.0;
+        }
+
+        self.weight_multiplier = (multiplier != 1.0).then_some(multiplier);
+    }
+
+    fn chi_squared_ui(&self, ui: &mut egui::Ui, worst: f64) {
+        match self.chi_squared {
+            Some(chi_squared) => {
+                let text = format!("{:.2}", chi_squared);
+                if worst > 0.0 && chi_squared >= worst {
+                    ui.colored_label(ui.visuals().error_fg_color, text)
+                        .on_hover_text("Largest contribution to the χ² of this detector's fit");
+                } else {
+                    ui.label(text);
+                }
+            }
+            None => {
+                ui.label("");
+            }
+        }
+    }
+
+    fn studentized_residual_ui(&self, ui: &mut egui::Ui) {
+        match self.studentized_residual {
+            Some(residual) => {
+                let text = format!("{:.2}", residual);
+                if self.outlier {
+                    ui.colored_label(ui.visuals().error_fg_color, format!("⚠ {}", text))
+                        .on_hover_text("Outlier, check the peak area and intensity of this line");
+                } else {
+                    ui.label(text);
+                }
+            }
+            None => {
+                ui.label("");
+            }
+        }
     }
 
     pub fn draw_uncertainty(
@@ -57,13 +294,43 @@ impl DetectorLine {
 pub struct Detector {
     pub name: String,
     pub source_name: String,
+    #[serde(deserialize_with = "deserialize_sorted_lines")]
     pub lines: Vec<DetectorLine>,
     pub points: EguiPoints,
     pub to_remove: Option<bool>,
+    #[serde(default)]
+    pub direct_efficiency: bool,
+    #[serde(default)]
+    pub show_background: bool,
+    #[serde(default)]
+    pub low_energy_cutoff: Option<f64>, // keV, the efficiency is zero below the detector threshold
+    #[serde(default)]
+    pub live_time: Option<f64>, // hours, counts are corrected from the source run time (real time) to it
+    #[serde(default)]
+    pub show_summing: bool,
+    #[serde(skip)]
+    pub summing_import: Option<SummingImport>, // open import window
+}
+
+// lines are kept in ascending energy, projects saved before that may have them in any order
+fn deserialize_sorted_lines<'de, D>(deserializer: D) -> Result<Vec<DetectorLine>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut lines: Vec<DetectorLine> = serde::Deserialize::deserialize(deserializer)?;
+    lines.sort_by(|a, b| a.energy.total_cmp(&b.energy));
+    Ok(lines)
 }
 
 impl Detector {
-    pub fn ui(&mut self, ui: &mut egui::Ui, gamma_source: &GammaSource) {
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        gamma_source: &GammaSource,
+        pile_up: PileUpCorrection,
+        energy_unit: EnergyUnit,
+        uncertainty_display: UncertaintyDisplay,
+    ) {
         ui.horizontal(|ui| {
             ui.label("Detector Name:");
             ui.text_edit_singleline(&mut self.name);
@@ -73,6 +340,92 @@ impl Detector {
             }
         });
 
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.direct_efficiency, "Direct Efficiency")
+                .on_hover_text("Enter efficiencies directly instead of computing them from counts and the source activity");
+
+            if !self.direct_efficiency {
+                ui.checkbox(&mut self.show_background, "Background")
+                    .on_hover_text("Show the ambient background counts subtracted from each line");
+
+                ui.checkbox(&mut self.show_summing, "Summing")
+                    .on_hover_text("Show the true coincidence summing correction factor of each line");
+
+                if self.show_summing
+                    && ui
+                        .button("Import Summing")
+                        .on_hover_text("Set the summing corrections of the lines from a list of energy, factor and uncertainty")
+                        .clicked()
+                {
+                    self.summing_import = Some(SummingImport::default());
+                }
+            }
+
+            if !self.direct_efficiency {
+                let mut live_time = self.live_time.is_some();
+                if ui
+                    .checkbox(&mut live_time, "Live Time")
+                    .on_hover_text("Acquisition live time of this detector. The efficiency uses it in place of the run time of the source, so enter the raw counts")
+                    .changed()
+                {
+                    self.live_time = live_time.then_some(gamma_source.measurement_time);
+                }
+
+                if let Some(live_time) = &mut self.live_time {
+                    ui.add(
+                        egui::DragValue::new(live_time)
+                            .speed(0.01)
+                            .clamp_range(0.0..=f64::INFINITY)
+                            .suffix(" hours"),
+                    )
+                    .on_hover_text(format!(
+                        "Dead time {:.2}% of the {} hour run",
+                        dead_time_percent(*live_time, gamma_source.measurement_time),
+                        gamma_source.measurement_time
+                    ));
+                }
+            }
+
+            let mut threshold = self.low_energy_cutoff.is_some();
+            if ui
+                .checkbox(&mut threshold, "Threshold")
+                .on_hover_text("Zero the fitted efficiency below this energy to model the detector's low-energy threshold. The fit itself is unchanged")
+                .changed()
+            {
+                self.low_energy_cutoff = threshold.then_some(50.0);
+            }
+
+            if let Some(cutoff) = &mut self.low_energy_cutoff {
+                energy_unit.drag_value(ui, cutoff);
+            }
+        });
+
+        if let Some(import) = &mut self.summing_import {
+            let mut open = true;
+            let imported = import.window(
+                ui.ctx(),
+                ui.id().with("summing_import"),
+                &format!("{} Summing Corrections", self.name),
+                &mut open,
+            );
+
+            if let Some(corrections) = imported {
+                let matched = self.apply_summing_corrections(&corrections);
+                log::info!(
+                    "{}: applied {} of {} summing corrections",
+                    self.name,
+                    matched,
+                    corrections.len()
+                );
+                self.summing_import = None;
+            } else if !open {
+                self.summing_import = None;
+            }
+        }
+
+        let show_background = self.show_background && !self.direct_efficiency;
+        let show_summing = self.show_summing && !self.direct_efficiency;
+
         // ui.collapsing(self.name.to_string(), |ui| {
         egui::CollapsingHeader::new(self.name.to_string())
             .default_open(true)
@@ -80,22 +433,65 @@ impl Detector {
                 let gamma_lines = gamma_source
                     .gamma_lines
                     .iter()
-                    .map(|line| format!("{:.1} keV", line.energy))
+                    .map(|line| energy_unit.format(line.energy))
                     .collect::<Vec<_>>();
 
+                let show_chi_squared = self.lines.iter().any(|line| line.chi_squared.is_some());
+                let worst_chi_squared = self
+                    .lines
+                    .iter()
+                    .filter_map(|line| line.chi_squared)
+                    .fold(0.0, f64::max);
+
                 egui::Grid::new("detector_grid")
                     .striped(false)
                     .num_columns(4)
                     .show(ui, |ui| {
                         ui.label("Energy");
-                        ui.label("Counts");
-                        ui.label("Uncertainty");
+                        ui.label("ΔE").on_hover_text(
+                            "Energy (centroid) uncertainty, only used when a fit accounts for energy errors",
+                        );
+                        ui.label("Weight").on_hover_text(
+                            "Manual multiplier on the fit weight of the line",
+                        );
+                        if self.direct_efficiency {
+                            ui.label("Efficiency");
+                            ui.label("Uncertainty");
+                        } else {
+                            ui.label("Counts");
+                            ui.label("Uncertainty");
+                            if show_background {
+                                ui.label("Background");
+                                ui.label("Uncertainty");
+                            }
+                            if show_summing {
+                                ui.label("Summing").on_hover_text(
+                                    "True coincidence summing correction, the efficiency is multiplied by it",
+                                );
+                                ui.label("Uncertainty");
+                            }
+                            ui.label("Efficiency");
+                            ui.label("Breakdown").on_hover_text(
+                                "Share of the efficiency variance from counting statistics (blue), branching ratio (gold), source activity (red), summing correction (green) and pile-up correction (purple)",
+                            );
+                        }
+                        ui.label("Exclude")
+                            .on_hover_text("Exclude the line from the fit. Clicking a point on the plot toggles it, shift + drag toggles every line in an energy range");
+                        if show_chi_squared {
+                            ui.label("χ²").on_hover_text(
+                                "Squared weighted residual of each line in the last fit",
+                            );
+                            ui.label("r").on_hover_text(
+                                "Studentized residual of each line in the last fit, lines past the outlier threshold are flagged",
+                            );
+                        }
                         ui.end_row();
 
                         let mut index_to_remove = None;
+                        let mut energy_changed = false;
                         for (index, line) in self.lines.iter_mut().enumerate() {
                             egui::ComboBox::from_id_source(format!("Line {}", index))
-                                .selected_text(format!("{:.1} keV", line.energy))
+                                .selected_text(energy_unit.format(line.energy))
                                 .show_ui(ui, |ui| {
                                     for (gamma_index, gamma_line_str) in
                                         gamma_lines.iter().enumerate()
@@ -115,11 +511,27 @@ impl Detector {
                                             line.intensity_uncertainty = gamma_source.gamma_lines
                                                 [gamma_index]
                                                 .intensity_uncertainty;
+                                            energy_changed = true;
                                         }
                                     }
                                 });
 
-                            line.ui(ui);
+                            energy_unit.drag_value(ui, &mut line.energy_uncertainty);
+
+                            line.weight_multiplier_ui(ui);
+
+                            if self.direct_efficiency {
+                                line.direct_efficiency_ui(ui);
+                            } else {
+                                line.ui(ui, show_background, show_summing, uncertainty_display);
+                            }
+
+                            ui.checkbox(&mut line.exclude_from_fit, "");
+
+                            if show_chi_squared {
+                                line.chi_squared_ui(ui, worst_chi_squared);
+                                line.studentized_residual_ui(ui);
+                            }
 
                             if ui.button("X").clicked() {
                                 index_to_remove = Some(index);
@@ -131,70 +543,208 @@ impl Detector {
                         if let Some(index) = index_to_remove {
                             self.remove_line(index);
                         }
+
+                        if energy_changed {
+                            self.sort_lines();
+                        }
                     });
 
                 ui.horizontal(|ui| {
                     if ui.button("+").clicked() {
                         self.lines.push(DetectorLine::default());
                     }
+
+                    if self.lines.iter().any(|line| line.exclude_from_fit)
+                        && ui
+                            .button("Restore All Points")
+                            .on_hover_text("Include every line of this detector in the fit again")
+                            .clicked()
+                    {
+                        for line in &mut self.lines {
+                            line.exclude_from_fit = false;
+                        }
+                    }
                 });
 
-                for line in &mut self.lines {
-                    gamma_source.gamma_line_efficiency_from_source_measurement(line);
+                if !self.direct_efficiency {
+                    for line in &mut self.lines {
+                        gamma_source.gamma_line_efficiency_from_source_measurement(
+                            line,
+                            self.live_time,
+                            pile_up,
+                        );
+                    }
                 }
             });
     }
 
+    // fraction of the real time the detector was live, one without a live time
+    pub fn live_fraction(&self, real_time: f64) -> f64 {
+        match self.live_time {
+            Some(live_time) if real_time > 0.0 && live_time > 0.0 => live_time / real_time,
+            _ => 1.0,
+        }
+    }
+
+    // sets the summing correction of the line closest to each energy, returns how many matched
+    pub fn apply_summing_corrections(&mut self, corrections: &[(f64, SummingCorrection)]) -> usize {
+        let mut matched = 0;
+
+        for (energy, correction) in corrections {
+            let closest = self
+                .lines
+                .iter_mut()
+                .filter(|line| (line.energy - energy).abs() <= LINE_MATCH_TOLERANCE)
+                .min_by(|a, b| {
+                    (a.energy - energy)
+                        .abs()
+                        .total_cmp(&(b.energy - energy).abs())
+                });
+
+            match closest {
+                Some(line) => {
+                    line.summing_correction = *correction;
+                    matched += 1;
+                }
+                None => log::warn!(
+                    "{}: no line near {:.1} keV for the summing correction",
+                    self.name,
+                    energy
+                ),
+            }
+        }
+
+        matched
+    }
+
+    // keeps the lines in ascending energy, duplicates stay in the order they were entered
+    pub fn sort_lines(&mut self) {
+        self.lines.sort_by(|a, b| a.energy.total_cmp(&b.energy));
+    }
+
     fn remove_line(&mut self, index: usize) {
         self.lines.remove(index);
     }
 
-    fn get_detector_points(&self) -> Vec<[f64; 2]> {
+    fn get_detector_points(&self, excluded: bool) -> Vec<[f64; 2]> {
         self.lines
             .iter()
+            .filter(|line| line.exclude_from_fit == excluded)
             .map(|line| [line.energy, line.efficiency])
             .collect()
     }
 
     pub fn draw(&mut self, plot_ui: &mut egui_plot::PlotUi, name: Option<String>) {
-        self.points.points = self.get_detector_points();
+        self.points.points = self.get_detector_points(false);
 
         if self.points.draw {
             for line in &self.lines {
                 line.draw_uncertainty(plot_ui, self.points.color, name.clone());
             }
+
+            // excluded lines use the opposite fill of the fitted ones (hollow by default) so they
+            // stay visible without looking like fit input, whichever marker fill is selected
+            let excluded = self.get_detector_points(true);
+            if !excluded.is_empty() {
+                let mut points = egui_plot::Points::new(excluded)
+                    .color(self.points.color)
+                    .radius(self.points.radius)
+                    .filled(!self.points.filled);
+
+                if let Some(shape) = self.points.shape {
+                    points = points.shape(shape);
+                }
+
+                if self.points.name_in_legend {
+                    points = points.name(self.points.name.clone());
+                }
+
+                plot_ui.points(points);
+            }
         }
 
         self.points.draw(plot_ui);
+
+        // ring the flagged lines so a typo'd peak area stands out before the curve is exported
+        let outliers: Vec<[f64; 2]> = self
+            .lines
+            .iter()
+            .filter(|line| line.outlier)
+            .map(|line| [line.energy, line.efficiency])
+            .collect();
+
+        if self.points.draw && !outliers.is_empty() {
+            plot_ui.points(
+                egui_plot::Points::new(outliers)
+                    .color(egui::Color32::RED)
+                    .radius(self.points.radius * 2.0)
+                    .filled(false)
+                    .shape(egui_plot::MarkerShape::Circle),
+            );
+        }
     }
 
-    pub fn menu_button(&mut self, ui: &mut egui::Ui) {
+    pub fn draw_labels(
+        &self,
+        plot_ui: &mut egui_plot::PlotUi,
+        point_label: PointLabel,
+        source_name: &str,
+        energy_unit: EnergyUnit,
+    ) {
+        if point_label == PointLabel::Off || !self.points.draw {
+            return;
+        }
+
+        for line in &self.lines {
+            let text = match point_label {
+                PointLabel::EnergyAndSource => line.line_label(source_name, energy_unit),
+                _ => energy_unit.format(line.energy),
+            };
+
+            plot_ui.text(
+                egui_plot::Text::new(
+                    egui_plot::PlotPoint::new(line.energy, line.efficiency),
+                    text,
+                )
+                .color(self.points.color)
+                .anchor(egui::Align2::LEFT_BOTTOM),
+            );
+        }
+    }
+
+    pub fn menu_button(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit, source_name: &str) {
         ui.horizontal(|ui| {
             if ui
             .button("📋")
-            .on_hover_text("Copy data to clipboard (CSV format)\nEnergy,Counts,Uncertainty,Intensity,Intensity Uncertainty,Efficiency,Efficiency Uncertainty")
+            .on_hover_text(format!("Copy data to clipboard (CSV format)\nLine Label,Energy [{}],Counts,Uncertainty,Intensity,Intensity Uncertainty,Summing Correction,Summing Correction Uncertainty,Efficiency [%],Efficiency Uncertainty [% abs]", energy_unit.label()))
             .clicked()
                 {
-                    let stat_str = self.lines_csv();
+                    let stat_str = self.lines_csv(energy_unit, source_name);
                     ui.output_mut(|o| o.copied_text = stat_str);
                 }
             self.points.menu_button(ui);
         });
     }
 
-    pub fn lines_csv(&self) -> String {
+    pub fn lines_csv(&self, energy_unit: EnergyUnit, source_name: &str) -> String {
         let mut csv = String::new();
 
-        csv.push_str("Energy,Counts,Uncertainty,Intensity,Intensity Uncertainty,Efficiency,Efficiency Uncertainty\n");
+        csv.push_str(&format!("Line Label,Energy [{}],Counts,Uncertainty,Intensity,Intensity Uncertainty,Summing Correction,Summing Correction Uncertainty,Efficiency [%],Efficiency Uncertainty [% abs]\n", energy_unit.label()));
 
-        for line in &self.lines {
+        let mut lines: Vec<&DetectorLine> = self.lines.iter().collect();
+        lines.sort_by(|a, b| a.energy.total_cmp(&b.energy));
+
+        for line in lines {
             csv.push_str(&format!(
-                "{},{},{},{},{},{},{}\n",
-                line.energy,
+                "{},{},{},{},{},{},{},{},{},{}\n",
+                line.line_label(source_name, energy_unit),
+                energy_unit.scale_from_kev(line.energy),
                 line.count,
                 line.uncertainty,
                 line.intensity,
                 line.intensity_uncertainty,
+                line.summing_correction.factor,
+                line.summing_correction.uncertainty,
                 line.efficiency,
                 line.efficiency_uncertainty
             ));
@@ -203,3 +753,72 @@ impl Detector {
         csv
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(energies: &[f64]) -> Detector {
+        Detector {
+            name: "test".to_string(),
+            lines: energies
+                .iter()
+                .map(|&energy| DetectorLine {
+                    energy,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lines_are_sorted_on_load_and_export() {
+        let detector = detector(&[344.28, 121.78, 1408.0, 244.7]);
+
+        let yaml = serde_yaml::to_string(&detector).unwrap();
+        let loaded: Detector = serde_yaml::from_str(&yaml).unwrap();
+        let energies: Vec<f64> = loaded.lines.iter().map(|line| line.energy).collect();
+        assert_eq!(energies, vec![121.78, 244.7, 344.28, 1408.0]);
+
+        // the export is sorted even when the lines in memory are not
+        let csv = detector.lines_csv(EnergyUnit::KeV, "152Eu");
+        let exported: Vec<f64> = csv
+            .lines()
+            .skip(1)
+            .map(|row| row.split(',').nth(1).unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(exported, vec![121.78, 244.7, 344.28, 1408.0]);
+    }
+
+    #[test]
+    fn live_fraction_and_dead_time() {
+        let mut detector = detector(&[]);
+        assert_eq!(detector.live_fraction(2.0), 1.0);
+
+        detector.live_time = Some(1.5);
+        assert!((detector.live_fraction(2.0) - 0.75).abs() < 1e-12);
+        assert!((dead_time_percent(1.5, 2.0) - 25.0).abs() < 1e-12);
+
+        // no run time to compare with
+        assert_eq!(detector.live_fraction(0.0), 1.0);
+        assert_eq!(dead_time_percent(1.5, 0.0), 0.0);
+    }
+
+    #[test]
+    fn summing_corrections_go_to_the_closest_line() {
+        let mut detector = detector(&[121.78, 122.5, 344.28]);
+        let correction = SummingCorrection {
+            factor: 1.1,
+            uncertainty: 0.01,
+        };
+
+        let matched =
+            detector.apply_summing_corrections(&[(122.4, correction), (500.0, correction)]);
+
+        assert_eq!(matched, 1);
+        assert!(detector.lines[0].summing_correction.is_identity());
+        assert_eq!(detector.lines[1].summing_correction.factor, 1.1);
+        assert!(detector.lines[2].summing_correction.is_identity());
+    }
+}
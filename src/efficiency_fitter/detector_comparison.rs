@@ -0,0 +1,108 @@
+use super::exp_fitter::Fitter;
+use super::units::EnergyUnit;
+
+use std::collections::HashMap;
+
+use egui_plot::{Bar, BarChart, Line, Plot};
+
+// Efficiency of every detector's active fit at a single energy, to compare crystals in the array
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct DetectorComparison {
+    pub show: bool,
+    pub energy: f64, // keV
+}
+
+impl Default for DetectorComparison {
+    fn default() -> Self {
+        Self {
+            show: false,
+            energy: 1000.0,
+        }
+    }
+}
+
+impl DetectorComparison {
+    // (name, efficiency, uncertainty) sorted by detector name
+    pub fn efficiencies(&self, fitters: &HashMap<String, Fitter>) -> Vec<(String, f64, f64)> {
+        let mut efficiencies: Vec<(String, f64, f64)> = fitters
+            .iter()
+            .filter(|(_, fitter)| fitter.active().is_some())
+            .map(|(name, fitter)| {
+                (
+                    name.clone(),
+                    fitter.evaluate(self.energy),
+                    fitter.uncertainity(self.energy, 1.0),
+                )
+            })
+            .collect();
+
+        efficiencies.sort_by(|a, b| a.0.cmp(&b.0));
+        efficiencies
+    }
+
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        fitters: &HashMap<String, Fitter>,
+        energy_unit: EnergyUnit,
+    ) {
+        ui.horizontal(|ui| {
+            ui.heading("Detector Comparison");
+            ui.label("Energy:");
+            energy_unit.drag_value(ui, &mut self.energy);
+        });
+
+        let efficiencies = self.efficiencies(fitters);
+
+        if efficiencies.is_empty() {
+            ui.label("No fits to compare");
+            return;
+        }
+
+        let bars: Vec<Bar> = efficiencies
+            .iter()
+            .enumerate()
+            .map(|(index, (name, efficiency, _))| {
+                Bar::new(index as f64, *efficiency).name(name).width(0.6)
+            })
+            .collect();
+
+        let names: Vec<String> = efficiencies
+            .iter()
+            .map(|(name, _, _)| name.clone())
+            .collect();
+
+        Plot::new("Detector Comparison")
+            .x_axis_label("Detector")
+            .y_axis_label(format!(
+                "Efficiency at {} [%]",
+                energy_unit.format(self.energy)
+            ))
+            .x_axis_formatter(move |mark, _chars, _range| {
+                // only label the integer positions where the bars are
+                let index = mark.value.round();
+                if (mark.value - index).abs() > 1e-6 || index < 0.0 {
+                    return String::new();
+                }
+                names.get(index as usize).cloned().unwrap_or_default()
+            })
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .show(ui, |plot_ui| {
+                plot_ui.bar_chart(BarChart::new(bars).color(egui::Color32::LIGHT_BLUE));
+
+                for (index, (_, efficiency, uncertainty)) in efficiencies.iter().enumerate() {
+                    let x = index as f64;
+                    plot_ui.line(
+                        Line::new(vec![
+                            [x, efficiency - uncertainty],
+                            [x, efficiency + uncertainty],
+                        ])
+                        .color(egui::Color32::DARK_GRAY)
+                        .width(2.0),
+                    );
+                }
+            });
+    }
+}
@@ -0,0 +1,501 @@
+use super::{ExpFitter, ParameterConstraint};
+use egui_plot::PlotPoints;
+use nalgebra::DVector;
+
+// |correlation| above which two parameters are treated as degenerate in the sanity check
+const DEGENERATE_CORRELATION: f64 = 0.995;
+
+// Δχ² of one parameter held fixed over a range while the others are refit, the profile likelihood
+// check on the covariance errors
+#[derive(Debug, Default, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ProfileScan {
+    pub parameter: String,
+    pub best: f64,
+    pub uncertainty: f64, // covariance error of the parameter when the scan was made
+    pub points: Vec<[f64; 2]>, // (value, Δχ²) sorted by value
+    pub threshold: f64,   // Δχ² of a 1σ step, the reduced χ² when the errors are scaled by it
+}
+
+impl ProfileScan {
+    // where the profile crosses the threshold below and above the best value, None when the scan
+    // did not reach it
+    pub fn interval(&self) -> (Option<f64>, Option<f64>) {
+        let crossing = |a: &[f64; 2], b: &[f64; 2]| {
+            let fraction = (self.threshold - a[1]) / (b[1] - a[1]);
+            a[0] + fraction * (b[0] - a[0])
+        };
+
+        let lower = self
+            .points
+            .windows(2)
+            .rev()
+            .filter(|pair| pair[1][0] <= self.best)
+            .find(|pair| pair[0][1] >= self.threshold && pair[1][1] < self.threshold)
+            .map(|pair| crossing(&pair[0], &pair[1]));
+        let upper = self
+            .points
+            .windows(2)
+            .filter(|pair| pair[0][0] >= self.best)
+            .find(|pair| pair[0][1] < self.threshold && pair[1][1] >= self.threshold)
+            .map(|pair| crossing(&pair[0], &pair[1]));
+
+        (lower, upper)
+    }
+
+    pub fn plot(&self, ui: &mut egui::Ui) {
+        let parameter = self.parameter.clone();
+
+        egui_plot::Plot::new(ui.id().with("profile_scan"))
+            .height(250.0)
+            .width(400.0)
+            .allow_scroll(false)
+            .x_axis_label(self.parameter.clone())
+            .y_axis_label("Δχ²")
+            .label_formatter(move |_name, value| {
+                format!("{} = {:.4e}\nΔχ² = {:.3}", parameter, value.x, value.y)
+            })
+            .show(ui, |plot_ui| {
+                plot_ui.line(
+                    egui_plot::Line::new(PlotPoints::new(self.points.clone()))
+                        .color(egui::Color32::LIGHT_BLUE)
+                        .name("Profile"),
+                );
+                plot_ui.points(
+                    egui_plot::Points::new(PlotPoints::new(self.points.clone()))
+                        .color(egui::Color32::LIGHT_BLUE)
+                        .radius(2.0),
+                );
+                plot_ui.hline(
+                    egui_plot::HLine::new(self.threshold)
+                        .color(egui::Color32::GRAY)
+                        .name("1σ"),
+                );
+                for bound in [self.best - self.uncertainty, self.best + self.uncertainty] {
+                    plot_ui.vline(
+                        egui_plot::VLine::new(bound)
+                            .color(egui::Color32::GRAY)
+                            .style(egui_plot::LineStyle::dashed_loose())
+                            .name("Covariance ±1σ"),
+                    );
+                }
+            });
+
+        let format_bound = |bound: Option<f64>| match bound {
+            Some(bound) => format!("{:+.3e}", bound - self.best),
+            None => "out of range".to_string(),
+        };
+        let (lower, upper) = self.interval();
+        ui.label(format!(
+            "{} = {:.4e}: covariance ±{:.3e}, profile {} / {}",
+            self.parameter,
+            self.best,
+            self.uncertainty,
+            format_bound(lower),
+            format_bound(upper)
+        ))
+        .on_hover_text("The profile interval is where Δχ² crosses the dashed 1σ line. Matching errors mean the parabolic covariance estimate holds");
+    }
+}
+
+// range and resolution of the next profile scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileSettings {
+    pub parameter: String,
+    pub width: f64,   // covariance σ on each side of the best value
+    pub steps: usize, // refits on each side
+}
+
+impl Default for ProfileSettings {
+    fn default() -> Self {
+        Self {
+            parameter: String::new(),
+            width: 3.0,
+            steps: 10,
+        }
+    }
+}
+
+impl ExpFitter {
+    // correlation matrix as a grid of cells, red for positive and blue for negative correlation
+    pub fn correlation_ui(&self, ui: &mut egui::Ui) {
+        let (model, result) = match (&self.model, &self.fit_result) {
+            (Some(model), Some(result)) => (model.fit_model(), result),
+            _ => {
+                ui.label("No fit");
+                return;
+            }
+        };
+
+        let names = model.parameter_names();
+        let n_parameters = names.len();
+        if result.correlation_matrix.len() != n_parameters * n_parameters {
+            ui.label("No correlation matrix for this fit");
+            return;
+        }
+
+        let cell_size = egui::vec2(52.0, 22.0);
+
+        egui::Grid::new(ui.id().with("correlation_grid"))
+            .spacing(egui::vec2(2.0, 2.0))
+            .show(ui, |ui| {
+                ui.label("");
+                for name in &names {
+                    ui.label(name);
+                }
+                ui.end_row();
+
+                for (i, row_name) in names.iter().enumerate() {
+                    ui.label(row_name);
+
+                    for (j, column_name) in names.iter().enumerate() {
+                        let value = result.correlation_matrix[i * n_parameters + j];
+                        let color = if !value.is_finite() {
+                            egui::Color32::GRAY
+                        } else if value >= 0.0 {
+                            egui::Color32::from_rgb(214, 39, 40).gamma_multiply(value as f32)
+                        } else {
+                            egui::Color32::from_rgb(31, 119, 180).gamma_multiply(-value as f32)
+                        };
+
+                        let (rect, response) =
+                            ui.allocate_exact_size(cell_size, egui::Sense::hover());
+                        ui.painter().rect_filled(rect, 2.0, color);
+                        ui.painter().text(
+                            rect.center(),
+                            egui::Align2::CENTER_CENTER,
+                            format!("{:.2}", value),
+                            egui::FontId::monospace(12.0),
+                            ui.visuals().text_color(),
+                        );
+
+                        let mut hover = format!("{}–{}: {:.4}", row_name, column_name, value);
+                        if i != j && value.abs() > DEGENERATE_CORRELATION {
+                            hover.push_str("\nNearly degenerate");
+                        }
+                        response.on_hover_text(hover);
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    // Refits the model once per point with that point held out and stores how far the measured
+    // efficiency is from the prediction, in units of the combined data and prediction error
+    pub fn leave_one_out(&mut self, constraints: &[ParameterConstraint]) {
+        let model = match (&self.model, &self.fit_result) {
+            (Some(model), Some(result)) => model.seeded(&result.parameters()),
+            _ => return,
+        };
+
+        let (x_data, y_data, weights) = self.fit_data();
+        let n_points = x_data.len();
+
+        let mut checks: Vec<[f64; 4]> = Vec::new();
+        for held_out in 0..n_points {
+            let keep = |data: &DVector<f64>| {
+                DVector::from_iterator(
+                    n_points - 1,
+                    (0..n_points).filter(|&i| i != held_out).map(|i| data[i]),
+                )
+            };
+
+            let result = match model.fit_model().fit_constrained(
+                constraints,
+                &keep(&x_data),
+                &keep(&y_data),
+                &keep(&weights),
+                &self.solver,
+            ) {
+                Ok(result) => result,
+                Err(message) => {
+                    log::warn!(
+                        "Leave-one-out refit without {:.1} keV failed: {}",
+                        self.x[held_out],
+                        message
+                    );
+                    continue;
+                }
+            };
+
+            let x = self.x[held_out];
+            let measured = self.y[held_out];
+            let parameters = result.parameters();
+            let predicted = self.evaluate_with(x, &parameters);
+
+            // the refit's own standard error at x, in efficiency units like the data error
+            let gradient = model.fit_model().gradient(x, &parameters);
+            let n_parameters = gradient.len();
+            let cov = &result.covariance_matrix;
+            let mut prediction_variance = if cov.len() == n_parameters * n_parameters {
+                (0..n_parameters)
+                    .flat_map(|i| (0..n_parameters).map(move |j| (i, j)))
+                    .map(|(i, j)| gradient[i] * gradient[j] * cov[i * n_parameters + j])
+                    .sum()
+            } else {
+                0.0
+            };
+            // the held out line and the curve share the normalization, so only the shape counts
+            prediction_variance *= self.fit_space_scale(x).powi(2);
+
+            let data_error = 1.0 / self.weights[held_out];
+            let pull = (measured - predicted) / (data_error.powi(2) + prediction_variance).sqrt();
+
+            if pull.is_finite() {
+                checks.push([x, measured, predicted, pull]);
+            }
+        }
+
+        log::info!(
+            "Leave-one-out: {} of {} refits converged, pulls {:?}",
+            checks.len(),
+            n_points,
+            checks.iter().map(|check| check[3]).collect::<Vec<_>>()
+        );
+
+        if checks.is_empty() {
+            self.fit_failed(format!(
+                "Leave-one-out check failed: none of the {} refits converged",
+                n_points
+            ));
+        }
+
+        if let Some(result) = &mut self.fit_result {
+            result.leave_one_out = checks;
+        }
+    }
+
+    // Steps `parameter` out to `width` covariance errors on each side of the best value, holding it
+    // fixed and refitting the others from the neighbouring step. Steps that fail are left out
+    pub fn profile_scan(
+        &mut self,
+        parameter: &str,
+        settings: &ProfileSettings,
+        constraints: &[ParameterConstraint],
+    ) {
+        let (model, result) = match (&self.model, &self.fit_result) {
+            (Some(model), Some(result)) => (model.clone(), result),
+            _ => return,
+        };
+
+        let best_parameters = result.parameters();
+        let threshold = result.reduced_chi_squared * self.covariance_factor();
+        let (best, uncertainty) = match self
+            .parameters()
+            .into_iter()
+            .find(|(name, _, _)| name == parameter)
+        {
+            Some((_, value, uncertainty)) => (value, uncertainty),
+            None => {
+                self.fit_failed(format!("χ² scan: no parameter named {}", parameter));
+                return;
+            }
+        };
+
+        if !(uncertainty.is_finite() && uncertainty > 0.0) {
+            self.fit_failed(format!(
+                "χ² scan: {} has no covariance error to set the range",
+                parameter
+            ));
+            return;
+        }
+
+        let (x_data, y_data, weights) = self.fit_data();
+        let chi_squared = |parameters: &[f64]| -> f64 {
+            (0..x_data.len())
+                .map(|i| {
+                    ((y_data[i] - model.fit_model().evaluate(x_data[i], parameters)) * weights[i])
+                        .powi(2)
+                })
+                .sum()
+        };
+        let minimum = chi_squared(&best_parameters);
+
+        let mut points = vec![[best, 0.0]];
+        let steps = settings.steps.max(1);
+        for side in [-1.0, 1.0] {
+            let mut seed = best_parameters.clone();
+
+            for step in 1..=steps {
+                let value = best + side * settings.width * uncertainty * step as f64 / steps as f64;
+
+                let mut scan_constraints: Vec<ParameterConstraint> = constraints
+                    .iter()
+                    .filter(|constraint| constraint.name != parameter)
+                    .cloned()
+                    .collect();
+                let mut fixed = ParameterConstraint::new(parameter);
+                fixed.fixed = Some(value);
+                scan_constraints.push(fixed);
+
+                match model.seeded(&seed).fit_model().fit_constrained(
+                    &scan_constraints,
+                    &x_data,
+                    &y_data,
+                    &weights,
+                    &self.solver,
+                ) {
+                    Ok(refit) => {
+                        seed = refit.parameters();
+                        points.push([value, chi_squared(&seed) - minimum]);
+                    }
+                    Err(message) => {
+                        log::warn!(
+                            "χ² scan at {} = {:.4e} failed: {}",
+                            parameter,
+                            value,
+                            message
+                        );
+                    }
+                }
+            }
+        }
+
+        if points.len() == 1 {
+            self.fit_failed(format!("χ² scan of {} failed at every step", parameter));
+            return;
+        }
+
+        points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+
+        if let Some(result) = &mut self.fit_result {
+            result.profile = Some(ProfileScan {
+                parameter: parameter.to_string(),
+                best,
+                uncertainty,
+                points,
+                threshold,
+            });
+        }
+    }
+
+    // (energy, (data - fit) / uncertainty) of every fitted point, empty before the first fit
+    pub fn pulls(&self) -> Vec<[f64; 2]> {
+        if !self.is_fitted() {
+            return Vec::new();
+        }
+
+        self.x
+            .iter()
+            .zip(self.y.iter())
+            .zip(self.weights.iter())
+            .map(|((&x, &y), &w)| [x, (y - self.evaluate(x)) * w])
+            .collect()
+    }
+
+    // Externally studentized residual of every fitted point. The weighted residual is divided by
+    // sqrt(χ²ᵥ (1 - h)), where the leverage h is how strongly the point pulls the curve toward
+    // itself, then corrected to the χ²ᵥ of the fit without the point so a bad line cannot hide
+    // behind the χ²ᵥ it inflates. Empty before the first fit
+    pub fn studentized_residuals(&self) -> Vec<f64> {
+        let (model, result) = match (&self.model, &self.fit_result) {
+            (Some(model), Some(result)) => (model.fit_model(), result),
+            _ => return Vec::new(),
+        };
+
+        let parameters = result.parameters();
+        let n_parameters = parameters.len();
+        let cov = &result.covariance_matrix;
+        if cov.len() != n_parameters * n_parameters {
+            return Vec::new();
+        }
+
+        // leverage needs (JᵀWJ)⁻¹, the stored covariance carries a factor of χ²ᵥ
+        let unscaled = result.unscaled_covariance_factor();
+        let scale = (1.0 / unscaled).sqrt();
+
+        let dof = result.degrees_of_freedom(self.x.len());
+        if dof <= 1.0 {
+            return Vec::new();
+        }
+
+        let (x_data, y_data, weights) = self.fit_data();
+        x_data
+            .iter()
+            .zip(y_data.iter())
+            .zip(weights.iter())
+            .map(|((&x, &y), &w)| {
+                let gradient = model.gradient(x, &parameters);
+                let leverage = w
+                    * w
+                    * unscaled
+                    * (0..n_parameters)
+                        .flat_map(|i| (0..n_parameters).map(move |j| (i, j)))
+                        .map(|(i, j)| gradient[i] * gradient[j] * cov[i * n_parameters + j])
+                        .sum::<f64>();
+
+                let residual = (y - model.evaluate(x, &parameters)) * w;
+                let internal = residual / (scale * (1.0 - leverage).max(f64::EPSILON).sqrt());
+                internal * ((dof - 1.0) / (dof - internal * internal).max(f64::EPSILON)).sqrt()
+            })
+            .collect()
+    }
+
+    // heuristic checks for parameters that are mathematically valid but physically meaningless
+    pub fn sanity_check(&mut self) {
+        self.warnings.clear();
+
+        let (model, result) = match (&self.model, &self.fit_result) {
+            (Some(model), Some(result)) => (model.fit_model(), result),
+            _ => return,
+        };
+
+        let min_x = self.x.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+        let max_x = self.x.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+
+        self.warnings
+            .extend(model.warnings(&result.parameters(), min_x, max_x));
+
+        // When a decay constant is large next to the energies, exp(-x/b) is nearly flat and trades
+        // off against its amplitude, so the covariance and the confidence band blow up
+        let names = model.parameter_names();
+        let n_parameters = names.len();
+        let suggestion = if n_parameters > 2 {
+            "try a model with fewer parameters"
+        } else {
+            "add lines over a wider energy range"
+        };
+
+        if result
+            .correlation_matrix
+            .iter()
+            .any(|value| !value.is_finite())
+        {
+            self.warnings.push(format!(
+                "Parameter covariance is singular, the uncertainties are unreliable ({})",
+                suggestion
+            ));
+        } else if result.correlation_matrix.len() == n_parameters * n_parameters {
+            for i in 0..n_parameters {
+                for j in (i + 1)..n_parameters {
+                    let value = result.correlation_matrix[i * n_parameters + j];
+                    if value.abs() > DEGENERATE_CORRELATION {
+                        self.warnings.push(format!(
+                            "{} and {} are nearly degenerate (correlation {:.3}), {}",
+                            names[i], names[j], value, suggestion
+                        ));
+                    }
+                }
+            }
+        }
+
+        // above the photopeak region a physical efficiency curve only falls with energy, so a bump
+        // between the data points usually means the double exponential is over-fitting
+        let curve: Vec<&[f64; 2]> = self
+            .fit_line
+            .points
+            .iter()
+            .filter(|[x, _]| *x >= min_x && *x <= max_x)
+            .collect();
+
+        if let Some(window) = curve.windows(2).find(|window| window[1][1] > window[0][1]) {
+            self.warnings.push(format!(
+                "Fit is not monotonically decreasing over the data range (rises near {:.1} keV)",
+                window[0][0]
+            ));
+        }
+
+        for warning in &self.warnings {
+            log::warn!("{}", warning);
+        }
+    }
+}
@@ -0,0 +1,2213 @@
+use super::{
+    default_scale_covariance, pdg_scale_factor, BandMethod, BatchModel, CurveSampling, ExpFitter,
+    FitJob, FitResult, JobKind, Loss, ProfileSettings, MULTI_START_FACTORS,
+};
+use crate::curve::Normalization;
+use crate::efficiency_fitter::code_export::{code_snippet, CodeLanguage};
+use crate::efficiency_fitter::fit_model::{
+    Exponential, FitModel, Formula, LogPolynomial, Model, ParameterConstraint, SolverSettings,
+    Spline,
+};
+use crate::efficiency_fitter::fit_report::{FitReport, ReportFormat};
+use crate::efficiency_fitter::measurements::WeightingScheme;
+use crate::efficiency_fitter::units::EnergyUnit;
+use crate::egui_plot_stuff::colors::Rgb;
+use crate::egui_plot_stuff::egui_line::EguiLine;
+use egui_plot::PlotUi;
+use nalgebra::DVector;
+use std::collections::HashMap;
+
+// keV, how close a line has to be to the chosen reference energy of a relative fit
+const REFERENCE_LINE_TOLERANCE: f64 = 0.5;
+
+// refits per bootstrap, each resamples the detector lines with replacement
+const BOOTSTRAP_SAMPLES: usize = 500;
+
+// colors the earlier fits kept in the history cycle through, so the overlaid curves can be told apart
+const HISTORY_COLORS: [egui::Color32; 5] = [
+    egui::Color32::RED,
+    egui::Color32::DARK_GREEN,
+    egui::Color32::BROWN,
+    egui::Color32::DARK_BLUE,
+    egui::Color32::KHAKI,
+];
+
+// fits that are not the active one, drawn dashed in these with Compare Models
+const COMPARE_COLORS: [egui::Color32; 4] = [
+    egui::Color32::GOLD,
+    egui::Color32::DARK_RED,
+    egui::Color32::LIGHT_GREEN,
+    egui::Color32::from_rgb(128, 0, 128),
+];
+
+// color and style of a fit curve, handed over when another fit becomes the active one
+type LineStyling = (egui::Color32, Rgb, Option<egui_plot::LineStyle>);
+
+fn line_styling(line: &EguiLine) -> LineStyling {
+    (line.color, line.color_rgb, line.style)
+}
+
+fn set_line_styling(line: &mut EguiLine, (color, color_rgb, style): LineStyling) {
+    line.color = color;
+    line.color_rgb = color_rgb;
+    line.style = style;
+}
+
+// what the guess preview is computed from, it is only recomputed when one of these changes
+#[derive(Clone, PartialEq)]
+struct GuessPreviewInputs {
+    decays: Vec<f64>,
+    data: (Vec<f64>, Vec<f64>, Vec<f64>),
+    normalization: Option<Normalization>,
+    energy_times_efficiency: bool,
+    sampling: CurveSampling,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct Fitter {
+    pub name: String,
+    pub data: (Vec<f64>, Vec<f64>, Vec<f64>), // (x_data, y_data, weights)
+    #[serde(default)]
+    pub fits: HashMap<String, ExpFitter>, // named results, e.g. "Single", "Double", "Global"
+    #[serde(default)]
+    pub active_fit: String, // the fit that is plotted and summed
+    // single fit saved before named fits existed, moved into `fits` by `migrate_legacy_fit`
+    #[serde(default, rename = "exp_fitter", skip_serializing)]
+    legacy_fit: Option<ExpFitter>,
+    pub initial_b_guess: f64,
+    pub initial_d_guess: f64,
+    // estimate b and d from the data before every exponential fit. On for new detectors, off for
+    // files saved before it existed so their hand tuned guesses are kept
+    #[serde(default)]
+    pub auto_guess: bool,
+    #[serde(default)]
+    pub multi_start: bool, // start exponential fits from a grid of (b, d) and keep the best
+    #[serde(default)]
+    pub compare_models: bool,
+    #[serde(default)]
+    pub keep_history: bool, // a refit keeps the fit it replaces as "<name> #n" instead of dropping it
+    #[serde(skip)]
+    pub sources: Vec<(String, usize)>, // (source name, number of fitted points)
+    #[serde(default)]
+    pub energy_times_efficiency: bool,
+    #[serde(default)]
+    pub reference_line: Option<f64>, // keV, fit efficiencies relative to this line
+    #[serde(default)]
+    pub sampling: CurveSampling, // where the curves of this detector are drawn
+    #[serde(default)]
+    pub target_efficiency: f64, // percent
+    #[serde(default)]
+    pub data_modified_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default = "default_error_scale")]
+    pub error_scale: f64,
+    #[serde(skip)]
+    pub fit_error: Option<String>,
+    #[serde(skip)]
+    pub pinned: Vec<EguiLine>, // faint copies of earlier curves to compare new attempts against
+    #[serde(skip)]
+    pub guess_preview: Option<usize>, // number of exponential terms to preview while editing a guess
+    #[serde(skip)]
+    guess_preview_cache: Option<(GuessPreviewInputs, Option<EguiLine>)>,
+    #[serde(skip)]
+    pub low_energy_cutoff: Option<f64>, // keV, copied from the detectors on every sync
+    #[serde(default)]
+    pub log_polynomial: LogPolynomial, // order and reference energy for the next ln-poly fit
+    #[serde(default = "default_spline_knots")]
+    pub spline_knots: usize, // number of knots for the next spline fit
+    #[serde(default = "default_exponential_terms")]
+    pub exponential_terms: usize, // number of terms for the N-exponential fit
+    #[serde(default)]
+    pub extra_decay_guesses: Vec<f64>, // initial guesses for the terms after b and d (f, h, ...)
+    #[serde(default)]
+    pub formula: Formula, // user typed model for the formula fit
+    #[serde(default)]
+    pub constraints: Vec<ParameterConstraint>, // bounds and fixed values, matched by parameter name
+    #[serde(default)]
+    pub band_method: BandMethod, // applied to every fit of this detector
+    #[serde(skip)]
+    pub energy_uncertainties: Vec<f64>, // keV, copied from the detector lines on every sync
+    #[serde(skip)]
+    pub relative_sources: Vec<Option<String>>, // per point, the source when its activity is relative only
+    #[serde(skip)]
+    pub weighting: WeightingScheme, // copied from the measurements on every sync, recorded with each fit
+    #[serde(default)]
+    pub energy_errors: bool, // include the energy uncertainties in the fit weights
+    #[serde(default)]
+    pub loss: Loss, // loss for the next fit, the loss of a stored fit is in its result
+    #[serde(default)]
+    pub solver: SolverSettings, // Levenberg-Marquardt settings for the next fit
+    #[serde(default = "default_scale_covariance")]
+    pub scale_covariance: bool, // applied to every fit of this detector
+    #[serde(skip)]
+    pub job: Option<FitJob>, // fit in progress, stored by `poll_job` when it finishes
+    #[serde(default)]
+    pub report_format: ReportFormat,
+    #[serde(default)]
+    pub code_language: CodeLanguage,
+    #[serde(skip)]
+    pub show_correlation: bool, // correlation matrix window of the active fit
+    #[serde(skip)]
+    pub profile_settings: ProfileSettings,
+    #[serde(skip)]
+    pub show_profile: bool, // χ² scan window of the active fit
+    #[serde(default)]
+    pub fit_min_energy: Option<f64>, // keV, lines below are plotted but left out of the fits
+    #[serde(default)]
+    pub fit_max_energy: Option<f64>, // keV, lines above are plotted but left out of the fits
+}
+
+fn default_error_scale() -> f64 {
+    1.0
+}
+
+fn default_exponential_terms() -> usize {
+    3
+}
+
+fn default_spline_knots() -> usize {
+    5
+}
+
+impl Default for Fitter {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            data: (Vec::new(), Vec::new(), Vec::new()),
+            fits: HashMap::new(),
+            active_fit: String::new(),
+            legacy_fit: None,
+            initial_b_guess: 500.0,
+            initial_d_guess: 5000.0,
+            auto_guess: true,
+            multi_start: false,
+            compare_models: false,
+            keep_history: false,
+            sources: Vec::new(),
+            energy_times_efficiency: false,
+            reference_line: None,
+            sampling: CurveSampling::default(),
+            target_efficiency: 1.0,
+            data_modified_at: None,
+            error_scale: 1.0,
+            fit_error: None,
+            pinned: Vec::new(),
+            guess_preview: None,
+            guess_preview_cache: None,
+            low_energy_cutoff: None,
+            log_polynomial: LogPolynomial::default(),
+            spline_knots: default_spline_knots(),
+            exponential_terms: default_exponential_terms(),
+            extra_decay_guesses: Vec::new(),
+            formula: Formula::default(),
+            constraints: Vec::new(),
+            band_method: BandMethod::default(),
+            energy_uncertainties: Vec::new(),
+            relative_sources: Vec::new(),
+            weighting: WeightingScheme::default(),
+            energy_errors: false,
+            loss: Loss::default(),
+            solver: SolverSettings::default(),
+            scale_covariance: true,
+            job: None,
+            report_format: ReportFormat::default(),
+            code_language: CodeLanguage::default(),
+            show_correlation: false,
+            profile_settings: ProfileSettings::default(),
+            show_profile: false,
+            fit_min_energy: None,
+            fit_max_energy: None,
+        }
+    }
+}
+
+impl Fitter {
+    pub fn active(&self) -> Option<&ExpFitter> {
+        self.fits.get(&self.active_fit)
+    }
+
+    pub fn active_mut(&mut self) -> Option<&mut ExpFitter> {
+        self.fits.get_mut(&self.active_fit)
+    }
+
+    fn below_cutoff(&self, x: f64) -> bool {
+        self.low_energy_cutoff.is_some_and(|cutoff| x < cutoff)
+    }
+
+    pub fn in_fit_range(&self, x: f64) -> bool {
+        self.fit_min_energy.map_or(true, |min| x >= min)
+            && self.fit_max_energy.map_or(true, |max| x <= max)
+    }
+
+    // efficiency of the active fit, zero when nothing has been fit or below the detector threshold
+    pub fn evaluate(&self, x: f64) -> f64 {
+        if self.below_cutoff(x) {
+            return 0.0;
+        }
+
+        self.active().map_or(0.0, |fit| fit.evaluate(x))
+    }
+
+    pub fn uncertainity(&self, x: f64, sigma: f64) -> f64 {
+        if self.below_cutoff(x) {
+            return 0.0;
+        }
+
+        self.active().map_or(0.0, |fit| fit.uncertainity(x, sigma))
+    }
+
+    // (efficiency, uncertainty) of the active fit at every energy, zeros when nothing has been fit
+    pub fn efficiencies(&self, energies: &[f64], sigma: f64) -> Vec<(f64, f64)> {
+        let mut efficiencies = match self.active() {
+            Some(fit) => fit.efficiencies(energies, sigma),
+            None => vec![(0.0, 0.0); energies.len()],
+        };
+
+        for (energy, efficiency) in energies.iter().zip(efficiencies.iter_mut()) {
+            if self.below_cutoff(*energy) {
+                *efficiency = (0.0, 0.0);
+            }
+        }
+
+        efficiencies
+    }
+
+    // `draws` curves of the active fit sampled from its covariance, zero below the detector
+    // threshold. None when there is no fit or covariance to sample
+    pub fn sampled_efficiencies(&self, energies: &[f64], draws: usize) -> Option<Vec<Vec<f64>>> {
+        let mut curves = self.active()?.sampled_curves(energies, draws)?;
+
+        for curve in &mut curves {
+            for (energy, efficiency) in energies.iter().zip(curve.iter_mut()) {
+                if self.below_cutoff(*energy) {
+                    *efficiency = 0.0;
+                }
+            }
+        }
+
+        Some(curves)
+    }
+
+    fn sorted_fit_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.fits.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // stores a fit under `fit_name`, replacing the previous one with that name, and makes it active.
+    // A failed fit is not stored so the previous curve stays on screen, only its error is kept
+    pub fn insert_fit(&mut self, fit_name: &str, mut exp_fitter: ExpFitter) {
+        self.fit_error = exp_fitter
+            .fit_error
+            .take()
+            .map(|error| format!("{} fit failed: {}", fit_name, error));
+
+        if self.fit_error.is_some() {
+            return;
+        }
+
+        exp_fitter.fit_line.name = format!("{} {} Fit", self.name, fit_name);
+        exp_fitter.set_band_method(self.band_method);
+        exp_fitter.set_scale_covariance(self.scale_covariance);
+
+        // the new fit takes over the curve style of the active one
+        if let Some(active) = self.active() {
+            set_line_styling(&mut exp_fitter.fit_line, line_styling(&active.fit_line));
+
+            if active.prediction_band {
+                exp_fitter.prediction_band = true;
+                exp_fitter.update_fit_line();
+            }
+        }
+
+        if self.keep_history {
+            self.retire_fit(fit_name);
+        }
+
+        self.fits.insert(fit_name.to_string(), exp_fitter);
+
+        let previous = std::mem::replace(&mut self.active_fit, fit_name.to_string());
+        if previous != fit_name {
+            self.demote_fit(&previous);
+        }
+    }
+
+    // a fit that is no longer active gets a color no other fit uses and a dashed line, so it can
+    // be told apart from the active curve with Compare Models
+    fn demote_fit(&mut self, fit_name: &str) {
+        let used: Vec<egui::Color32> = self
+            .fits
+            .iter()
+            .filter(|(name, _)| name.as_str() != fit_name)
+            .map(|(_, fit)| fit.fit_line.color)
+            .collect();
+        let color = COMPARE_COLORS
+            .iter()
+            .copied()
+            .find(|color| !used.contains(color))
+            .unwrap_or(COMPARE_COLORS[used.len() % COMPARE_COLORS.len()]);
+
+        if let Some(fit) = self.fits.get_mut(fit_name) {
+            let length = fit.fit_line.style_length;
+            set_line_styling(
+                &mut fit.fit_line,
+                (
+                    color,
+                    Rgb::from_color32(color),
+                    Some(egui_plot::LineStyle::Dashed { length }),
+                ),
+            );
+        }
+    }
+
+    // makes `fit_name` the active fit, it swaps curve styles with the previous one so the active
+    // curve keeps the detector's style
+    pub fn set_active_fit(&mut self, fit_name: &str) {
+        if fit_name == self.active_fit || !self.fits.contains_key(fit_name) {
+            return;
+        }
+
+        let previous = std::mem::replace(&mut self.active_fit, fit_name.to_string());
+        let primary = match self.fits.get(&previous) {
+            Some(fit) => line_styling(&fit.fit_line),
+            None => return,
+        };
+
+        if let Some(fit) = self.fits.get_mut(fit_name) {
+            let compared = line_styling(&fit.fit_line);
+            set_line_styling(&mut fit.fit_line, primary);
+
+            if let Some(fit) = self.fits.get_mut(&previous) {
+                set_line_styling(&mut fit.fit_line, compared);
+            }
+        }
+    }
+
+    fn remove_active_fit(&mut self) {
+        let primary = self
+            .fits
+            .remove(&self.active_fit)
+            .map(|fit| line_styling(&fit.fit_line));
+        self.promote_first_fit(primary);
+    }
+
+    // after the active fit was removed the first remaining one takes over, in its curve style
+    fn promote_first_fit(&mut self, primary: Option<LineStyling>) {
+        self.active_fit = self.sorted_fit_names().first().cloned().unwrap_or_default();
+
+        if let (Some(primary), Some(fit)) = (primary, self.fits.get_mut(&self.active_fit)) {
+            set_line_styling(&mut fit.fit_line, primary);
+        }
+    }
+
+    // moves the fit stored under `fit_name` to the next free "<name> #n" in its own color
+    fn retire_fit(&mut self, fit_name: &str) {
+        let mut fit = match self.fits.remove(fit_name) {
+            Some(fit) => fit,
+            None => return,
+        };
+
+        let number = (1..)
+            .find(|n| !self.fits.contains_key(&format!("{} #{}", fit_name, n)))
+            .unwrap_or(1);
+        let history_name = format!("{} #{}", fit_name, number);
+
+        let color = HISTORY_COLORS[(number - 1) % HISTORY_COLORS.len()];
+        let length = fit.fit_line.style_length;
+        set_line_styling(
+            &mut fit.fit_line,
+            (
+                color,
+                Rgb::from_color32(color),
+                Some(egui_plot::LineStyle::Dashed { length }),
+            ),
+        );
+        fit.fit_line.name = format!("{} {} Fit", self.name, history_name);
+
+        self.fits.insert(history_name, fit);
+    }
+
+    // earlier fits kept by `retire_fit`
+    fn is_history(fit_name: &str) -> bool {
+        fit_name
+            .rsplit_once(" #")
+            .is_some_and(|(_, number)| number.parse::<usize>().is_ok())
+    }
+
+    pub fn migrate_legacy_fit(&mut self) {
+        if let Some(mut legacy_fit) = self.legacy_fit.take() {
+            legacy_fit.migrate_model();
+            if legacy_fit.is_fitted() && self.fits.is_empty() {
+                self.fits.insert("Fit".to_string(), legacy_fit);
+                self.active_fit = "Fit".to_string();
+            }
+        }
+
+        for (name, fit) in self.fits.iter_mut() {
+            fit.migrate_model();
+
+            // line styles are not saved, compared fits get their dashes back after a load
+            if fit.fit_line.style.is_none() {
+                fit.fit_line.style = Some(if *name == self.active_fit {
+                    egui_plot::LineStyle::Solid
+                } else {
+                    egui_plot::LineStyle::Dashed {
+                        length: fit.fit_line.style_length,
+                    }
+                });
+            }
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        ui.vertical(|ui| {
+            ui.label(self.name.to_string());
+
+            let sources = self
+                .sources
+                .iter()
+                .map(|(source, count)| format!("{} ({})", source, count))
+                .collect::<Vec<_>>()
+                .join(", ");
+            ui.small(sources)
+                .on_hover_text("Sources contributing to this fit (number of points)");
+
+            if self.is_stale() {
+                ui.colored_label(ui.visuals().warn_fg_color, "stale — refit")
+                    .on_hover_text("The detector data changed after this fit was made");
+            }
+        });
+
+        self.guesses_ui(ui);
+
+        self.fits_ui(ui);
+
+        self.settings_ui(ui, energy_unit);
+
+        self.results_ui(ui, energy_unit);
+
+        self.job_ui(ui);
+
+        self.model_comparison_ui(ui);
+
+        if let Some(fit_error) = self.fit_error.clone() {
+            ui.horizontal(|ui| {
+                ui.colored_label(ui.visuals().error_fg_color, format!("✖ {}", fit_error));
+
+                if ui.small_button("Dismiss").clicked() {
+                    self.fit_error = None;
+                }
+            });
+        }
+
+        if let Some(active) = self.active() {
+            if !active.warnings.is_empty() {
+                let warn_color = ui.visuals().warn_fg_color;
+                ui.vertical(|ui| {
+                    for warning in &active.warnings {
+                        ui.colored_label(warn_color, format!("⚠ {}", warning));
+                    }
+                });
+            }
+        }
+    }
+
+    // the b and d guesses, hovering or dragging one previews the curve the fit starts from
+    fn guesses_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Initial Guesses:");
+
+            let b_response = ui.add(
+                egui::DragValue::new(&mut self.initial_b_guess)
+                    .prefix("b: ")
+                    .speed(10.0)
+                    .clamp_range(0.0..=f64::INFINITY),
+            );
+
+            let d_response = ui.add(
+                egui::DragValue::new(&mut self.initial_d_guess)
+                    .prefix("d: ")
+                    .speed(10.0)
+                    .clamp_range(0.0..=f64::INFINITY),
+            );
+
+            let active = |response: &egui::Response| {
+                response.hovered() || response.dragged() || response.has_focus()
+            };
+
+            // d only enters the double exponential, b previews whichever model is active
+            self.guess_preview = if active(&d_response) {
+                Some(2)
+            } else if active(&b_response) {
+                Some(self.active_terms().max(1))
+            } else {
+                None
+            };
+
+            self.auto_guess_ui(ui);
+        });
+    }
+
+    // every model that can be fit and the analyses of the active fit
+    fn fits_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            self.single_exp_fit_button(ui);
+            self.double_exp_fit_button(ui);
+            self.bootstrap_button(ui);
+            self.leave_one_out_button(ui);
+            self.error_scale_ui(ui);
+        });
+
+        self.profile_ui(ui);
+
+        ui.horizontal(|ui| {
+            if self.exponential_fit_ui(ui) {
+                self.guess_preview = Some(self.exponential_terms);
+            }
+        });
+
+        self.update_guess_preview();
+
+        ui.horizontal(|ui| {
+            self.log_polynomial_fit_button(ui);
+        });
+
+        ui.horizontal(|ui| {
+            self.spline_fit_button(ui);
+        });
+
+        self.formula_fit_ui(ui);
+
+        self.constraints_ui(ui);
+    }
+
+    // what the fits are made of and how they are drawn
+    fn settings_ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        ui.horizontal(|ui| {
+            self.active_fit_ui(ui);
+            self.energy_times_efficiency_ui(ui);
+            self.energy_errors_ui(ui);
+            self.compare_models_ui(ui);
+            self.pinned_ui(ui);
+        });
+
+        ui.horizontal(|ui| {
+            self.relative_ui(ui, energy_unit);
+        });
+
+        ui.horizontal(|ui| {
+            self.band_method_ui(ui);
+        });
+
+        ui.horizontal(|ui| {
+            self.loss_ui(ui);
+        });
+
+        self.solver_ui(ui);
+
+        self.sampling_ui(ui, energy_unit);
+
+        ui.horizontal(|ui| {
+            self.fit_range_ui(ui, energy_unit);
+        });
+    }
+
+    // parameters of the active fit with every uncertainty that has been computed for them
+    fn results_ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        ui.label(self.parameters_heading());
+
+        if let Some(fit) = self.active() {
+            let bootstrap = fit.bootstrap_uncertainties();
+            let (alternate_name, alternate) = fit.alternate_uncertainties();
+            for (index, (name, value, uncertainty)) in fit.parameters().into_iter().enumerate() {
+                if fit.is_fixed(&name) {
+                    ui.label(format!("{}: {:.4e} (fixed)", name, value));
+                    continue;
+                }
+
+                let mut text = format!("{}: {:.4e} ± {:.1e}", name, value, uncertainty);
+                if let Some(other) = alternate.get(index) {
+                    text.push_str(&format!(" ({} ± {:.1e})", alternate_name, other));
+                }
+                if let Some(spread) = bootstrap.get(index) {
+                    text.push_str(&format!(" (bootstrap ± {:.1e})", spread));
+                }
+                ui.label(text);
+            }
+
+            if let Some(normalization) = &fit.normalization {
+                ui.label(format!(
+                    "N: {:.4e} ± {:.1e} (ε at {})",
+                    normalization.value,
+                    normalization.uncertainty,
+                    energy_unit.format(normalization.energy)
+                ));
+            }
+        }
+
+        self.solver_report_ui(ui);
+
+        self.leave_one_out_ui(ui, energy_unit);
+    }
+
+    // data inside the fit energy range with the error bars scaled by `error_scale`
+    pub fn scaled_data(&self) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+        let (x_data, y_data, weights) = &self.data;
+        let mut scaled = (Vec::new(), Vec::new(), Vec::new());
+
+        for ((&x, &y), &w) in x_data.iter().zip(y_data).zip(weights) {
+            if self.in_fit_range(x) {
+                scaled.0.push(x);
+                scaled.1.push(y);
+                scaled.2.push(w / self.error_scale);
+            }
+        }
+
+        scaled
+    }
+
+    // The reference line of a relative fit, None for absolute fits. A line measured with several
+    // sources uses the most precise one, its uncertainty is 1 / weight like the fit sees it
+    fn normalization(&self) -> Result<Option<Normalization>, String> {
+        let reference = match self.reference_line {
+            Some(reference) => reference,
+            None => return Ok(None),
+        };
+
+        let (x_data, y_data, weights) = self.scaled_data();
+        let best = (0..x_data.len())
+            .filter(|&i| (x_data[i] - reference).abs() < REFERENCE_LINE_TOLERANCE)
+            .filter(|&i| y_data[i] > 0.0 && weights[i] > 0.0)
+            .max_by(|&a, &b| (y_data[a] * weights[a]).total_cmp(&(y_data[b] * weights[b])));
+
+        match best {
+            Some(i) => Ok(Some(Normalization {
+                energy: x_data[i],
+                value: y_data[i],
+                uncertainty: 1.0 / weights[i],
+            })),
+            None => Err(format!(
+                "No line at {:.1} keV inside the fit range to normalize to",
+                reference
+            )),
+        }
+    }
+
+    // relative only source of each point in `scaled_data`, None for sources with a known activity
+    pub fn scaled_relative_sources(&self) -> Vec<Option<String>> {
+        self.data
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(_, &x)| self.in_fit_range(x))
+            .map(|(index, _)| self.relative_sources.get(index).cloned().flatten())
+            .collect()
+    }
+
+    // energy uncertainties of the points in `scaled_data`
+    fn scaled_energy_uncertainties(&self) -> Vec<f64> {
+        self.data
+            .0
+            .iter()
+            .zip(&self.energy_uncertainties)
+            .filter(|(&x, _)| self.in_fit_range(x))
+            .map(|(_, &sigma)| sigma)
+            .collect()
+    }
+
+    // Fits the detector data with `model` on a worker thread, `poll_job` stores the result under
+    // `fit_name`. Starting another fit abandons the one in progress
+    pub fn fit(&mut self, fit_name: &str, model: Model) {
+        let normalization = match self.normalization() {
+            Ok(normalization) => normalization,
+            Err(err) => {
+                log::error!("{} fit failed: {}", fit_name, err);
+                self.fit_error = Some(format!("{} fit failed: {}", fit_name, err));
+                self.job = None;
+                return;
+            }
+        };
+
+        let (x_data, y_data, weights) = self.scaled_data();
+
+        let mut exp_fitter = ExpFitter::new(x_data, y_data, weights);
+        exp_fitter.energy_times_efficiency = self.energy_times_efficiency;
+        exp_fitter.normalization = normalization;
+        exp_fitter.sampling = self.sampling;
+        exp_fitter.loss = self.loss;
+        exp_fitter.solver = self.solver;
+        if self.energy_errors {
+            exp_fitter.energy_uncertainties = self.scaled_energy_uncertainties();
+        }
+
+        let constraints = self.constraints.clone();
+        let multi_start = self.multi_start;
+        let weighting = self.weighting;
+        let error_scale = self.error_scale;
+        let name = fit_name.to_string();
+
+        self.start_job(fit_name, JobKind::Fit, move || {
+            let model = if multi_start {
+                exp_fitter.multi_start(model, &constraints)
+            } else {
+                model
+            };
+            exp_fitter.fit(model, &constraints);
+            if let Some(provenance) = exp_fitter
+                .fit_result
+                .as_mut()
+                .and_then(|result| result.provenance.as_mut())
+            {
+                provenance.weighting = Some(weighting);
+                provenance.error_scale = error_scale;
+            }
+            vec![(name, exp_fitter)]
+        });
+    }
+
+    // The active fit's model refit right away to the points of `data` where `keep` is true. None
+    // without an active fit, for relative fits, whose efficiencies have no scale without their
+    // reference line, and when the kept points cannot be fitted
+    pub fn refit_without(&self, keep: &[bool]) -> Option<ExpFitter> {
+        if self.reference_line.is_some() {
+            return None;
+        }
+
+        let active = self.active()?;
+        let mut model = active.model.clone()?;
+        if let Some(result) = &active.fit_result {
+            model = model.seeded(&result.parameters());
+        }
+
+        let (x_data, y_data, weights) = &self.data;
+        let mut kept = (Vec::new(), Vec::new(), Vec::new());
+        let mut energy_uncertainties = Vec::new();
+        for (index, &x) in x_data.iter().enumerate() {
+            if keep.get(index) == Some(&true) && self.in_fit_range(x) {
+                kept.0.push(x);
+                kept.1.push(y_data[index]);
+                kept.2.push(weights[index] / self.error_scale);
+                energy_uncertainties
+                    .push(self.energy_uncertainties.get(index).copied().unwrap_or(0.0));
+            }
+        }
+
+        let mut exp_fitter = ExpFitter::new(kept.0, kept.1, kept.2);
+        exp_fitter.energy_times_efficiency = self.energy_times_efficiency;
+        exp_fitter.sampling = self.sampling;
+        exp_fitter.loss = self.loss;
+        exp_fitter.solver = self.solver;
+        if self.energy_errors {
+            exp_fitter.energy_uncertainties = energy_uncertainties;
+        }
+
+        exp_fitter.fit(model, &self.constraints);
+        exp_fitter.fit_result.is_some().then_some(exp_fitter)
+    }
+
+    // Starts the `batch` fit with this detector's settings and returns the name the result will be
+    // stored under, or why it could not start
+    pub fn fit_batch(&mut self, batch: BatchModel) -> Result<String, String> {
+        if self.data.0.is_empty() {
+            return Err("No fittable lines".to_string());
+        }
+
+        if self.scaled_data().0.is_empty() {
+            return Err("No lines inside the fit range".to_string());
+        }
+
+        match batch {
+            BatchModel::Single => self.single_fit(),
+            BatchModel::Double => self.double_fit(),
+            BatchModel::Exponential => self.exponential_fit(self.exponential_terms),
+            BatchModel::LogPolynomial => {
+                self.fit("ln-Poly", Model::LogPolynomial(self.log_polynomial.clone()))
+            }
+            BatchModel::Formula => {
+                if let Err(err) = self.formula.tree() {
+                    return Err(err.clone());
+                }
+                self.fit("Formula", Model::Formula(self.formula.clone()));
+            }
+        }
+
+        match &self.job {
+            Some(job) => Ok(job.fit_name.clone()),
+            None => Err("The fit did not start".to_string()),
+        }
+    }
+
+    // copies or saves the report of the active fit
+    pub fn report_ui(&mut self, ui: &mut egui::Ui) {
+        let report = self
+            .active()
+            .and_then(|fit| FitReport::new(&self.name, &self.active_fit, fit));
+
+        ui.horizontal(|ui| {
+            ui.label("Fit Report:");
+            self.report_format.ui(ui);
+
+            ui.add_enabled_ui(report.is_some(), |ui| {
+                if ui
+                    .button("📋")
+                    .on_hover_text("Copy the parameters, covariance, correlation, χ² and ranges of the active fit")
+                    .clicked()
+                {
+                    if let Some(report) = &report {
+                        let text = self.report_format.render(report);
+                        ui.output_mut(|o| o.copied_text = text);
+                    }
+                }
+
+                if ui.button("💾").on_hover_text("Save the report to a file").clicked() {
+                    if let Some(report) = &report {
+                        self.report_format.save(
+                            &format!("{}_{}", self.name, self.active_fit),
+                            self.report_format.render(report),
+                        );
+                    }
+                }
+
+                if ui
+                    .button("Curve")
+                    .on_hover_text("Copy the active fit as JSON for cebra_efficiency::EfficiencyCurve")
+                    .clicked()
+                {
+                    if let Some(mut curve) = self
+                        .active()
+                        .and_then(|fit| fit.curve(&self.name, &self.active_fit))
+                    {
+                        curve.low_energy_cutoff = self.low_energy_cutoff;
+                        match serde_json::to_string_pretty(&curve) {
+                            Ok(text) => ui.output_mut(|o| o.copied_text = text),
+                            Err(err) => log::error!("Failed to serialize the curve: {}", err),
+                        }
+                    }
+                }
+            });
+        });
+    }
+
+    // copies eff(E) and its uncertainty with the active fit's parameters as source code
+    pub fn code_export_ui(&mut self, ui: &mut egui::Ui) {
+        let has_fit = self.active().is_some_and(|fit| fit.is_fitted());
+
+        ui.horizontal(|ui| {
+            ui.label("Copy as Code:");
+            self.code_language.ui(ui);
+
+            if ui
+                .add_enabled(has_fit, egui::Button::new("📋"))
+                .on_hover_text("Copy an efficiency(E) function with the fitted parameters and an efficiency_uncertainty(E) from the covariance")
+                .clicked()
+            {
+                let title = format!("{} {} fit", self.name, self.active_fit);
+                let snippet = self
+                    .active()
+                    .ok_or_else(|| "Nothing has been fit".to_string())
+                    .and_then(|fit| code_snippet(fit, &title, self.code_language));
+
+                match snippet {
+                    Ok(snippet) => ui.output_mut(|o| o.copied_text = snippet),
+                    Err(err) => {
+                        log::error!("Code export failed: {}", err);
+                        self.fit_error = Some(format!("Code export failed: {}", err));
+                    }
+                }
+            }
+        });
+    }
+
+    pub fn correlation_window(&mut self, ctx: &egui::Context) {
+        if !self.show_correlation {
+            return;
+        }
+
+        let mut open = true;
+        egui::Window::new(format!("{} {} Correlation", self.name, self.active_fit))
+            .id(egui::Id::new(("correlation_window", &self.name)))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| match self.active() {
+                Some(fit) => fit.correlation_ui(ui),
+                None => {
+                    ui.label("No fit");
+                }
+            });
+
+        self.show_correlation = open;
+    }
+
+    // parameter, range and steps of the χ² scan, the result opens in its own window
+    pub fn profile_ui(&mut self, ui: &mut egui::Ui) {
+        let names: Vec<String> = match self.active() {
+            Some(fit) if fit.is_fitted() => fit
+                .parameters()
+                .into_iter()
+                .map(|(name, _, _)| name)
+                .filter(|name| !fit.is_fixed(name))
+                .collect(),
+            _ => return,
+        };
+
+        if !names.contains(&self.profile_settings.parameter) {
+            // the nonlinear parameters come last and are the ones worth scanning
+            self.profile_settings.parameter = names.last().cloned().unwrap_or_default();
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("χ² Scan:");
+
+            egui::ComboBox::from_id_source(ui.id().with("profile_parameter"))
+                .selected_text(self.profile_settings.parameter.clone())
+                .show_ui(ui, |ui| {
+                    for name in &names {
+                        ui.selectable_value(&mut self.profile_settings.parameter, name.clone(), name);
+                    }
+                });
+
+            ui.add(
+                egui::DragValue::new(&mut self.profile_settings.width)
+                    .prefix("±")
+                    .suffix("σ")
+                    .speed(0.1)
+                    .clamp_range(0.5..=10.0),
+            )
+            .on_hover_text("Range of the scan in covariance errors on each side of the best value");
+            ui.add(
+                egui::DragValue::new(&mut self.profile_settings.steps)
+                    .suffix(" steps")
+                    .clamp_range(2..=100),
+            )
+            .on_hover_text("Refits on each side of the best value");
+
+            if ui
+                .add_enabled(self.job.is_none(), egui::Button::new("Scan"))
+                .on_hover_text("Hold the parameter fixed at each step, refit the others and plot Δχ² against it. The profile width can be compared with the covariance error")
+                .clicked()
+            {
+                let constraints = self.constraints.clone();
+                let settings = self.profile_settings.clone();
+                self.show_profile = false;
+                self.start_analysis(JobKind::Profile, move |active| {
+                    active.profile_scan(&settings.parameter, &settings, &constraints);
+                });
+            }
+
+            let has_scan = self
+                .active()
+                .and_then(|fit| fit.fit_result.as_ref())
+                .is_some_and(|result| result.profile.is_some());
+            if has_scan {
+                ui.checkbox(&mut self.show_profile, "Show");
+            }
+        });
+    }
+
+    pub fn profile_window(&mut self, ctx: &egui::Context) {
+        if !self.show_profile {
+            return;
+        }
+
+        let scan = match self
+            .active()
+            .and_then(|fit| fit.fit_result.as_ref())
+            .and_then(|result| result.profile.clone())
+        {
+            Some(scan) => scan,
+            None => return,
+        };
+
+        let mut open = true;
+        egui::Window::new(format!("{} {} χ² Scan", self.name, self.active_fit))
+            .id(egui::Id::new(("profile_window", &self.name)))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| scan.plot(ui));
+
+        self.show_profile = open;
+    }
+
+    // replaces b and d with the estimate from the data, leaves them alone if the data has no decay
+    pub fn guess_decays(&mut self) {
+        let (x_data, y_data, weights) = self.scaled_data();
+        let x_data = DVector::from_vec(x_data);
+        let mut y_data = DVector::from_vec(y_data);
+        let mut weights = DVector::from_vec(weights);
+
+        // the guesses are for the function that is fit, E * eff(E) when that option is on
+        if self.energy_times_efficiency {
+            y_data = y_data.component_mul(&x_data);
+            weights = weights.component_div(&x_data);
+        }
+
+        match Exponential::guess_decays(&x_data, &y_data, &weights) {
+            Some((b, d)) => {
+                log::info!("{}: estimated b = {:.1}, d = {:.1}", self.name, b, d);
+                self.initial_b_guess = b;
+                self.initial_d_guess = d;
+            }
+            None => log::warn!(
+                "{}: could not estimate the decay constants, keeping the current guesses",
+                self.name
+            ),
+        }
+    }
+
+    pub fn auto_guess_ui(&mut self, ui: &mut egui::Ui) {
+        if ui
+            .button("Auto Guess")
+            .on_hover_text("Estimate b and d from the slopes of ln(efficiency) over the low and high energy halves of the data")
+            .clicked()
+        {
+            self.guess_decays();
+        }
+
+        ui.checkbox(&mut self.auto_guess, "Before Fits")
+            .on_hover_text("Estimate b and d from the data before every exponential fit");
+
+        ui.checkbox(&mut self.multi_start, "Multi-Start").on_hover_text(format!(
+            "Launch exponential fits from every combination of b and d times {:?} and keep the lowest reduced χ²",
+            MULTI_START_FACTORS
+        ));
+    }
+
+    fn single_fit(&mut self) {
+        let model = self.exponential_model(1);
+        self.fit("Single", model);
+    }
+
+    fn double_fit(&mut self) {
+        let model = self.exponential_model(2);
+        self.fit("Double", model);
+    }
+
+    fn exponential_fit(&mut self, terms: usize) {
+        let model = self.exponential_model(terms);
+        self.fit(&format!("{}-Exp", terms), model);
+    }
+
+    // exponential model started from the guesses, estimated from the data when auto guess is on
+    fn exponential_model(&mut self, terms: usize) -> Model {
+        if self.auto_guess {
+            self.guess_decays();
+        }
+
+        Model::Exponential(Exponential::new(self.decay_guesses(terms)))
+    }
+
+    // initial decay constants for the first `terms` terms, b and d followed by the extra guesses.
+    // Terms without a guess start a decade above the previous one
+    fn decay_guesses(&self, terms: usize) -> Vec<f64> {
+        let mut decays = vec![self.initial_b_guess, self.initial_d_guess];
+        decays.extend(self.extra_decay_guesses.iter().cloned());
+
+        while decays.len() < terms {
+            let last = decays.last().copied().unwrap_or(500.0);
+            decays.push(10.0 * last);
+        }
+
+        decays.truncate(terms);
+        decays
+    }
+
+    // Lower/upper bounds and fixed values for the parameters of the active model (a, b, c, d before
+    // the first fit). They apply to every model with a parameter of that name
+    pub fn constraints_ui(&mut self, ui: &mut egui::Ui) {
+        let parameters: Vec<(String, f64)> = match self.active() {
+            Some(fit) if fit.is_fitted() => fit
+                .parameters()
+                .into_iter()
+                .map(|(name, value, _)| (name, value))
+                .collect(),
+            _ => ["a", "b", "c", "d"]
+                .iter()
+                .map(|name| (name.to_string(), 0.0))
+                .collect(),
+        };
+
+        let active_count = self
+            .constraints
+            .iter()
+            .filter(|constraint| constraint.is_active())
+            .count();
+
+        egui::CollapsingHeader::new(format!("Constraints ({})", active_count))
+            .id_source(format!("{} Constraints", self.name))
+            .show(ui, |ui| {
+                egui::Grid::new(format!("{} Constraint Grid", self.name))
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Parameter");
+                        ui.label("Lower");
+                        ui.label("Upper");
+                        ui.label("Fixed");
+                        ui.end_row();
+
+                        for (name, value) in parameters {
+                            let index = match self
+                                .constraints
+                                .iter()
+                                .position(|constraint| constraint.name == name)
+                            {
+                                Some(index) => index,
+                                None => {
+                                    self.constraints.push(ParameterConstraint::new(&name));
+                                    self.constraints.len() - 1
+                                }
+                            };
+                            let constraint = &mut self.constraints[index];
+
+                            ui.label(&name);
+                            optional_value_ui(ui, &mut constraint.lower, value);
+                            optional_value_ui(ui, &mut constraint.upper, value);
+                            optional_value_ui(ui, &mut constraint.fixed, value);
+                            ui.end_row();
+                        }
+                    });
+
+                if active_count > 0 && ui.button("Clear Constraints").clicked() {
+                    self.constraints.clear();
+                }
+            });
+    }
+
+    // expression entry, a guess for each parameter it uses and the fit button
+    pub fn formula_fit_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Formula: y =");
+
+            if ui
+                .text_edit_singleline(&mut self.formula.expression)
+                .on_hover_text("Any expression in x, e.g. a * exp(-x / b) + c * x^d. Every other name is a fit parameter. exp, ln, log10, sqrt and pow are available")
+                .changed()
+            {
+                self.formula.expression_changed();
+            }
+
+            if ui
+                .add_enabled(self.formula.tree().is_ok(), egui::Button::new("Fit"))
+                .on_hover_text("Fit the formula with Levenberg-Marquardt starting from the guesses below")
+                .clicked()
+            {
+                self.fit("Formula", Model::Formula(self.formula.clone()));
+            }
+        });
+
+        if let Err(err) = self.formula.tree() {
+            ui.colored_label(ui.visuals().error_fg_color, format!("✖ {}", err));
+            return;
+        }
+
+        let names = self.formula.parameter_names();
+        ui.horizontal_wrapped(|ui| {
+            for (name, guess) in self.formula.initial_guesses.iter_mut() {
+                if names.contains(name) {
+                    ui.add(
+                        egui::DragValue::new(guess)
+                            .prefix(format!("{}: ", name))
+                            .speed(0.1),
+                    );
+                }
+            }
+        });
+    }
+
+    // N-exponential fit button, the number of terms and the guesses for the terms after b and d.
+    // Returns whether one of the guesses is being edited
+    pub fn exponential_fit_ui(&mut self, ui: &mut egui::Ui) -> bool {
+        if ui
+            .button("N-Exp")
+            .on_hover_text("Fit the data with a sum of N exponentials. Uses b, d and the guesses to the right for the initial decay constants")
+            .clicked()
+        {
+            self.exponential_fit(self.exponential_terms);
+        }
+
+        ui.add(
+            egui::DragValue::new(&mut self.exponential_terms)
+                .prefix("N: ")
+                .clamp_range(1..=6),
+        );
+
+        let extra_terms = self.exponential_terms.saturating_sub(2);
+        if self.extra_decay_guesses.len() < extra_terms {
+            self.extra_decay_guesses = self.decay_guesses(self.exponential_terms)[2..].to_vec();
+        }
+
+        let mut editing = false;
+        for (index, guess) in self
+            .extra_decay_guesses
+            .iter_mut()
+            .take(extra_terms)
+            .enumerate()
+        {
+            let response = ui.add(
+                egui::DragValue::new(guess)
+                    .prefix(format!("{}: ", Exponential::decay_name(index + 2)))
+                    .speed(10.0)
+                    .clamp_range(0.0..=f64::INFINITY),
+            );
+
+            editing |= response.hovered() || response.dragged() || response.has_focus();
+        }
+
+        editing
+    }
+
+    pub fn bootstrap_button(&mut self, ui: &mut egui::Ui) {
+        let idle = self.job.is_none() && self.active().is_some_and(|fit| fit.is_fitted());
+
+        if ui
+            .add_enabled(idle, egui::Button::new("Bootstrap"))
+            .on_hover_text(format!(
+                "Refit the active model to {} resamplings of the lines (with replacement) and show the spread of each parameter next to the covariance errors",
+                BOOTSTRAP_SAMPLES
+            ))
+            .clicked()
+        {
+            let constraints = self.constraints.clone();
+            self.start_analysis(JobKind::Bootstrap, move |active| {
+                active.bootstrap(BOOTSTRAP_SAMPLES, &constraints);
+            });
+        }
+
+        if let Some(result) = self.active().and_then(|fit| fit.fit_result.as_ref()) {
+            if result.bootstrap_samples > 0 {
+                ui.label(format!("n = {}", result.bootstrap_samples))
+                    .on_hover_text("Bootstrap refits that converged");
+            }
+        }
+    }
+
+    pub fn leave_one_out_button(&mut self, ui: &mut egui::Ui) {
+        let idle = self.job.is_none() && self.active().is_some_and(|fit| fit.is_fitted());
+
+        if ui
+            .add_enabled(idle, egui::Button::new("LOO Check"))
+            .on_hover_text("Refit the active model once per line with that line left out and show how well the rest of the lines predict it")
+            .clicked()
+        {
+            let constraints = self.constraints.clone();
+            self.start_analysis(JobKind::LeaveOneOut, move |active| {
+                active.leave_one_out(&constraints);
+            });
+        }
+    }
+
+    // held out pulls of the last leave-one-out check, lines that steer the curve stand out
+    pub fn leave_one_out_ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        let checks = match self.active().and_then(|fit| fit.fit_result.as_ref()) {
+            Some(result) if !result.leave_one_out.is_empty() => result.leave_one_out.clone(),
+            _ => return,
+        };
+
+        let rms =
+            (checks.iter().map(|check| check[3].powi(2)).sum::<f64>() / checks.len() as f64).sqrt();
+
+        egui::CollapsingHeader::new(format!("Leave-One-Out (RMS pull {:.2})", rms))
+            .id_source(ui.id().with("leave_one_out"))
+            .show(ui, |ui| {
+                egui::Grid::new(ui.id().with("leave_one_out_grid"))
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Energy");
+                        ui.label("Measured [%]");
+                        ui.label("Predicted [%]");
+                        ui.label("Pull [σ]");
+                        ui.end_row();
+
+                        for [energy, measured, predicted, pull] in &checks {
+                            ui.label(energy_unit.format(*energy));
+                            ui.label(format!("{:.4}", measured));
+                            ui.label(format!("{:.4}", predicted));
+                            if pull.abs() > 3.0 {
+                                ui.colored_label(
+                                    ui.visuals().warn_fg_color,
+                                    format!("{:.2}", pull),
+                                )
+                                .on_hover_text("The other lines predict this one poorly");
+                            } else {
+                                ui.label(format!("{:.2}", pull));
+                            }
+                            ui.end_row();
+                        }
+                    });
+
+                if ui.small_button("Clear").clicked() {
+                    if let Some(result) = self.active_mut().and_then(|fit| fit.fit_result.as_mut())
+                    {
+                        result.leave_one_out.clear();
+                    }
+                }
+            });
+    }
+
+    pub fn single_exp_fit_button(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Single").on_hover_text("Fit the data with a single exponential fit. Uses parameter b for the initial guess").clicked() {
+            self.single_fit();
+        }
+    }
+
+    pub fn double_exp_fit_button(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Double").on_hover_text("Fit the data with a double exponential fit. Uses parameter b and d for the initial guess").clicked() {
+            self.double_fit();
+        }
+    }
+
+    pub fn log_polynomial_fit_button(&mut self, ui: &mut egui::Ui) {
+        if ui.button("ln-Poly").on_hover_text("Fit ln(ε) as a polynomial in ln(E/E0), the usual HPGe parameterization. Needs no initial guesses").clicked() {
+            self.fit("ln-Poly", Model::LogPolynomial(self.log_polynomial.clone()));
+        }
+
+        ui.add(
+            egui::DragValue::new(&mut self.log_polynomial.order)
+                .prefix("order: ")
+                .clamp_range(1..=6),
+        );
+
+        ui.add(
+            egui::DragValue::new(&mut self.log_polynomial.reference_energy)
+                .prefix("E0: ")
+                .suffix(" keV")
+                .speed(10.0)
+                .clamp_range(1.0..=f64::INFINITY),
+        )
+        .on_hover_text("Reference energy, keeps the powers of ln(E/E0) small");
+    }
+
+    pub fn spline_fit_button(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Spline").on_hover_text("Fit ln(ε) as a natural cubic spline in ln(E) with the knots spread evenly over the data. Follows any shape, the uncertainty comes from the scatter of the points about it").clicked() {
+            let spline = Spline::new(self.spline_knots, &self.scaled_data().0);
+            self.fit("Spline", Model::Spline(spline));
+        }
+
+        ui.add(
+            egui::DragValue::new(&mut self.spline_knots)
+                .prefix("knots: ")
+                .clamp_range(3..=10),
+        )
+        .on_hover_text("More knots follow the data more closely but need more points");
+    }
+
+    // number of exponential terms in the active fit, zero when nothing has been fit
+    fn active_terms(&self) -> usize {
+        match self.active().and_then(|fit| fit.model.as_ref()) {
+            Some(Model::Exponential(model)) => model.terms(),
+            _ => 0,
+        }
+    }
+
+    // Recomputes the guess preview when its inputs changed, dragging a guess redraws it every
+    // frame otherwise
+    fn update_guess_preview(&mut self) {
+        let terms = match self.guess_preview {
+            Some(terms) => terms,
+            None => return,
+        };
+
+        let inputs = GuessPreviewInputs {
+            decays: self.decay_guesses(terms),
+            data: self.scaled_data(),
+            normalization: self.normalization().ok().flatten(),
+            energy_times_efficiency: self.energy_times_efficiency,
+            sampling: self.sampling,
+        };
+
+        if self
+            .guess_preview_cache
+            .as_ref()
+            .is_some_and(|(cached, _)| *cached == inputs)
+        {
+            return;
+        }
+
+        let line = self.guess_preview_line(&inputs);
+        self.guess_preview_cache = Some((inputs, line));
+    }
+
+    // the model evaluated at the initial guesses, with the amplitudes solved for those decay
+    // constants. Not a fit, only a hint for where the guesses start the solver
+    fn guess_preview_line(&self, inputs: &GuessPreviewInputs) -> Option<EguiLine> {
+        let (x_data, y_data, weights) = inputs.data.clone();
+
+        let mut exp_fitter = ExpFitter::new(x_data, y_data, weights);
+        exp_fitter.energy_times_efficiency = inputs.energy_times_efficiency;
+        exp_fitter.normalization = inputs.normalization;
+
+        let model = Exponential::new(inputs.decays.clone());
+
+        let (x_data, y_data, weights) = exp_fitter.fit_data();
+        let amplitudes = model.linear_amplitudes(&x_data, &y_data, &weights)?;
+
+        // no covariance, so the preview is drawn without a confidence band
+        exp_fitter.fit_result = Some(FitResult {
+            linear_parameters: amplitudes,
+            nonlinear_parameters: model.initial_decays.clone(),
+            ..Default::default()
+        });
+        exp_fitter.model = Some(Model::Exponential(model));
+        exp_fitter.sampling = CurveSampling {
+            points: 200,
+            ..inputs.sampling
+        };
+        exp_fitter.update_fit_line();
+
+        let mut line = exp_fitter.fit_line;
+        line.name = format!("{} Guess Preview", self.name);
+        line.width = 1.0;
+        line.style = Some(egui_plot::LineStyle::dashed_loose());
+
+        Some(line)
+    }
+
+    // PDG-style scale factor: multiply the error bars by sqrt(reduced chi-square) and refit the
+    // active model, which brings the reduced chi-square of the new fit to ~1
+    pub fn error_scale_ui(&mut self, ui: &mut egui::Ui) {
+        let scale_factor = self
+            .active()
+            .and_then(|fit| fit.fit_result.as_ref())
+            .and_then(pdg_scale_factor);
+
+        // the global fit is refit from the global fitter, a local refit would replace it
+        let global = self.active_fit == "Global";
+        // the active result would still be the one before the pending refit
+        let can_refit = self.job.is_none() && !global;
+
+        if let Some(scale_factor) = scale_factor {
+            let response = ui
+                .add_enabled(can_refit, egui::Button::new("Scale Errors"))
+                .on_hover_text(format!(
+                    "Multiply the unscaled error bars by √(χ²/ν) = {:.3} and refit",
+                    scale_factor
+                ))
+                .on_disabled_hover_text("Rerun the global fit to scale its errors");
+            if response.clicked() {
+                self.error_scale = scale_factor;
+                self.refit_active();
+            }
+        }
+
+        // the factor the shown fit was made with, which differs from `error_scale` until it is refit
+        let applied = self
+            .active()
+            .and_then(|fit| fit.fit_result.as_ref())
+            .and_then(|result| result.provenance.as_ref())
+            .map_or(1.0, |provenance| provenance.error_scale);
+        if applied != 1.0 {
+            ui.label(format!("S = {:.3}", applied))
+                .on_hover_text("Error bars were multiplied by this factor before fitting");
+        }
+
+        if self.error_scale != 1.0
+            && ui
+                .add_enabled(self.job.is_none(), egui::Button::new("Reset"))
+                .clicked()
+        {
+            self.error_scale = 1.0;
+            if !global {
+                self.refit_active();
+            }
+        }
+    }
+
+    // refits the active fit's model under its own name, with the current error scale
+    fn refit_active(&mut self) {
+        if let Some(model) = self.active().and_then(|fit| fit.model.clone()) {
+            self.fit(&self.active_fit.clone(), model);
+        }
+    }
+
+    pub fn draw(
+        &self,
+        plot_ui: &mut PlotUi,
+        band_decimation: usize,
+        low_energy_clamp: Option<f64>,
+    ) {
+        for pinned in &self.pinned {
+            pinned.draw(plot_ui);
+        }
+
+        for bound in [self.fit_min_energy, self.fit_max_energy]
+            .into_iter()
+            .flatten()
+        {
+            plot_ui.vline(
+                egui_plot::VLine::new(bound)
+                    .color(egui::Color32::GRAY)
+                    .style(egui_plot::LineStyle::dashed_loose())
+                    .name(format!("{} Fit Range", self.name)),
+            );
+        }
+
+        // the cache can be from an earlier preview, only draw it while a guess is being edited
+        if let Some((_, Some(line))) = self
+            .guess_preview_cache
+            .as_ref()
+            .filter(|_| self.guess_preview.is_some())
+        {
+            let mut line = line.clone();
+            line.color = self
+                .active()
+                .map_or(line.color, |fit| fit.fit_line.color)
+                .gamma_multiply(0.5);
+            line.draw(plot_ui);
+        }
+
+        if self.compare_models {
+            for fit_name in self.sorted_fit_names() {
+                self.fits[&fit_name].draw(
+                    plot_ui,
+                    band_decimation,
+                    low_energy_clamp,
+                    self.low_energy_cutoff,
+                );
+            }
+        } else if let Some(active) = self.active() {
+            active.draw(
+                plot_ui,
+                band_decimation,
+                low_energy_clamp,
+                self.low_energy_cutoff,
+            );
+        }
+    }
+
+    // the data changed after the current fit was made
+    pub fn is_stale(&self) -> bool {
+        let fitted_at = self
+            .active()
+            .and_then(|fit| fit.fit_result.as_ref())
+            .and_then(|result| result.fitted_at);
+
+        match (fitted_at, self.data_modified_at) {
+            (Some(fitted_at), Some(modified_at)) => modified_at > fitted_at,
+            _ => false,
+        }
+    }
+
+    pub fn inverse_ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        ui.horizontal(|ui| {
+            ui.label("Energy at Efficiency:");
+            ui.add(
+                egui::DragValue::new(&mut self.target_efficiency)
+                    .speed(0.01)
+                    .clamp_range(0.0..=100.0)
+                    .suffix("%"),
+            );
+        });
+
+        let active = match self.active() {
+            Some(active) if active.is_fitted() => active,
+            _ => return,
+        };
+
+        let crossings = active.energies_at_efficiency(self.target_efficiency);
+
+        if crossings.is_empty() {
+            ui.label("No crossing within the data range");
+            return;
+        }
+
+        if crossings.len() > 1 {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!(
+                    "⚠ The fit crosses {:.3}% {} times",
+                    self.target_efficiency,
+                    crossings.len()
+                ),
+            );
+        }
+
+        for energy in crossings {
+            let uncertainty = active.energy_uncertainty_at(energy, 1.0);
+            ui.label(format!(
+                "E = {} ± {}",
+                energy_unit.format(energy),
+                energy_unit.format(uncertainty)
+            ));
+        }
+    }
+
+    pub fn active_fit_ui(&mut self, ui: &mut egui::Ui) {
+        if self.fits.is_empty() {
+            return;
+        }
+
+        let mut selected = self.active_fit.clone();
+        egui::ComboBox::from_id_source(format!("{} Active Fit", self.name))
+            .selected_text(self.active_fit.clone())
+            .show_ui(ui, |ui| {
+                for fit_name in self.sorted_fit_names() {
+                    ui.selectable_value(&mut selected, fit_name.clone(), fit_name);
+                }
+            })
+            .response
+            .on_hover_text("Fit that is plotted and used for the summed efficiency");
+        self.set_active_fit(&selected);
+
+        if ui
+            .button("🗑")
+            .on_hover_text("Remove the active fit")
+            .clicked()
+        {
+            self.remove_active_fit();
+        }
+    }
+
+    pub fn solver_report_ui(&self, ui: &mut egui::Ui) {
+        let result = self.active().and_then(|fit| fit.fit_result.as_ref());
+
+        if let Some(report) = result.and_then(|result| result.solver.as_ref()) {
+            report.ui(ui);
+        }
+
+        if let Some(provenance) = result.and_then(|result| result.provenance.as_ref()) {
+            provenance.ui(ui);
+        }
+    }
+
+    // names the loss of the active fit when it was not ordinary least squares
+    fn parameters_heading(&self) -> String {
+        match self
+            .active()
+            .and_then(|fit| fit.fit_result.as_ref())
+            .map(|result| result.loss)
+        {
+            Some(loss) if loss != Loss::Linear => format!("Parameters ({:?} loss):", loss),
+            _ => "Parameters:".to_string(),
+        }
+    }
+
+    // optional energy window for the fits, every line stays on the plot
+    pub fn fit_range_ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        let lowest = self.data.0.iter().copied().fold(f64::INFINITY, f64::min);
+        let highest = self
+            .data
+            .0
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let previous = (self.fit_min_energy, self.fit_max_energy);
+
+        ui.label("Fit Range:");
+
+        let mut min = self.fit_min_energy.is_some();
+        if ui
+            .checkbox(&mut min, "Min")
+            .on_hover_text("Leave the lines below this energy out of the fits")
+            .changed()
+        {
+            self.fit_min_energy = min.then_some(if lowest.is_finite() { lowest } else { 0.0 });
+        }
+
+        if let Some(min) = &mut self.fit_min_energy {
+            energy_unit.drag_value(ui, min);
+        }
+
+        let mut max = self.fit_max_energy.is_some();
+        if ui
+            .checkbox(&mut max, "Max")
+            .on_hover_text("Leave the lines above this energy out of the fits, e.g. where the model breaks down")
+            .changed()
+        {
+            self.fit_max_energy = max.then_some(if highest.is_finite() { highest } else { 0.0 });
+        }
+
+        if let Some(max) = &mut self.fit_max_energy {
+            energy_unit.drag_value(ui, max);
+        }
+
+        let excluded = self.data.0.len() - self.scaled_data().0.len();
+        if excluded > 0 {
+            ui.label(format!("{} outside", excluded))
+                .on_hover_text("Lines outside the fit range, plotted but not fit");
+        }
+
+        // the fits no longer match the points they would be made from
+        if (self.fit_min_energy, self.fit_max_energy) != previous {
+            self.data_modified_at = Some(chrono::Utc::now());
+        }
+    }
+
+    pub fn loss_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Loss:");
+        self.loss.ui(ui);
+    }
+
+    pub fn solver_ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Solver", |ui| {
+            self.solver.ui(ui);
+        })
+        .header_response
+        .on_hover_text("Levenberg-Marquardt tolerances and limits for the next fit, loosen them when a fit stops without converging");
+    }
+
+    // applies to every fit of the detector, they are redrawn right away
+    pub fn sampling_ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        let last_point = self.data.0.iter().copied().fold(0.0, f64::max);
+
+        let changed = ui
+            .collapsing("Curve", |ui| self.sampling.ui(ui, energy_unit, last_point))
+            .body_returned
+            .unwrap_or(false);
+
+        if changed {
+            for fit in self.fits.values_mut() {
+                fit.sampling = self.sampling;
+                if fit.is_fitted() {
+                    // the Monte Carlo band is resampled on the new grid
+                    fit.update_fit_line();
+                }
+            }
+        }
+    }
+
+    pub fn band_method_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Band:");
+
+        let previous = self.band_method;
+        self.band_method.ui(ui);
+
+        if self.band_method != previous {
+            for fit in self.fits.values_mut() {
+                fit.set_band_method(self.band_method);
+            }
+        }
+
+        if ui
+            .checkbox(&mut self.scale_covariance, "Scale by √χ²ᵥ")
+            .on_hover_text("Scale the parameter errors and bands by the square root of the reduced χ² (lmfit's scale_covar). Off treats the data uncertainties as absolute")
+            .changed()
+        {
+            for fit in self.fits.values_mut() {
+                fit.set_scale_covariance(self.scale_covariance);
+            }
+        }
+
+        if let Some(active) = self.active_mut() {
+            active.prediction_band_ui(ui);
+        }
+    }
+
+    pub fn energy_times_efficiency_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.energy_times_efficiency, "E·ε")
+            .on_hover_text("Fit energy times efficiency to flatten the dynamic range of the data. The curve is divided by the energy for display");
+    }
+
+    // Relative mode: every efficiency is divided by the one of the reference line before fitting so
+    // a source with a poorly known activity only contributes its shape
+    pub fn relative_ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        let mut energies = self.scaled_data().0;
+        energies.dedup();
+
+        let mut relative = self.reference_line.is_some();
+        if ui
+            .checkbox(&mut relative, "Relative to")
+            .on_hover_text("Divide every efficiency by the one of a reference line before fitting, so an unknown activity cancels. The efficiency of that line is kept as a separate normalization parameter and its error is added to the band")
+            .changed()
+        {
+            // start from the most precisely measured line
+            self.reference_line = if relative {
+                self.normalization_candidate()
+            } else {
+                None
+            };
+        }
+
+        if let Some(reference) = &mut self.reference_line {
+            egui::ComboBox::from_id_source(ui.id().with("reference_line"))
+                .selected_text(energy_unit.format(*reference))
+                .show_ui(ui, |ui| {
+                    for energy in energies {
+                        ui.selectable_value(reference, energy, energy_unit.format(energy));
+                    }
+                });
+        }
+    }
+
+    // the line with the smallest relative uncertainty
+    fn normalization_candidate(&self) -> Option<f64> {
+        let (x_data, y_data, weights) = self.scaled_data();
+        (0..x_data.len())
+            .filter(|&i| y_data[i] > 0.0 && weights[i] > 0.0)
+            .max_by(|&a, &b| (y_data[a] * weights[a]).total_cmp(&(y_data[b] * weights[b])))
+            .map(|i| x_data[i])
+    }
+
+    pub fn energy_errors_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.energy_errors, "Energy Errors (ODR)")
+            .on_hover_text("Include the ΔE of each line in the fit weights through the slope of the curve (effective variance), so steep regions do not over-trust the energies");
+    }
+
+    // copies the active curve into the pinned set, drawn thin and faded under the live fit
+    pub fn pin_active(&mut self) {
+        let mut line = match self.active() {
+            Some(active) if active.is_fitted() => active.fit_line.clone(),
+            _ => return,
+        };
+
+        line.name = format!("{} (pin {})", line.name, self.pinned.len() + 1);
+        line.color = line.color.gamma_multiply(0.35);
+        line.color_rgb = Rgb::from_color32(line.color);
+        line.width = 1.0;
+
+        self.pinned.push(line);
+    }
+
+    pub fn pinned_ui(&mut self, ui: &mut egui::Ui) {
+        let has_fit = self.active().is_some_and(|fit| fit.is_fitted());
+
+        if ui
+            .add_enabled(has_fit, egui::Button::new("📌 Pin"))
+            .on_hover_text("Keep a faint copy of the current curve to compare later fits against")
+            .clicked()
+        {
+            self.pin_active();
+        }
+
+        if !self.pinned.is_empty()
+            && ui
+                .button(format!("Clear Pins ({})", self.pinned.len()))
+                .clicked()
+        {
+            self.pinned.clear();
+        }
+    }
+
+    // AIC, BIC and reduced χ² of every stored fit, the lowest criterion is the best supported model.
+    // Δ > 2 is a meaningful preference, Δ > 10 a strong one
+    pub fn model_comparison_ui(&mut self, ui: &mut egui::Ui) {
+        let rows: Vec<(String, &FitResult)> = self
+            .sorted_fit_names()
+            .into_iter()
+            .filter_map(|name| {
+                let result = self.fits[&name].fit_result.as_ref()?;
+                Some((name, result))
+            })
+            .collect();
+
+        if rows.len() < 2 {
+            return;
+        }
+
+        let best_aic = rows
+            .iter()
+            .map(|(_, result)| result.aic())
+            .fold(f64::INFINITY, f64::min);
+        let best_bic = rows
+            .iter()
+            .map(|(_, result)| result.bic())
+            .fold(f64::INFINITY, f64::min);
+
+        let mut selected = None;
+
+        egui::CollapsingHeader::new("Model Comparison")
+            .id_source(format!("{}_model_comparison", self.name))
+            .show(ui, |ui| {
+                egui::Grid::new(format!("{}_model_comparison_grid", self.name))
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Fit");
+                        ui.label("Parameters");
+                        ui.label("χ²/ν");
+                        ui.label("AIC");
+                        ui.label("ΔAIC");
+                        ui.label("BIC");
+                        ui.label("ΔBIC");
+                        ui.end_row();
+
+                        for (name, result) in &rows {
+                            if ui
+                                .selectable_label(*name == self.active_fit, name)
+                                .on_hover_text("Make this the active fit")
+                                .clicked()
+                            {
+                                selected = Some(name.clone());
+                            }
+                            ui.label(result.free_parameter_count().to_string());
+                            ui.label(format!("{:.3}", result.reduced_chi_squared));
+                            ui.label(format!("{:.2}", result.aic()));
+                            ui.label(format!("{:.2}", result.aic() - best_aic));
+                            ui.label(format!("{:.2}", result.bic()));
+                            ui.label(format!("{:.2}", result.bic() - best_bic));
+                            ui.end_row();
+                        }
+                    });
+            });
+
+        if let Some(name) = selected {
+            self.set_active_fit(&name);
+        }
+    }
+
+    pub fn compare_models_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.compare_models, "Compare Models")
+            .on_hover_text("Overlay every stored fit instead of only the active one");
+
+        ui.checkbox(&mut self.keep_history, "Keep History")
+            .on_hover_text("Refitting keeps the previous fit of the same name as \"Single #1\", \"Single #2\", ... so earlier attempts can be overlaid with Compare Models");
+
+        let history = self
+            .fits
+            .keys()
+            .filter(|name| Self::is_history(name))
+            .count();
+        if history > 0 && ui.button(format!("Clear History ({})", history)).clicked() {
+            let primary = self.active().map(|fit| line_styling(&fit.fit_line));
+            self.fits.retain(|name, _| !Self::is_history(name));
+            if !self.fits.contains_key(&self.active_fit) {
+                self.promote_first_fit(primary);
+            }
+        }
+
+        if self.compare_models {
+            for fit_name in self.sorted_fit_names() {
+                if let Some(fit) = self.fits.get_mut(&fit_name) {
+                    ui.checkbox(&mut fit.fit_line.draw, fit_name);
+                }
+            }
+        }
+    }
+
+    pub fn menu_button(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        ui.separator();
+        ui.horizontal(|ui| {
+            let formula = match self.active().and_then(|fit| fit.model.as_ref()) {
+                Some(model) => model.fit_model().formula(),
+                None => "y = a exp(-x/b) + c exp(-x/d)".to_string(),
+            };
+            ui.label(formula);
+        });
+        ui.separator();
+
+        self.guesses_ui(ui);
+
+        ui.separator();
+
+        self.fits_ui(ui);
+
+        ui.separator();
+
+        self.results_ui(ui, energy_unit);
+
+        ui.add_enabled(
+            self.active().is_some_and(|fit| fit.is_fitted()),
+            egui::Checkbox::new(&mut self.show_correlation, "Correlation Matrix"),
+        )
+        .on_hover_text("Show the parameter correlations of the active fit in a window");
+
+        self.report_ui(ui);
+
+        self.code_export_ui(ui);
+
+        ui.separator();
+
+        self.inverse_ui(ui, energy_unit);
+
+        ui.separator();
+
+        if let Some(active) = self.active_mut() {
+            active.valid_range_ui(ui, energy_unit);
+            ui.separator();
+        }
+
+        if let Some(active) = self.active_mut() {
+            active.menu_button(ui, energy_unit);
+        }
+
+        self.settings_ui(ui, energy_unit);
+
+        if self.compare_models {
+            for fit_name in self.sorted_fit_names() {
+                if fit_name == self.active_fit {
+                    continue;
+                }
+                if let Some(fit) = self.fits.get_mut(&fit_name) {
+                    fit.fit_line.menu_button(ui);
+                }
+            }
+        }
+
+        ui.separator();
+    }
+}
+
+// checkbox that turns an optional value on (starting at `default`) with a drag value to edit it
+fn optional_value_ui(ui: &mut egui::Ui, value: &mut Option<f64>, default: f64) {
+    ui.horizontal(|ui| {
+        let mut enabled = value.is_some();
+        if ui.checkbox(&mut enabled, "").changed() {
+            *value = if enabled { Some(default) } else { None };
+        }
+
+        if let Some(value) = value {
+            ui.add(egui::DragValue::new(value).speed(0.1));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::efficiency_fitter::exp_fitter::tests::{clean_data, log_polynomial};
+
+    #[test]
+    fn compared_fits_keep_their_own_style() {
+        let mut fitter = Fitter::default();
+        fitter.insert_fit("Single", clean_data());
+        let blue = egui::Color32::BLUE;
+        set_line_styling(
+            &mut fitter.fits.get_mut("Single").unwrap().fit_line,
+            (
+                blue,
+                Rgb::from_color32(blue),
+                Some(egui_plot::LineStyle::Solid),
+            ),
+        );
+
+        let styling = |fitter: &Fitter, name: &str| {
+            let line = &fitter.fits[name].fit_line;
+            (line.color, line.style)
+        };
+        let dashed = Some(egui_plot::LineStyle::Dashed { length: 15.0 });
+
+        // the new fit is drawn like the active one was, the old one moves to its own style
+        fitter.insert_fit("Double", clean_data());
+        assert_eq!(
+            styling(&fitter, "Double"),
+            (blue, Some(egui_plot::LineStyle::Solid))
+        );
+        assert_eq!(styling(&fitter, "Single"), (COMPARE_COLORS[0], dashed));
+
+        fitter.set_active_fit("Single");
+        assert_eq!(
+            styling(&fitter, "Single"),
+            (blue, Some(egui_plot::LineStyle::Solid))
+        );
+        assert_eq!(styling(&fitter, "Double"), (COMPARE_COLORS[0], dashed));
+
+        fitter.remove_active_fit();
+        assert_eq!(fitter.active_fit, "Double");
+        assert_eq!(
+            styling(&fitter, "Double"),
+            (blue, Some(egui_plot::LineStyle::Solid))
+        );
+    }
+
+    #[test]
+    fn guess_preview_is_only_recomputed_when_its_inputs_change() {
+        let data = clean_data();
+        let mut fitter = Fitter {
+            name: "A".to_string(),
+            data: (data.x, data.y, data.weights),
+            guess_preview: Some(1),
+            ..Default::default()
+        };
+
+        fitter.update_guess_preview();
+        let preview = |fitter: &Fitter| fitter.guess_preview_cache.clone().unwrap().1.unwrap();
+        assert_eq!(preview(&fitter).points.len(), 201);
+
+        // a marker survives frames where nothing changed
+        if let Some((_, Some(line))) = &mut fitter.guess_preview_cache {
+            line.name = "cached".to_string();
+        }
+        fitter.update_guess_preview();
+        assert_eq!(preview(&fitter).name, "cached");
+
+        fitter.initial_b_guess = 800.0;
+        fitter.update_guess_preview();
+        assert_eq!(preview(&fitter).name, "A Guess Preview");
+    }
+
+    fn wait_for_jobs(fitter: &mut Fitter) {
+        while fitter.poll_job() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn monte_carlo_band_follows_the_curve_grid() {
+        let mut fit = clean_data();
+        fit.fit(log_polynomial(), &[]);
+
+        let mut fitter = Fitter {
+            band_method: BandMethod::MonteCarlo,
+            ..Default::default()
+        };
+        fitter.insert_fit("ln-Poly", fit);
+
+        // sampled by a job, not while the fit is stored
+        assert!(fitter.fits["ln-Poly"].needs_monte_carlo_band());
+        wait_for_jobs(&mut fitter);
+
+        let band_end = |fitter: &Fitter| {
+            fitter.fits["ln-Poly"]
+                .fit_result
+                .as_ref()
+                .unwrap()
+                .monte_carlo_band
+                .last()
+                .unwrap()[0]
+        };
+        assert!((band_end(&fitter) - 2408.0).abs() < 1e-9);
+
+        let fit = fitter.fits.get_mut("ln-Poly").unwrap();
+        fit.sampling.end = Some(5000.0);
+        fit.update_fit_line();
+
+        // until it is resampled, there is no band past the sampled range
+        assert!(fit.needs_monte_carlo_band());
+        assert!(fit.monte_carlo_bounds(3000.0).is_none());
+
+        wait_for_jobs(&mut fitter);
+        assert!((band_end(&fitter) - 5000.0).abs() < 1e-9);
+
+        let fit = &fitter.fits["ln-Poly"];
+        let curve_end = fit.upper_uncertainity_points.last().unwrap()[0];
+        assert!(fit.monte_carlo_bounds(curve_end).is_some());
+        assert!(fit.monte_carlo_bounds(6000.0).is_none());
+    }
+
+    #[test]
+    fn analysis_jobs_update_the_stored_fit() {
+        let mut fit = clean_data();
+        fit.fit(log_polynomial(), &[]);
+
+        let mut fitter = Fitter::default();
+        fitter.insert_fit("ln-Poly", fit);
+        fitter.start_analysis(JobKind::LeaveOneOut, |active| active.leave_one_out(&[]));
+        assert_eq!(
+            fitter.job.as_ref().map(|job| job.kind),
+            Some(JobKind::LeaveOneOut)
+        );
+
+        wait_for_jobs(&mut fitter);
+
+        let result = fitter.fits["ln-Poly"].fit_result.as_ref().unwrap();
+        assert_eq!(result.leave_one_out.len(), 8);
+        assert!(fitter.fit_error.is_none());
+    }
+
+    #[test]
+    fn scaled_refit_keeps_the_active_fit_name() {
+        let data = clean_data();
+        let mut fit = clean_data();
+        fit.fit(log_polynomial(), &[]);
+
+        let mut fitter = Fitter {
+            data: (data.x, data.y, data.weights),
+            ..Default::default()
+        };
+        fitter.insert_fit("ln-Poly", fit);
+
+        fitter.error_scale = 2.0;
+        fitter.refit_active();
+        wait_for_jobs(&mut fitter);
+
+        assert_eq!(fitter.fits.len(), 1);
+        let result = fitter.fits["ln-Poly"].fit_result.as_ref().unwrap();
+        assert_eq!(result.provenance.as_ref().unwrap().error_scale, 2.0);
+    }
+}
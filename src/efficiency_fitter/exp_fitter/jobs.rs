@@ -0,0 +1,273 @@
+#[cfg(not(target_arch = "wasm32"))]
+use super::{BatchModel, Model};
+use super::{ExpFitter, FitResult, Fitter};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+// Runs `work` off the UI thread, its result arrives on the returned channel exactly once
+pub fn spawn_job<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> Receiver<T> {
+    let (sender, receiver) = channel();
+    let job = move || {
+        let _ = sender.send(work());
+    };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    std::thread::spawn(job);
+
+    // no threads in the browser, the job runs once this frame is drawn so the spinner shows
+    #[cfg(target_arch = "wasm32")]
+    wasm_bindgen_futures::spawn_local(async move { job() });
+
+    receiver
+}
+
+// what a job of a Fitter computes, decides how `poll_job` stores what comes back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobKind {
+    Fit,
+    Bootstrap,
+    LeaveOneOut,
+    Profile,
+    Band, // Monte Carlo bands of every fit that needs one
+}
+
+impl JobKind {
+    fn label(self) -> &'static str {
+        match self {
+            JobKind::Fit => "Fitting",
+            JobKind::Bootstrap => "Bootstrapping",
+            JobKind::LeaveOneOut => "Leave-one-out refits of",
+            JobKind::Profile => "χ² scan of",
+            JobKind::Band => "Sampling the Monte Carlo bands of",
+        }
+    }
+}
+
+// Work running off the UI thread, the fits it made or updated arrive on the channel exactly once.
+// The receiver is shared so the Fitter stays cloneable, a clone polls the same job
+#[derive(Clone)]
+pub struct FitJob {
+    pub fit_name: String,
+    pub kind: JobKind,
+    receiver: Arc<Mutex<Receiver<JobResults>>>,
+}
+
+// (fit name, fit) of everything a job made or updated
+pub type JobResults = Vec<(String, ExpFitter)>;
+
+impl Fitter {
+    // replaces the job in progress, its result is dropped
+    pub(super) fn start_job(
+        &mut self,
+        fit_name: &str,
+        kind: JobKind,
+        work: impl FnOnce() -> JobResults + Send + 'static,
+    ) {
+        self.job = Some(FitJob {
+            fit_name: fit_name.to_string(),
+            kind,
+            receiver: Arc::new(Mutex::new(spawn_job(work))),
+        });
+    }
+
+    // Runs `analysis` on a copy of the active fit off the UI thread, `poll_job` stores the result
+    pub(super) fn start_analysis(
+        &mut self,
+        kind: JobKind,
+        analysis: impl FnOnce(&mut ExpFitter) + Send + 'static,
+    ) {
+        let mut active = match self.active() {
+            Some(active) if active.is_fitted() => active.clone(),
+            _ => return,
+        };
+        let name = self.active_fit.clone();
+
+        self.fit_error = None;
+        self.start_job(&name.clone(), kind, move || {
+            analysis(&mut active);
+            vec![(name, active)]
+        });
+    }
+
+    // samples the Monte Carlo bands that are missing or drawn on an old grid
+    pub(super) fn start_band_job(&mut self) -> bool {
+        let fits: Vec<(String, ExpFitter)> = self
+            .fits
+            .iter()
+            .filter(|(_, fit)| fit.needs_monte_carlo_band())
+            .map(|(name, fit)| (name.clone(), fit.clone()))
+            .collect();
+
+        if fits.is_empty() {
+            return false;
+        }
+
+        self.start_job(&self.active_fit.clone(), JobKind::Band, move || {
+            fits.into_iter()
+                .map(|(name, mut fit)| {
+                    fit.sample_monte_carlo_band();
+                    (name, fit)
+                })
+                .collect()
+        });
+        true
+    }
+
+    // stores what a finished job sent back
+    pub(super) fn finish_job(&mut self, kind: JobKind, results: JobResults) {
+        for (name, mut finished) in results {
+            if kind == JobKind::Fit {
+                self.insert_fit(&name, finished);
+                continue;
+            }
+
+            let stored = match self.fits.get_mut(&name) {
+                Some(stored) => stored,
+                None => continue,
+            };
+
+            // the fit may have changed while the job ran, e.g. its covariance scaling
+            let same_fit = stored.scale_covariance == finished.scale_covariance
+                && stored.fit_result.as_ref().map(FitResult::parameters)
+                    == finished.fit_result.as_ref().map(FitResult::parameters);
+            if !same_fit {
+                continue;
+            }
+
+            if kind == JobKind::Band {
+                if let (Some(stored), Some(finished)) =
+                    (&mut stored.fit_result, finished.fit_result)
+                {
+                    stored.monte_carlo_band = finished.monte_carlo_band;
+                }
+            } else {
+                stored.fit_result = finished.fit_result;
+                self.fit_error = finished.fit_error.take();
+                self.show_profile |= kind == JobKind::Profile && self.fit_error.is_none();
+            }
+
+            if let Some(stored) = self.fits.get_mut(&name) {
+                stored.update_fit_line();
+            }
+        }
+    }
+
+    // outcome of the fit stored under `fit_name`, None while it is still running
+    pub fn fit_status(&self, fit_name: &str) -> Option<Result<String, String>> {
+        if self
+            .job
+            .as_ref()
+            .is_some_and(|job| job.kind == JobKind::Fit && job.fit_name == fit_name)
+        {
+            return None;
+        }
+
+        if let Some(fit_error) = &self.fit_error {
+            return Some(Err(fit_error.clone()));
+        }
+
+        Some(
+            match self
+                .fits
+                .get(fit_name)
+                .and_then(|fit| fit.fit_result.as_ref())
+            {
+                Some(result) => Ok(format!("χ²ᵥ = {:.3}", result.reduced_chi_squared)),
+                None => Err("No result".to_string()),
+            },
+        )
+    }
+
+    // Stores the result from the worker once it is done and starts sampling any Monte Carlo band
+    // that is missing, returns true while a job is still running
+    pub fn poll_job(&mut self) -> bool {
+        let job = match &self.job {
+            Some(job) => job.clone(),
+            None => return self.start_band_job(),
+        };
+
+        let received = match job.receiver.lock() {
+            Ok(receiver) => receiver.try_recv(),
+            Err(_) => Err(TryRecvError::Disconnected),
+        };
+
+        match received {
+            Ok(results) => {
+                self.job = None;
+                self.finish_job(job.kind, results);
+                self.start_band_job()
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => {
+                self.job = None;
+                let message = format!("{} {} was interrupted", job.kind.label(), job.fit_name);
+                log::error!("{}", message);
+                self.fit_error = Some(message);
+                false
+            }
+        }
+    }
+
+    // Refits every saved fit with the model it was made with, one after another, waiting for each
+    // to finish. A detector without saved fits gets the `batch` model. For running without a window
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn refit_blocking(&mut self, batch: BatchModel) -> Vec<(String, Result<String, String>)> {
+        let mut saved: Vec<(String, Model)> = self
+            .fits
+            .iter()
+            .filter_map(|(name, fit)| Some((name.clone(), fit.model.clone()?)))
+            .collect();
+        saved.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if saved.is_empty() {
+            return vec![match self.fit_batch(batch) {
+                Ok(fit_name) => {
+                    let status = self.wait_for_job(&fit_name);
+                    (fit_name, status)
+                }
+                Err(err) => (batch.label().to_string(), Err(err)),
+            }];
+        }
+
+        if self.scaled_data().0.is_empty() {
+            return saved
+                .into_iter()
+                .map(|(name, _)| (name, Err("No lines inside the fit range".to_string())))
+                .collect();
+        }
+
+        let active_fit = self.active_fit.clone();
+        let statuses = saved
+            .into_iter()
+            .map(|(name, model)| {
+                self.fit(&name, model);
+                let status = self.wait_for_job(&name);
+                (name, status)
+            })
+            .collect();
+
+        // every refit becomes the active fit, put back the one that was plotted
+        self.set_active_fit(&active_fit);
+
+        statuses
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(super) fn wait_for_job(&mut self, fit_name: &str) -> Result<String, String> {
+        while self.poll_job() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        self.fit_status(fit_name)
+            .unwrap_or_else(|| Err("The fit did not finish".to_string()))
+    }
+
+    pub fn job_ui(&self, ui: &mut egui::Ui) {
+        if let Some(job) = &self.job {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("{} {}…", job.kind.label(), job.fit_name));
+            });
+        }
+    }
+}
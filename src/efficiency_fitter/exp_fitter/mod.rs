@@ -0,0 +1,1336 @@
+use super::fit_model::{Exponential, FitModel, Model, ParameterConstraint, SolverSettings};
+use super::measurements::WeightingScheme;
+use super::units::EnergyUnit;
+use crate::curve::{CurveModel, EfficiencyCurve, Normalization};
+use crate::egui_plot_stuff::egui_line::EguiLine;
+use crate::egui_plot_stuff::plot_settings::decimate;
+use egui_plot::{PlotPoint, PlotPoints, PlotUi, Polygon};
+use nalgebra::DVector;
+
+mod diagnostics;
+mod fitter;
+mod jobs;
+mod uncertainty;
+
+pub use diagnostics::{ProfileScan, ProfileSettings};
+pub use fitter::Fitter;
+pub use jobs::{spawn_job, FitJob, JobKind, JobResults};
+use uncertainty::pdg_scale_factor;
+pub use uncertainty::{percentile_band, BandMethod};
+
+// multiples of the b and d guesses the multi-start fit is launched from
+const MULTI_START_FACTORS: [f64; 5] = [0.2, 0.5, 1.0, 2.0, 5.0];
+
+// refits with updated weights for the energy uncertainties (effective variance) and the robust
+// losses (iteratively reweighted least squares), the weights settle in a few
+const REWEIGHT_ITERATIONS: usize = 10;
+
+// pull (in σ) beyond which the robust losses start to downweight a point
+const ROBUST_SCALE: f64 = 1.345;
+
+// Energies the fit line and its band are drawn at. The end defaults to 1 MeV past the last point,
+// set it to draw a dense curve over only the region of interest
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CurveSampling {
+    pub start: f64,       // keV
+    pub end: Option<f64>, // keV
+    pub points: usize,
+}
+
+impl Default for CurveSampling {
+    fn default() -> Self {
+        Self {
+            start: 0.0,
+            end: None,
+            points: 1000,
+        }
+    }
+}
+
+impl CurveSampling {
+    // returns true when a setting changed
+    pub fn ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit, last_point: f64) -> bool {
+        let previous = *self;
+
+        egui::Grid::new(ui.id().with("curve_sampling"))
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Start");
+                energy_unit.drag_value(ui, &mut self.start);
+                ui.end_row();
+
+                let mut end = self.end.is_some();
+                ui.checkbox(&mut end, "End")
+                    .on_hover_text("Off draws the curve to 1 MeV past the last line");
+                if end != self.end.is_some() {
+                    self.end = end.then_some(last_point.max(self.start) + 1000.0);
+                }
+                if let Some(end) = &mut self.end {
+                    energy_unit.drag_value(ui, end);
+                }
+                ui.end_row();
+
+                ui.label("Points");
+                ui.add(egui::DragValue::new(&mut self.points).clamp_range(2..=100_000));
+                ui.end_row();
+            });
+
+        if ui.button("Reset").clicked() {
+            *self = Self::default();
+        }
+
+        *self != previous
+    }
+}
+
+// penalty on the pulls minimized by the fit, the robust ones limit the pull of a single bad point
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum Loss {
+    #[default]
+    Linear, // ordinary χ²
+    Huber,  // quadratic up to ROBUST_SCALE, linear past it
+    SoftL1, // smooth version of the absolute pull, 2 (sqrt(1 + r²) - 1)
+}
+
+impl Loss {
+    // IRLS factor on the squared weight of a point with pull `r`, ψ(r) / r for the loss ρ(r)
+    fn weight_factor(self, pull: f64) -> f64 {
+        let r = pull.abs() / ROBUST_SCALE;
+        match self {
+            Loss::Linear => 1.0,
+            Loss::Huber if r <= 1.0 => 1.0,
+            Loss::Huber => 1.0 / r,
+            Loss::SoftL1 => 1.0 / (1.0 + r * r).sqrt(),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.radio_value(self, Loss::Linear, "χ²")
+            .on_hover_text("Ordinary least squares");
+        ui.radio_value(self, Loss::Huber, "Huber")
+            .on_hover_text(format!(
+                "Points more than {}σ off the curve count linearly instead of quadratically",
+                ROBUST_SCALE
+            ));
+        ui.radio_value(self, Loss::SoftL1, "Soft L1").on_hover_text(
+            "Smoothly downweights points far off the curve, more forgiving of outliers than Huber",
+        );
+    }
+}
+
+// model the "Fit All Detectors" action runs on every detector, with that detector's guesses
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum BatchModel {
+    #[default]
+    Single,
+    Double,
+    Exponential, // N terms, N from each detector's N-Exp setting
+    LogPolynomial,
+    Formula,
+}
+
+impl BatchModel {
+    const ALL: [BatchModel; 5] = [
+        BatchModel::Single,
+        BatchModel::Double,
+        BatchModel::Exponential,
+        BatchModel::LogPolynomial,
+        BatchModel::Formula,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            BatchModel::Single => "Single",
+            BatchModel::Double => "Double",
+            BatchModel::Exponential => "N-Exp",
+            BatchModel::LogPolynomial => "ln-Poly",
+            BatchModel::Formula => "Formula",
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_id_source(ui.id().with("batch_model"))
+            .selected_text(self.label())
+            .show_ui(ui, |ui| {
+                for model in Self::ALL {
+                    ui.selectable_value(self, model, model.label());
+                }
+            });
+    }
+}
+
+// how the solver finished, shown with the fit parameters
+#[derive(Debug, Default, Clone, serde::Deserialize, serde::Serialize)]
+pub struct SolverReport {
+    pub converged: bool,
+    pub termination: String,
+    pub iterations: usize, // solver iterations, cost function evaluations for varpro
+    pub cost: f64,         // final χ²
+}
+
+impl SolverReport {
+    pub fn new(
+        converged: bool,
+        termination: impl Into<String>,
+        iterations: usize,
+        cost: f64,
+    ) -> Self {
+        Self {
+            converged,
+            termination: termination.into(),
+            iterations,
+            cost,
+        }
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        let text = format!(
+            "Solver: {} after {} iterations, χ² = {:.4}",
+            self.termination, self.iterations, self.cost
+        );
+
+        if self.converged {
+            ui.label(text);
+        } else {
+            ui.colored_label(ui.visuals().warn_fg_color, format!("⚠ {}", text))
+                .on_hover_text("The solver stopped before converging, the parameters may not be at the minimum");
+        }
+    }
+}
+
+// Everything a fit was made from, saved with the result so a project documents how each curve was
+// produced. The hash covers the fitted points and weights, so two records with the same hash and
+// settings come from the same input
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct FitProvenance {
+    pub model: String,
+    pub formula: String,
+    pub initial_guesses: Vec<(String, f64)>, // where the solver started, after any multi-start
+    pub constraints: Vec<ParameterConstraint>, // only the active ones
+    pub weighting: Option<WeightingScheme>,  // None when the fit was not started from a detector
+    pub error_scale: f64,
+    pub energy_times_efficiency: bool,
+    pub energy_errors: bool,
+    pub loss: Loss,
+    pub solver: SolverSettings,
+    pub points: usize,
+    pub points_hash: String,
+    pub version: String, // crate version that made the fit
+    pub fitted_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl FitProvenance {
+    pub fn new(
+        model: &Model,
+        constraints: &[ParameterConstraint],
+        fitter: &ExpFitter,
+        fitted_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Self {
+        Self {
+            model: model.fit_model().name(),
+            formula: model.fit_model().formula(),
+            initial_guesses: model.initial_guesses(),
+            constraints: constraints
+                .iter()
+                .filter(|constraint| constraint.is_active())
+                .cloned()
+                .collect(),
+            weighting: None,
+            error_scale: 1.0,
+            energy_times_efficiency: fitter.energy_times_efficiency,
+            energy_errors: !fitter.energy_uncertainties.is_empty(),
+            loss: fitter.loss,
+            solver: fitter.solver,
+            points: fitter.x.len(),
+            points_hash: points_hash(&fitter.x, &fitter.y, &fitter.weights),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            fitted_at,
+        }
+    }
+
+    pub fn ui(&self, ui: &mut egui::Ui) {
+        let guesses: Vec<String> = self
+            .initial_guesses
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect();
+
+        let mut details = format!(
+            "{}\nInitial guesses: {}\nWeights: {}, errors scaled by {}\nLoss: {:?}\n{} points, hash {}",
+            self.formula,
+            if guesses.is_empty() {
+                "none needed".to_string()
+            } else {
+                guesses.join(", ")
+            },
+            self.weighting
+                .map_or("not recorded".to_string(), |weighting| format!(
+                    "{:?}",
+                    weighting
+                )),
+            self.error_scale,
+            self.loss,
+            self.points,
+            self.points_hash,
+        );
+        if !self.constraints.is_empty() {
+            details.push_str(&format!(
+                "\n{} constrained parameters",
+                self.constraints.len()
+            ));
+        }
+
+        ui.weak(format!(
+            "Fit made with v{}, data {}",
+            self.version,
+            self.points_hash.get(..8).unwrap_or(&self.points_hash)
+        ))
+        .on_hover_text(details);
+    }
+}
+
+// FNV-1a over the bits of every energy, efficiency and weight. Written out instead of using
+// `DefaultHasher`, whose output may change between Rust versions
+fn points_hash(x: &[f64], y: &[f64], weights: &[f64]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for value in x.iter().chain(y).chain(weights) {
+        for byte in value.to_bits().to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    format!("{:016x}", hash)
+}
+
+fn default_scale_covariance() -> bool {
+    true
+}
+
+#[derive(Default, Clone, serde::Deserialize, serde::Serialize)]
+pub struct FitResult {
+    pub linear_parameters: Vec<f64>,
+    pub linear_variances: Vec<f64>,
+    pub nonlinear_parameters: Vec<f64>,
+    pub nonlinear_variances: Vec<f64>,
+    pub covariance_matrix: Vec<f64>,
+    pub correlation_matrix: Vec<f64>,
+    pub reduced_chi_squared: f64,
+    pub regression_standard_error: f64,
+    pub weighted_residuals: Vec<f64>,
+    #[serde(default)]
+    pub fitted_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub fixed_parameters: Vec<String>, // names of the parameters held fixed in the fit
+    #[serde(default)]
+    pub monte_carlo_band: Vec<[f64; 3]>, // (energy, 15.9th, 84.1th percentile), empty until sampled
+    #[serde(default)]
+    pub bootstrap_uncertainties: Vec<f64>, // standard deviation of each parameter over the refits
+    #[serde(default)]
+    pub bootstrap_samples: usize, // refits that converged
+    #[serde(default)]
+    pub leave_one_out: Vec<[f64; 4]>, // (energy, measured, predicted without the point, pull)
+    #[serde(default)]
+    pub profile: Option<ProfileScan>, // last χ² scan, None until one is made
+    #[serde(default)]
+    pub loss: Loss,
+    #[serde(default)]
+    pub solver: Option<SolverReport>, // None for previews and fits saved without one
+    #[serde(default)]
+    pub provenance: Option<FitProvenance>, // None for previews and fits saved without one
+    #[serde(default)]
+    pub degrees_of_freedom: Option<f64>, // of a fit over more data than this curve's points, e.g. the global fit
+}
+
+impl FitResult {
+    pub fn log_info_result(&self) {
+        log::info!("Linear Parameters: {:?}", self.linear_parameters);
+        log::info!("Linear Variances: {:?}", self.linear_variances);
+        log::info!("Nonlinear Parameters: {:?}", self.nonlinear_parameters);
+        log::info!("Nonlinear Variances: {:?}", self.nonlinear_variances);
+        log::info!("Covariance Matrix: {:?}", self.covariance_matrix);
+        log::info!("Correlation Matrix: {:?}", self.correlation_matrix);
+        log::info!("Reduced Chi-squared: {:?}", self.reduced_chi_squared);
+        log::info!(
+            "Regression Standard Error: {:?}",
+            self.regression_standard_error
+        );
+        log::info!("Weighted Residuals: {:?}", self.weighted_residuals);
+        log::info!("Loss: {:?}", self.loss);
+        log::info!("Solver: {:?}", self.solver);
+    }
+
+    // linear then nonlinear parameters, the order of the covariance matrix
+    pub fn parameters(&self) -> Vec<f64> {
+        self.linear_parameters
+            .iter()
+            .chain(self.nonlinear_parameters.iter())
+            .cloned()
+            .collect()
+    }
+
+    pub fn uncertainties(&self) -> Vec<f64> {
+        self.linear_variances
+            .iter()
+            .chain(self.nonlinear_variances.iter())
+            .map(|variance| variance.sqrt())
+            .collect()
+    }
+
+    // every solver scales the covariance by the reduced χ², this undoes it so the uncertainties
+    // follow from the data errors alone
+    pub fn unscaled_covariance_factor(&self) -> f64 {
+        if self.reduced_chi_squared.is_finite() && self.reduced_chi_squared > 0.0 {
+            1.0 / self.reduced_chi_squared
+        } else {
+            1.0
+        }
+    }
+
+    pub fn chi_squared(&self) -> f64 {
+        self.weighted_residuals.iter().map(|r| r * r).sum()
+    }
+
+    // points minus free parameters of the fit the result came from, `n_points` are the points of
+    // this curve and only count when the fit stored no degrees of freedom of its own
+    pub fn degrees_of_freedom(&self, n_points: usize) -> f64 {
+        self.degrees_of_freedom
+            .unwrap_or(n_points as f64 - self.free_parameter_count() as f64)
+    }
+
+    // parameters the solver was free to move, fixed ones do not count against the model
+    pub fn free_parameter_count(&self) -> usize {
+        self.parameters()
+            .len()
+            .saturating_sub(self.fixed_parameters.len())
+    }
+
+    // information criteria from the χ² (Gaussian likelihood with known uncertainties),
+    // only differences between fits of the same data mean anything
+    pub fn aic(&self) -> f64 {
+        self.chi_squared() + 2.0 * self.free_parameter_count() as f64
+    }
+
+    pub fn bic(&self) -> f64 {
+        let n_points = self.weighted_residuals.len() as f64;
+        self.chi_squared() + self.free_parameter_count() as f64 * n_points.ln()
+    }
+
+    // ((amplitude, uncertainty), (decay, uncertainty)) for each term of an exponential fit
+    #[allow(clippy::type_complexity)]
+    pub fn exponential_terms(&self) -> Vec<((f64, f64), (f64, f64))> {
+        let uncertainties = self.uncertainties();
+        let terms = self.linear_parameters.len();
+
+        (0..terms.min(self.nonlinear_parameters.len()))
+            .map(|term| {
+                (
+                    (self.linear_parameters[term], uncertainties[term]),
+                    (self.nonlinear_parameters[term], uncertainties[terms + term]),
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Default, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ExpFitter {
+    #[allow(clippy::type_complexity)]
+    pub fit_params: Option<Vec<((f64, f64), (f64, f64))>>,
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub weights: Vec<f64>,
+    pub upper_uncertainity_points: Vec<[f64; 2]>,
+    pub lower_uncertainity_points: Vec<[f64; 2]>,
+    pub fit_line: EguiLine,
+    pub fit_result: Option<FitResult>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    #[serde(default)]
+    pub energy_times_efficiency: bool, // fit E * eff(E) and divide by E afterwards
+    #[serde(default)]
+    pub valid_min: f64, // keV, the fit is only trusted between valid_min and valid_max
+    #[serde(default)]
+    pub valid_max: f64,
+    #[serde(skip)]
+    pub fit_error: Option<String>, // why the last fit failed, shown in the fit panel
+    #[serde(default)]
+    pub model: Option<Model>, // filled in by `migrate_model` for fits saved without one
+    #[serde(default)]
+    pub band_method: BandMethod,
+    #[serde(default)]
+    pub energy_uncertainties: Vec<f64>, // keV per point, empty fits the energies as exact
+    #[serde(default)]
+    pub loss: Loss,
+    #[serde(default)]
+    pub solver: SolverSettings,
+    #[serde(default = "default_scale_covariance")]
+    pub scale_covariance: bool, // errors scaled by sqrt(reduced χ²) like lmfit, off trusts the data errors as absolute
+    #[serde(default)]
+    pub prediction_band: bool, // also draw where a new measurement is expected to fall
+    #[serde(default)]
+    pub upper_prediction_points: Vec<[f64; 2]>,
+    #[serde(default)]
+    pub lower_prediction_points: Vec<[f64; 2]>,
+    #[serde(default)]
+    pub normalization: Option<Normalization>, // relative fit: the data is divided by this line before fitting
+    #[serde(default)]
+    pub sampling: CurveSampling,
+}
+
+impl ExpFitter {
+    pub fn new(x: Vec<f64>, y: Vec<f64>, weights: Vec<f64>) -> Self {
+        let valid_min = x.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+        let valid_max = x.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+
+        Self {
+            fit_params: None,
+            x,
+            y,
+            weights,
+            upper_uncertainity_points: Vec::new(),
+            lower_uncertainity_points: Vec::new(),
+            fit_line: EguiLine::new(egui::Color32::BLUE),
+            fit_result: None,
+            warnings: Vec::new(),
+            energy_times_efficiency: false,
+            valid_min,
+            valid_max,
+            fit_error: None,
+            model: None,
+            band_method: BandMethod::default(),
+            energy_uncertainties: Vec::new(),
+            loss: Loss::default(),
+            solver: SolverSettings::default(),
+            scale_covariance: true,
+            prediction_band: false,
+            upper_prediction_points: Vec::new(),
+            lower_prediction_points: Vec::new(),
+            normalization: None,
+            sampling: CurveSampling::default(),
+        }
+    }
+
+    fn fit_failed(&mut self, message: String) {
+        log::error!("{}", message);
+        self.fit_error = Some(message);
+    }
+
+    // energy span the fit can be trusted in, falls back to the data range for fits saved without one
+    pub fn valid_range(&self) -> (f64, f64) {
+        if self.valid_max > self.valid_min {
+            (self.valid_min, self.valid_max)
+        } else {
+            (
+                self.x.iter().fold(f64::INFINITY, |a, &b| a.min(b)),
+                self.x.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b)),
+            )
+        }
+    }
+
+    pub fn valid_range_ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        ui.horizontal(|ui| {
+            let (mut valid_min, mut valid_max) = self.valid_range();
+
+            ui.label("Valid Range:")
+                .on_hover_text("Energy span the fit is trusted in. Included in the exports and bracketed on the plot");
+            let min_changed = energy_unit.drag_value(ui, &mut valid_min).changed();
+            ui.label("to");
+            let max_changed = energy_unit.drag_value(ui, &mut valid_max).changed();
+
+            // the data range shown while unset is only stored once the user edits it, so the range
+            // keeps following the data until then
+            if ui.button("Data Range").clicked() {
+                self.valid_min = 0.0;
+                self.valid_max = 0.0;
+            } else if min_changed || max_changed {
+                self.valid_min = valid_min;
+                self.valid_max = valid_max;
+            }
+        });
+    }
+
+    pub fn fit(&mut self, model: Model, constraints: &[ParameterConstraint]) {
+        self.fit_params = None;
+        self.fit_result = None;
+        self.fit_line.name = format!("{} Fit", model.fit_model().name());
+        self.upper_uncertainity_points = Vec::new();
+        self.lower_uncertainity_points = Vec::new();
+
+        self.fit_error = None;
+
+        let (x_data, y_data, weights) = self.fit_data();
+
+        let mut result = match model.fit_model().fit_constrained(
+            constraints,
+            &x_data,
+            &y_data,
+            &weights,
+            &self.solver,
+        ) {
+            Ok(result) => result,
+            Err(message) => {
+                self.fit_failed(message);
+                return;
+            }
+        };
+
+        if self.has_energy_uncertainties() || self.loss != Loss::Linear {
+            for _ in 0..REWEIGHT_ITERATIONS {
+                let seeded = model.seeded(&result.parameters());
+                let weights = self.iteration_weights(seeded.fit_model(), &result.parameters());
+
+                match seeded.fit_model().fit_constrained(
+                    constraints,
+                    &x_data,
+                    &y_data,
+                    &weights,
+                    &self.solver,
+                ) {
+                    Ok(refit) => result = refit,
+                    Err(message) => {
+                        self.fit_failed(format!("Reweighted refit failed: {}", message));
+                        return;
+                    }
+                }
+            }
+        }
+
+        result.loss = self.loss;
+        result.provenance = Some(FitProvenance::new(
+            &model,
+            constraints,
+            self,
+            result.fitted_at,
+        ));
+
+        result.log_info_result();
+
+        let parameters: Vec<String> = model
+            .fit_model()
+            .parameter_names()
+            .iter()
+            .zip(
+                result
+                    .parameters()
+                    .iter()
+                    .zip(result.uncertainties().iter()),
+            )
+            .map(|(name, (value, uncertainty))| {
+                format!("{} = {:.2} ± {:.2}", name, value, uncertainty)
+            })
+            .collect();
+
+        log::info!(
+            "{}: {}\n",
+            model.fit_model().formula(),
+            parameters.join(", ")
+        );
+
+        // the (amplitude, decay) pairs are still saved so older versions can read exponential fits
+        if let Model::Exponential(_) = &model {
+            self.fit_params = Some(result.exponential_terms());
+        }
+
+        self.fit_result = Some(result);
+        self.model = Some(model);
+        self.update_fit_line();
+        self.sanity_check();
+    }
+
+    pub fn is_fixed(&self, name: &str) -> bool {
+        self.fit_result
+            .as_ref()
+            .is_some_and(|result| result.fixed_parameters.iter().any(|fixed| fixed == name))
+    }
+
+    pub fn is_fitted(&self) -> bool {
+        self.model.is_some() && self.fit_result.is_some()
+    }
+
+    // (name, value, uncertainty) for every parameter of the fitted model
+    pub fn parameters(&self) -> Vec<(String, f64, f64)> {
+        let scale = self.covariance_factor().sqrt();
+
+        match (&self.model, &self.fit_result) {
+            (Some(model), Some(result)) => model
+                .fit_model()
+                .parameter_names()
+                .into_iter()
+                .zip(result.parameters())
+                .zip(result.uncertainties())
+                .map(|((name, value), uncertainty)| (name, value, scale * uncertainty))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // parameter covariance (row major, covariance order) with the same scaling as `parameters`
+    pub fn covariance(&self) -> Vec<f64> {
+        let factor = self.covariance_factor();
+        self.fit_result.as_ref().map_or(Vec::new(), |result| {
+            result
+                .covariance_matrix
+                .iter()
+                .map(|value| value * factor)
+                .collect()
+        })
+    }
+
+    // the fit as a GUI-free curve, None before the first fit
+    pub fn curve(&self, detector: &str, fit_name: &str) -> Option<EfficiencyCurve> {
+        let model = match self.model.as_ref()? {
+            Model::Exponential(model) => CurveModel::Exponential {
+                terms: model.terms(),
+            },
+            Model::LogPolynomial(model) => CurveModel::LogPolynomial {
+                order: model.order,
+                reference_energy: model.reference_energy,
+            },
+            Model::Formula(model) => CurveModel::Formula {
+                expression: model.expression.clone(),
+            },
+            Model::Spline(model) => CurveModel::Spline {
+                knots: model.knots.clone(),
+            },
+        };
+
+        let mut curve = EfficiencyCurve::new(
+            detector,
+            fit_name,
+            model,
+            self.fit_result.as_ref()?.parameters(),
+            self.covariance(),
+            self.valid_range(),
+        );
+        curve.energy_times_efficiency = self.energy_times_efficiency;
+        curve.normalization = self.normalization;
+        Some(curve)
+    }
+
+    // fits saved before the model was stored are single or double exponentials
+    pub fn migrate_model(&mut self) {
+        if self.model.is_none() {
+            if let Some(result) = &self.fit_result {
+                self.model = Some(Model::Exponential(Exponential::new(
+                    result.nonlinear_parameters.clone(),
+                )));
+            }
+        }
+    }
+
+    pub fn evaluate(&self, x: f64) -> f64 {
+        match &self.fit_result {
+            Some(result) => self.evaluate_with(x, &result.parameters()),
+            None => 0.0,
+        }
+    }
+
+    // efficiency at x for any parameter set of the model
+    fn evaluate_with(&self, x: f64, parameters: &[f64]) -> f64 {
+        let y = match &self.model {
+            Some(model) => model.fit_model().evaluate(x, parameters),
+            None => 0.0,
+        } * self
+            .normalization
+            .map_or(1.0, |normalization| normalization.value);
+
+        if self.energy_times_efficiency {
+            // eff(E) = f(E) / E is undefined at zero energy
+            if x > 0.0 {
+                y / x
+            } else {
+                0.0
+            }
+        } else {
+            y
+        }
+    }
+
+    // Energies in the data range where the fitted curve crosses `target`. The range is scanned for
+    // sign changes and each bracket is refined by bisection, so every crossing is returned. Each
+    // step covers (low, high], so a crossing exactly on a step is counted once
+    pub fn energies_at_efficiency(&self, target: f64) -> Vec<f64> {
+        if !self.is_fitted() || self.x.is_empty() {
+            return Vec::new();
+        }
+
+        let min_x = self.x.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+        let max_x = self.x.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+
+        let num_steps = 1000;
+        let step = (max_x - min_x) / num_steps as f64;
+        let difference = |x: f64| self.evaluate(x) - target;
+
+        let mut crossings = Vec::new();
+        if difference(min_x) == 0.0 {
+            crossings.push(min_x);
+        }
+
+        for i in 0..num_steps {
+            let mut low = min_x + i as f64 * step;
+            let mut high = low + step;
+            let (low_difference, high_difference) = (difference(low), difference(high));
+
+            if high_difference == 0.0 {
+                crossings.push(high);
+                continue;
+            }
+
+            // a zero at low was the previous step's high
+            if low_difference == 0.0 || (low_difference < 0.0) == (high_difference < 0.0) {
+                continue;
+            }
+
+            let below = low_difference < 0.0;
+            for _ in 0..60 {
+                let mid = 0.5 * (low + high);
+                if (difference(mid) < 0.0) == below {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+
+            crossings.push(0.5 * (low + high));
+        }
+
+        crossings
+    }
+
+    // observations and weights handed to the solver, E * eff(E) has an uncertainty of E * sigma
+    // exponential models start from the best of the multi-start grid, other models are unchanged
+    pub fn multi_start(&self, model: Model, constraints: &[ParameterConstraint]) -> Model {
+        match &model {
+            Model::Exponential(exponential) => {
+                match self.multi_start_decays(&exponential.initial_decays, constraints) {
+                    Some(decays) => Model::Exponential(Exponential::new(decays)),
+                    None => model,
+                }
+            }
+            _ => model,
+        }
+    }
+
+    // Fits from every combination of the b and d guesses scaled by `MULTI_START_FACTORS` and
+    // returns the decay constants of the lowest reduced χ² solution. A single start often lands
+    // the double exponential in a degenerate minimum
+    fn multi_start_decays(
+        &self,
+        decays: &[f64],
+        constraints: &[ParameterConstraint],
+    ) -> Option<Vec<f64>> {
+        let (x_data, y_data, weights) = self.fit_data();
+
+        let d_factors: &[f64] = if decays.len() > 1 {
+            &MULTI_START_FACTORS
+        } else {
+            &[1.0]
+        };
+
+        let mut best: Option<(f64, Vec<f64>)> = None;
+        let mut converged = 0;
+        for b_factor in MULTI_START_FACTORS {
+            for d_factor in d_factors {
+                let mut start = decays.to_vec();
+                start[0] *= b_factor;
+                if let Some(d) = start.get_mut(1) {
+                    *d *= d_factor;
+                }
+
+                // the terms are ordered, a start with b past d is a relabelling of another one
+                if start.len() > 1 && start[0] >= start[1] {
+                    continue;
+                }
+
+                let model = Exponential::new(start);
+                let result = match model.fit_constrained(
+                    constraints,
+                    &x_data,
+                    &y_data,
+                    &weights,
+                    &self.solver,
+                ) {
+                    Ok(result) => result,
+                    Err(_) => continue,
+                };
+
+                let chi_squared = result.reduced_chi_squared;
+                if !chi_squared.is_finite() || result.parameters().iter().any(|p| !p.is_finite()) {
+                    continue;
+                }
+
+                converged += 1;
+                if best.as_ref().map_or(true, |(best, _)| chi_squared < *best) {
+                    best = Some((chi_squared, result.nonlinear_parameters.clone()));
+                }
+            }
+        }
+
+        match &best {
+            Some((chi_squared, decays)) => log::info!(
+                "Multi-start: {} starts converged, best reduced χ² {:.3} at {:?}",
+                converged,
+                chi_squared,
+                decays
+            ),
+            None => log::warn!("No multi-start fit converged"),
+        }
+
+        best.map(|(_, decays)| decays)
+    }
+
+    // weights for the next reweighted refit around `params`, effective variance first and then the
+    // robust loss factor from the pulls with those weights
+    fn iteration_weights(&self, model: &dyn FitModel, params: &[f64]) -> DVector<f64> {
+        let (x_data, y_data, weights) = self.fit_data();
+
+        let mut weights = if self.has_energy_uncertainties() {
+            self.effective_weights(model, params)
+        } else {
+            weights
+        };
+
+        if self.loss != Loss::Linear {
+            for i in 0..weights.len() {
+                let pull = (y_data[i] - model.evaluate(x_data[i], params)) * weights[i];
+                weights[i] *= self.loss.weight_factor(pull).sqrt();
+            }
+        }
+
+        weights
+    }
+
+    fn has_energy_uncertainties(&self) -> bool {
+        self.energy_uncertainties.len() == self.x.len()
+            && self.energy_uncertainties.iter().any(|&sigma| sigma > 0.0)
+    }
+
+    // Effective variance weights (Orear): the energy uncertainty is projected onto the efficiency
+    // axis through the slope of the current curve, 1/w² = σy² + (f'(E) σE)². Refitting with these
+    // until they settle approximates orthogonal distance regression
+    fn effective_weights(&self, model: &dyn FitModel, params: &[f64]) -> DVector<f64> {
+        let (x_data, _, weights) = self.fit_data();
+
+        DVector::from_iterator(
+            x_data.len(),
+            x_data
+                .iter()
+                .zip(weights.iter())
+                .zip(self.energy_uncertainties.iter())
+                .map(|((&x, &w), &sigma_x)| {
+                    if sigma_x <= 0.0 {
+                        return w;
+                    }
+
+                    let step = sigma_x.max(1e-3);
+                    let slope = (model.evaluate(x + step, params)
+                        - model.evaluate(x - step, params))
+                        / (2.0 * step);
+                    let variance = (1.0 / w).powi(2) + (slope * sigma_x).powi(2);
+
+                    if variance.is_finite() && variance > 0.0 {
+                        1.0 / variance.sqrt()
+                    } else {
+                        w
+                    }
+                }),
+        )
+    }
+
+    fn fit_data(&self) -> (DVector<f64>, DVector<f64>, DVector<f64>) {
+        let x_data = DVector::from_vec(self.x.clone());
+        let mut y_data = DVector::from_vec(self.y.clone());
+        let mut weights = DVector::from_vec(self.weights.clone());
+
+        if let Some(normalization) = &self.normalization {
+            y_data /= normalization.value;
+            weights *= normalization.value;
+        }
+
+        if self.energy_times_efficiency {
+            y_data = y_data.component_mul(&x_data);
+            weights = weights.component_div(&x_data);
+        }
+
+        (x_data, y_data, weights)
+    }
+
+    // `num_points` steps over the sampled range, which ends 1 MeV past the last data point unless
+    // an end is set
+    fn curve_energies(&self, num_points: usize) -> Vec<f64> {
+        let max_x = self.x.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+
+        let start = if self.energy_times_efficiency {
+            self.sampling.start.max(1.0)
+        } else {
+            self.sampling.start
+        };
+
+        let end = match self.sampling.end {
+            Some(end) if end > start => end,
+            _ => max_x.max(start) + 1000.0,
+        };
+
+        let step = (end - start) / num_points as f64;
+
+        (0..=num_points).map(|i| start + i as f64 * step).collect()
+    }
+
+    pub fn update_fit_line(&mut self) {
+        let energies = self.curve_energies(self.sampling.points.max(1));
+
+        // followed lmfits implementation for the confidence band
+        let efficiencies = self.efficiencies(&energies, 1.0);
+
+        let fit_points: Vec<[f64; 2]> = energies
+            .iter()
+            .zip(efficiencies.iter())
+            .map(|(&x, &(y, _))| [x, y])
+            .collect();
+
+        let confidence_band: Vec<[f64; 2]> = energies
+            .iter()
+            .zip(efficiencies.iter())
+            .map(|(&x, &(_, uncertainty))| [x, uncertainty])
+            .collect();
+
+        let lower_points: Vec<[f64; 2]> = fit_points
+            .iter()
+            .zip(confidence_band.iter())
+            .map(|(fit_point, confidence_point)| [fit_point[0], fit_point[1] - confidence_point[1]])
+            .collect();
+
+        let upper_points: Vec<[f64; 2]> = fit_points
+            .iter()
+            .zip(confidence_band.iter())
+            .map(|(fit_point, confidence_point)| [fit_point[0], fit_point[1] + confidence_point[1]])
+            .collect();
+
+        // the sampled band is not symmetric about the curve, so draw its percentiles directly
+        let (lower_points, upper_points) = if self.band_method == BandMethod::MonteCarlo {
+            energies
+                .iter()
+                .zip(lower_points.iter().zip(upper_points.iter()))
+                .map(|(&x, (&lower, &upper))| match self.monte_carlo_bounds(x) {
+                    Some((low, high)) => ([x, low], [x, high]),
+                    None => (lower, upper),
+                })
+                .unzip()
+        } else {
+            (lower_points, upper_points)
+        };
+
+        (self.upper_prediction_points, self.lower_prediction_points) = if self.prediction_band {
+            energies
+                .iter()
+                .zip(fit_points.iter())
+                .map(|(&x, point)| {
+                    let half_width = self.prediction_uncertainty(x, 1.0);
+                    ([x, point[1] + half_width], [x, point[1] - half_width])
+                })
+                .unzip()
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        self.fit_line.points = fit_points;
+        self.upper_uncertainity_points = upper_points;
+        self.lower_uncertainity_points = lower_points;
+    }
+
+    pub fn draw(
+        &self,
+        plot_ui: &mut PlotUi,
+        band_decimation: usize,
+        low_energy_clamp: Option<f64>,
+        low_energy_cutoff: Option<f64>,
+    ) {
+        // Display only: below the lowest data point the exponential keeps rising toward E = 0, so
+        // the drawn curve and band can be capped there. The fit and exports are untouched.
+        // Below the detector threshold the curve is drawn at zero
+        let min_x = self.x.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+        let clamp = |points: &[[f64; 2]]| -> Vec<[f64; 2]> {
+            points
+                .iter()
+                .map(|&[x, y]| match (low_energy_cutoff, low_energy_clamp) {
+                    (Some(cutoff), _) if x < cutoff => [x, 0.0],
+                    (_, Some(max)) if x < min_x => [x, y.min(max)],
+                    _ => [x, y],
+                })
+                .collect()
+        };
+
+        let name = match low_energy_clamp {
+            Some(max) => format!(
+                "{} (≤ {:.1}% below {:.0} keV)",
+                self.fit_line.name, max, min_x
+            ),
+            None => self.fit_line.name.clone(),
+        };
+
+        // convert the fit line points to PlotPoints
+        if low_energy_clamp.is_some() || low_energy_cutoff.is_some() {
+            let mut fit_line = self.fit_line.clone();
+            fit_line.points = clamp(&self.fit_line.points);
+            fit_line.name.clone_from(&name);
+            fit_line.draw(plot_ui);
+        } else {
+            self.fit_line.draw(plot_ui);
+        }
+
+        if self.fit_line.draw {
+            // bracket the energy span the fit is trusted in
+            let (valid_min, valid_max) = self.valid_range();
+            if valid_max > valid_min {
+                for energy in [valid_min, valid_max] {
+                    plot_ui.vline(
+                        egui_plot::VLine::new(energy)
+                            .color(self.fit_line.color)
+                            .style(egui_plot::LineStyle::dotted_loose())
+                            .name(name.clone()),
+                    );
+                }
+            }
+
+            if self.prediction_band {
+                for points in [&self.upper_prediction_points, &self.lower_prediction_points] {
+                    plot_ui.line(
+                        egui_plot::Line::new(PlotPoints::new(clamp(points)))
+                            .color(self.fit_line.color)
+                            .style(egui_plot::LineStyle::dashed_dense())
+                            .name(name.clone()),
+                    );
+                }
+            }
+
+            // convert the upper uncertainity points to PlotPoints
+            let upper_uncertainity_plot_points: Vec<PlotPoint> =
+                decimate(&clamp(&self.upper_uncertainity_points), band_decimation)
+                    .iter()
+                    .map(|[x, y]| PlotPoint::new(*x, *y))
+                    .collect();
+            let lower_uncertainity_plot_points: Vec<PlotPoint> =
+                decimate(&clamp(&self.lower_uncertainity_points), band_decimation)
+                    .iter()
+                    .map(|[x, y]| PlotPoint::new(*x, *y))
+                    .collect();
+
+            // egui only supports convex polygons so i need to split the polygon into multiple.
+            // So each polygon will be the two points in the upper and two in the lower
+            // then the next polygon will be the next two points in the upper and lower
+            // and so on
+
+            // check is number of points is the greater than 4
+            if upper_uncertainity_plot_points.len() < 2 {
+                return;
+            }
+
+            let num_points = upper_uncertainity_plot_points.len() - 1;
+            let mut polygons: Vec<Vec<PlotPoint>> = Vec::new();
+            for i in 0..num_points {
+                let polygon = vec![
+                    upper_uncertainity_plot_points[i],
+                    upper_uncertainity_plot_points[i + 1],
+                    lower_uncertainity_plot_points[i + 1],
+                    lower_uncertainity_plot_points[i],
+                ];
+                polygons.push(polygon);
+            }
+
+            for points in polygons.iter() {
+                let uncertainity_band = Polygon::new(PlotPoints::Owned(points.clone()))
+                    .stroke(egui::Stroke::new(0.0, self.fit_line.color))
+                    .highlight(false)
+                    .width(0.0)
+                    .name(name.clone());
+
+                plot_ui.polygon(uncertainity_band);
+            }
+        }
+    }
+
+    pub fn points_csv(&self, energy_unit: EnergyUnit) -> String {
+        let mut csv = String::new();
+        csv.push_str(&format!(
+            "X [{}],Y [%],Uncertainty [% abs],Valid\n",
+            energy_unit.label()
+        ));
+
+        let (valid_min, valid_max) = self.valid_range();
+
+        for (fit_point, upper_point) in self
+            .fit_line
+            .points
+            .iter()
+            .zip(self.upper_uncertainity_points.iter())
+        {
+            let uncertainty = upper_point[1] - fit_point[1];
+            let valid = fit_point[0] >= valid_min && fit_point[0] <= valid_max;
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                energy_unit.scale_from_kev(fit_point[0]),
+                fit_point[1],
+                uncertainty,
+                valid
+            ));
+        }
+
+        csv
+    }
+
+    pub fn menu_button(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        ui.horizontal(|ui| {
+            if ui
+                .button("📋")
+                .on_hover_text(format!(
+                    "Copy data to clipboard (CSV format)\nX [{}],Y [%],Uncertainty [% abs],Valid",
+                    energy_unit.label()
+                ))
+                .clicked()
+            {
+                let csv = self.points_csv(energy_unit);
+                ui.output_mut(|o| o.copied_text = csv);
+            }
+
+            self.fit_line.menu_button(ui);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::efficiency_fitter::fit_model::LogPolynomial;
+    use statrs::distribution::ContinuousCDF;
+    use std::f64::consts::SQRT_2;
+
+    // ln(eff) quadratic in ln(E / 1 MeV), E * eff(E) has the same form so both fits are exact
+    pub(super) fn clean_data() -> ExpFitter {
+        let x: Vec<f64> = vec![121.8, 244.7, 344.3, 443.9, 778.9, 964.1, 1112.1, 1408.0];
+        let y: Vec<f64> = x
+            .iter()
+            .map(|energy| {
+                let u = (energy / 1000.0f64).ln();
+                (0.5 - 0.7 * u - 0.1 * u * u).exp()
+            })
+            .collect();
+        let weights: Vec<f64> = y
+            .iter()
+            .map(|efficiency| 1.0 / (0.02 * efficiency))
+            .collect();
+
+        ExpFitter::new(x, y, weights)
+    }
+
+    pub(super) fn log_polynomial() -> Model {
+        Model::LogPolynomial(LogPolynomial {
+            order: 2,
+            reference_energy: 1000.0,
+        })
+    }
+
+    #[test]
+    fn energy_times_efficiency_recovers_the_direct_fit() {
+        let mut direct = clean_data();
+        direct.fit(log_polynomial(), &[]);
+
+        let mut flattened = clean_data();
+        flattened.energy_times_efficiency = true;
+        flattened.fit(log_polynomial(), &[]);
+
+        assert!(direct.is_fitted() && flattened.is_fitted());
+
+        for energy in [150.0, 300.0, 661.7, 1000.0, 1332.5] {
+            let expected = direct.evaluate(energy);
+            let recovered = flattened.evaluate(energy);
+            assert!(
+                ((recovered - expected) / expected).abs() < 1e-6,
+                "{} keV: {} vs {}",
+                energy,
+                recovered,
+                expected
+            );
+        }
+    }
+
+    // one point of a detector in a global single exponential fit, a ± 0.5 with b fixed by the
+    // other detectors
+    fn global_detector(degrees_of_freedom: Option<f64>) -> ExpFitter {
+        let mut fit = ExpFitter::new(vec![300.0], vec![5.0], vec![1.0]);
+        fit.model = Some(Model::Exponential(Exponential::new(vec![400.0])));
+        fit.fit_result = Some(FitResult {
+            linear_parameters: vec![10.0],
+            linear_variances: vec![0.25],
+            nonlinear_parameters: vec![400.0],
+            nonlinear_variances: vec![0.0],
+            covariance_matrix: vec![0.25, 0.0, 0.0, 0.0],
+            correlation_matrix: vec![1.0, 0.0, 0.0, 0.0],
+            reduced_chi_squared: 1.0,
+            regression_standard_error: 1.0,
+            weighted_residuals: vec![0.0],
+            degrees_of_freedom,
+            ..Default::default()
+        });
+        fit
+    }
+
+    #[test]
+    fn detector_with_fewer_points_than_parameters_uses_the_global_dof() {
+        // its own 1 - 2 degrees of freedom have no t-distribution
+        assert_eq!(global_detector(None).uncertainity(300.0, 1.0), 0.0);
+
+        let t_value = statrs::distribution::StudentsT::new(0.0, 1.0, 8.0)
+            .unwrap()
+            .inverse_cdf(1.0 - (1.0 - statrs::function::erf::erf(1.0 / SQRT_2)) / 2.0);
+        let expected = t_value * 0.5 * (-300.0f64 / 400.0).exp();
+
+        let uncertainty = global_detector(Some(8.0)).uncertainity(300.0, 1.0);
+        assert!(
+            (uncertainty - expected).abs() < 1e-9,
+            "{} vs {}",
+            uncertainty,
+            expected
+        );
+    }
+
+    #[test]
+    fn crossing_exactly_on_a_step_is_counted_once() {
+        // rising curve 10 exp(x / 400) over 100 to 1100 keV, so the scan steps by exactly 1 keV
+        let mut fit = ExpFitter::new(vec![100.0, 1100.0], vec![1.0, 1.0], vec![1.0, 1.0]);
+        fit.model = Some(Model::Exponential(Exponential::new(vec![-400.0])));
+        fit.fit_result = Some(FitResult {
+            linear_parameters: vec![10.0],
+            nonlinear_parameters: vec![-400.0],
+            ..Default::default()
+        });
+
+        assert_eq!(fit.energies_at_efficiency(fit.evaluate(600.0)), vec![600.0]);
+        assert_eq!(fit.energies_at_efficiency(fit.evaluate(100.0)), vec![100.0]);
+
+        let crossings = fit.energies_at_efficiency(fit.evaluate(432.1));
+        assert_eq!(crossings.len(), 1);
+        assert!((crossings[0] - 432.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn scale_factor_does_not_compound() {
+        let mut fit = clean_data();
+        fit.fit(log_polynomial(), &[]);
+        let mut result = fit.fit_result.unwrap();
+
+        result.reduced_chi_squared = 4.0;
+        result.provenance.as_mut().unwrap().error_scale = 1.0;
+        assert_eq!(pdg_scale_factor(&result), Some(2.0));
+
+        // refitting with the errors doubled quarters the reduced chi-square, the factor stays 2
+        result.reduced_chi_squared = 1.0;
+        result.provenance.as_mut().unwrap().error_scale = 2.0;
+        assert_eq!(pdg_scale_factor(&result), Some(2.0));
+
+        result.provenance = None;
+        result.reduced_chi_squared = 0.0;
+        assert_eq!(pdg_scale_factor(&result), None);
+    }
+}
@@ -0,0 +1,529 @@
+use super::{ExpFitter, FitResult, ParameterConstraint};
+use nalgebra::{DMatrix, DVector};
+use rand::distributions::Distribution;
+use rand::Rng;
+use statrs::distribution::ContinuousCDF;
+use std::f64::consts::SQRT_2;
+
+// parameter draws and energies for the Monte Carlo band, the drawn band is interpolated between
+const MONTE_CARLO_DRAWS: usize = 10_000;
+const MONTE_CARLO_ENERGIES: usize = 200;
+
+// how the confidence band and the fit uncertainties are computed
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum BandMethod {
+    #[default]
+    Linearized, // Student-t scaled error propagation, like lmfit
+    MonteCarlo, // 1 sigma percentiles of curves drawn from the parameter covariance
+}
+
+impl BandMethod {
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.radio_value(self, BandMethod::Linearized, "Linearized")
+            .on_hover_text(
+                "Propagate the covariance to first order and scale by the Student-t factor",
+            );
+        ui.radio_value(self, BandMethod::MonteCarlo, "Monte Carlo")
+            .on_hover_text(format!(
+                "Draw {} parameter sets from the covariance and use the 1σ percentiles of the curves. Catches the asymmetry the linear band misses",
+                MONTE_CARLO_DRAWS
+            ));
+    }
+}
+
+// (energy, 15.9th, 84.1th percentile) over sampled curves, the 1σ band without assuming the curves
+// are normally distributed. Draws that are not finite are left out
+pub fn percentile_band(energies: &[f64], curves: &[Vec<f64>]) -> Vec<[f64; 3]> {
+    let lower_quantile = 0.5 * (1.0 - statrs::function::erf::erf(1.0 / SQRT_2));
+
+    energies
+        .iter()
+        .enumerate()
+        .map(|(index, &energy)| {
+            let mut values: Vec<f64> = curves
+                .iter()
+                .filter_map(|curve| curve.get(index).copied())
+                .filter(|value| value.is_finite())
+                .collect();
+            values.sort_by(|a, b| a.total_cmp(b));
+
+            let percentile = |quantile: f64| {
+                if values.is_empty() {
+                    return 0.0;
+                }
+                let index = (quantile * (values.len() - 1) as f64).round() as usize;
+                values[index]
+            };
+
+            [
+                energy,
+                percentile(lower_quantile),
+                percentile(1.0 - lower_quantile),
+            ]
+        })
+        .collect()
+}
+
+// sqrt(reduced chi-square) of the fit to the unscaled error bars. A result fitted with its errors
+// already scaled has chi-square smaller by the square of that scale, so pressing Scale Errors again
+// gives the same factor instead of compounding it
+pub(super) fn pdg_scale_factor(result: &FitResult) -> Option<f64> {
+    let fitted_scale = result
+        .provenance
+        .as_ref()
+        .map_or(1.0, |provenance| provenance.error_scale);
+    let scale_factor = fitted_scale * result.reduced_chi_squared.sqrt();
+
+    (scale_factor.is_finite() && scale_factor > 0.0).then_some(scale_factor)
+}
+
+impl ExpFitter {
+    pub fn uncertainity(&self, x: f64, sigma: f64) -> f64 {
+        // the sampled band already includes the normalization
+        if let Some(half_width) = self.monte_carlo_half_width(x) {
+            return sigma * half_width;
+        }
+
+        self.t_value(sigma)
+            .map_or(0.0, |t_value| self.band_half_width(x, t_value))
+    }
+
+    // (efficiency, uncertainty) at every energy. The t-value only depends on sigma, so it is
+    // computed once for the whole list instead of once per energy like `uncertainity`
+    pub fn efficiencies(&self, energies: &[f64], sigma: f64) -> Vec<(f64, f64)> {
+        let t_value = self.t_value(sigma);
+
+        energies
+            .iter()
+            .map(|&x| {
+                let uncertainty = match self.monte_carlo_half_width(x) {
+                    Some(half_width) => sigma * half_width,
+                    None => t_value.map_or(0.0, |t_value| self.band_half_width(x, t_value)),
+                };
+                (self.evaluate(x), uncertainty)
+            })
+            .collect()
+    }
+
+    fn band_half_width(&self, x: f64, t_value: f64) -> f64 {
+        t_value
+            * (self.model_standard_error(x) * self.fit_space_scale(x))
+                .hypot(self.normalization_error(x))
+    }
+
+    // Factor from the fitted function to the efficiency at x: the band was fit on E * eff(E) or on
+    // the relative efficiency, so it is scaled back like the curve
+    pub(super) fn fit_space_scale(&self, x: f64) -> f64 {
+        let scale = self
+            .normalization
+            .map_or(1.0, |normalization| normalization.value);
+
+        if self.energy_times_efficiency {
+            if x > 0.0 {
+                scale / x
+            } else {
+                0.0
+            }
+        } else {
+            scale
+        }
+    }
+
+    // 1 sigma spread of the curve at x from the normalization of a relative fit alone
+    fn normalization_error(&self, x: f64) -> f64 {
+        self.normalization.map_or(0.0, |normalization| {
+            self.evaluate(x) * normalization.relative_uncertainty()
+        })
+    }
+
+    // Half width of the prediction interval at x: the spread of a new measurement around the curve,
+    // not only of the curve itself. The scatter of a new point is its expected uncertainty
+    // (interpolated from the fitted points) scaled by the regression standard error
+    pub fn prediction_uncertainty(&self, x: f64, sigma: f64) -> f64 {
+        let (t_value, result) = match (self.t_value(sigma), &self.fit_result) {
+            (Some(t_value), Some(result)) => (t_value, result),
+            _ => return 0.0,
+        };
+
+        let scatter = result.regression_standard_error * self.data_uncertainty(x);
+        let half_width = (self.model_standard_error(x).powi(2) + scatter.powi(2)).sqrt();
+
+        t_value * (half_width * self.fit_space_scale(x)).hypot(self.normalization_error(x))
+    }
+
+    // 1 / weight of the fitted points linearly interpolated to x (in the fit space), held constant
+    // past the first and last point
+    fn data_uncertainty(&self, x: f64) -> f64 {
+        let (x_data, _, weights) = self.fit_data();
+
+        let mut points: Vec<(f64, f64)> = x_data
+            .iter()
+            .zip(weights.iter())
+            .filter(|(_, &w)| w > 0.0 && w.is_finite())
+            .map(|(&x, &w)| (x, 1.0 / w))
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let (first, last) = match (points.first(), points.last()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return 0.0,
+        };
+
+        if x <= first.0 {
+            return first.1;
+        }
+        if x >= last.0 {
+            return last.1;
+        }
+
+        let index = points.partition_point(|point| point.0 <= x).max(1);
+        let (low, high) = (points[index - 1], points[index]);
+        if high.0 == low.0 {
+            return low.1;
+        }
+
+        low.1 + (x - low.0) / (high.0 - low.0) * (high.1 - low.1)
+    }
+
+    // two-tailed Student's t factor for a `sigma` wide confidence interval
+    fn t_value(&self, sigma: f64) -> Option<f64> {
+        let result = self.fit_result.as_ref()?;
+
+        // guess previews have no covariance to scale
+        if result.covariance_matrix.is_empty() {
+            return None;
+        }
+
+        let dof = result.degrees_of_freedom(self.x.len());
+
+        let prob = statrs::function::erf::erf(sigma / SQRT_2); // 1 sigma probability (0.682689492137)
+
+        let alpha = 1.0 - prob; // significance level
+
+        // we want the two-tailed t-value t_alpha/2,dof... this will be the scale factor for the confidence interval
+        match statrs::distribution::StudentsT::new(0.0, 1.0, dof) {
+            Ok(dist) => Some(dist.inverse_cdf(1.0 - alpha / 2.0)),
+            Err(e) => {
+                log::error!("Error creating StudentsT distribution: {:?}", e);
+                None
+            }
+        }
+    }
+
+    // standard error of the model at x propagated from the parameter covariance. The stored
+    // covariance already includes the reduced chi-square, `covariance_factor` takes it out when
+    // scaling is off
+    fn model_standard_error(&self, x: f64) -> f64 {
+        let (model, result) = match (&self.model, &self.fit_result) {
+            (Some(model), Some(result)) => (model, result),
+            _ => return 0.0,
+        };
+
+        let gradient = model.fit_model().gradient(x, &result.parameters());
+        let n_parameters = gradient.len();
+        let cov = &result.covariance_matrix;
+
+        if cov.len() != n_parameters * n_parameters {
+            return 0.0;
+        }
+
+        let variance: f64 = (0..n_parameters)
+            .flat_map(|i| (0..n_parameters).map(move |j| (i, j)))
+            .map(|(i, j)| gradient[i] * gradient[j] * cov[i * n_parameters + j])
+            .sum();
+
+        (variance * self.covariance_factor()).sqrt()
+    }
+
+    // Refits the model to the data resampled with replacement and stores the spread of each
+    // parameter in the fit result. Refits that fail, e.g. too few distinct energies, are skipped
+    pub fn bootstrap(&mut self, samples: usize, constraints: &[ParameterConstraint]) {
+        let model = match (&self.model, &self.fit_result) {
+            (Some(model), Some(result)) => model.seeded(&result.parameters()),
+            _ => return,
+        };
+
+        let (x_data, y_data, weights) = self.fit_data();
+        let n_points = x_data.len();
+        let mut rng = rand::thread_rng();
+
+        let mut draws: Vec<Vec<f64>> = Vec::new();
+        for _ in 0..samples {
+            let indices: Vec<usize> = (0..n_points).map(|_| rng.gen_range(0..n_points)).collect();
+            let resample = |data: &DVector<f64>| {
+                DVector::from_iterator(n_points, indices.iter().map(|&i| data[i]))
+            };
+
+            if let Ok(result) = model.fit_model().fit_constrained(
+                constraints,
+                &resample(&x_data),
+                &resample(&y_data),
+                &resample(&weights),
+                &self.solver,
+            ) {
+                let parameters = result.parameters();
+                if parameters.iter().all(|value| value.is_finite()) {
+                    draws.push(parameters);
+                }
+            }
+        }
+
+        let spread: Vec<f64> = if draws.len() > 1 {
+            let count = draws.len() as f64;
+            (0..draws[0].len())
+                .map(|k| {
+                    let mean = draws.iter().map(|draw| draw[k]).sum::<f64>() / count;
+                    let variance = draws
+                        .iter()
+                        .map(|draw| (draw[k] - mean).powi(2))
+                        .sum::<f64>()
+                        / (count - 1.0);
+                    variance.sqrt()
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        log::info!(
+            "Bootstrap: {} of {} refits converged, parameter spread {:?}",
+            draws.len(),
+            samples,
+            spread
+        );
+
+        if spread.is_empty() {
+            self.fit_failed(format!(
+                "Bootstrap failed: only {} of {} refits converged",
+                draws.len(),
+                samples
+            ));
+        }
+
+        if let Some(result) = &mut self.fit_result {
+            result.bootstrap_uncertainties = spread;
+            result.bootstrap_samples = draws.len();
+        }
+    }
+
+    pub fn bootstrap_uncertainties(&self) -> &[f64] {
+        self.fit_result
+            .as_ref()
+            .map_or(&[], |result| result.bootstrap_uncertainties.as_slice())
+    }
+
+    // multiplies the stored (χ² scaled) covariance before it is used for errors and bands
+    pub(super) fn covariance_factor(&self) -> f64 {
+        match &self.fit_result {
+            Some(result) if !self.scale_covariance => result.unscaled_covariance_factor(),
+            _ => 1.0,
+        }
+    }
+
+    // the uncertainties with the other covariance convention, shown next to the ones in use
+    pub fn alternate_uncertainties(&self) -> (&'static str, Vec<f64>) {
+        let result = match &self.fit_result {
+            Some(result) => result,
+            None => return ("", Vec::new()),
+        };
+
+        if self.scale_covariance {
+            let scale = result.unscaled_covariance_factor().sqrt();
+            let uncertainties = result.uncertainties().iter().map(|u| u * scale).collect();
+            ("unscaled", uncertainties)
+        } else {
+            ("χ² scaled", result.uncertainties())
+        }
+    }
+
+    pub fn set_scale_covariance(&mut self, scale_covariance: bool) {
+        if self.scale_covariance == scale_covariance {
+            return;
+        }
+
+        self.scale_covariance = scale_covariance;
+
+        // drawn from the old covariance, a job samples it again
+        if let Some(result) = &mut self.fit_result {
+            result.monte_carlo_band.clear();
+        }
+
+        if self.is_fitted() {
+            self.update_fit_line();
+        }
+    }
+
+    // energy uncertainty of a crossing from the width of the band and the local slope of the curve
+    pub fn energy_uncertainty_at(&self, energy: f64, sigma: f64) -> f64 {
+        let h = 1e-3 * energy.abs().max(1.0);
+        let slope = (self.evaluate(energy + h) - self.evaluate(energy - h)) / (2.0 * h);
+
+        if slope == 0.0 {
+            return f64::INFINITY;
+        }
+
+        self.uncertainity(energy, sigma) / slope.abs()
+    }
+
+    // Samples parameter sets from the fit covariance and stores the 1 sigma percentiles of the
+    // resulting curves in the fit result. The covariance is factored with an eigen decomposition
+    // so fixed parameters (zero variance) don't break it
+    pub fn sample_monte_carlo_band(&mut self) {
+        let energies = self.curve_energies(MONTE_CARLO_ENERGIES);
+        let curves = match self.sampled_curves(&energies, MONTE_CARLO_DRAWS) {
+            Some(curves) => curves,
+            None => return,
+        };
+
+        if let Some(result) = &mut self.fit_result {
+            result.monte_carlo_band = percentile_band(&energies, &curves);
+        }
+    }
+
+    // the efficiency at `energies` for `draws` parameter sets drawn from the covariance, one curve
+    // per draw. None without a fit or with a covariance that does not match its parameters
+    pub fn sampled_curves(&self, energies: &[f64], draws: usize) -> Option<Vec<Vec<f64>>> {
+        let result = match &self.fit_result {
+            Some(result) if self.model.is_some() => result,
+            _ => return None,
+        };
+
+        let mean = result.parameters();
+        let n_parameters = mean.len();
+        if result.covariance_matrix.len() != n_parameters * n_parameters {
+            return None;
+        }
+
+        let covariance =
+            DMatrix::from_column_slice(n_parameters, n_parameters, &result.covariance_matrix)
+                * self.covariance_factor();
+        let eigen = covariance.symmetric_eigen();
+        let scales = eigen.eigenvalues.map(|value| value.max(0.0).sqrt());
+        let factor = &eigen.eigenvectors * DMatrix::from_diagonal(&scales);
+
+        let normal = match statrs::distribution::Normal::new(0.0, 1.0) {
+            Ok(normal) => normal,
+            Err(e) => {
+                log::error!("Error creating Normal distribution: {:?}", e);
+                return None;
+            }
+        };
+        let mut rng = rand::thread_rng();
+
+        let curves = (0..draws)
+            .map(|_| {
+                let draw = DVector::from_fn(n_parameters, |_, _| normal.sample(&mut rng));
+                let offset = &factor * draw;
+                let parameters: Vec<f64> = mean
+                    .iter()
+                    .zip(offset.iter())
+                    .map(|(value, offset)| value + offset)
+                    .collect();
+
+                // the normalization of a relative fit is drawn on its own, it is not in the covariance
+                let scale = self.normalization.map_or(1.0, |normalization| {
+                    1.0 + normal.sample(&mut rng) * normalization.relative_uncertainty()
+                });
+
+                energies
+                    .iter()
+                    .map(|&energy| scale * self.evaluate_with(energy, &parameters))
+                    .collect()
+            })
+            .collect();
+
+        Some(curves)
+    }
+
+    // (lower, upper) of the Monte Carlo band at x, interpolated between the sampled energies. None
+    // when the linearized band is selected, nothing has been sampled or x is outside the sampled
+    // range, the band is not extrapolated
+    pub(super) fn monte_carlo_bounds(&self, x: f64) -> Option<(f64, f64)> {
+        if self.band_method != BandMethod::MonteCarlo {
+            return None;
+        }
+
+        let band = &self.fit_result.as_ref()?.monte_carlo_band;
+        let first = band.first()?;
+        let last = band.last()?;
+
+        if x < first[0] || x > last[0] {
+            return None;
+        }
+
+        if band.len() < 2 {
+            return Some((first[1], first[2]));
+        }
+
+        // x at the last sampled energy interpolates on the last interval
+        let index = band
+            .partition_point(|point| point[0] <= x)
+            .clamp(1, band.len() - 1);
+        let (low, high) = (band[index - 1], band[index]);
+        let fraction = (x - low[0]) / (high[0] - low[0]);
+
+        Some((
+            low[1] + fraction * (high[1] - low[1]),
+            low[2] + fraction * (high[2] - low[2]),
+        ))
+    }
+
+    fn monte_carlo_half_width(&self, x: f64) -> Option<f64> {
+        self.monte_carlo_bounds(x)
+            .map(|(lower, upper)| 0.5 * (upper - lower))
+    }
+
+    // whether the sampled band was drawn on the current curve grid, it goes stale when the sampled
+    // range or the data changes. Inside its range a stale band is still right, the curve falls
+    // back to the linearized band outside it until `Fitter::poll_job` resamples it
+    fn monte_carlo_band_is_current(&self) -> bool {
+        let band = match &self.fit_result {
+            Some(result) => &result.monte_carlo_band,
+            None => return false,
+        };
+        let energies = self.curve_energies(MONTE_CARLO_ENERGIES);
+
+        band.len() == energies.len()
+            && band
+                .iter()
+                .zip(energies.iter())
+                .all(|(point, &energy)| (point[0] - energy).abs() <= 1e-9 * energy.abs().max(1.0))
+    }
+
+    // the Monte Carlo band is selected but missing or drawn on another grid. Fits without a
+    // covariance to sample from never get one
+    pub fn needs_monte_carlo_band(&self) -> bool {
+        let has_covariance = self.model.is_some()
+            && self.fit_result.as_ref().is_some_and(|result| {
+                result.covariance_matrix.len() == result.parameters().len().pow(2)
+            });
+
+        self.band_method == BandMethod::MonteCarlo
+            && has_covariance
+            && !self.monte_carlo_band_is_current()
+    }
+
+    // switches the band method, the Monte Carlo band is sampled by a job when it is needed
+    pub fn set_band_method(&mut self, band_method: BandMethod) {
+        if self.band_method == band_method {
+            return;
+        }
+
+        self.band_method = band_method;
+
+        if self.is_fitted() {
+            self.update_fit_line();
+        }
+    }
+
+    pub fn prediction_band_ui(&mut self, ui: &mut egui::Ui) {
+        if ui
+            .checkbox(&mut self.prediction_band, "Prediction Band")
+            .on_hover_text("Dashed band where a new measurement of a line is expected to fall (1σ). Wider than the confidence band of the curve because it includes the scatter of the data")
+            .changed()
+            && self.is_fitted()
+        {
+            self.update_fit_line();
+        }
+    }
+}
@@ -0,0 +1,1515 @@
+use super::exp_fitter::{FitResult, SolverReport};
+
+use crate::curve::{self, ParsedFormula};
+use evalexpr::Node;
+use levenberg_marquardt::{LeastSquaresProblem, LevenbergMarquardt};
+use nalgebra::{DMatrix, DVector, Dyn, Owned};
+use std::sync::OnceLock;
+use varpro::model::builder::SeparableModelBuilder;
+use varpro::solvers::levmar::{LevMarProblemBuilder, LevMarSolver};
+
+// Levenberg-Marquardt settings for both varpro and `least_squares_fit`. The tolerances default to
+// the MINPACK values varpro uses
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SolverSettings {
+    pub ftol: f64, // relative decrease of χ² below which the fit has converged
+    pub xtol: f64, // relative parameter step below which the fit has converged
+    pub gtol: f64, // largest cosine between the residuals and a jacobian column at convergence
+    #[serde(alias = "max_iterations")]
+    pub patience: usize, // the solver allows this many evaluations per parameter
+    pub epsilon: f64, // relative step of numerical derivatives, for formula fits
+}
+
+impl Default for SolverSettings {
+    fn default() -> Self {
+        Self {
+            ftol: 1.49012e-8,
+            xtol: 1.49012e-8,
+            gtol: 0.0,
+            patience: 200,
+            epsilon: 1e-6,
+        }
+    }
+}
+
+impl SolverSettings {
+    pub fn levenberg_marquardt(&self) -> LevenbergMarquardt<f64> {
+        LevenbergMarquardt::new()
+            .with_ftol(self.ftol)
+            .with_xtol(self.xtol)
+            .with_gtol(self.gtol)
+            .with_patience(self.patience)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new(ui.id().with("solver_settings"))
+            .num_columns(2)
+            .show(ui, |ui| {
+                for (label, value, minimum, hover) in [
+                    ("ftol", &mut self.ftol, 0.0, "Converged once χ² drops by less than this fraction"),
+                    ("xtol", &mut self.xtol, 0.0, "Converged once the parameters move by less than this fraction"),
+                    ("gtol", &mut self.gtol, 0.0, "Converged once the residuals are this close to orthogonal to the jacobian, 0 turns the test off"),
+                    ("epsilon", &mut self.epsilon, 1e-12, "Relative step of the numerical derivatives of formula fits"),
+                ] {
+                    // drag in steps proportional to the value, the tolerances span many decades
+                    let speed = (*value * 0.05).max(1e-12);
+                    ui.label(label).on_hover_text(hover);
+                    ui.add(
+                        egui::DragValue::new(value)
+                            .speed(speed)
+                            .clamp_range(minimum..=1e-1)
+                            .custom_formatter(|v, _| format!("{:.2e}", v))
+                            .custom_parser(|text| text.parse().ok()),
+                    );
+                    ui.end_row();
+                }
+
+                ui.label("Patience").on_hover_text(
+                    "The solver gives up after this many model evaluations per parameter",
+                );
+                ui.add(egui::DragValue::new(&mut self.patience).clamp_range(1..=100_000));
+                ui.end_row();
+            });
+
+        if ui.button("Reset").clicked() {
+            *self = Self::default();
+        }
+    }
+}
+
+// An efficiency curve parameterization. Parameters are always passed in covariance order, the same
+// order as `FitResult::parameters`, so the confidence band and the exports work for any model
+pub trait FitModel {
+    fn name(&self) -> String;
+
+    fn formula(&self) -> String;
+
+    fn parameter_names(&self) -> Vec<String>;
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64;
+
+    // partial derivatives of the model with respect to each parameter at x
+    fn gradient(&self, x: f64, parameters: &[f64]) -> Vec<f64>;
+
+    // `gradient` with the relative step of numerical derivatives, analytic models ignore the step
+    fn gradient_with_step(&self, x: f64, parameters: &[f64], _step: f64) -> Vec<f64> {
+        self.gradient(x, parameters)
+    }
+
+    fn fit(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+        solver: &SolverSettings,
+    ) -> Result<FitResult, String>;
+
+    // model specific checks for parameters that are valid but physically meaningless
+    fn warnings(&self, _parameters: &[f64], _min_x: f64, _max_x: f64) -> Vec<String> {
+        Vec::new()
+    }
+
+    // number of leading parameters stored as `FitResult::linear_parameters`
+    fn linear_parameter_count(&self) -> usize {
+        0
+    }
+
+    // starting point for `least_squares_fit`, in covariance order
+    fn initial_parameters(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+    ) -> Result<Vec<f64>, String>;
+
+    // Fit with some parameters bounded or held fixed. Varpro can't constrain parameters, so a
+    // constrained fit always goes through `least_squares_fit`
+    fn fit_constrained(
+        &self,
+        constraints: &[ParameterConstraint],
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+        solver: &SolverSettings,
+    ) -> Result<FitResult, String> {
+        let names = self.parameter_names();
+        let transforms: Vec<Transform> = names
+            .iter()
+            .map(|name| {
+                constraints
+                    .iter()
+                    .find(|constraint| constraint.name == *name)
+                    .map_or(Transform::Free, ParameterConstraint::transform)
+            })
+            .collect();
+
+        if transforms
+            .iter()
+            .all(|transform| *transform == Transform::Free)
+        {
+            return self.fit(x_data, y_data, weights, solver);
+        }
+
+        let initial_parameters = self.initial_parameters(x_data, y_data, weights)?;
+        least_squares_fit(
+            self,
+            &initial_parameters,
+            &transforms,
+            x_data,
+            y_data,
+            weights,
+            solver,
+        )
+    }
+}
+
+// Per-parameter limits for a fit, matched to the model parameters by name. A fixed value wins over
+// the bounds
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ParameterConstraint {
+    pub name: String,
+    pub lower: Option<f64>,
+    pub upper: Option<f64>,
+    pub fixed: Option<f64>,
+}
+
+impl ParameterConstraint {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.lower.is_some() || self.upper.is_some() || self.fixed.is_some()
+    }
+
+    fn transform(&self) -> Transform {
+        match (self.fixed, self.lower, self.upper) {
+            (Some(value), _, _) => Transform::Fixed(value),
+            (None, Some(lower), Some(upper)) if upper > lower => Transform::Between(lower, upper),
+            (None, Some(lower), None) => Transform::Lower(lower),
+            (None, None, Some(upper)) => Transform::Upper(upper),
+            _ => Transform::Free,
+        }
+    }
+}
+
+// MINUIT style mapping between the bounded parameter p and an unbounded internal one u, so the
+// solver never steps outside the bounds. Fixed parameters are dropped from the solve
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transform {
+    Free,
+    Fixed(f64),
+    Lower(f64),
+    Upper(f64),
+    Between(f64, f64),
+}
+
+impl Transform {
+    fn external(&self, u: f64) -> f64 {
+        match *self {
+            Transform::Free => u,
+            Transform::Fixed(value) => value,
+            Transform::Lower(lower) => lower - 1.0 + (u * u + 1.0).sqrt(),
+            Transform::Upper(upper) => upper + 1.0 - (u * u + 1.0).sqrt(),
+            Transform::Between(lower, upper) => lower + (upper - lower) * (u.sin() + 1.0) / 2.0,
+        }
+    }
+
+    fn derivative(&self, u: f64) -> f64 {
+        match *self {
+            Transform::Free => 1.0,
+            Transform::Fixed(_) => 0.0,
+            Transform::Lower(_) => u / (u * u + 1.0).sqrt(),
+            Transform::Upper(_) => -u / (u * u + 1.0).sqrt(),
+            Transform::Between(lower, upper) => (upper - lower) * u.cos() / 2.0,
+        }
+    }
+
+    // inverse of `external`. Starting exactly on a bound has a zero derivative, so the start is
+    // moved slightly inside
+    fn internal(&self, p: f64) -> f64 {
+        let inside = |bound: f64| 1e-3 * bound.abs().max(1.0);
+
+        match *self {
+            Transform::Free | Transform::Fixed(_) => p,
+            Transform::Lower(lower) => {
+                let p = p.max(lower + inside(lower));
+                ((p - lower + 1.0).powi(2) - 1.0).sqrt()
+            }
+            Transform::Upper(upper) => {
+                let p = p.min(upper - inside(upper));
+                ((upper - p + 1.0).powi(2) - 1.0).sqrt()
+            }
+            Transform::Between(lower, upper) => {
+                let margin = 1e-3 * (upper - lower);
+                let p = p.clamp(lower + margin, upper - margin);
+                (2.0 * (p - lower) / (upper - lower) - 1.0).asin()
+            }
+        }
+    }
+}
+
+// Every model the fitter can use. The enum is what gets saved, `fit_model` hands out the trait
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum Model {
+    Exponential(Exponential),
+    LogPolynomial(LogPolynomial),
+    Formula(Formula),
+    Spline(Spline),
+}
+
+impl Model {
+    pub fn fit_model(&self) -> &dyn FitModel {
+        match self {
+            Model::Exponential(model) => model,
+            Model::LogPolynomial(model) => model,
+            Model::Formula(model) => model,
+            Model::Spline(model) => model,
+        }
+    }
+
+    // the same model with its initial guesses moved to `parameters`, for refits near a solution
+    pub fn seeded(&self, parameters: &[f64]) -> Model {
+        match self {
+            Model::Exponential(model) => Model::Exponential(Exponential::new(
+                parameters.get(model.terms()..).unwrap_or_default().to_vec(),
+            )),
+            Model::LogPolynomial(model) => Model::LogPolynomial(model.clone()),
+            Model::Spline(model) => Model::Spline(model.clone()),
+            Model::Formula(model) => {
+                let mut model = model.clone();
+                model.initial_guesses = model
+                    .parameter_names()
+                    .into_iter()
+                    .zip(parameters.iter().cloned())
+                    .collect();
+                Model::Formula(model)
+            }
+        }
+    }
+
+    // the guesses the solver starts from by parameter name, the linear models need none
+    pub fn initial_guesses(&self) -> Vec<(String, f64)> {
+        match self {
+            Model::Exponential(model) => (0..model.terms())
+                .map(Exponential::decay_name)
+                .zip(model.initial_decays.iter().cloned())
+                .collect(),
+            Model::LogPolynomial(_) | Model::Spline(_) => Vec::new(),
+            Model::Formula(model) => model
+                .parameter_names()
+                .into_iter()
+                .zip(model.initial_guesses())
+                .collect(),
+        }
+    }
+}
+
+// y = a exp(-x/b) + c exp(-x/d) + ..., one term per decay constant. The amplitudes are linear and
+// solved by varpro, only the decay constants need initial guesses
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Exponential {
+    pub initial_decays: Vec<f64>,
+}
+
+impl Exponential {
+    pub fn new(initial_decays: Vec<f64>) -> Self {
+        Self { initial_decays }
+    }
+
+    pub fn terms(&self) -> usize {
+        self.initial_decays.len()
+    }
+
+    // a, b for the first term, c, d for the second and so on
+    pub fn amplitude_name(term: usize) -> String {
+        ((b'a' + 2 * term as u8) as char).to_string()
+    }
+
+    pub fn decay_name(term: usize) -> String {
+        ((b'b' + 2 * term as u8) as char).to_string()
+    }
+
+    pub fn exponential(x: &DVector<f64>, b: f64) -> DVector<f64> {
+        x.map(|x_val| (-x_val / b).exp())
+    }
+
+    pub fn exponential_pd(x: &DVector<f64>, b: f64) -> DVector<f64> {
+        x.map(|x_val| (x_val / b.powi(2)) * (-x_val / b).exp())
+    }
+
+    // weighted least-squares amplitudes for the initial decay constants, the linear step varpro solves
+    pub fn linear_amplitudes(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+    ) -> Option<Vec<f64>> {
+        let decays = &self.initial_decays;
+        if x_data.len() < decays.len() || decays.iter().any(|decay| *decay <= 0.0) {
+            return None;
+        }
+
+        let basis = DMatrix::from_fn(x_data.len(), decays.len(), |i, k| {
+            weights[i] * (-x_data[i] / decays[k]).exp()
+        });
+        let observations = y_data.component_mul(weights);
+
+        let amplitudes = (basis.transpose() * &basis)
+            .lu()
+            .solve(&(basis.transpose() * observations))?;
+
+        Some(amplitudes.iter().cloned().collect())
+    }
+
+    // Starting decay constants (b, d) read off the data: a straight line in ln(y) over the low and
+    // high energy halves, each slope is -1/decay. With fewer than four points d is a decade above b
+    pub fn guess_decays(
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+    ) -> Option<(f64, f64)> {
+        let mut points: Vec<(f64, f64, f64)> = (0..x_data.len())
+            .map(|i| (x_data[i], y_data[i], weights[i]))
+            .filter(|(x, y, w)| x.is_finite() && *y > 0.0 && *w > 0.0 && w.is_finite())
+            .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        if points.len() < 4 {
+            let b = Self::log_linear_decay(&points)?;
+            return Some((b, 10.0 * b));
+        }
+
+        let (low, high) = points.split_at(points.len() / 2);
+        match (Self::log_linear_decay(low), Self::log_linear_decay(high)) {
+            (Some(b), Some(d)) if d > b => Some((b, d)),
+            (Some(b), Some(d)) => Some((d, 10.0 * b)), // the halves disagree, keep the decays apart
+            (Some(b), None) => Some((b, 10.0 * b)),
+            (None, Some(d)) => Some((d / 10.0, d)),
+            (None, None) => None,
+        }
+    }
+
+    // -1 / slope of the weighted straight line through (x, ln y), None unless the data falls off.
+    // The uncertainty of ln y is 1 / (w y)
+    fn log_linear_decay(points: &[(f64, f64, f64)]) -> Option<f64> {
+        if points.len() < 2 {
+            return None;
+        }
+
+        let (mut s, mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for &(x, y, w) in points {
+            let weight = (w * y).powi(2);
+            let ln_y = y.ln();
+            s += weight;
+            sx += weight * x;
+            sy += weight * ln_y;
+            sxx += weight * x * x;
+            sxy += weight * x * ln_y;
+        }
+
+        let slope = (s * sxy - sx * sy) / (s * sxx - sx * sx);
+        (slope.is_finite() && slope < 0.0).then(|| -1.0 / slope)
+    }
+}
+
+impl FitModel for Exponential {
+    fn name(&self) -> String {
+        match self.terms() {
+            1 => "Single Exponential".to_string(),
+            2 => "Double Exponential".to_string(),
+            terms => format!("{}-Term Exponential", terms),
+        }
+    }
+
+    fn formula(&self) -> String {
+        let terms: Vec<String> = (0..self.terms())
+            .map(|term| {
+                format!(
+                    "{} exp(-x/{})",
+                    Self::amplitude_name(term),
+                    Self::decay_name(term)
+                )
+            })
+            .collect();
+
+        format!("y = {}", terms.join(" + "))
+    }
+
+    fn parameter_names(&self) -> Vec<String> {
+        (0..self.terms())
+            .map(Self::amplitude_name)
+            .chain((0..self.terms()).map(Self::decay_name))
+            .collect()
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        curve::exponential(x, parameters)
+    }
+
+    fn gradient(&self, x: f64, parameters: &[f64]) -> Vec<f64> {
+        let (amplitudes, decays) = parameters.split_at(parameters.len() / 2);
+
+        let amplitude_derivatives = decays.iter().map(|b| (-x / b).exp());
+        let decay_derivatives = amplitudes
+            .iter()
+            .zip(decays.iter())
+            .map(|(a, b)| a * (x / b.powi(2)) * (-x / b).exp());
+
+        amplitude_derivatives.chain(decay_derivatives).collect()
+    }
+
+    fn fit(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+        solver: &SolverSettings,
+    ) -> Result<FitResult, String> {
+        let terms = self.terms();
+
+        if terms == 0 {
+            return Err("The model has no exponential terms".to_string());
+        }
+
+        if x_data.len() < 2 * terms {
+            return Err(format!(
+                "Not enough data: a {} needs at least {} points, got {}",
+                self.name().to_lowercase(),
+                2 * terms,
+                x_data.len()
+            ));
+        }
+
+        let parameter_names: Vec<String> = (0..terms).map(Self::decay_name).collect();
+
+        let mut builder_proxy = SeparableModelBuilder::<f64>::new(parameter_names.clone())
+            .initial_parameters(self.initial_decays.clone())
+            .independent_variable(x_data.clone())
+            .function(&[parameter_names[0].as_str()], Self::exponential)
+            .partial_deriv(&parameter_names[0], Self::exponential_pd);
+
+        for name in parameter_names.iter().skip(1) {
+            builder_proxy = builder_proxy
+                .function(&[name.as_str()], Self::exponential)
+                .partial_deriv(name, Self::exponential_pd);
+        }
+
+        let model = match builder_proxy.build() {
+            Ok(model) => model,
+            Err(err) => return Err(format!("Error building model: {}", err)),
+        };
+
+        let problem = match LevMarProblemBuilder::new(model)
+            .observations(y_data.clone())
+            .weights(weights.clone())
+            .build()
+        {
+            Ok(problem) => problem,
+            Err(err) => return Err(format!("Error building problem: {}", err)),
+        };
+
+        let (fit_result, fit_statistics) = match LevMarSolver::with_solver(
+            solver.levenberg_marquardt(),
+        )
+        .fit_with_statistics(problem)
+        {
+            Ok(result) => result,
+            Err(failed) => {
+                let report = &failed.minimization_report;
+                return Err(if report.termination.was_successful() {
+                    "Solver converged but the covariance could not be calculated".to_string()
+                } else {
+                    format!(
+                            "Solver did not converge ({:?} after {} evaluations), try different initial guesses",
+                            report.termination, report.number_of_evaluations
+                        )
+                });
+            }
+        };
+
+        let report = &fit_result.minimization_report;
+        let solver = SolverReport::new(
+            report.termination.was_successful(),
+            format!("{:?}", report.termination),
+            report.number_of_evaluations,
+            2.0 * report.objective_function, // varpro minimizes half the squared residual norm
+        );
+
+        let linear_parameters = match fit_result.linear_coefficients() {
+            Some(coefficients) => coefficients.iter().cloned().collect(),
+            None => return Err("Solver returned no linear coefficients".to_string()),
+        };
+
+        Ok(FitResult {
+            linear_parameters,
+            linear_variances: fit_statistics
+                .linear_coefficients_variance()
+                .iter()
+                .cloned()
+                .collect(),
+            nonlinear_parameters: fit_result.nonlinear_parameters().iter().cloned().collect(),
+            nonlinear_variances: fit_statistics
+                .nonlinear_parameters_variance()
+                .iter()
+                .cloned()
+                .collect(),
+            covariance_matrix: fit_statistics.covariance_matrix().iter().cloned().collect(),
+            correlation_matrix: fit_statistics
+                .calculate_correlation_matrix()
+                .iter()
+                .cloned()
+                .collect(),
+            reduced_chi_squared: fit_statistics.reduced_chi2(),
+            regression_standard_error: fit_statistics.regression_standard_error(),
+            weighted_residuals: fit_statistics
+                .weighted_residuals()
+                .iter()
+                .cloned()
+                .collect(),
+            fitted_at: Some(chrono::Utc::now()),
+            fixed_parameters: Vec::new(),
+            solver: Some(solver),
+            ..Default::default()
+        })
+    }
+
+    fn linear_parameter_count(&self) -> usize {
+        self.terms()
+    }
+
+    fn initial_parameters(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+    ) -> Result<Vec<f64>, String> {
+        let amplitudes = self
+            .linear_amplitudes(x_data, y_data, weights)
+            .ok_or("Could not solve for the initial amplitudes, check the decay guesses")?;
+
+        Ok(amplitudes
+            .into_iter()
+            .chain(self.initial_decays.iter().cloned())
+            .collect())
+    }
+
+    fn warnings(&self, parameters: &[f64], min_x: f64, max_x: f64) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let energy_range = max_x - min_x;
+        let (amplitudes, decays) = parameters.split_at(parameters.len() / 2);
+
+        for (term, (amplitude, decay)) in amplitudes.iter().zip(decays.iter()).enumerate() {
+            let (amplitude_name, decay_name) = (Self::amplitude_name(term), Self::decay_name(term));
+
+            if *amplitude < 0.0 {
+                warnings.push(format!(
+                    "Amplitude {} is negative ({:.3e})",
+                    amplitude_name, amplitude
+                ));
+            }
+
+            if *decay < min_x {
+                warnings.push(format!(
+                    "Decay constant {} ({:.1}) is smaller than the lowest energy ({:.1} keV)",
+                    decay_name, decay, min_x
+                ));
+            }
+
+            if energy_range > 0.0 && *decay > 10.0 * energy_range {
+                warnings.push(format!(
+                    "Decay constant {} ({:.1}) is larger than 10x the energy range ({:.1} keV)",
+                    decay_name, decay, energy_range
+                ));
+            }
+        }
+
+        warnings
+    }
+}
+
+// ln(y) = a0 + a1 ln(E/E0) + a2 ln(E/E0)^2 + ..., the usual HPGe parameterization (radware effit
+// without the low energy region). E0 keeps the powers of the log near one so the coefficients are
+// not as correlated. The model is linear in ln(y), so it is solved directly without initial guesses
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct LogPolynomial {
+    pub order: usize,
+    pub reference_energy: f64, // keV
+}
+
+impl Default for LogPolynomial {
+    fn default() -> Self {
+        Self {
+            order: 3,
+            reference_energy: 1000.0,
+        }
+    }
+}
+
+impl LogPolynomial {
+    fn powers(&self, x: f64) -> Vec<f64> {
+        let log_x = (x / self.reference_energy).ln();
+        (0..=self.order)
+            .map(|power| log_x.powi(power as i32))
+            .collect()
+    }
+}
+
+impl FitModel for LogPolynomial {
+    fn name(&self) -> String {
+        format!("ln-Polynomial (order {})", self.order)
+    }
+
+    fn formula(&self) -> String {
+        let terms: Vec<String> = (0..=self.order)
+            .map(|power| match power {
+                0 => "a0".to_string(),
+                1 => "a1 L".to_string(),
+                _ => format!("a{} L^{}", power, power),
+            })
+            .collect();
+
+        format!(
+            "ln(y) = {}, L = ln(x/{})",
+            terms.join(" + "),
+            self.reference_energy
+        )
+    }
+
+    fn parameter_names(&self) -> Vec<String> {
+        (0..=self.order)
+            .map(|power| format!("a{}", power))
+            .collect()
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        curve::log_polynomial(x, self.reference_energy, parameters)
+    }
+
+    fn gradient(&self, x: f64, parameters: &[f64]) -> Vec<f64> {
+        if x <= 0.0 {
+            return vec![0.0; parameters.len()];
+        }
+
+        let y = self.evaluate(x, parameters);
+        self.powers(x).iter().map(|power| y * power).collect()
+    }
+
+    fn linear_parameter_count(&self) -> usize {
+        self.order + 1
+    }
+
+    // the unconstrained solution, it is direct so there is nothing better to start from
+    fn initial_parameters(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+    ) -> Result<Vec<f64>, String> {
+        // the ln-polynomial fit is linear, the solver settings do not matter
+        Ok(self
+            .fit(x_data, y_data, weights, &SolverSettings::default())?
+            .parameters())
+    }
+
+    fn fit(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+        _solver: &SolverSettings,
+    ) -> Result<FitResult, String> {
+        let n_parameters = self.order + 1;
+
+        if x_data.len() <= n_parameters {
+            return Err(format!(
+                "Not enough data: an order {} ln-polynomial needs more than {} points, got {}",
+                self.order,
+                n_parameters,
+                x_data.len()
+            ));
+        }
+
+        if self.reference_energy <= 0.0 {
+            return Err("The reference energy must be positive".to_string());
+        }
+
+        if x_data
+            .iter()
+            .chain(y_data.iter())
+            .any(|value| *value <= 0.0)
+        {
+            return Err(
+                "The ln-polynomial needs positive energies and efficiencies, exclude the zero points"
+                    .to_string(),
+            );
+        }
+
+        solve_log_linear(
+            self,
+            |x| self.powers(x),
+            x_data,
+            y_data,
+            weights,
+            "Normal matrix is singular, try a lower order",
+        )
+    }
+}
+
+// weighted linear least squares of ln(y) on the basis functions of `model`, which is exp(sum p_k
+// basis_k). The ln-polynomial and the spline are both solved this way
+fn solve_log_linear(
+    model: &dyn FitModel,
+    basis: impl Fn(f64) -> Vec<f64>,
+    x_data: &DVector<f64>,
+    y_data: &DVector<f64>,
+    weights: &DVector<f64>,
+    singular: &str,
+) -> Result<FitResult, String> {
+    let n_parameters = model.linear_parameter_count();
+
+    // sigma(ln y) = sigma(y) / y, so the weight 1/sigma becomes y/sigma
+    let log_weights = weights.component_mul(y_data);
+    let rows: Vec<Vec<f64>> = x_data.iter().map(|x| basis(*x)).collect();
+    let design = DMatrix::from_fn(x_data.len(), n_parameters, |i, k| {
+        log_weights[i] * rows[i][k]
+    });
+    let observations = y_data.map(f64::ln).component_mul(&log_weights);
+
+    let normal = design.transpose() * &design;
+    let normal_inverse = match normal.clone().try_inverse() {
+        Some(inverse) => inverse,
+        None => return Err(singular.to_string()),
+    };
+    let coefficients = &normal_inverse * (design.transpose() * observations);
+    let parameters: Vec<f64> = coefficients.iter().cloned().collect();
+
+    let weighted_residuals: Vec<f64> = (0..x_data.len())
+        .map(|i| (y_data[i] - model.evaluate(x_data[i], &parameters)) * weights[i])
+        .collect();
+
+    let dof = (x_data.len() - n_parameters) as f64;
+    let chi_squared = weighted_residuals.iter().map(|r| r * r).sum::<f64>();
+    let reduced_chi_squared = chi_squared / dof;
+
+    // scaled by the reduced chi-square like the varpro covariance
+    let covariance = normal_inverse * reduced_chi_squared;
+    let correlation = DMatrix::from_fn(n_parameters, n_parameters, |i, j| {
+        covariance[(i, j)] / (covariance[(i, i)] * covariance[(j, j)]).sqrt()
+    });
+
+    Ok(FitResult {
+        linear_parameters: parameters,
+        linear_variances: covariance.diagonal().iter().cloned().collect(),
+        nonlinear_parameters: Vec::new(),
+        nonlinear_variances: Vec::new(),
+        covariance_matrix: covariance.iter().cloned().collect(),
+        correlation_matrix: correlation.iter().cloned().collect(),
+        reduced_chi_squared,
+        regression_standard_error: reduced_chi_squared.sqrt(),
+        weighted_residuals,
+        fitted_at: Some(chrono::Utc::now()),
+        fixed_parameters: Vec::new(),
+        solver: Some(SolverReport::new(
+            true,
+            "Linear least squares",
+            1,
+            chi_squared,
+        )),
+        ..Default::default()
+    })
+}
+
+// ln(y) as a natural cubic spline in ln(x), for when no parametric shape follows the data. The
+// knots are spread evenly in ln(x) over the data and the curve is a straight line in ln-ln outside
+// the outer ones. Linear like the ln-polynomial, the uncertainty comes from the residual scatter
+// through the reduced chi-square
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Spline {
+    pub knots: Vec<f64>, // keV
+}
+
+impl Spline {
+    pub fn new(count: usize, x_data: &[f64]) -> Self {
+        let positive = x_data.iter().cloned().filter(|x| *x > 0.0);
+        let min = positive.clone().fold(f64::INFINITY, f64::min);
+        let max = positive.fold(f64::NEG_INFINITY, f64::max);
+        if !min.is_finite() || !max.is_finite() || count < 2 {
+            return Self { knots: Vec::new() };
+        }
+
+        let step = (max.ln() - min.ln()) / (count - 1) as f64;
+        Self {
+            knots: (0..count)
+                .map(|k| (min.ln() + step * k as f64).exp())
+                .collect(),
+        }
+    }
+
+    fn log_knots(&self) -> Vec<f64> {
+        self.knots.iter().map(|knot| knot.ln()).collect()
+    }
+
+    fn basis(&self, x: f64) -> Vec<f64> {
+        curve::natural_spline_basis(x.ln(), &self.log_knots())
+    }
+}
+
+impl FitModel for Spline {
+    fn name(&self) -> String {
+        format!("Spline ({} knots)", self.knots.len())
+    }
+
+    fn formula(&self) -> String {
+        let knots: Vec<String> = self
+            .knots
+            .iter()
+            .map(|knot| format!("{:.0}", knot))
+            .collect();
+        format!(
+            "ln(y) = natural cubic spline in ln(x), knots at {} keV",
+            knots.join(", ")
+        )
+    }
+
+    fn parameter_names(&self) -> Vec<String> {
+        (0..self.knots.len()).map(|k| format!("s{}", k)).collect()
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        curve::spline(x, &self.log_knots(), parameters)
+    }
+
+    fn gradient(&self, x: f64, parameters: &[f64]) -> Vec<f64> {
+        if x <= 0.0 {
+            return vec![0.0; parameters.len()];
+        }
+
+        let y = self.evaluate(x, parameters);
+        self.basis(x).iter().map(|basis| y * basis).collect()
+    }
+
+    fn linear_parameter_count(&self) -> usize {
+        self.knots.len()
+    }
+
+    // the unconstrained solution, it is direct so there is nothing better to start from
+    fn initial_parameters(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+    ) -> Result<Vec<f64>, String> {
+        Ok(self
+            .fit(x_data, y_data, weights, &SolverSettings::default())?
+            .parameters())
+    }
+
+    fn fit(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+        _solver: &SolverSettings,
+    ) -> Result<FitResult, String> {
+        if self.knots.len() < 3 {
+            return Err("The spline needs at least 3 knots".to_string());
+        }
+
+        if x_data.len() <= self.knots.len() {
+            return Err(format!(
+                "Not enough data: a spline with {} knots needs more than {} points, got {}",
+                self.knots.len(),
+                self.knots.len(),
+                x_data.len()
+            ));
+        }
+
+        if x_data
+            .iter()
+            .chain(y_data.iter())
+            .any(|value| *value <= 0.0)
+        {
+            return Err(
+                "The spline needs positive energies and efficiencies, exclude the zero points"
+                    .to_string(),
+            );
+        }
+
+        solve_log_linear(
+            self,
+            |x| self.basis(x),
+            x_data,
+            y_data,
+            weights,
+            "Normal matrix is singular, try fewer knots",
+        )
+    }
+}
+
+// user typed expression in x, e.g. `a * exp(-x / b) + c * x^d`. Every identifier other than x is
+// a parameter. The parsed tree and its parameter names are cached and rebuilt after loading, the
+// expression is what is saved
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Formula {
+    pub expression: String,
+    pub initial_guesses: Vec<(String, f64)>, // kept by name so editing the expression keeps them
+    #[serde(skip)]
+    parsed: OnceLock<Result<ParsedFormula, String>>,
+}
+
+// the cached tree is derived from the expression, so it is left out of the comparison
+impl PartialEq for Formula {
+    fn eq(&self, other: &Self) -> bool {
+        self.expression == other.expression && self.initial_guesses == other.initial_guesses
+    }
+}
+
+impl Default for Formula {
+    fn default() -> Self {
+        Self::new("a * exp(-x / b) + c * exp(-x / d)")
+    }
+}
+
+impl Formula {
+    pub fn new(expression: &str) -> Self {
+        let mut formula = Self {
+            expression: expression.to_string(),
+            initial_guesses: Vec::new(),
+            parsed: OnceLock::new(),
+        };
+        formula.sync_guesses();
+        formula
+    }
+
+    fn parsed(&self) -> Result<&ParsedFormula, &String> {
+        self.parsed
+            .get_or_init(|| ParsedFormula::new(&self.expression))
+            .as_ref()
+    }
+
+    pub fn tree(&self) -> Result<&Node, &String> {
+        self.parsed().map(ParsedFormula::tree)
+    }
+
+    // call after editing `expression`
+    pub fn expression_changed(&mut self) {
+        self.parsed = OnceLock::new();
+        self.sync_guesses();
+    }
+
+    // adds a guess of 1 for new parameters, guesses for removed parameters are kept
+    fn sync_guesses(&mut self) {
+        for name in self.parameter_names() {
+            if !self.initial_guesses.iter().any(|(guess, _)| *guess == name) {
+                self.initial_guesses.push((name, 1.0));
+            }
+        }
+    }
+
+    pub fn initial_guesses(&self) -> Vec<f64> {
+        self.parameter_names()
+            .iter()
+            .map(|name| {
+                self.initial_guesses
+                    .iter()
+                    .find(|(guess, _)| guess == name)
+                    .map_or(1.0, |(_, value)| *value)
+            })
+            .collect()
+    }
+}
+
+impl FitModel for Formula {
+    fn name(&self) -> String {
+        "Formula".to_string()
+    }
+
+    fn formula(&self) -> String {
+        format!("y = {}", self.expression)
+    }
+
+    fn parameter_names(&self) -> Vec<String> {
+        self.parsed()
+            .map_or(Vec::new(), |formula| formula.parameter_names().to_vec())
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        match self.parsed() {
+            Ok(formula) => formula.evaluate(x, parameters),
+            Err(_) => f64::NAN,
+        }
+    }
+
+    fn gradient(&self, x: f64, parameters: &[f64]) -> Vec<f64> {
+        self.gradient_with_step(x, parameters, SolverSettings::default().epsilon)
+    }
+
+    // central differences, the expression has no analytic derivatives
+    fn gradient_with_step(&self, x: f64, parameters: &[f64], step: f64) -> Vec<f64> {
+        let mut shifted = parameters.to_vec();
+
+        (0..parameters.len())
+            .map(|k| {
+                let h = step * parameters[k].abs().max(step);
+
+                shifted[k] = parameters[k] + h;
+                let upper = self.evaluate(x, &shifted);
+                shifted[k] = parameters[k] - h;
+                let lower = self.evaluate(x, &shifted);
+                shifted[k] = parameters[k];
+
+                (upper - lower) / (2.0 * h)
+            })
+            .collect()
+    }
+
+    fn fit(
+        &self,
+        x_data: &DVector<f64>,
+        y_data: &DVector<f64>,
+        weights: &DVector<f64>,
+        solver: &SolverSettings,
+    ) -> Result<FitResult, String> {
+        if let Err(err) = self.tree() {
+            return Err(format!("Invalid formula: {}", err));
+        }
+
+        if self.parameter_names().is_empty() {
+            return Err("The formula has no parameters to fit".to_string());
+        }
+
+        least_squares_fit(
+            self,
+            &self.initial_parameters(x_data, y_data, weights)?,
+            &[],
+            x_data,
+            y_data,
+            weights,
+            solver,
+        )
+    }
+
+    fn initial_parameters(
+        &self,
+        _x_data: &DVector<f64>,
+        _y_data: &DVector<f64>,
+        _weights: &DVector<f64>,
+    ) -> Result<Vec<f64>, String> {
+        Ok(self.initial_guesses())
+    }
+}
+
+// A model, its data and the parameter transforms as a problem for the levenberg_marquardt crate.
+// The solver moves the internal parameters of the free parameters, the fixed ones keep the value
+// they have in `parameters`
+struct ModelProblem<'a, M: FitModel + ?Sized> {
+    model: &'a M,
+    transforms: Vec<Transform>,
+    free: Vec<usize>,
+    parameters: Vec<f64>, // external, every parameter of the model
+    internal: DVector<f64>,
+    x_data: &'a DVector<f64>,
+    y_data: &'a DVector<f64>,
+    weights: &'a DVector<f64>,
+    epsilon: f64,
+}
+
+impl<M: FitModel + ?Sized> ModelProblem<'_, M> {
+    // (data - model) * weight, the sign the fit results store
+    fn weighted_residuals(&self) -> DVector<f64> {
+        DVector::from_fn(self.x_data.len(), |i, _| {
+            (self.y_data[i] - self.model.evaluate(self.x_data[i], &self.parameters))
+                * self.weights[i]
+        })
+    }
+
+    // derivatives of the weighted model with respect to the free external parameters
+    fn external_jacobian(&self) -> DMatrix<f64> {
+        let mut jacobian = DMatrix::zeros(self.x_data.len(), self.free.len());
+        for i in 0..self.x_data.len() {
+            let gradient =
+                self.model
+                    .gradient_with_step(self.x_data[i], &self.parameters, self.epsilon);
+            for (column, &k) in self.free.iter().enumerate() {
+                jacobian[(i, column)] = self.weights[i] * gradient[k];
+            }
+        }
+        jacobian
+    }
+}
+
+impl<M: FitModel + ?Sized> LeastSquaresProblem<f64, Dyn, Dyn> for ModelProblem<'_, M> {
+    type ResidualStorage = Owned<f64, Dyn>;
+    type JacobianStorage = Owned<f64, Dyn, Dyn>;
+    type ParameterStorage = Owned<f64, Dyn>;
+
+    fn set_params(&mut self, internal: &DVector<f64>) {
+        self.internal.copy_from(internal);
+        for (column, &k) in self.free.iter().enumerate() {
+            self.parameters[k] = self.transforms[k].external(internal[column]);
+        }
+    }
+
+    fn params(&self) -> DVector<f64> {
+        self.internal.clone()
+    }
+
+    // model - data so the jacobian is the one of the model
+    fn residuals(&self) -> Option<DVector<f64>> {
+        Some(-self.weighted_residuals())
+    }
+
+    fn jacobian(&self) -> Option<DMatrix<f64>> {
+        let mut jacobian = self.external_jacobian();
+        for (column, &k) in self.free.iter().enumerate() {
+            let derivative = self.transforms[k].derivative(self.internal[column]);
+            jacobian.column_mut(column).scale_mut(derivative);
+        }
+        Some(jacobian)
+    }
+}
+
+// Levenberg-Marquardt on any model, for the models varpro can't separate and for constrained fits.
+// The solver works on the internal parameters of `transforms` (an empty slice leaves every
+// parameter free) and the covariance is scaled by the reduced chi-square like the varpro one
+pub fn least_squares_fit<M: FitModel + ?Sized>(
+    model: &M,
+    initial_parameters: &[f64],
+    transforms: &[Transform],
+    x_data: &DVector<f64>,
+    y_data: &DVector<f64>,
+    weights: &DVector<f64>,
+    solver: &SolverSettings,
+) -> Result<FitResult, String> {
+    let transforms: Vec<Transform> = (0..initial_parameters.len())
+        .map(|k| transforms.get(k).copied().unwrap_or(Transform::Free))
+        .collect();
+    let free: Vec<usize> = (0..initial_parameters.len())
+        .filter(|&k| !matches!(transforms[k], Transform::Fixed(_)))
+        .collect();
+    let n_free = free.len();
+
+    if n_free == 0 {
+        return Err("Every parameter is fixed, there is nothing to fit".to_string());
+    }
+
+    if x_data.len() <= n_free {
+        return Err(format!(
+            "Not enough data: {} free parameters need more than {} points, got {}",
+            n_free,
+            n_free,
+            x_data.len()
+        ));
+    }
+
+    let internal = DVector::from_iterator(
+        n_free,
+        free.iter()
+            .map(|&k| transforms[k].internal(initial_parameters[k])),
+    );
+    let mut problem = ModelProblem {
+        model,
+        parameters: initial_parameters
+            .iter()
+            .zip(&transforms)
+            .map(|(&p, transform)| transform.external(p))
+            .collect(),
+        transforms,
+        free,
+        internal: internal.clone(),
+        x_data,
+        y_data,
+        weights,
+        epsilon: solver.epsilon,
+    };
+    problem.set_params(&internal);
+
+    if !problem.weighted_residuals().norm_squared().is_finite() {
+        return Err("The model is not finite at the initial guesses".to_string());
+    }
+
+    let (problem, report) = solver.levenberg_marquardt().minimize(problem);
+
+    if report.termination.was_usage_issue() {
+        return Err(format!("Solver could not run: {:?}", report.termination));
+    }
+
+    // The covariance of the free parameters comes from the external jacobian, a parameter sitting
+    // on a bound has no internal derivative and would make the internal one singular
+    let parameters = problem.parameters.clone();
+    let j = problem.external_jacobian();
+    let normal_inverse = match (j.transpose() * &j).try_inverse() {
+        Some(inverse) => inverse,
+        None => {
+            return Err(
+                "Parameter covariance is singular, a parameter does not change the model"
+                    .to_string(),
+            )
+        }
+    };
+
+    let weighted_residuals = problem.weighted_residuals();
+    let cost = weighted_residuals.norm_squared();
+    let reduced_chi_squared = cost / (x_data.len() - n_free) as f64;
+
+    // fixed parameters keep zero variance and zero correlation
+    let free = &problem.free;
+    let n_parameters = parameters.len();
+    let mut covariance = DMatrix::zeros(n_parameters, n_parameters);
+    for (row, &i) in free.iter().enumerate() {
+        for (column, &k) in free.iter().enumerate() {
+            covariance[(i, k)] = normal_inverse[(row, column)] * reduced_chi_squared;
+        }
+    }
+    let correlation = DMatrix::from_fn(n_parameters, n_parameters, |i, k| {
+        let scale = (covariance[(i, i)] * covariance[(k, k)]).sqrt();
+        if scale > 0.0 {
+            covariance[(i, k)] / scale
+        } else {
+            0.0
+        }
+    });
+    let variances: Vec<f64> = covariance.diagonal().iter().cloned().collect();
+
+    let n_linear = model.linear_parameter_count().min(n_parameters);
+    let names = model.parameter_names();
+
+    Ok(FitResult {
+        linear_parameters: parameters[..n_linear].to_vec(),
+        linear_variances: variances[..n_linear].to_vec(),
+        nonlinear_parameters: parameters[n_linear..].to_vec(),
+        nonlinear_variances: variances[n_linear..].to_vec(),
+        covariance_matrix: covariance.iter().cloned().collect(),
+        correlation_matrix: correlation.iter().cloned().collect(),
+        reduced_chi_squared,
+        regression_standard_error: reduced_chi_squared.sqrt(),
+        weighted_residuals: weighted_residuals.iter().cloned().collect(),
+        fitted_at: Some(chrono::Utc::now()),
+        fixed_parameters: (0..n_parameters)
+            .filter(|k| !free.contains(k))
+            .filter_map(|k| names.get(k).cloned())
+            .collect(),
+        solver: Some(SolverReport::new(
+            report.termination.was_successful(),
+            format!("{:?}", report.termination),
+            report.number_of_evaluations,
+            cost,
+        )),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formula_names_follow_the_expression() {
+        let mut formula = Formula::new("a * exp(-x / b) + c");
+        assert_eq!(formula.parameter_names(), vec!["a", "b", "c"]);
+        assert!(
+            (formula.evaluate(100.0, &[10.0, 100.0, 1.0]) - (10.0 / std::f64::consts::E + 1.0))
+                .abs()
+                < 1e-12
+        );
+
+        formula.expression = "k * x".to_string();
+        formula.expression_changed();
+        assert_eq!(formula.parameter_names(), vec!["k"]);
+        assert_eq!(formula.evaluate(3.0, &[2.0]), 6.0);
+    }
+
+    fn data(function: impl Fn(f64) -> f64) -> (DVector<f64>, DVector<f64>, DVector<f64>) {
+        let x = DVector::from_vec(vec![100.0, 200.0, 300.0, 500.0, 800.0, 1200.0, 1600.0]);
+        let y = x.map(function);
+        let weights = DVector::from_element(x.len(), 1.0);
+        (x, y, weights)
+    }
+
+    #[test]
+    fn recovers_an_exact_exponential() {
+        let (x, y, weights) = data(|x| 20.0 * (-x / 400.0).exp() + 2.0);
+        let mut formula = Formula::new("a * exp(-x / b) + c");
+        formula.initial_guesses = vec![
+            ("a".to_string(), 10.0),
+            ("b".to_string(), 300.0),
+            ("c".to_string(), 1.0),
+        ];
+
+        let result = formula
+            .fit(&x, &y, &weights, &SolverSettings::default())
+            .unwrap();
+        let parameters = result.parameters();
+
+        assert!((parameters[0] - 20.0).abs() < 1e-6, "{:?}", parameters);
+        assert!((parameters[1] - 400.0).abs() < 1e-4, "{:?}", parameters);
+        assert!((parameters[2] - 2.0).abs() < 1e-6, "{:?}", parameters);
+        assert!(result.solver.unwrap().converged);
+    }
+
+    #[test]
+    fn straight_line_matches_weighted_least_squares() {
+        // y = 3 + 0.01 x with alternating ± 0.1 noise, weights 1 / σ with σ = 0.1
+        let x = DVector::from_vec(vec![100.0, 200.0, 300.0, 400.0, 500.0, 600.0]);
+        let y = DVector::from_fn(x.len(), |i, _| {
+            3.0 + 0.01 * x[i] + if i % 2 == 0 { 0.1 } else { -0.1 }
+        });
+        let weights = DVector::from_element(x.len(), 10.0);
+
+        let result = least_squares_fit(
+            &Formula::new("a + b * x"),
+            &[1.0, 0.0],
+            &[],
+            &x,
+            &y,
+            &weights,
+            &SolverSettings::default(),
+        )
+        .unwrap();
+
+        // normal equations of the same problem
+        let design = DMatrix::from_fn(x.len(), 2, |i, k| if k == 0 { 10.0 } else { 10.0 * x[i] });
+        let normal = (design.transpose() * &design).try_inverse().unwrap();
+        let expected = &normal * design.transpose() * y.map(|y| 10.0 * y);
+
+        let parameters = result.parameters();
+        assert!((parameters[0] - expected[0]).abs() < 1e-6);
+        assert!((parameters[1] - expected[1]).abs() < 1e-9);
+
+        let chi_squared: f64 = result.weighted_residuals.iter().map(|r| r * r).sum();
+        assert!((result.reduced_chi_squared - chi_squared / 4.0).abs() < 1e-9);
+        let variance = normal[(1, 1)] * result.reduced_chi_squared;
+        assert!((result.covariance_matrix[3] - variance).abs() < 1e-6 * variance);
+    }
+
+    #[test]
+    fn too_few_points_or_nothing_free() {
+        let x = DVector::from_vec(vec![1.0, 2.0]);
+        let y = DVector::from_vec(vec![1.0, 2.0]);
+        let weights = DVector::from_vec(vec![1.0, 1.0]);
+        let formula = Formula::new("a + b * x");
+        let solver = SolverSettings::default();
+
+        assert!(least_squares_fit(&formula, &[0.0, 0.0], &[], &x, &y, &weights, &solver).is_err());
+
+        let fixed = [Transform::Fixed(0.0), Transform::Fixed(1.0)];
+        assert!(
+            least_squares_fit(&formula, &[0.0, 1.0], &fixed, &x, &y, &weights, &solver).is_err()
+        );
+    }
+
+    #[test]
+    fn transforms_round_trip() {
+        let transforms = [
+            Transform::Free,
+            Transform::Lower(2.0),
+            Transform::Upper(-1.0),
+            Transform::Between(10.0, 20.0),
+        ];
+        let values = [
+            [-5.0, 0.0, 7.5],
+            [2.5, 3.0, 100.0],
+            [-50.0, -3.0, -1.5],
+            [11.0, 15.0, 19.0],
+        ];
+
+        for (transform, values) in transforms.iter().zip(values) {
+            for p in values {
+                let u = transform.internal(p);
+                assert!(
+                    (transform.external(u) - p).abs() < 1e-9,
+                    "{:?} at {}",
+                    transform,
+                    p
+                );
+                assert!((transform.internal(transform.external(u)) - u).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn transforms_stay_within_the_bounds() {
+        // any internal value maps inside, a start on a bound is moved just inside
+        for u in [-1e6, -3.0, -0.5, 0.0, 0.5, 3.0, 1e6] {
+            assert!(Transform::Lower(2.0).external(u) >= 2.0);
+            assert!(Transform::Upper(-1.0).external(u) <= -1.0);
+            let between = Transform::Between(10.0, 20.0).external(u);
+            assert!((10.0..=20.0).contains(&between));
+        }
+
+        for transform in [Transform::Lower(2.0), Transform::Between(2.0, 4.0)] {
+            let u = transform.internal(2.0);
+            assert!(transform.external(u) > 2.0);
+            assert!(transform.derivative(u) != 0.0);
+        }
+        let u = Transform::Upper(-1.0).internal(-1.0);
+        assert!(Transform::Upper(-1.0).external(u) < -1.0);
+
+        assert_eq!(Transform::Fixed(3.0).external(100.0), 3.0);
+        assert_eq!(Transform::Fixed(3.0).derivative(1.0), 0.0);
+    }
+
+    #[test]
+    fn derivative_matches_finite_differences() {
+        for transform in [
+            Transform::Lower(2.0),
+            Transform::Upper(-1.0),
+            Transform::Between(10.0, 20.0),
+        ] {
+            for u in [-2.0, -0.3, 0.7, 1.5] {
+                let h = 1e-6;
+                let numeric = (transform.external(u + h) - transform.external(u - h)) / (2.0 * h);
+                assert!(
+                    (transform.derivative(u) - numeric).abs() < 1e-6,
+                    "{:?} at {}",
+                    transform,
+                    u
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fit_pinned_at_a_bound() {
+        // the slope wants to be 0.01 but is bounded below by 0.02, it ends up on the bound
+        let (x, y, weights) = data(|x| 3.0 + 0.01 * x);
+        let formula = Formula::new("a + b * x");
+        let constraints = [ParameterConstraint {
+            name: "b".to_string(),
+            lower: Some(0.02),
+            ..Default::default()
+        }];
+
+        let result = formula
+            .fit_constrained(&constraints, &x, &y, &weights, &SolverSettings::default())
+            .unwrap();
+        let parameters = result.parameters();
+        assert!(parameters[1] >= 0.02);
+        assert!((parameters[1] - 0.02).abs() < 1e-4, "{:?}", parameters);
+
+        // a fixed parameter is reported and keeps its value with no variance
+        let constraints = [ParameterConstraint {
+            name: "a".to_string(),
+            fixed: Some(3.0),
+            ..Default::default()
+        }];
+        let result = formula
+            .fit_constrained(&constraints, &x, &y, &weights, &SolverSettings::default())
+            .unwrap();
+        assert_eq!(result.parameters()[0], 3.0);
+        assert!((result.parameters()[1] - 0.01).abs() < 1e-9);
+        assert_eq!(result.covariance_matrix[0], 0.0);
+        assert_eq!(result.fixed_parameters, vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn solver_patience_reads_the_old_max_iterations() {
+        let yaml = "ftol: 1e-8\nxtol: 1e-8\ngtol: 0.0\nmax_iterations: 50\nepsilon: 1e-6\n";
+        let settings: SolverSettings = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(settings.patience, 50);
+    }
+}
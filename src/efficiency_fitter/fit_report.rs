@@ -0,0 +1,392 @@
+use super::exp_fitter::{ExpFitter, FitProvenance, Loss, SolverReport};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::curve::EfficiencyCurve;
+use crate::curve::Normalization;
+
+// Everything about one fit that otherwise only goes to the log, for the JSON and text exports.
+// Energies are always in keV so a report does not depend on the display unit
+#[derive(serde::Serialize)]
+pub struct FitReport {
+    pub detector: String,
+    pub fit: String,
+    pub model: String,
+    pub formula: String,
+    pub energy_times_efficiency: bool, // the formula is E * eff(E)
+    pub normalization: Option<Normalization>, // the formula is relative to this line
+    pub parameters: Vec<ReportParameter>,
+    pub covariance: Vec<Vec<f64>>,
+    pub correlation: Vec<Vec<f64>>,
+    pub covariance_scaled: bool, // errors scaled by sqrt(reduced χ²)
+    pub chi_squared: f64,
+    pub degrees_of_freedom: usize,
+    pub reduced_chi_squared: f64,
+    pub points: usize,
+    pub data_range: [f64; 2],  // keV
+    pub valid_range: [f64; 2], // keV
+    pub loss: Loss,
+    pub solver: Option<SolverReport>,
+    pub fitted_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub provenance: Option<FitProvenance>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ReportParameter {
+    pub name: String,
+    pub value: f64,
+    pub uncertainty: f64,
+    pub fixed: bool,
+}
+
+// flat row major matrix as rows, empty when the length does not match
+fn rows(matrix: &[f64], size: usize) -> Vec<Vec<f64>> {
+    if size == 0 || matrix.len() != size * size {
+        return Vec::new();
+    }
+
+    matrix.chunks(size).map(|row| row.to_vec()).collect()
+}
+
+impl FitReport {
+    // None until `fit` has a result
+    pub fn new(detector: &str, fit_name: &str, fit: &ExpFitter) -> Option<Self> {
+        let (model, result) = match (&fit.model, &fit.fit_result) {
+            (Some(model), Some(result)) => (model.fit_model(), result),
+            _ => return None,
+        };
+
+        let parameters: Vec<ReportParameter> = fit
+            .parameters()
+            .into_iter()
+            .map(|(name, value, uncertainty)| ReportParameter {
+                fixed: fit.is_fixed(&name),
+                name,
+                value,
+                uncertainty,
+            })
+            .collect();
+
+        let size = parameters.len();
+        let free_parameters = result.free_parameter_count();
+
+        let data_min = fit.x.iter().fold(f64::INFINITY, |a, &b| a.min(b));
+        let data_max = fit.x.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b));
+        let (valid_min, valid_max) = fit.valid_range();
+
+        Some(Self {
+            detector: detector.to_string(),
+            fit: fit_name.to_string(),
+            model: model.name(),
+            formula: model.formula(),
+            energy_times_efficiency: fit.energy_times_efficiency,
+            normalization: fit.normalization,
+            parameters,
+            covariance: rows(&fit.covariance(), size),
+            correlation: rows(&result.correlation_matrix, size),
+            covariance_scaled: fit.scale_covariance,
+            chi_squared: result.chi_squared(),
+            degrees_of_freedom: fit.x.len().saturating_sub(free_parameters),
+            reduced_chi_squared: result.reduced_chi_squared,
+            points: fit.x.len(),
+            data_range: [data_min, data_max],
+            valid_range: [valid_min, valid_max],
+            loss: result.loss,
+            solver: result.solver.clone(),
+            fitted_at: result.fitted_at,
+            provenance: result.provenance.clone(),
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_else(|err| {
+            log::error!("Failed to serialize the fit report: {}", err);
+            String::new()
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        text.push_str(&format!("{:<20}{}\n", "DETECTOR", self.detector));
+        text.push_str(&format!("{:<20}{} ({})\n", "FIT", self.fit, self.model));
+        text.push_str(&format!("{:<20}{}\n", "FORMULA", self.formula));
+        if self.energy_times_efficiency {
+            text.push_str(&format!("{:<20}{}\n", "", "fit to E * eff(E)"));
+        }
+        if let Some(normalization) = &self.normalization {
+            text.push_str(&format!(
+                "{:<20}{:.6e} ± {:.6e} (efficiency at {:.1} keV, the formula is relative to it)\n",
+                "NORMALIZATION",
+                normalization.value,
+                normalization.uncertainty,
+                normalization.energy
+            ));
+        }
+        if let Some(fitted_at) = self.fitted_at {
+            text.push_str(&format!(
+                "{:<20}{}\n",
+                "FITTED",
+                fitted_at.format("%Y-%m-%d %H:%M:%S UTC")
+            ));
+        }
+        text.push_str(&format!(
+            "{:<20}{:.1} - {:.1} keV ({} points)\n",
+            "DATA RANGE", self.data_range[0], self.data_range[1], self.points
+        ));
+        text.push_str(&format!(
+            "{:<20}{:.1} - {:.1} keV\n",
+            "VALID RANGE", self.valid_range[0], self.valid_range[1]
+        ));
+        text.push_str(&format!(
+            "{:<20}{:.4} / {} = {:.4}\n",
+            "CHI2 / DOF", self.chi_squared, self.degrees_of_freedom, self.reduced_chi_squared
+        ));
+        text.push_str(&format!("{:<20}{:?}\n", "LOSS", self.loss));
+        text.push_str(&format!(
+            "{:<20}{}\n",
+            "UNCERTAINTIES",
+            if self.covariance_scaled {
+                "scaled by sqrt(reduced chi2)"
+            } else {
+                "from the data errors"
+            }
+        ));
+        if let Some(solver) = &self.solver {
+            text.push_str(&format!(
+                "{:<20}{} after {} iterations\n",
+                "SOLVER", solver.termination, solver.iterations
+            ));
+        }
+
+        if let Some(provenance) = &self.provenance {
+            text.push_str("\nPROVENANCE\n");
+            text.push_str(&format!("{:<20}{}\n", "VERSION", provenance.version));
+            if let Some(weighting) = provenance.weighting {
+                text.push_str(&format!(
+                    "{:<20}{:?}, errors scaled by {}\n",
+                    "WEIGHTS", weighting, provenance.error_scale
+                ));
+            }
+            text.push_str(&format!(
+                "{:<20}{} ({} points)\n",
+                "POINTS HASH", provenance.points_hash, provenance.points
+            ));
+            if !provenance.initial_guesses.is_empty() {
+                let guesses: Vec<String> = provenance
+                    .initial_guesses
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .collect();
+                text.push_str(&format!(
+                    "{:<20}{}\n",
+                    "INITIAL GUESSES",
+                    guesses.join(", ")
+                ));
+            }
+            for constraint in &provenance.constraints {
+                let limits = match constraint.fixed {
+                    Some(value) => format!("fixed at {}", value),
+                    None => format!(
+                        "{} to {}",
+                        constraint
+                            .lower
+                            .map_or("-inf".to_string(), |v| v.to_string()),
+                        constraint
+                            .upper
+                            .map_or("inf".to_string(), |v| v.to_string())
+                    ),
+                };
+                text.push_str(&format!(
+                    "{:<20}{} {}\n",
+                    "CONSTRAINT", constraint.name, limits
+                ));
+            }
+            let solver = &provenance.solver;
+            text.push_str(&format!(
+                "{:<20}ftol {:e}, xtol {:e}, gtol {:e}, patience {}, epsilon {:e}\n",
+                "SOLVER SETTINGS",
+                solver.ftol,
+                solver.xtol,
+                solver.gtol,
+                solver.patience,
+                solver.epsilon
+            ));
+            if provenance.energy_errors {
+                text.push_str(&format!("{:<20}{}\n", "", "energy errors in the weights"));
+            }
+        }
+
+        text.push_str("\nPARAMETERS\n");
+        for parameter in &self.parameters {
+            if parameter.fixed {
+                text.push_str(&format!(
+                    "{:<8}{:>16.6e}   (fixed)\n",
+                    parameter.name, parameter.value
+                ));
+            } else {
+                text.push_str(&format!(
+                    "{:<8}{:>16.6e} ± {:.6e}\n",
+                    parameter.name, parameter.value, parameter.uncertainty
+                ));
+            }
+        }
+
+        let names: Vec<&str> = self.parameters.iter().map(|p| p.name.as_str()).collect();
+        // covariances span many orders of magnitude, correlations are always within ±1
+        for (title, matrix, scientific) in [
+            ("COVARIANCE", &self.covariance, true),
+            ("CORRELATION", &self.correlation, false),
+        ] {
+            if matrix.is_empty() {
+                continue;
+            }
+
+            text.push_str(&format!("\n{}\n{:<8}", title, ""));
+            for name in &names {
+                text.push_str(&format!("{:>14}", name));
+            }
+            text.push('\n');
+
+            for (name, row) in names.iter().zip(matrix) {
+                text.push_str(&format!("{:<8}", name));
+                for value in row {
+                    if scientific {
+                        text.push_str(&format!("{:>14.6e}", value));
+                    } else {
+                        text.push_str(&format!("{:>14.4}", value));
+                    }
+                }
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+}
+
+// What a headless `cebra_efficiency fit` run writes: the report of every fit that finished, the
+// same fits as curves for the library API, and why the others failed
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(serde::Serialize)]
+pub struct BatchReport {
+    pub project: String,
+    pub generated_at: chrono::DateTime<chrono::Utc>,
+    pub fits: Vec<FitReport>,
+    pub curves: Vec<EfficiencyCurve>,
+    pub failures: Vec<BatchFailure>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(serde::Serialize)]
+pub struct BatchFailure {
+    pub detector: String,
+    pub fit: String,
+    pub error: String,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BatchReport {
+    pub fn new(project: &str) -> Self {
+        Self {
+            project: project.to_string(),
+            generated_at: chrono::Utc::now(),
+            fits: Vec::new(),
+            curves: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        detector: &str,
+        fit_name: &str,
+        fit: &ExpFitter,
+        low_energy_cutoff: Option<f64>,
+    ) {
+        if let Some(report) = FitReport::new(detector, fit_name, fit) {
+            self.fits.push(report);
+        }
+
+        if let Some(mut curve) = fit.curve(detector, fit_name) {
+            curve.low_energy_cutoff = low_energy_cutoff;
+            self.curves.push(curve);
+        }
+    }
+
+    pub fn fail(&mut self, detector: &str, fit_name: &str, error: String) {
+        log::error!("{} {}: {}", detector, fit_name, error);
+        self.failures.push(BatchFailure {
+            detector: detector.to_string(),
+            fit: fit_name.to_string(),
+            error,
+        });
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Text,
+}
+
+impl ReportFormat {
+    pub fn render(self, report: &FitReport) -> String {
+        match self {
+            ReportFormat::Json => report.to_json(),
+            ReportFormat::Text => report.to_text(),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Json => "json",
+            ReportFormat::Text => "txt",
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.radio_value(self, ReportFormat::Json, "JSON");
+        ui.radio_value(self, ReportFormat::Text, "Text");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(self, file_name: &str, contents: String) {
+        use std::io::Write;
+
+        let path = match rfd::FileDialog::new()
+            .set_title("Export Fit Report")
+            .set_file_name(format!("{}.{}", file_name, self.extension()))
+            .add_filter(self.extension(), &[self.extension()])
+            .save_file()
+        {
+            Some(path) => path,
+            None => return,
+        };
+
+        match std::fs::File::create(&path) {
+            Ok(mut file) => {
+                if let Err(err) = file.write_all(contents.as_bytes()) {
+                    log::error!("Failed to write {}: {}", path.display(), err);
+                }
+            }
+            Err(err) => log::error!("Failed to create {}: {}", path.display(), err),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(self, file_name: &str, contents: String) {
+        let task = rfd::AsyncFileDialog::new()
+            .set_title("Export Fit Report")
+            .set_file_name(format!("{}.{}", file_name, self.extension()))
+            .add_filter(self.extension(), &[self.extension()])
+            .save_file();
+
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Some(file) = task.await {
+                if let Err(err) = file.write(contents.as_bytes()).await {
+                    log::error!("Failed to save the fit report: {}", err);
+                }
+            }
+        });
+    }
+}
@@ -1,10 +1,20 @@
-use super::detector::DetectorLine;
+use super::decay_data::{embedded, search_embedded, DecayData, DecayImport};
+use super::detector::{DetectorLine, UncertaintyBreakdown};
+use super::pile_up::PileUpCorrection;
+use super::source_library::SourceLibrary;
+use super::units::{ActivityUnit, EnergyUnit};
+use chrono::Timelike;
+
+// keV, detector lines are matched to the closest gamma line within this window
+pub const LINE_MATCH_TOLERANCE: f64 = 1.0;
 
 #[derive(Default, Clone, serde::Deserialize, serde::Serialize)]
 pub struct GammaLine {
     pub energy: f64, // keV
     pub intensity: f64,
     pub intensity_uncertainty: f64,
+    #[serde(default)]
+    pub emitter: String, // daughter the intensity is per decay of, empty for the parent
 }
 
 impl GammaLine {
@@ -12,13 +22,8 @@ impl GammaLine {
         Self::default()
     }
 
-    pub fn gamma_line_ui(&mut self, ui: &mut egui::Ui) {
-        ui.add(
-            egui::DragValue::new(&mut self.energy)
-                .speed(1.0)
-                .clamp_range(0.0..=f64::INFINITY)
-                .suffix(" keV"),
-        );
+    pub fn gamma_line_ui(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
+        energy_unit.drag_value(ui, &mut self.energy);
 
         ui.add(
             egui::DragValue::new(&mut self.intensity)
@@ -36,10 +41,122 @@ impl GammaLine {
     }
 }
 
+// Daughter of the calibrated parent that emits some of the lines (137mBa in 137Cs, the 226Ra
+// chain). Each daughter is fed straight from the parent, a longer chain in secular equilibrium
+// can list every member with the parent as its feeder
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct Daughter {
+    pub name: String,
+    pub half_life: f64,    // years, zero for a daughter that decays right away
+    pub branching: f64,    // fraction of the parent decays that feed it
+    pub equilibrium: bool, // in equilibrium at the calibration, otherwise separated (no activity) then
+}
+
+impl Default for Daughter {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            half_life: 0.0,
+            branching: 1.0,
+            equilibrium: true,
+        }
+    }
+}
+
+impl Daughter {
+    // Daughter activity over the parent activity `days` after the calibration, from the Bateman
+    // equation for one step:
+    // A_d / A_p = b λd / (λd - λp) (1 - e^{-(λd - λp) t}) + A_d0 / A_p0 e^{-(λd - λp) t}
+    pub fn activity_ratio(&self, parent_half_life: f64, days: f64) -> f64 {
+        if self.half_life <= 0.0 {
+            return self.branching;
+        }
+
+        let parent_decay = if parent_half_life > 0.0 {
+            std::f64::consts::LN_2 / parent_half_life
+        } else {
+            0.0
+        };
+        let daughter_decay = std::f64::consts::LN_2 / self.half_life;
+        let difference = daughter_decay - parent_decay;
+        let years = days / 365.25;
+
+        // (1 - e^{-x t}) / x, which is t when the half-lives match
+        let ingrowth = if difference != 0.0 {
+            -(-difference * years).exp_m1() / difference
+        } else {
+            years
+        };
+
+        // a daughter living longer than its parent never reaches equilibrium, start it at zero
+        let initial_ratio = if self.equilibrium && difference > 0.0 {
+            self.branching * daughter_decay / difference
+        } else {
+            0.0
+        };
+
+        self.branching * daughter_decay * ingrowth + initial_ratio * (-difference * years).exp()
+    }
+}
+
 #[derive(Default, Clone, serde::Deserialize, serde::Serialize)]
 pub struct SourceActivity {
     pub activity: f64, // kBq
-    pub date: Option<chrono::NaiveDate>,
+    #[serde(default, deserialize_with = "deserialize_date_time")]
+    pub date: Option<chrono::NaiveDateTime>, // times in the same zone for calibration and measurement
+    #[serde(default)]
+    pub uncertainty: f64, // same unit as the activity
+    #[serde(default)]
+    pub unit: ActivityUnit, // only how the activity is entered and shown
+}
+
+// projects saved before the time of day was kept only have the date, read as midnight
+fn deserialize_date_time<'de, D>(deserializer: D) -> Result<Option<chrono::NaiveDateTime>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let text: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+
+    text.map(|text| {
+        text.parse::<chrono::NaiveDateTime>()
+            .ok()
+            .or_else(|| midnight(text.parse().ok()))
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid date: {}", text)))
+    })
+    .transpose()
+}
+
+fn midnight(date: Option<chrono::NaiveDate>) -> Option<chrono::NaiveDateTime> {
+    date?.and_hms_opt(0, 0, 0)
+}
+
+// date picker with the hour and minute next to it
+fn date_time_ui(ui: &mut egui::Ui, date_time: &mut chrono::NaiveDateTime, id_source: &str) {
+    let mut date = date_time.date();
+    let mut hour = date_time.hour();
+    let mut minute = date_time.minute();
+
+    ui.horizontal(|ui| {
+        ui.add(
+            egui_extras::DatePickerButton::new(&mut date)
+                .id_source(id_source)
+                .highlight_weekends(false),
+        );
+        ui.add(
+            egui::DragValue::new(&mut hour)
+                .clamp_range(0..=23)
+                .suffix(" h"),
+        );
+        ui.add(
+            egui::DragValue::new(&mut minute)
+                .clamp_range(0..=59)
+                .suffix(" min"),
+        );
+    });
+
+    if let Some(changed) = date.and_hms_opt(hour, minute, date_time.second()) {
+        *date_time = changed;
+    }
 }
 
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
@@ -47,10 +164,22 @@ pub struct GammaSource {
     pub name: String,
     pub gamma_lines: Vec<GammaLine>,
     pub half_life: f64, // years
+    #[serde(default)]
+    pub half_life_uncertainty: f64, // years
     pub source_activity_calibration: SourceActivity,
     pub source_activity_measurement: SourceActivity,
-    pub source_activity_uncertainty: f64, // percentage of measurement
-    pub measurement_time: f64,            // hours
+    // percent of the calibration activity. Projects saved before the calibration had its own
+    // uncertainty only have this, it is kept in step with it so older versions still read it
+    pub source_activity_uncertainty: f64,
+    pub measurement_time: f64, // hours
+    #[serde(default)]
+    pub daughters: Vec<Daughter>,
+    #[serde(default)]
+    pub relative_only: bool, // activity not calibrated, the global fit scales it to the other sources
+    #[serde(skip)]
+    pub decay_import: Option<DecayImport>, // open import window
+    #[serde(skip)]
+    pub nuclide_search: String,
 }
 
 impl Default for GammaSource {
@@ -65,10 +194,15 @@ impl GammaSource {
             name: String::new(),
             gamma_lines: Vec::new(),
             half_life: 0.0,
+            half_life_uncertainty: 0.0,
             source_activity_calibration: SourceActivity::default(),
             source_activity_measurement: SourceActivity::default(),
             source_activity_uncertainty: 5.0,
             measurement_time: 0.0,
+            daughters: Vec::new(),
+            relative_only: false,
+            decay_import: None,
+            nuclide_search: String::new(),
         }
     }
 
@@ -77,9 +211,12 @@ impl GammaSource {
 
         self.name = "152Eu".to_string();
         self.half_life = 13.517; // years
+        self.half_life_uncertainty = 0.014; // years
 
         self.source_activity_calibration.activity = 74.370; // kBq
-        self.source_activity_calibration.date = chrono::NaiveDate::from_ymd_opt(2017, 3, 17);
+        self.source_activity_calibration.date =
+            midnight(chrono::NaiveDate::from_ymd_opt(2017, 3, 17));
+        self.relative_only = false;
 
         self.add_gamma_line(121.7817, 28.53, 0.16);
         self.add_gamma_line(244.6974, 7.55, 0.04);
@@ -101,9 +238,13 @@ impl GammaSource {
 
         let co60_halflife_days = 77.236; // days
         self.half_life = co60_halflife_days / 365.25; // years
+        self.half_life_uncertainty = 0.026 / 365.25; // years
 
-        self.source_activity_calibration.activity = 108.0; // kBq (arbitrary scaled to match 152Eu)
-        self.source_activity_calibration.date = chrono::NaiveDate::from_ymd_opt(2022, 4, 18);
+        // not calibrated, 108 kBq only puts it near 152Eu. The global fit scales it properly
+        self.source_activity_calibration.activity = 108.0; // kBq
+        self.source_activity_calibration.date =
+            midnight(chrono::NaiveDate::from_ymd_opt(2022, 4, 18));
+        self.relative_only = true;
 
         self.add_gamma_line(846.7638, 99.9399, 0.0023);
         self.add_gamma_line(1037.8333, 14.03, 0.05);
@@ -117,66 +258,320 @@ impl GammaSource {
 
         self.name = "60Co".to_string();
         self.half_life = 5.2714; // years
+        self.half_life_uncertainty = 0.0005; // years
 
         self.source_activity_calibration.activity = 185.3; // kBq
-        self.source_activity_calibration.date = chrono::NaiveDate::from_ymd_opt(2018, 9, 1);
+        self.source_activity_calibration.date =
+            midnight(chrono::NaiveDate::from_ymd_opt(2018, 9, 1));
+        self.relative_only = false;
 
         self.add_gamma_line(1173.22, 99.85, 0.03);
         self.add_gamma_line(1332.492, 99.9826, 0.0006);
     }
 
+    // the calibration activity and date of the check sources are left as entered
+    pub fn fsu_137cs_source(&mut self) {
+        self.gamma_lines.clear();
+
+        self.name = "137Cs".to_string();
+        self.half_life = 30.08; // years
+        self.half_life_uncertainty = 0.09; // years
+        self.relative_only = false;
+
+        self.add_gamma_line(661.657, 85.10, 0.20);
+    }
+
+    pub fn fsu_22na_source(&mut self) {
+        self.gamma_lines.clear();
+
+        self.name = "22Na".to_string();
+        self.half_life = 2.6018; // years
+        self.half_life_uncertainty = 0.0022; // years
+        self.relative_only = false;
+
+        // annihilation photons, Doppler broadened and partly lost when the positron escapes the holder
+        self.add_gamma_line(511.0, 180.7, 0.2);
+        self.add_gamma_line(1274.537, 99.94, 0.13);
+    }
+
+    // lines from ENSDF. Anchors the efficiency below the 121.8 keV line of 152Eu
+    pub fn fsu_133ba_source(&mut self) {
+        self.gamma_lines.clear();
+
+        self.name = "133Ba".to_string();
+        self.half_life = 10.551; // years
+        self.half_life_uncertainty = 0.011; // years
+
+        // the calibration activity and date are left as entered, fill them in from the certificate
+        self.relative_only = false;
+
+        self.add_gamma_line(53.1622, 2.14, 0.03);
+        self.add_gamma_line(79.6142, 2.65, 0.05);
+        self.add_gamma_line(80.9979, 32.9, 0.3);
+        self.add_gamma_line(160.6120, 0.638, 0.004);
+        self.add_gamma_line(223.2368, 0.453, 0.003);
+        self.add_gamma_line(276.3989, 7.16, 0.05);
+        self.add_gamma_line(302.8508, 18.34, 0.13);
+        self.add_gamma_line(356.0129, 62.05, 0.19);
+        self.add_gamma_line(383.8485, 8.94, 0.06);
+    }
+
+    // Search box over the embedded decay table, picking a nuclide fills in its name, half-life and
+    // lines. The calibration stays as entered
+    fn nuclide_search_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Nuclide:");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.nuclide_search)
+                    .hint_text("152Eu, 56Co, Ba...")
+                    .desired_width(100.0),
+            );
+
+            if self.nuclide_search.trim().is_empty() {
+                return;
+            }
+
+            let matches = search_embedded(&self.nuclide_search);
+            if matches.is_empty() {
+                ui.weak("No match");
+            }
+
+            for nuclide in matches {
+                if ui
+                    .button(&nuclide)
+                    .on_hover_text(format!(
+                        "Fill in the half-life and gamma lines of {}",
+                        nuclide
+                    ))
+                    .clicked()
+                {
+                    if let Some(data) = embedded(&nuclide) {
+                        self.import_decay_data(data);
+                        self.nuclide_search.clear();
+                    }
+                }
+            }
+        });
+    }
+
+    // replaces the gamma lines with imported ones, and the name and half-life when the file has them
+    pub fn import_decay_data(&mut self, data: DecayData) {
+        if !data.nuclide.is_empty() {
+            self.name = data.nuclide;
+        }
+        if let Some(half_life) = data.half_life {
+            self.half_life = half_life;
+            self.half_life_uncertainty = data.half_life_uncertainty;
+        }
+        self.gamma_lines = data.gamma_lines;
+    }
+
     pub fn add_gamma_line(&mut self, energy: f64, intensity: f64, intensity_uncertainty: f64) {
         let gamma_line = GammaLine {
             energy,
             intensity,
             intensity_uncertainty,
+            emitter: String::new(),
         };
 
         self.gamma_lines.push(gamma_line);
     }
 
+    // days (with the fraction from the time of day) from the calibration to the measurement,
+    // None until both dates are set. Whole days are off by up to 1% for 56Co
+    fn elapsed_days(&self) -> Option<f64> {
+        let calibration_date = self.source_activity_calibration.date?;
+        let measurement_date = self.source_activity_measurement.date?;
+
+        Some(
+            measurement_date
+                .signed_duration_since(calibration_date)
+                .num_seconds() as f64
+                / 86400.0,
+        )
+    }
+
+    // Relative error of the decayed activity from the half-life error. A = A0 exp(-ln2 t / T), so
+    // σA / A = ln2 t σT / T², which grows with the time since the calibration
+    pub fn decay_relative_uncertainty(&self) -> f64 {
+        match self.elapsed_days() {
+            Some(days) if self.half_life > 0.0 => {
+                std::f64::consts::LN_2 * days.abs() * self.half_life_uncertainty
+                    / (self.half_life.powi(2) * 365.25)
+            }
+            _ => 0.0,
+        }
+    }
+
+    // relative uncertainty of the certificate activity, the old percentage if none was entered
+    pub fn calibration_relative_uncertainty(&self) -> f64 {
+        let calibration = &self.source_activity_calibration;
+
+        if calibration.uncertainty > 0.0 && calibration.activity > 0.0 {
+            calibration.uncertainty / calibration.activity
+        } else {
+            self.source_activity_uncertainty / 100.0
+        }
+    }
+
+    // the calibration uncertainty and the half-life error combined, relative to the activity
+    pub fn activity_relative_uncertainty(&self) -> f64 {
+        self.calibration_relative_uncertainty()
+            .hypot(self.decay_relative_uncertainty())
+    }
+
+    // fills the calibration uncertainty of projects saved with only the percentage
+    fn migrate_activity_uncertainty(&mut self) {
+        let calibration = &mut self.source_activity_calibration;
+
+        if calibration.uncertainty == 0.0 && calibration.activity > 0.0 {
+            calibration.uncertainty =
+                calibration.activity * self.source_activity_uncertainty / 100.0;
+        }
+    }
+
     pub fn calculate_source_activity_for_measurement(&mut self) {
-        let calibration_date = self.source_activity_calibration.date.unwrap();
-        let measurement_date = self.source_activity_measurement.date.unwrap();
         let half_life_years = self.half_life;
         let half_life_days = half_life_years * 365.25; // convert years to days
 
-        let time_difference = measurement_date
-            .signed_duration_since(calibration_date)
-            .num_days() as f64;
-        let decay_constant = 0.693 / half_life_days;
+        let time_difference = match self.elapsed_days() {
+            Some(days) => days,
+            None => {
+                log::warn!(
+                    "{}: set the calibration and measurement dates first",
+                    self.name
+                );
+                return;
+            }
+        };
+        let decay_constant = std::f64::consts::LN_2 / half_life_days;
         let source_activity_bq = self.source_activity_calibration.activity * 1000.0; // convert kBq to Bq
         let activity = source_activity_bq * (-decay_constant * time_difference).exp();
 
         self.source_activity_measurement.activity = activity;
+        self.source_activity_measurement.uncertainty =
+            activity * self.activity_relative_uncertainty();
+
+        log::info!(
+            "{}: {:.0} ± {:.0} Bq after {:.3} days ({:.3}% from the calibration, {:.3}% from the half-life)",
+            self.name,
+            activity,
+            self.source_activity_measurement.uncertainty,
+            time_difference,
+            self.calibration_relative_uncertainty() * 100.0,
+            self.decay_relative_uncertainty() * 100.0
+        );
     }
 
-    pub fn gamma_line_efficiency_from_source_measurement(&self, line: &mut DetectorLine) {
-        let source_activity = self.source_activity_measurement.activity;
+    // Activity emitting the lines of `emitter` over the parent activity at the measurement, one
+    // for the parent's own lines
+    pub fn emitter_activity_ratio(&self, emitter: &str) -> f64 {
+        if emitter.is_empty() {
+            return 1.0;
+        }
 
-        // let mut activity_uncertainty = 0.0;
-        // if let Some(source_activity_uncertainty) = self.source_activity_uncertainty {
-        let activity_uncertainty = self.source_activity_uncertainty / 100.0 * source_activity;
-        // }
+        match self
+            .daughters
+            .iter()
+            .find(|daughter| daughter.name == emitter)
+        {
+            Some(daughter) => {
+                daughter.activity_ratio(self.half_life, self.elapsed_days().unwrap_or(0.0))
+            }
+            None => 1.0,
+        }
+    }
 
-        let run_time = self.measurement_time * 3600.0; // convert hours to seconds
+    // emitter activity ratio of the gamma line matching a detector line at `energy`
+    pub fn line_activity_ratio(&self, energy: f64) -> f64 {
+        self.matching_gamma_line(energy, LINE_MATCH_TOLERANCE)
+            .map_or(1.0, |gamma_line| {
+                self.emitter_activity_ratio(&gamma_line.emitter)
+            })
+    }
+
+    // `live_time` (hours) of the detector replaces the run time when it was measured, `pile_up`
+    // is the correction of the whole run
+    pub fn gamma_line_efficiency_from_source_measurement(
+        &self,
+        line: &mut DetectorLine,
+        live_time: Option<f64>,
+        pile_up: PileUpCorrection,
+    ) {
+        // the activity of the nuclide emitting the line, a daughter's lines follow its own activity
+        let source_activity =
+            self.source_activity_measurement.activity * self.line_activity_ratio(line.energy);
+
+        // recomputed rather than read from the measurement so projects saved before it was stored work
+        let activity_uncertainty = self.activity_relative_uncertainty() * source_activity;
+
+        let run_time = live_time.unwrap_or(self.measurement_time) * 3600.0; // convert hours to seconds
         let intensity = line.intensity;
         let intensity_uncertainty = line.intensity_uncertainty;
-        let counts = line.count;
-        let count_uncertainity = line.uncertainty;
+        // subtract the ambient background under the peak, its uncertainty adds in quadrature
+        let counts = line.net_counts();
+        let count_uncertainity =
+            (line.uncertainty.powi(2) + line.background_uncertainty.powi(2)).sqrt();
+
+        // a zero intensity, activity or run time has no defined efficiency, nor does a background
+        // that swallows the peak. Leave the line at zero so it is skipped by the fit instead of
+        // feeding it NaN weights or a negative efficiency
+        if intensity <= 0.0
+            || source_activity <= 0.0
+            || run_time <= 0.0
+            || line.background_exceeds_counts()
+        {
+            line.efficiency = 0.0;
+            line.efficiency_uncertainty = 0.0;
+            line.uncertainty_breakdown = UncertaintyBreakdown::default();
+            return;
+        }
 
-        let efficiency = counts / (intensity * source_activity * run_time * 0.01) * 100.0; // efficiency in percent
-        let efficiency_uncertainty = efficiency
-            * ((count_uncertainity / counts).powi(2)
-                + (intensity_uncertainty / intensity).powi(2)
-                + (activity_uncertainty / source_activity).powi(2))
-            .sqrt();
+        // efficiency per count, so the counting term does not divide by the counts. The summing
+        // and pile-up corrections turn the apparent full-energy peak efficiency into the true one
+        let summing = line.summing_correction;
+        let scale = 100.0 * summing.factor * pile_up.factor
+            / (intensity * source_activity * run_time * 0.01);
+
+        let efficiency = counts * scale; // efficiency in percent
+
+        // intensity and its uncertainty are both in percent, so the ratio is unitless
+        let breakdown = UncertaintyBreakdown {
+            counting: scale * count_uncertainity,
+            intensity: efficiency.abs() * intensity_uncertainty / intensity,
+            activity: efficiency.abs() * activity_uncertainty / source_activity,
+            summing: efficiency.abs() * summing.relative_uncertainty(),
+            pile_up: efficiency.abs() * pile_up.relative_uncertainty(),
+        };
+        let efficiency_uncertainty = (breakdown.counting.powi(2)
+            + breakdown.intensity.powi(2)
+            + breakdown.activity.powi(2)
+            + breakdown.summing.powi(2)
+            + breakdown.pile_up.powi(2))
+        .sqrt();
+
+        debug_assert!(
+            efficiency.is_finite() && efficiency_uncertainty.is_finite(),
+            "non-finite efficiency from counts {}, intensity {} ± {}",
+            counts,
+            intensity,
+            intensity_uncertainty
+        );
 
         line.efficiency = efficiency;
         line.efficiency_uncertainty = efficiency_uncertainty;
+        line.uncertainty_breakdown = breakdown;
     }
 
-    pub fn source_ui(&mut self, ui: &mut egui::Ui) {
+    // returns true when the source should be pushed to every detector line of the measurement
+    pub fn source_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        energy_unit: EnergyUnit,
+        library: &mut SourceLibrary,
+    ) -> bool {
+        let mut apply_to_detectors = false;
+
         egui::CollapsingHeader::new("Source")
             .default_open(true)
             .show(ui, |ui| {
@@ -195,8 +590,48 @@ impl GammaSource {
                     if ui.button("56Co").clicked() {
                         self.fsu_56co_source();
                     }
+
+                    let hover = "Lines and half-life only, enter the calibration activity and date from the certificate";
+
+                    if ui.button("137Cs").on_hover_text(hover).clicked() {
+                        self.fsu_137cs_source();
+                    }
+
+                    if ui.button("22Na").on_hover_text(hover).clicked() {
+                        self.fsu_22na_source();
+                    }
+
+                    if ui.button("133Ba").on_hover_text(hover).clicked() {
+                        self.fsu_133ba_source();
+                    }
                 });
 
+                library.ui(ui, self);
+
+                self.nuclide_search_ui(ui);
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Import Decay Data")
+                        .on_hover_text("Fill in the gamma lines and half-life from an ENSDF dataset or an NNDC CSV export")
+                        .clicked()
+                    {
+                        self.decay_import = Some(DecayImport::new(&self.name));
+                    }
+                });
+
+                if let Some(import) = &mut self.decay_import {
+                    let mut open = true;
+                    let imported = import.window(ui.ctx(), ui.id().with("decay_import"), &mut open);
+
+                    if let Some(data) = imported {
+                        self.import_decay_data(data);
+                        self.decay_import = None;
+                    } else if !open {
+                        self.decay_import = None;
+                    }
+                }
+
             ui.separator();
 
             egui::Grid::new("source_ui")
@@ -213,6 +648,17 @@ impl GammaSource {
                             .clamp_range(0.0..=f64::INFINITY)
                             .suffix(" years"),
                     );
+                    ui.add(
+                        egui::DragValue::new(&mut self.half_life_uncertainty)
+                            .speed(0.001)
+                            .clamp_range(0.0..=f64::INFINITY)
+                            .prefix("± ")
+                            .suffix(" years"),
+                    )
+                    .on_hover_text(format!(
+                        "Adds {:.3}% to the activity uncertainty over the time since the calibration",
+                        self.decay_relative_uncertainty() * 100.0
+                    ));
 
                     ui.end_row();
 
@@ -223,20 +669,36 @@ impl GammaSource {
                     let calibration_date = self
                         .source_activity_calibration
                         .date
-                        .get_or_insert_with(|| chrono::offset::Utc::now().date_naive());
-                    ui.add(
-                        egui_extras::DatePickerButton::new(calibration_date)
-                            .id_source("calibration_date")
-                            .highlight_weekends(false),
-                    );
+                        .get_or_insert_with(|| chrono::offset::Utc::now().naive_utc());
+                    date_time_ui(ui, calibration_date, "calibration_date");
 
                     ui.label("Activity:");
-                    ui.add(
-                        egui::DragValue::new(&mut self.source_activity_calibration.activity)
-                            .speed(1.0)
-                            .clamp_range(0.0..=f64::INFINITY)
-                            .suffix(" kBq"),
-                    );
+
+                    self.migrate_activity_uncertainty();
+
+                    ui.horizontal(|ui| {
+                        let calibration = &mut self.source_activity_calibration;
+                        let unit = calibration.unit;
+
+                        unit.drag_value(ui, &mut calibration.activity, "");
+                        unit.drag_value(ui, &mut calibration.uncertainty, "± ")
+                            .on_hover_text(format!(
+                                "Activity uncertainty from the calibration certificate ({:.2}%)",
+                                if calibration.activity > 0.0 {
+                                    100.0 * calibration.uncertainty / calibration.activity
+                                } else {
+                                    0.0
+                                }
+                            ));
+                        calibration.unit.ui(ui, "calibration_activity_unit");
+                    });
+
+                    if self.source_activity_calibration.activity > 0.0 {
+                        self.source_activity_uncertainty = self.calibration_relative_uncertainty() * 100.0;
+                    }
+
+                    ui.checkbox(&mut self.relative_only, "Relative Only")
+                        .on_hover_text("The activity is not calibrated. The global fit treats it as a free scale set by the overlap with the other sources, so only the shape of these lines counts");
 
                     ui.end_row();
 
@@ -247,12 +709,8 @@ impl GammaSource {
                     let measurement_date = self
                         .source_activity_measurement
                         .date
-                        .get_or_insert_with(|| chrono::offset::Utc::now().date_naive());
-                    ui.add(
-                        egui_extras::DatePickerButton::new(measurement_date)
-                            .id_source("measurement_date")
-                            .highlight_weekends(false),
-                    );
+                        .get_or_insert_with(|| chrono::offset::Utc::now().naive_utc());
+                    date_time_ui(ui, measurement_date, "measurement_date");
 
                     ui.label("Run Time:");
                     ui.add(
@@ -270,27 +728,42 @@ impl GammaSource {
 
                     ui.label("Activity:");
 
-                    ui.label(&format!(
-                        "{:.0} Bq",
-                        self.source_activity_measurement.activity
-                    ));
+                    let hover = format!(
+                        "{:.2}% from the calibration and {:.3}% from the half-life, in quadrature",
+                        self.calibration_relative_uncertainty() * 100.0,
+                        self.decay_relative_uncertainty() * 100.0
+                    );
 
+                    ui.horizontal(|ui| {
+                        // the decayed activity is kept in Bq
+                        let measurement = &mut self.source_activity_measurement;
 
-                    ui.add(
-                        egui::DragValue::new(&mut self.source_activity_uncertainty)
-                            .speed(0.1)
-                            .clamp_range(0.0..=100.0)
-                            .suffix("%")
-                            .prefix("± "),
-                    ).on_hover_text("Uncertainty in the source activity measurement as a percentage of the current measurement value");
+                        ui.label(measurement.unit.format(
+                            measurement.activity / 1000.0,
+                            measurement.uncertainty / 1000.0,
+                        ))
+                        .on_hover_text(hover);
+                        measurement.unit.ui(ui, "measurement_activity_unit");
+                    });
 
                     ui.end_row();
 
                     ui.end_row();
+
+                    // the emitter column only shows up once the source has daughters
+                    let emitters: Vec<String> = self
+                        .daughters
+                        .iter()
+                        .map(|daughter| daughter.name.clone())
+                        .collect();
+
                     ui.label("Energy");
                     ui.label("Intensity");
                     ui.label("");
                     ui.label("Delete");
+                    if !emitters.is_empty() {
+                        ui.label("Emitter");
+                    }
                     ui.end_row();
                     ui.label("Value");
                     ui.label("Value");
@@ -300,12 +773,29 @@ impl GammaSource {
                     let mut index_to_remove: Option<usize> = None;
 
                     for (index, gamma_line) in self.gamma_lines.iter_mut().enumerate() {
-                        gamma_line.gamma_line_ui(ui);
+                        gamma_line.gamma_line_ui(ui, energy_unit);
 
                         if ui.button("X").clicked() {
                             index_to_remove = Some(index);
                         }
 
+                        if !emitters.is_empty() {
+                            egui::ComboBox::from_id_source(("gamma_line_emitter", index))
+                                .selected_text(if gamma_line.emitter.is_empty() {
+                                    "Parent"
+                                } else {
+                                    gamma_line.emitter.as_str()
+                                })
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut gamma_line.emitter, String::new(), "Parent");
+                                    for emitter in &emitters {
+                                        ui.selectable_value(&mut gamma_line.emitter, emitter.clone(), emitter);
+                                    }
+                                })
+                                .response
+                                .on_hover_text("Nuclide the intensity is given per decay of");
+                        }
+
                         ui.end_row();
                     }
 
@@ -317,12 +807,446 @@ impl GammaSource {
                         self.gamma_lines.push(GammaLine::new());
                     }
                 });
+
+            self.daughters_ui(ui);
+
+            let duplicates = self.duplicate_energies();
+            if !duplicates.is_empty() {
+                ui.horizontal_wrapped(|ui| {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!(
+                            "⚠ Duplicate γ lines at {}",
+                            duplicates
+                                .iter()
+                                .map(|energy| energy_unit.format(*energy))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    )
+                    .on_hover_text(format!(
+                        "Lines within {} keV of each other make detector line matching ambiguous",
+                        LINE_MATCH_TOLERANCE
+                    ));
+
+                    if ui
+                        .button("Merge Duplicates")
+                        .on_hover_text("Keep the first line of each duplicate group and remove the rest")
+                        .clicked()
+                    {
+                        let removed = self.deduplicate_lines();
+                        log::info!("Removed {} duplicate γ lines from {}", removed, self.name);
+                    }
+                });
+            }
+
+            if ui
+                .button("Apply to Detectors")
+                .on_hover_text("Copy the current gamma line energies and intensities to every detector line and recompute the efficiencies")
+                .clicked()
+            {
+                apply_to_detectors = true;
+            }
         });
 
         ui.separator();
+
+        apply_to_detectors
+    }
+
+    fn daughters_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new(format!("Daughters ({})", self.daughters.len()))
+            .id_source("source_daughters")
+            .default_open(!self.daughters.is_empty())
+            .show(ui, |ui| {
+                let days = self.elapsed_days().unwrap_or(0.0);
+                let parent_activity = self.source_activity_measurement.activity / 1000.0; // kBq
+                let unit = self.source_activity_measurement.unit;
+                let mut index_to_remove: Option<usize> = None;
+
+                egui::Grid::new("source_daughters_grid")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Name");
+                        ui.label("Half-life");
+                        ui.label("Branching");
+                        ui.label("Equilibrium");
+                        ui.label("Activity");
+                        ui.end_row();
+
+                        for (index, daughter) in self.daughters.iter_mut().enumerate() {
+                            let previous_name = daughter.name.clone();
+                            if ui
+                                .add(egui::TextEdit::singleline(&mut daughter.name).desired_width(60.0))
+                                .changed()
+                            {
+                                // the lines follow the renamed daughter
+                                for line in &mut self.gamma_lines {
+                                    if line.emitter == previous_name {
+                                        line.emitter.clone_from(&daughter.name);
+                                    }
+                                }
+                            }
+                            ui.add(
+                                egui::DragValue::new(&mut daughter.half_life)
+                                    .speed(0.001)
+                                    .clamp_range(0.0..=f64::INFINITY)
+                                    .suffix(" years"),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut daughter.branching)
+                                    .speed(0.001)
+                                    .clamp_range(0.0..=1.0),
+                            );
+                            ui.checkbox(&mut daughter.equilibrium, "")
+                                .on_hover_text("In equilibrium with the parent at the calibration. Unchecked, the daughter had no activity then and grows in");
+
+                            let ratio = daughter.activity_ratio(self.half_life, days);
+                            ui.label(unit.format(parent_activity * ratio, 0.0))
+                                .on_hover_text(format!("{:.5} of the parent activity at the measurement", ratio));
+
+                            if ui.button("X").clicked() {
+                                index_to_remove = Some(index);
+                            }
+                            ui.end_row();
+                        }
+                    });
+
+                if let Some(index) = index_to_remove {
+                    let removed = self.daughters.remove(index);
+                    for line in &mut self.gamma_lines {
+                        if line.emitter == removed.name {
+                            line.emitter.clear();
+                        }
+                    }
+                }
+
+                if ui
+                    .button("Add Daughter")
+                    .on_hover_text("Lines of a daughter are given per daughter decay, e.g. 137mBa in 137Cs or the 214Pb and 214Bi lines of 226Ra")
+                    .clicked()
+                {
+                    self.daughters.push(Daughter::default());
+                }
+            });
+    }
+
+    // gamma line closest in energy to `energy` within `tolerance` keV
+    pub fn matching_gamma_line(&self, energy: f64, tolerance: f64) -> Option<&GammaLine> {
+        self.gamma_lines
+            .iter()
+            .filter(|line| (line.energy - energy).abs() <= tolerance)
+            .min_by(|a, b| {
+                (a.energy - energy)
+                    .abs()
+                    .total_cmp(&(b.energy - energy).abs())
+            })
+    }
+
+    // energies of lines that sit within the match tolerance of an earlier line
+    pub fn duplicate_energies(&self) -> Vec<f64> {
+        self.gamma_lines
+            .iter()
+            .enumerate()
+            .filter(|(index, line)| {
+                self.gamma_lines[..*index]
+                    .iter()
+                    .any(|earlier| (earlier.energy - line.energy).abs() <= LINE_MATCH_TOLERANCE)
+            })
+            .map(|(_, line)| line.energy)
+            .collect()
+    }
+
+    // keeps the first of every group of duplicate lines (and its intensity), returns how many were removed
+    pub fn deduplicate_lines(&mut self) -> usize {
+        let original_count = self.gamma_lines.len();
+        let mut kept: Vec<GammaLine> = Vec::new();
+
+        for line in self.gamma_lines.drain(..) {
+            if !kept
+                .iter()
+                .any(|earlier| (earlier.energy - line.energy).abs() <= LINE_MATCH_TOLERANCE)
+            {
+                kept.push(line);
+            }
+        }
+
+        self.gamma_lines = kept;
+        original_count - self.gamma_lines.len()
     }
 
     pub fn remove_gamma_line(&mut self, index: usize) {
         self.gamma_lines.remove(index);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::coincidence_summing::SummingCorrection;
+    use super::*;
+
+    const MINUTES_PER_YEAR: f64 = 365.25 * 24.0 * 60.0;
+
+    // 1000 Bq at the measurement, a one hour run and 5 % activity uncertainty
+    fn source() -> GammaSource {
+        let mut source = GammaSource::new();
+        source.name = "test".to_string();
+        source.half_life = 30.08;
+        source.measurement_time = 1.0;
+        source.source_activity_measurement.activity = 1000.0;
+        source
+    }
+
+    // a 50 % line with 1800 ± 0 counts, an efficiency of 0.1 % for the source above
+    fn line() -> DetectorLine {
+        DetectorLine {
+            energy: 661.657,
+            count: 1800.0,
+            intensity: 50.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn daughter_in_equilibrium_follows_branching() {
+        // 137mBa (2.552 min) fed by 94.4 % of the 137Cs decays
+        let daughter = Daughter {
+            name: "137mBa".to_string(),
+            half_life: 2.552 / MINUTES_PER_YEAR,
+            branching: 0.944,
+            equilibrium: true,
+        };
+
+        for days in [0.0, 100.0, 5000.0] {
+            let ratio = daughter.activity_ratio(30.08, days);
+            assert!(
+                (ratio - 0.944).abs() < 1e-6,
+                "ratio {} after {} days",
+                ratio,
+                days
+            );
+        }
+    }
+
+    #[test]
+    fn separated_daughter_grows_in() {
+        // 222Rn (3.8235 d) removed from 226Ra (1600 y) is half way to equilibrium after one half-life
+        let daughter = Daughter {
+            name: "222Rn".to_string(),
+            half_life: 3.8235 / 365.25,
+            branching: 1.0,
+            equilibrium: false,
+        };
+
+        assert_eq!(daughter.activity_ratio(1600.0, 0.0), 0.0);
+        assert!((daughter.activity_ratio(1600.0, 3.8235) - 0.5).abs() < 1e-4);
+        assert!((daughter.activity_ratio(1600.0, 3.8235 * 20.0) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn daughter_with_the_parent_half_life_grows_linearly() {
+        // λ t after one half-life is ln 2
+        let daughter = Daughter {
+            name: "twin".to_string(),
+            half_life: 1.0,
+            branching: 1.0,
+            equilibrium: false,
+        };
+
+        let ratio = daughter.activity_ratio(1.0, 365.25);
+        assert!((ratio - std::f64::consts::LN_2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn prompt_daughter_is_the_branching() {
+        let daughter = Daughter {
+            branching: 0.3,
+            ..Default::default()
+        };
+
+        assert_eq!(daughter.activity_ratio(10.0, 1000.0), 0.3);
+    }
+
+    #[test]
+    fn daughter_lines_use_the_daughter_activity() {
+        let mut source = source();
+        source.daughters.push(Daughter {
+            name: "137mBa".to_string(),
+            half_life: 2.552 / MINUTES_PER_YEAR,
+            branching: 0.944,
+            equilibrium: true,
+        });
+        source.gamma_lines.push(GammaLine {
+            energy: 661.657,
+            intensity: 89.9,
+            intensity_uncertainty: 0.1,
+            emitter: "137mBa".to_string(),
+        });
+
+        assert!((source.line_activity_ratio(661.657) - 0.944).abs() < 1e-6);
+        assert_eq!(source.line_activity_ratio(1000.0), 1.0);
+    }
+
+    fn lines(lines: &[(f64, f64)]) -> GammaSource {
+        let mut source = GammaSource::new();
+        source.gamma_lines = lines
+            .iter()
+            .map(|&(energy, intensity)| GammaLine {
+                energy,
+                intensity,
+                ..Default::default()
+            })
+            .collect();
+        source
+    }
+
+    #[test]
+    fn duplicates_within_the_tolerance() {
+        let source = lines(&[(344.0, 26.6), (121.0, 28.5), (344.4, 27.0), (121.0, 1.0)]);
+
+        assert_eq!(source.duplicate_energies(), vec![344.4, 121.0]);
+    }
+
+    #[test]
+    fn duplicates_exactly_at_the_tolerance() {
+        let source = lines(&[(100.0, 1.0), (100.0 + LINE_MATCH_TOLERANCE, 2.0)]);
+        assert_eq!(source.duplicate_energies(), vec![101.0]);
+
+        let source = lines(&[(100.0, 1.0), (100.0 + 1.5 * LINE_MATCH_TOLERANCE, 2.0)]);
+        assert!(source.duplicate_energies().is_empty());
+    }
+
+    #[test]
+    fn deduplicate_keeps_the_first_line_of_each_group() {
+        let mut source = lines(&[
+            (344.0, 26.6),
+            (121.0, 28.5),
+            (344.4, 27.0),
+            (779.0, 12.9),
+            (121.5, 1.0),
+        ]);
+
+        assert_eq!(source.deduplicate_lines(), 2);
+
+        let kept: Vec<(f64, f64)> = source
+            .gamma_lines
+            .iter()
+            .map(|line| (line.energy, line.intensity))
+            .collect();
+        assert_eq!(kept, vec![(344.0, 26.6), (121.0, 28.5), (779.0, 12.9)]);
+        assert!(source.duplicate_energies().is_empty());
+        assert_eq!(source.deduplicate_lines(), 0);
+    }
+
+    #[test]
+    fn efficiency_from_counts() {
+        let mut line = line();
+        source().gamma_line_efficiency_from_source_measurement(
+            &mut line,
+            None,
+            PileUpCorrection::default(),
+        );
+
+        assert!((line.efficiency - 0.1).abs() < 1e-12);
+        // only the 5 % activity uncertainty without counting or intensity errors
+        assert!((line.efficiency_uncertainty - 0.005).abs() < 1e-12);
+    }
+
+    #[test]
+    fn intensity_uncertainty_propagates_as_a_ratio() {
+        // 50 ± 1 % is a 2 % relative error whatever unit both are in
+        let mut line = line();
+        line.intensity_uncertainty = 1.0;
+        source().gamma_line_efficiency_from_source_measurement(
+            &mut line,
+            None,
+            PileUpCorrection::default(),
+        );
+
+        assert!((line.uncertainty_breakdown.intensity - 0.1 * 0.02).abs() < 1e-12);
+        assert!(line.efficiency.is_finite() && line.efficiency_uncertainty.is_finite());
+    }
+
+    #[test]
+    fn zero_intensity_leaves_the_line_out() {
+        // would be NaN and poison the fit weights
+        let mut line = line();
+        line.intensity = 0.0;
+        line.intensity_uncertainty = 1.0;
+        source().gamma_line_efficiency_from_source_measurement(
+            &mut line,
+            None,
+            PileUpCorrection::default(),
+        );
+
+        assert_eq!(line.efficiency, 0.0);
+        assert_eq!(line.efficiency_uncertainty, 0.0);
+    }
+
+    #[test]
+    fn background_above_the_counts_leaves_the_line_out() {
+        let mut line = line();
+        line.uncertainty = 40.0;
+        line.background = 900.0;
+        line.background_uncertainty = 30.0;
+        source().gamma_line_efficiency_from_source_measurement(
+            &mut line,
+            None,
+            PileUpCorrection::default(),
+        );
+
+        // half the counts are background, the counting term adds the two in quadrature
+        assert!((line.efficiency - 0.05).abs() < 1e-12);
+        assert!((line.uncertainty_breakdown.counting - 0.1 * 50.0 / 1800.0).abs() < 1e-12);
+
+        line.background = 2000.0;
+        source().gamma_line_efficiency_from_source_measurement(
+            &mut line,
+            None,
+            PileUpCorrection::default(),
+        );
+
+        assert!(line.background_exceeds_counts());
+        assert_eq!(line.efficiency, 0.0);
+        assert!(!line.is_fittable());
+    }
+
+    #[test]
+    fn live_time_replaces_the_run_time() {
+        // 30 min live in a one hour run doubles the efficiency
+        let mut line = line();
+        source().gamma_line_efficiency_from_source_measurement(
+            &mut line,
+            Some(0.5),
+            PileUpCorrection::default(),
+        );
+
+        assert!((line.efficiency - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn pile_up_and_summing_scale_the_efficiency() {
+        let mut line = line();
+        line.summing_correction = SummingCorrection {
+            factor: 1.05,
+            uncertainty: 0.021,
+        };
+        let pile_up = PileUpCorrection {
+            factor: 1.1,
+            uncertainty: 0.011,
+        };
+
+        source().gamma_line_efficiency_from_source_measurement(&mut line, None, pile_up);
+
+        let efficiency = 0.1 * 1.05 * 1.1;
+        assert!((line.efficiency - efficiency).abs() < 1e-12);
+
+        let breakdown = line.uncertainty_breakdown;
+        assert!((breakdown.summing - efficiency * 0.02).abs() < 1e-12);
+        assert!((breakdown.pile_up - efficiency * 0.01).abs() < 1e-12);
+        assert!((breakdown.activity - efficiency * 0.05).abs() < 1e-12);
+
+        let total = efficiency * (0.02f64.powi(2) + 0.01f64.powi(2) + 0.05f64.powi(2)).sqrt();
+        assert!((line.efficiency_uncertainty - total).abs() < 1e-12);
+    }
+}
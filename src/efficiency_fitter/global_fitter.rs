@@ -0,0 +1,723 @@
+use super::exp_fitter::{
+    spawn_job, ExpFitter, FitProvenance, FitResult, Fitter, JobResults, SolverReport,
+};
+use super::fit_model::{Exponential, Model, SolverSettings};
+
+use std::collections::HashMap;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+use nalgebra::{DMatrix, DVector};
+use varpro::model::builder::SeparableModelBuilder;
+use varpro::solvers::levmar::{LevMarProblemBuilder, LevMarSolver};
+
+// alternating fits of the shape and the relative source scales, and the change that ends them
+const SCALE_ITERATIONS: usize = 50;
+const SCALE_TOLERANCE: f64 = 1e-6;
+
+// what one varpro run of the global model returns, flattened so the scale loop can rerun it
+struct GlobalSolution {
+    linear_parameters: Vec<f64>,
+    linear_variances: Vec<f64>,
+    nonlinear_parameters: Vec<f64>,
+    nonlinear_variances: Vec<f64>,
+    covariance_matrix: DMatrix<f64>,
+    correlation_matrix: DMatrix<f64>,
+    weighted_residuals: Vec<f64>,
+    reduced_chi_squared: f64,
+    regression_standard_error: f64,
+    solver: SolverReport,
+}
+
+impl GlobalSolution {
+    // curve of `detector` at x, in the units of the (scaled) data
+    fn evaluate(&self, detector: usize, terms: usize, x: f64) -> f64 {
+        (0..terms)
+            .map(|term| {
+                self.linear_parameters[detector * terms + term]
+                    * (-x / self.nonlinear_parameters[term]).exp()
+            })
+            .sum()
+    }
+}
+
+// Fits every detector at once with shared decay constants (b, d) and per-detector amplitudes (a, c).
+// Each basis function is masked to a single detector's rows, so varpro solves for one amplitude per
+// detector while the nonlinear parameters are common to all of them.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct GlobalFitter {
+    pub initial_b_guess: f64,
+    pub initial_d_guess: f64,
+    pub shared_parameters: Vec<(f64, f64)>,
+    pub reduced_chi_squared: Option<f64>,
+    #[serde(default)]
+    pub solver: SolverSettings,
+    #[serde(default)]
+    pub source_scales: Vec<(String, f64, f64)>, // (source, activity factor, uncertainty) of the relative only sources
+    #[serde(skip)]
+    pub fit_error: Option<String>,
+    #[serde(skip)]
+    job: Option<GlobalJob>,
+}
+
+// the global fit running off the UI thread, the fitter with the shared results and the
+// per-detector fits arrive together
+#[derive(Clone)]
+struct GlobalJob {
+    double: bool,
+    receiver: Arc<Mutex<Receiver<(GlobalFitter, JobResults)>>>,
+}
+
+impl Default for GlobalFitter {
+    fn default() -> Self {
+        Self {
+            initial_b_guess: 500.0,
+            initial_d_guess: 5000.0,
+            shared_parameters: Vec::new(),
+            reduced_chi_squared: None,
+            solver: SolverSettings::default(),
+            source_scales: Vec::new(),
+            fit_error: None,
+            job: None,
+        }
+    }
+}
+
+impl GlobalFitter {
+    fn masked(
+        function: fn(&DVector<f64>, f64) -> DVector<f64>,
+        mask: DVector<f64>,
+    ) -> impl Fn(&DVector<f64>, f64) -> DVector<f64> {
+        move |x: &DVector<f64>, parameter: f64| function(x, parameter).component_mul(&mask)
+    }
+
+    fn fit_failed(&mut self, message: &str) {
+        log::error!("{}", message);
+        self.fit_error = Some(message.to_string());
+    }
+
+    // The shared exponential shape fit by varpro. Each basis function is masked to one detector's
+    // rows, so the linear coefficients are the per-detector amplitudes
+    fn solve(
+        &self,
+        x_data: &[f64],
+        y_data: DVector<f64>,
+        weights: DVector<f64>,
+        masks: &[DVector<f64>],
+        double: bool,
+    ) -> Result<GlobalSolution, String> {
+        let (parameter_names, initial_parameters) = if double {
+            (
+                vec!["b".to_string(), "d".to_string()],
+                vec![self.initial_b_guess, self.initial_d_guess],
+            )
+        } else {
+            (vec!["b".to_string()], vec![self.initial_b_guess])
+        };
+
+        let mut builder_proxy = SeparableModelBuilder::<f64>::new(parameter_names)
+            .initial_parameters(initial_parameters)
+            .independent_variable(DVector::from_vec(x_data.to_vec()))
+            .function(
+                &["b"],
+                Self::masked(Exponential::exponential, masks[0].clone()),
+            )
+            .partial_deriv(
+                "b",
+                Self::masked(Exponential::exponential_pd, masks[0].clone()),
+            );
+
+        if double {
+            builder_proxy = builder_proxy
+                .function(
+                    &["d"],
+                    Self::masked(Exponential::exponential, masks[0].clone()),
+                )
+                .partial_deriv(
+                    "d",
+                    Self::masked(Exponential::exponential_pd, masks[0].clone()),
+                );
+        }
+
+        for mask in masks.iter().skip(1) {
+            builder_proxy = builder_proxy
+                .function(&["b"], Self::masked(Exponential::exponential, mask.clone()))
+                .partial_deriv("b", Self::masked(Exponential::exponential_pd, mask.clone()));
+
+            if double {
+                builder_proxy = builder_proxy
+                    .function(&["d"], Self::masked(Exponential::exponential, mask.clone()))
+                    .partial_deriv("d", Self::masked(Exponential::exponential_pd, mask.clone()));
+            }
+        }
+
+        let model = builder_proxy
+            .build()
+            .map_err(|err| format!("Error building global model: {}", err))?;
+
+        let problem = LevMarProblemBuilder::new(model)
+            .observations(y_data)
+            .weights(weights)
+            .build()
+            .map_err(|err| format!("Error building global problem: {}", err))?;
+
+        let (fit_result, fit_statistics) = LevMarSolver::with_solver(
+            self.solver.levenberg_marquardt(),
+        )
+        .fit_with_statistics(problem)
+        .map_err(|failed| {
+            let report = &failed.minimization_report;
+            format!(
+                "Global fit did not converge ({:?} after {} evaluations), try different initial guesses",
+                report.termination, report.number_of_evaluations
+            )
+        })?;
+
+        let report = &fit_result.minimization_report;
+        let solver = SolverReport::new(
+            report.termination.was_successful(),
+            format!("{:?}", report.termination),
+            report.number_of_evaluations,
+            2.0 * report.objective_function,
+        );
+
+        let linear_parameters: Vec<f64> = fit_result
+            .linear_coefficients()
+            .ok_or_else(|| "Solver returned no linear coefficients".to_string())?
+            .iter()
+            .cloned()
+            .collect();
+
+        Ok(GlobalSolution {
+            linear_parameters,
+            linear_variances: fit_statistics
+                .linear_coefficients_variance()
+                .iter()
+                .cloned()
+                .collect(),
+            nonlinear_parameters: fit_result.nonlinear_parameters().iter().cloned().collect(),
+            nonlinear_variances: fit_statistics
+                .nonlinear_parameters_variance()
+                .iter()
+                .cloned()
+                .collect(),
+            covariance_matrix: fit_statistics.covariance_matrix().clone(),
+            correlation_matrix: fit_statistics.calculate_correlation_matrix(),
+            weighted_residuals: fit_statistics
+                .weighted_residuals()
+                .iter()
+                .cloned()
+                .collect(),
+            reduced_chi_squared: fit_statistics.reduced_chi2(),
+            regression_standard_error: fit_statistics.regression_standard_error(),
+            solver,
+        })
+    }
+
+    // Points of relative only sources are fit as scale * curve with one free scale per source.
+    // The scale times the amplitudes is not separable for varpro, so the shape fit and the scales
+    // alternate: the shape is fit with the points divided by their scale, then each scale is set
+    // to the weighted least squares factor between its points and the curve, until they settle
+    pub fn fit(&mut self, fitters: &HashMap<String, Fitter>, double: bool) -> JobResults {
+        self.shared_parameters.clear();
+        self.source_scales.clear();
+        self.reduced_chi_squared = None;
+        self.fit_error = None;
+
+        // sort the names so the ordering of the linear coefficients is deterministic
+        let mut names: Vec<String> = fitters
+            .iter()
+            .filter(|(_, fitter)| !fitter.data.0.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+
+        if names.is_empty() {
+            self.fit_failed("No detector data to fit");
+            return Vec::new();
+        }
+
+        let mut x_data: Vec<f64> = Vec::new();
+        let mut y_data: Vec<f64> = Vec::new();
+        let mut weights: Vec<f64> = Vec::new();
+        let mut detector_index: Vec<usize> = Vec::new();
+        let mut row_sources: Vec<Option<String>> = Vec::new();
+
+        for (index, name) in names.iter().enumerate() {
+            let (x, y, w) = fitters[name].scaled_data();
+            let sources = fitters[name].scaled_relative_sources();
+            row_sources.extend((0..x.len()).map(|i| sources.get(i).cloned().flatten()));
+            x_data.extend(&x);
+            y_data.extend(&y);
+            weights.extend(&w);
+            detector_index.extend(std::iter::repeat(index).take(x.len()));
+        }
+
+        let mut relative_names: Vec<String> = row_sources.iter().flatten().cloned().collect();
+        relative_names.sort();
+        relative_names.dedup();
+        let source_index: Vec<Option<usize>> = row_sources
+            .iter()
+            .map(|source| {
+                source
+                    .as_ref()
+                    .and_then(|source| relative_names.iter().position(|name| name == source))
+            })
+            .collect();
+
+        // a scale is only defined through a detector that also saw a source of known activity
+        for (j, source) in relative_names.iter().enumerate() {
+            let anchored = (0..names.len()).any(|detector| {
+                let rows = || (0..x_data.len()).filter(|&i| detector_index[i] == detector);
+                rows().any(|i| source_index[i] == Some(j))
+                    && rows().any(|i| source_index[i].is_none())
+            });
+
+            if !anchored {
+                self.fit_failed(&format!(
+                    "{} shares no detector with a source of known activity, its scale cannot be fit",
+                    source
+                ));
+                return Vec::new();
+            }
+        }
+
+        let masks: Vec<DVector<f64>> = (0..names.len())
+            .map(|index| {
+                DVector::from_iterator(
+                    detector_index.len(),
+                    detector_index
+                        .iter()
+                        .map(|&i| if i == index { 1.0 } else { 0.0 }),
+                )
+            })
+            .collect();
+
+        let terms = if double { 2 } else { 1 };
+        let initial_model = Model::Exponential(Exponential::new(
+            [self.initial_b_guess, self.initial_d_guess][..terms].to_vec(),
+        ));
+        let n_rows = x_data.len();
+
+        let mut scales = vec![1.0; relative_names.len()];
+        let mut iterations = 0;
+        let (mut solution, scaled_y, scaled_weights) = loop {
+            let scale = |row: usize| source_index[row].map_or(1.0, |j| scales[j]);
+            let scaled_y: Vec<f64> = (0..n_rows).map(|i| y_data[i] / scale(i)).collect();
+            let scaled_weights: Vec<f64> = (0..n_rows).map(|i| weights[i] * scale(i)).collect();
+
+            let solution = match self.solve(
+                &x_data,
+                DVector::from_vec(scaled_y.clone()),
+                DVector::from_vec(scaled_weights.clone()),
+                &masks,
+                double,
+            ) {
+                Ok(solution) => solution,
+                Err(err) => {
+                    self.fit_failed(&err);
+                    return Vec::new();
+                }
+            };
+            iterations += 1;
+
+            if scales.is_empty() {
+                break (solution, scaled_y, scaled_weights);
+            }
+
+            let mut change: f64 = 0.0;
+            for (j, scale) in scales.iter_mut().enumerate() {
+                let (numerator, denominator) = (0..n_rows)
+                    .filter(|&i| source_index[i] == Some(j))
+                    .fold((0.0, 0.0), |(numerator, denominator), i| {
+                        let curve = solution.evaluate(detector_index[i], terms, x_data[i]);
+                        let w2 = weights[i] * weights[i];
+                        (
+                            numerator + w2 * y_data[i] * curve,
+                            denominator + w2 * curve * curve,
+                        )
+                    });
+
+                if denominator > 0.0 && numerator > 0.0 {
+                    let updated = numerator / denominator;
+                    change = change.max((updated / *scale - 1.0).abs());
+                    *scale = updated;
+                }
+            }
+
+            if change < SCALE_TOLERANCE {
+                break (solution, scaled_y, scaled_weights);
+            }
+
+            if iterations >= SCALE_ITERATIONS {
+                log::warn!(
+                    "Source scales still changed by {:.1e} after {} global fits",
+                    change,
+                    iterations
+                );
+                break (solution, scaled_y, scaled_weights);
+            }
+        };
+
+        let n_linear = solution.linear_parameters.len();
+
+        // every detector's band uses the dof of the whole fit, a detector can have fewer points
+        // than its own amplitudes and the shared shape
+        let degrees_of_freedom = n_rows as f64 - (n_linear + terms + scales.len()) as f64;
+        if degrees_of_freedom <= 0.0 {
+            self.fit_failed(&format!(
+                "{} points cannot fit {} amplitudes, {} decay constants and {} source scales",
+                n_rows,
+                n_linear,
+                terms,
+                scales.len()
+            ));
+            return Vec::new();
+        }
+
+        // varpro does not know about the source scales, so its χ²/ν and the covariance scaled by
+        // it are recomputed with the scales counted as fit parameters
+        let chi_squared: f64 = solution.weighted_residuals.iter().map(|r| r * r).sum();
+        let reduced_chi_squared = chi_squared / degrees_of_freedom;
+        if solution.reduced_chi_squared > 0.0 {
+            let factor = reduced_chi_squared / solution.reduced_chi_squared;
+            solution.covariance_matrix *= factor;
+            for variance in solution
+                .linear_variances
+                .iter_mut()
+                .chain(solution.nonlinear_variances.iter_mut())
+            {
+                *variance *= factor;
+            }
+        }
+        solution.reduced_chi_squared = reduced_chi_squared;
+        solution.regression_standard_error = reduced_chi_squared.sqrt();
+
+        // error of each scale with the shape held fixed, scaled by the reduced χ² like the fit
+        self.source_scales = relative_names
+            .iter()
+            .enumerate()
+            .map(|(j, name)| {
+                let information: f64 = (0..n_rows)
+                    .filter(|&i| source_index[i] == Some(j))
+                    .map(|i| {
+                        (weights[i] * solution.evaluate(detector_index[i], terms, x_data[i]))
+                            .powi(2)
+                    })
+                    .sum();
+                let uncertainty = (solution.reduced_chi_squared / information).sqrt();
+                log::info!(
+                    "{} activity scale: {:.4} ± {:.4}",
+                    name,
+                    scales[j],
+                    uncertainty
+                );
+                (name.clone(), scales[j], uncertainty)
+            })
+            .collect();
+
+        self.shared_parameters = solution
+            .nonlinear_parameters
+            .iter()
+            .zip(solution.nonlinear_variances.iter())
+            .map(|(value, variance)| (*value, variance.sqrt()))
+            .collect();
+        self.reduced_chi_squared = Some(solution.reduced_chi_squared);
+
+        let mut results = Vec::new();
+        for (index, name) in names.iter().enumerate() {
+            // covariance ordering is the linear coefficients followed by the nonlinear parameters
+            let linear_indices: Vec<usize> = (0..terms).map(|term| index * terms + term).collect();
+            let parameter_indices: Vec<usize> = linear_indices
+                .iter()
+                .cloned()
+                .chain((0..terms).map(|term| n_linear + term))
+                .collect();
+
+            let sub_matrix = |matrix: &nalgebra::DMatrix<f64>| -> Vec<f64> {
+                parameter_indices
+                    .iter()
+                    .flat_map(|&i| parameter_indices.iter().map(move |&j| matrix[(i, j)]))
+                    .collect()
+            };
+
+            let rows: Vec<usize> = (0..n_rows)
+                .filter(|&i| detector_index[i] == index)
+                .collect();
+
+            let mut result = FitResult {
+                linear_parameters: linear_indices
+                    .iter()
+                    .map(|&i| solution.linear_parameters[i])
+                    .collect(),
+                linear_variances: linear_indices
+                    .iter()
+                    .map(|&i| solution.linear_variances[i])
+                    .collect(),
+                nonlinear_parameters: solution.nonlinear_parameters.clone(),
+                nonlinear_variances: solution.nonlinear_variances.clone(),
+                covariance_matrix: sub_matrix(&solution.covariance_matrix),
+                correlation_matrix: sub_matrix(&solution.correlation_matrix),
+                reduced_chi_squared: solution.reduced_chi_squared,
+                regression_standard_error: solution.regression_standard_error,
+                weighted_residuals: rows
+                    .iter()
+                    .map(|&i| solution.weighted_residuals[i])
+                    .collect(),
+                fitted_at: Some(chrono::Utc::now()),
+                fixed_parameters: Vec::new(),
+                solver: Some(solution.solver.clone()),
+                degrees_of_freedom: Some(degrees_of_freedom),
+                ..Default::default()
+            };
+
+            if let Some(fitter) = fitters.get(name) {
+                // the points of relative only sources are stored at their fitted scale
+                let mut exp_fitter = ExpFitter::new(
+                    rows.iter().map(|&i| x_data[i]).collect(),
+                    rows.iter().map(|&i| scaled_y[i]).collect(),
+                    rows.iter().map(|&i| scaled_weights[i]).collect(),
+                );
+                exp_fitter.solver = self.solver;
+                exp_fitter.sampling = fitter.sampling;
+
+                let mut provenance =
+                    FitProvenance::new(&initial_model, &[], &exp_fitter, result.fitted_at);
+                provenance.weighting = Some(fitter.weighting);
+                provenance.error_scale = fitter.error_scale;
+                result.provenance = Some(provenance);
+
+                exp_fitter.fit_params = Some(result.exponential_terms());
+                exp_fitter.model = Some(Model::Exponential(Exponential::new(
+                    result.nonlinear_parameters.clone(),
+                )));
+                exp_fitter.fit_result = Some(result);
+                exp_fitter.update_fit_line();
+                exp_fitter.sanity_check();
+
+                results.push((name.clone(), exp_fitter));
+            }
+        }
+
+        results
+    }
+
+    // Runs the global fit on a copy of the detector data off the UI thread, `poll_job` stores the
+    // result as each detector's "Global" fit
+    fn start_fit(&mut self, fitters: &HashMap<String, Fitter>, double: bool) {
+        let mut global = self.clone();
+        global.job = None;
+        let fitters = fitters.clone();
+
+        self.fit_error = None;
+        self.job = Some(GlobalJob {
+            double,
+            receiver: Arc::new(Mutex::new(spawn_job(move || {
+                let results = global.fit(&fitters, double);
+                (global, results)
+            }))),
+        });
+    }
+
+    // stores the finished global fit, returns true while it is still running
+    pub fn poll_job(&mut self, fitters: &mut HashMap<String, Fitter>) -> bool {
+        let job = match &self.job {
+            Some(job) => job.clone(),
+            None => return false,
+        };
+
+        let received = match job.receiver.lock() {
+            Ok(receiver) => receiver.try_recv(),
+            Err(_) => Err(TryRecvError::Disconnected),
+        };
+
+        match received {
+            Ok((global, results)) => {
+                self.job = None;
+                self.shared_parameters = global.shared_parameters;
+                self.source_scales = global.source_scales;
+                self.reduced_chi_squared = global.reduced_chi_squared;
+                self.fit_error = global.fit_error;
+
+                for (name, exp_fitter) in results {
+                    if let Some(fitter) = fitters.get_mut(&name) {
+                        fitter.insert_fit("Global", exp_fitter);
+                    }
+                }
+                false
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => {
+                self.job = None;
+                self.fit_failed("Global fit was interrupted");
+                false
+            }
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, fitters: &mut HashMap<String, Fitter>) {
+        ui.horizontal(|ui| {
+            ui.label("Global Shape Fit:")
+                .on_hover_text("Fit all detectors at once with shared decay constants (b, d) and per-detector amplitudes (a, c)");
+
+            ui.add(
+                egui::DragValue::new(&mut self.initial_b_guess)
+                    .prefix("b: ")
+                    .speed(10.0)
+                    .clamp_range(0.0..=f64::INFINITY),
+            );
+
+            ui.add(
+                egui::DragValue::new(&mut self.initial_d_guess)
+                    .prefix("d: ")
+                    .speed(10.0)
+                    .clamp_range(0.0..=f64::INFINITY),
+            );
+
+            let idle = self.job.is_none();
+            if ui.add_enabled(idle, egui::Button::new("Single")).clicked() {
+                self.start_fit(fitters, false);
+            }
+
+            if ui.add_enabled(idle, egui::Button::new("Double")).clicked() {
+                self.start_fit(fitters, true);
+            }
+
+            if let Some(job) = &self.job {
+                ui.spinner();
+                ui.label(if job.double {
+                    "Fitting double…"
+                } else {
+                    "Fitting single…"
+                });
+            }
+
+            ui.menu_button("Solver", |ui| {
+                self.solver.ui(ui);
+            });
+
+            let names = ["b", "d"];
+            for (name, (value, uncertainty)) in names.iter().zip(self.shared_parameters.iter()) {
+                ui.label(format!("{}: {:.1e} ± {:.1e}", name, value, uncertainty));
+            }
+
+            if let Some(rchi2) = self.reduced_chi_squared {
+                ui.label(format!("χ²/ν: {:.3}", rchi2));
+            }
+
+            for (source, scale, uncertainty) in &self.source_scales {
+                ui.label(format!("{} activity × {:.4} ± {:.4}", source, scale, uncertainty))
+                    .on_hover_text("Fitted activity over the entered one. The points of this source are plotted at the entered activity");
+            }
+
+            if let Some(fit_error) = &self.fit_error {
+                ui.colored_label(ui.visuals().error_fg_color, format!("✖ {}", fit_error));
+            }
+        });
+
+        if self.shared_parameters.is_empty() {
+            return;
+        }
+
+        // the per-detector results are stored as each detector's "Global" fit
+        let mut names: Vec<&String> = fitters
+            .iter()
+            .filter(|(_, fitter)| fitter.fits.contains_key("Global"))
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        egui::CollapsingHeader::new("Global Fit Amplitudes")
+            .id_source("Global Fit Amplitudes")
+            .show(ui, |ui| {
+                for name in names {
+                    let fit = &fitters[name].fits["Global"];
+                    let n_linear = fit
+                        .fit_result
+                        .as_ref()
+                        .map_or(0, |result| result.linear_parameters.len());
+
+                    let amplitudes: Vec<String> = fit
+                        .parameters()
+                        .iter()
+                        .take(n_linear)
+                        .map(|(parameter, value, uncertainty)| {
+                            format!("{}: {:.3e} ± {:.1e}", parameter, value, uncertainty)
+                        })
+                        .collect();
+
+                    ui.label(format!("{}: {}", name, amplitudes.join(", ")));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_fit_reports_back_through_its_job() {
+        let mut fitters = HashMap::from([("A".to_string(), Fitter::default())]);
+        let mut global = GlobalFitter::default();
+
+        global.start_fit(&fitters, false);
+        while global.poll_job(&mut fitters) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        assert!(global.job.is_none());
+        assert_eq!(global.fit_error.as_deref(), Some("No detector data to fit"));
+        assert!(fitters["A"].fits.is_empty());
+    }
+
+    #[test]
+    fn global_fit_recovers_the_activity_of_a_relative_source() {
+        // y = a exp(-x/300) per detector, the relative only source is entered 25% too active
+        let detector = |amplitude: f64| {
+            let mut fitter = Fitter::default();
+            for (index, x) in [80.0, 150.0, 250.0, 400.0, 600.0, 900.0, 1200.0, 1400.0]
+                .into_iter()
+                .enumerate()
+            {
+                let relative = index % 2 == 1;
+                let scale = if relative { 0.8 } else { 1.0 };
+                let y = scale * amplitude * (-x / 300.0f64).exp();
+                fitter.data.0.push(x);
+                fitter.data.1.push(y);
+                fitter.data.2.push(1.0 / (0.01 * y));
+                fitter
+                    .relative_sources
+                    .push(relative.then(|| "152Eu".to_string()));
+            }
+            fitter
+        };
+        let fitters = HashMap::from([
+            ("A".to_string(), detector(10.0)),
+            ("B".to_string(), detector(6.0)),
+        ]);
+
+        let mut global = GlobalFitter {
+            initial_b_guess: 250.0,
+            ..Default::default()
+        };
+        let results = global.fit(&fitters, false);
+
+        assert_eq!(global.fit_error, None);
+        let (source, scale, uncertainty) = &global.source_scales[0];
+        assert_eq!(source, "152Eu");
+        assert!((scale - 0.8).abs() < 1e-4, "scale {}", scale);
+        assert!(uncertainty.is_finite());
+        assert!((global.shared_parameters[0].0 - 300.0).abs() < 1e-2);
+
+        // 16 points, two amplitudes, one decay constant and one scale
+        let result = results[0].1.fit_result.as_ref().unwrap();
+        assert_eq!(result.degrees_of_freedom, Some(12.0));
+        let chi_squared: f64 = results
+            .iter()
+            .flat_map(|(_, fit)| fit.fit_result.as_ref().unwrap().weighted_residuals.clone())
+            .map(|r| r * r)
+            .sum();
+        assert!((result.reduced_chi_squared - chi_squared / 12.0).abs() < 1e-12);
+    }
+}
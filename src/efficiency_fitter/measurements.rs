@@ -1,17 +1,55 @@
-use super::detector::Detector;
-use super::exp_fitter::Fitter;
-use super::gamma_source::GammaSource;
+use super::detector::{Detector, DetectorLine, PointLabel};
+use super::detector_comparison::DetectorComparison;
+use super::exp_fitter::{percentile_band, spawn_job, BatchModel, ExpFitter, Fitter};
+#[cfg(not(target_arch = "wasm32"))]
+use super::fit_report::BatchReport;
+use super::gamma_source::{GammaSource, LINE_MATCH_TOLERANCE};
+use super::global_fitter::GlobalFitter;
+use super::pile_up::PileUpCorrection;
+use super::source_library::SourceLibrary;
+use super::units::{EnergyUnit, UncertaintyDisplay};
 
 use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
 
 use egui_plot::Plot;
+use rand::distributions::Distribution;
 
-use crate::egui_plot_stuff::{egui_line::EguiLine, plot_settings::EguiPlotSettings};
+use crate::egui_plot_stuff::{
+    egui_line::EguiLine,
+    plot_settings::{decimate, EguiPlotSettings},
+};
+
+// screen distance (points) within which a click on the plot picks a data point
+const CLICK_RADIUS: f32 = 8.0;
+
+// (measurement index, detector name, energy bits) of a line. Lines are kept sorted by energy, so
+// their position moves when one is added, this does not
+type ExclusionKey = (usize, String, u64);
+
+// parameter draws per detector for the Monte Carlo band of the summed efficiency
+const SUMMED_DRAWS: usize = 2000;
+
+// (energies, [energy, lower, upper] percentiles) of a sampled summed band
+type SummedBand = (Vec<f64>, Vec<[f64; 3]>);
 
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub struct Measurement {
     pub gamma_source: GammaSource,
     pub detectors: Vec<Detector>,
+    #[serde(default = "default_include_in_fits")]
+    pub include_in_fits: bool,
+    #[serde(default)]
+    pub pile_up: Option<PileUpCorrection>,
+}
+
+fn default_outlier_threshold() -> f64 {
+    3.0
+}
+
+fn default_include_in_fits() -> bool {
+    true
 }
 
 impl Measurement {
@@ -19,10 +57,22 @@ impl Measurement {
         Self {
             gamma_source: source.unwrap_or_default(),
             detectors: vec![],
+            include_in_fits: true,
+            pile_up: None,
         }
     }
 
-    pub fn measurement_ui(&mut self, ui: &mut egui::Ui) {
+    // the pile-up factor applied to the lines, one when the run has none
+    pub fn pile_up_correction(&self) -> PileUpCorrection {
+        self.pile_up.unwrap_or_default()
+    }
+
+    pub fn measurement_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        energy_unit: EnergyUnit,
+        uncertainty_display: UncertaintyDisplay,
+    ) {
         egui::CollapsingHeader::new("Measurement")
             .id_source(format!("{} Measurement", self.gamma_source.name))
             .default_open(true)
@@ -33,10 +83,32 @@ impl Measurement {
                     return;
                 }
 
+                ui.horizontal(|ui| {
+                    let mut pile_up = self.pile_up.is_some();
+                    if ui
+                        .checkbox(&mut pile_up, "Pile-up Correction")
+                        .on_hover_text("Factor (true over observed peak counts) for a high-rate run, applied to the efficiency of every detector line of this measurement")
+                        .changed()
+                    {
+                        self.pile_up = pile_up.then(PileUpCorrection::default);
+                    }
+
+                    if let Some(pile_up) = &mut self.pile_up {
+                        pile_up.ui(ui);
+                    }
+                });
+
+                let pile_up = self.pile_up_correction();
                 let mut index_to_remove = None;
 
                 for (index, detector) in &mut self.detectors.iter_mut().enumerate() {
-                    detector.ui(ui, &self.gamma_source);
+                    detector.ui(
+                        ui,
+                        &self.gamma_source,
+                        pile_up,
+                        energy_unit,
+                        uncertainty_display,
+                    );
 
                     if detector.to_remove == Some(true) {
                         index_to_remove = Some(index);
@@ -57,28 +129,85 @@ impl Measurement {
             });
     }
 
-    pub fn update_ui(&mut self, ui: &mut egui::Ui, index: usize) {
+    pub fn update_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        index: usize,
+        energy_unit: EnergyUnit,
+        uncertainty_display: UncertaintyDisplay,
+        library: &mut SourceLibrary,
+    ) {
         egui::CollapsingHeader::new(format!("{} Measurement", self.gamma_source.name))
             .id_source(index)
             .default_open(true)
             .show(ui, |ui| {
-                self.gamma_source.source_ui(ui);
-                self.measurement_ui(ui);
+                ui.checkbox(&mut self.include_in_fits, "Include in Fits")
+                    .on_hover_text("Use this source's lines in the detector fits. Unchecked sources are still shown on the plot");
+
+                if self.gamma_source.source_ui(ui, energy_unit, library) {
+                    self.apply_source_to_detectors();
+                }
+                self.measurement_ui(ui, energy_unit, uncertainty_display);
             });
     }
 
-    pub fn draw(&mut self, plot_ui: &mut egui_plot::PlotUi) {
+    // Pushes the source's gamma lines to every detector line and recomputes the efficiencies right
+    // away. Lines are matched to the closest gamma line within LINE_MATCH_TOLERANCE so small
+    // energy edits follow
+    pub fn apply_source_to_detectors(&mut self) {
+        let pile_up = self.pile_up_correction();
+
+        for detector in &mut self.detectors {
+            for line in &mut detector.lines {
+                match self
+                    .gamma_source
+                    .matching_gamma_line(line.energy, LINE_MATCH_TOLERANCE)
+                {
+                    Some(gamma_line) => {
+                        line.energy = gamma_line.energy;
+                        line.intensity = gamma_line.intensity;
+                        line.intensity_uncertainty = gamma_line.intensity_uncertainty;
+                    }
+                    None => log::warn!(
+                        "{}: no {} gamma line near {:.1} keV",
+                        detector.name,
+                        self.gamma_source.name,
+                        line.energy
+                    ),
+                }
+
+                if !detector.direct_efficiency {
+                    self.gamma_source
+                        .gamma_line_efficiency_from_source_measurement(
+                            line,
+                            detector.live_time,
+                            pile_up,
+                        );
+                }
+            }
+
+            detector.sort_lines();
+        }
+    }
+
+    pub fn draw(
+        &mut self,
+        plot_ui: &mut egui_plot::PlotUi,
+        point_label: PointLabel,
+        energy_unit: EnergyUnit,
+    ) {
         for detector in self.detectors.iter_mut() {
             let name = format!("{}: {}", detector.name, self.gamma_source.name);
             detector.points.name.clone_from(&name);
             detector.draw(plot_ui, Some(name));
+            detector.draw_labels(plot_ui, point_label, &self.gamma_source.name, energy_unit);
         }
     }
 
-    pub fn menu_button(&mut self, ui: &mut egui::Ui) {
+    pub fn menu_button(&mut self, ui: &mut egui::Ui, energy_unit: EnergyUnit) {
         ui.menu_button(format!("{} Measurement", self.gamma_source.name), |ui| {
             for detector in self.detectors.iter_mut() {
-                detector.menu_button(ui);
+                detector.menu_button(ui, energy_unit, &self.gamma_source.name);
             }
         });
     }
@@ -91,6 +220,8 @@ pub struct SummedEfficiency {
     pub uncertainty_lower_points: Vec<[f64; 2]>,
     pub uncertainty_upper_points: Vec<[f64; 2]>,
     pub max_energy: f64,
+    #[serde(default)]
+    pub monte_carlo: bool, // band from sampled fit parameters instead of the quadrature sum
 }
 
 impl SummedEfficiency {
@@ -104,23 +235,24 @@ impl SummedEfficiency {
             uncertainty_lower_points: vec![],
             uncertainty_upper_points: vec![],
             max_energy: 0.0,
+            monte_carlo: false,
         }
     }
 
-    pub fn draw(&mut self, plot_ui: &mut egui_plot::PlotUi) {
+    pub fn draw(&mut self, plot_ui: &mut egui_plot::PlotUi, band_decimation: usize) {
         self.line.draw(plot_ui);
 
         if self.line.draw {
-            let upper_uncertainity_plot_points: Vec<egui_plot::PlotPoint> = self
-                .uncertainty_upper_points
-                .iter()
-                .map(|[x, y]| egui_plot::PlotPoint::new(*x, *y))
-                .collect();
-            let lower_uncertainity_plot_points: Vec<egui_plot::PlotPoint> = self
-                .uncertainty_lower_points
-                .iter()
-                .map(|[x, y]| egui_plot::PlotPoint::new(*x, *y))
-                .collect();
+            let upper_uncertainity_plot_points: Vec<egui_plot::PlotPoint> =
+                decimate(&self.uncertainty_upper_points, band_decimation)
+                    .iter()
+                    .map(|[x, y]| egui_plot::PlotPoint::new(*x, *y))
+                    .collect();
+            let lower_uncertainity_plot_points: Vec<egui_plot::PlotPoint> =
+                decimate(&self.uncertainty_lower_points, band_decimation)
+                    .iter()
+                    .map(|[x, y]| egui_plot::PlotPoint::new(*x, *y))
+                    .collect();
 
             // check is number of points is the greater than 4
             if upper_uncertainity_plot_points.len() < 2 {
@@ -152,14 +284,19 @@ impl SummedEfficiency {
         }
     }
 
-    pub fn csv_points(&self) -> String {
+    pub fn csv_points(&self, energy_unit: EnergyUnit) -> String {
         let mut csv = String::new();
 
-        csv.push_str("Energy, Efficiency, Uncertainity\n");
+        csv.push_str(&format!(
+            "Energy [{}], Efficiency [%], Uncertainity [% abs]\n",
+            energy_unit.label()
+        ));
         for (index, point) in self.line.points.iter().enumerate() {
             csv.push_str(&format!(
                 "{}, {}, {}\n",
-                point[0], point[1], self.uncertainty[index]
+                energy_unit.scale_from_kev(point[0]),
+                point[1],
+                self.uncertainty[index]
             ));
         }
 
@@ -167,12 +304,225 @@ impl SummedEfficiency {
     }
 }
 
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct EfficiencyCalculator {
+    pub energy: f64, // keV
+    pub sigma: f64,
+    #[serde(default)]
+    pub correlated_activity: bool,
+}
+
+impl Default for EfficiencyCalculator {
+    fn default() -> Self {
+        Self {
+            energy: 1000.0,
+            sigma: 1.0,
+            correlated_activity: false,
+        }
+    }
+}
+
+impl EfficiencyCalculator {
+    // two-tailed probability covered by +/- sigma
+    pub fn confidence_level(&self) -> f64 {
+        statrs::function::erf::erf(self.sigma / std::f64::consts::SQRT_2) * 100.0
+    }
+
+    pub fn sigma_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut self.sigma)
+                    .speed(0.01)
+                    .clamp_range(0.1..=5.0)
+                    .suffix("σ"),
+            );
+
+            if ui.button("1σ").clicked() {
+                self.sigma = 1.0;
+            }
+
+            if ui.button("2σ").clicked() {
+                self.sigma = 2.0;
+            }
+
+            if ui.button("95%").clicked() {
+                self.sigma = 1.959964;
+            }
+
+            ui.label(format!("({:.1}% CL)", self.confidence_level()));
+        });
+    }
+}
+
+// Array-summed efficiency of one gamma line computed straight from the summed counts, next to the
+// summed efficiencies of the same detectors refit without any line of that source. The fits then
+// come from the other sources only, so an activity, intensity or counts entry error of this one
+// shows up as a difference instead of being fitted along
+#[derive(Clone)]
+pub struct CrossCheck {
+    pub source: String,
+    pub energy: f64,
+    pub direct: (f64, f64),
+    pub fitted: Option<(f64, f64)>, // None when a detector cannot be fitted without the source
+}
+
+impl CrossCheck {
+    // difference between the two in units of the combined uncertainty
+    pub fn deviation(&self) -> Option<f64> {
+        self.fitted.map(|fitted| {
+            (self.direct.0 - fitted.0) / (self.direct.1.powi(2) + fitted.1.powi(2)).sqrt()
+        })
+    }
+}
+
+// a line of the cross-check before the fits without its source are made
+struct CrossCheckLine {
+    source: String,
+    energy: f64,
+    direct: (f64, f64),
+    detectors: Vec<String>, // the detectors that counted it
+}
+
+// How each detector line is weighted in the fits. The solvers multiply the residuals by the
+// weight, so 1/σ gives the usual χ². The other schemes change what χ², the pulls and the
+// uncertainty bands mean, which is why the choice is shown with the fits
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum WeightingScheme {
+    #[default]
+    InverseSigma,
+    InverseVariance,
+    Equal,
+    Poisson, // counting statistics of the peak area alone
+}
+
+impl WeightingScheme {
+    // weight of `line` in the fits, including its manual multiplier
+    pub fn weight(self, line: &DetectorLine) -> f64 {
+        let inverse_sigma = 1.0 / line.efficiency_uncertainty;
+
+        let weight = match self {
+            WeightingScheme::InverseSigma => inverse_sigma,
+            WeightingScheme::InverseVariance => inverse_sigma * inverse_sigma,
+            WeightingScheme::Equal => 1.0,
+            // the efficiency comes from the net counts N - B, whose counting error is √(N + B), so
+            // σ = ε √(N + B) / (N - B). Lines entered as efficiencies have no counts and keep 1/σ
+            WeightingScheme::Poisson => {
+                line.net_counts() / (line.efficiency.abs() * (line.count + line.background).sqrt())
+            }
+        };
+
+        let weight = if weight.is_finite() && weight > 0.0 {
+            weight
+        } else {
+            inverse_sigma
+        };
+
+        weight * line.weight_multiplier.unwrap_or(1.0)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Weights:");
+            ui.radio_value(self, WeightingScheme::InverseSigma, "1/σ")
+                .on_hover_text("Residuals divided by the efficiency uncertainty, the usual χ²");
+            ui.radio_value(self, WeightingScheme::InverseVariance, "1/σ²")
+                .on_hover_text("Residuals divided by the variance, pulls and χ² are no longer in units of σ");
+            ui.radio_value(self, WeightingScheme::Equal, "Equal")
+                .on_hover_text("Every line counts the same, the uncertainties are ignored");
+            ui.radio_value(self, WeightingScheme::Poisson, "Poisson")
+                .on_hover_text("Only the counting statistics of the peak area, σ = ε√(N + B)/(N − B) with the background B. Lines without counts keep 1/σ");
+        });
+    }
+}
+
+// Caps the drawn fits below each detector's lowest data point, where the exponential is unphysical
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct LowEnergyClamp {
+    pub enabled: bool,
+    pub max_efficiency: f64, // percent
+}
+
+impl Default for LowEnergyClamp {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_efficiency: 10.0,
+        }
+    }
+}
+
+impl LowEnergyClamp {
+    pub fn value(&self) -> Option<f64> {
+        self.enabled.then_some(self.max_efficiency)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.enabled, "Clamp Below Data")
+                .on_hover_text("Display only: cap the drawn fits below the lowest data point of each detector. Fits and exports are not changed");
+            ui.add_enabled(
+                self.enabled,
+                egui::DragValue::new(&mut self.max_efficiency)
+                    .speed(0.1)
+                    .clamp_range(0.0..=100.0)
+                    .suffix("%"),
+            );
+        });
+    }
+}
+
+// the Monte Carlo band of the summed efficiency sampled off the UI thread, with the central
+// curve it is drawn around. The energies come back with the band
+#[derive(Clone)]
+struct SummedBandJob {
+    efficiencies: Vec<(f64, f64)>,
+    receiver: Arc<Mutex<Receiver<SummedBand>>>,
+}
+
 #[derive(Default, Clone, serde::Deserialize, serde::Serialize)]
 pub struct MeasurementHandler {
     pub measurements: Vec<Measurement>,
     pub measurement_exp_fits: HashMap<String, Fitter>,
     pub plot_settings: EguiPlotSettings,
     pub summed_efficiency: Option<SummedEfficiency>,
+    #[serde(default)]
+    pub energy_unit: EnergyUnit,
+    #[serde(default)]
+    pub global_fitter: GlobalFitter,
+    #[serde(skip)]
+    pub duplicate_detectors: Vec<(String, String)>,
+    #[serde(default)]
+    pub calculator: EfficiencyCalculator,
+    #[serde(default)]
+    pub initial_guesses: HashMap<String, (f64, f64)>,
+    #[serde(skip)]
+    pub brush: Option<(f64, f64)>, // (start, end) energy of the exclusion brush
+    #[serde(default)]
+    pub low_energy_clamp: LowEnergyClamp,
+    #[serde(default)]
+    pub detector_comparison: DetectorComparison,
+    #[serde(default)]
+    pub point_label: PointLabel,
+    #[serde(default)]
+    pub uncertainty_display: UncertaintyDisplay,
+    #[serde(skip)]
+    pub exclusion_history: Vec<HashMap<ExclusionKey, bool>>, // exclusion flags before each brush or click, for undo
+    #[serde(default)]
+    pub show_residuals: bool, // pulls of the active fits in a plot under the efficiency plot
+    #[serde(default)]
+    pub batch_model: BatchModel,
+    #[serde(skip)]
+    pub batch: Vec<(String, Result<String, String>)>, // (detector, fit name or why it did not start) of the last Fit All
+    #[serde(default = "default_outlier_threshold")]
+    pub outlier_threshold: f64, // lines with a larger |studentized residual| are flagged
+    #[serde(default)]
+    pub weighting: WeightingScheme, // applied to the lines of every detector
+    #[serde(skip)]
+    summed_band_job: Option<SummedBandJob>,
+    #[serde(skip)]
+    pub cross_checks: Vec<CrossCheck>, // result of the last counts cross-check
+    #[serde(skip)]
+    cross_check_job: Option<Arc<Mutex<Receiver<Vec<CrossCheck>>>>>,
 }
 
 impl MeasurementHandler {
@@ -182,6 +532,25 @@ impl MeasurementHandler {
             measurement_exp_fits: HashMap::new(),
             plot_settings: EguiPlotSettings::default(),
             summed_efficiency: None,
+            energy_unit: EnergyUnit::KeV,
+            global_fitter: GlobalFitter::default(),
+            duplicate_detectors: vec![],
+            calculator: EfficiencyCalculator::default(),
+            initial_guesses: HashMap::new(),
+            brush: None,
+            low_energy_clamp: LowEnergyClamp::default(),
+            detector_comparison: DetectorComparison::default(),
+            point_label: PointLabel::Off,
+            uncertainty_display: UncertaintyDisplay::Absolute,
+            exclusion_history: Vec::new(),
+            show_residuals: false,
+            batch_model: BatchModel::default(),
+            batch: Vec::new(),
+            outlier_threshold: default_outlier_threshold(),
+            weighting: WeightingScheme::default(),
+            summed_band_job: None,
+            cross_checks: Vec::new(),
+            cross_check_job: None,
         }
     }
 
@@ -200,20 +569,92 @@ impl MeasurementHandler {
             }
         }
 
+        // Flag detectors with identical data but different names. This is usually a copy-paste
+        // slip and would double count the detector in the summed efficiency
+        let mut sorted_names: Vec<&String> = detector_data.keys().collect();
+        sorted_names.sort();
+
+        self.duplicate_detectors.clear();
+        for (index, name) in sorted_names.iter().enumerate() {
+            let data = &detector_data[*name];
+            if data.0.is_empty() {
+                continue;
+            }
+
+            for other_name in sorted_names.iter().skip(index + 1) {
+                if data == &detector_data[*other_name] {
+                    self.duplicate_detectors
+                        .push((name.to_string(), other_name.to_string()));
+                }
+            }
+        }
+
+        // a detector name can appear in several measurements, use the highest threshold set on any of them
+        let mut cutoffs: HashMap<String, f64> = HashMap::new();
+        for detector in self.measurements.iter().flat_map(|m| m.detectors.iter()) {
+            if let Some(cutoff) = detector.low_energy_cutoff {
+                let entry = cutoffs.entry(detector.name.clone()).or_insert(cutoff);
+                *entry = entry.max(cutoff);
+            }
+        }
+
         // Iterate over detector names
         for name in &detector_names {
-            // Insert if not exists
-            self.measurement_exp_fits.entry(name.clone()).or_default();
+            // Insert if not exists, starting from the last guesses used for this detector name
+            self.measurement_exp_fits
+                .entry(name.clone())
+                .or_insert_with(|| {
+                    let mut fitter = Fitter::default();
+                    if let Some((b, d)) = self.initial_guesses.get(name) {
+                        fitter.initial_b_guess = *b;
+                        fitter.initial_d_guess = *d;
+                    }
+                    fitter
+                });
+
+            let energy_uncertainties: Vec<f64> = self
+                .fit_lines(name)
+                .iter()
+                .map(|(_, line)| line.energy_uncertainty)
+                .collect();
+
+            let relative_sources: Vec<Option<String>> = self
+                .fit_lines(name)
+                .iter()
+                .map(|(source, _)| source.relative_only.then(|| source.name.clone()))
+                .collect();
 
             // Update Fitter with pre-computed data
             if let Some(fitter) = self.measurement_exp_fits.get_mut(name) {
+                fitter.migrate_legacy_fit();
+
                 if let Some(data) = detector_data.get(name) {
                     fitter.name.clone_from(name);
-                    fitter.data = data.clone();
+                    if &fitter.data != data {
+                        fitter.data_modified_at = Some(chrono::Utc::now());
+                        fitter.data = data.clone();
+                    }
                 }
+
+                fitter.energy_uncertainties = energy_uncertainties;
+                fitter.relative_sources = relative_sources;
+                fitter.weighting = self.weighting;
+                fitter.sources = Self::detector_sources(&self.measurements, name);
+                fitter.low_energy_cutoff = cutoffs.get(name).copied();
             }
         }
 
+        // Remember the guesses so they are saved with the project. Detectors that are gone are
+        // dropped so the map does not keep every name a detector had while it was typed
+        for (name, fitter) in &self.measurement_exp_fits {
+            self.initial_guesses.insert(
+                name.clone(),
+                (fitter.initial_b_guess, fitter.initial_d_guess),
+            );
+        }
+        self.initial_guesses
+            .retain(|name, _| detector_names.contains(name));
+
         // Remove entries in the hashmap that don't correspond to any detector in measurements
         let keys: HashSet<String> = self.measurement_exp_fits.keys().cloned().collect();
         for key in keys {
@@ -223,24 +664,326 @@ impl MeasurementHandler {
         }
     }
 
+    // source names and the number of fitted lines each one contributes to a detector
+    fn detector_sources(measurements: &[Measurement], name: &str) -> Vec<(String, usize)> {
+        let mut sources: Vec<(String, usize)> = Vec::new();
+
+        for measurement in measurements.iter().filter(|m| m.include_in_fits) {
+            let count: usize = measurement
+                .detectors
+                .iter()
+                .filter(|detector| detector.name == name)
+                .map(|detector| {
+                    detector
+                        .lines
+                        .iter()
+                        .filter(|line| line.is_fittable())
+                        .count()
+                })
+                .sum();
+
+            if count == 0 {
+                continue;
+            }
+
+            let source_name = &measurement.gamma_source.name;
+            match sources.iter_mut().find(|(source, _)| source == source_name) {
+                Some((_, total)) => *total += count,
+                None => sources.push((source_name.clone(), count)),
+            }
+        }
+
+        sources
+    }
+
+    fn detector_names(&self) -> HashSet<String> {
+        self.measurements
+            .iter()
+            .flat_map(|measurement| measurement.detectors.iter())
+            .map(|detector| detector.name.clone())
+            .collect()
+    }
+
+    pub fn colliding_detector_names(&self, other: &MeasurementHandler) -> Vec<String> {
+        let names = self.detector_names();
+        let mut collisions: Vec<String> = other
+            .detector_names()
+            .into_iter()
+            .filter(|name| names.contains(name))
+            .collect();
+        collisions.sort();
+        collisions
+    }
+
+    // Appends the measurements of another session. Detectors with the same name are pooled
+    // together unless `keep_both` is set, in which case the imported ones are renamed
+    pub fn merge(&mut self, mut other: MeasurementHandler, keep_both: bool) {
+        if keep_both {
+            let mut taken = self.detector_names();
+            taken.extend(other.detector_names());
+
+            let mut renamed: HashMap<String, String> = HashMap::new();
+            for name in self.colliding_detector_names(&other) {
+                let mut index = 2;
+                let mut new_name = format!("{} ({})", name, index);
+                while taken.contains(&new_name) {
+                    index += 1;
+                    new_name = format!("{} ({})", name, index);
+                }
+                taken.insert(new_name.clone());
+                renamed.insert(name, new_name);
+            }
+
+            for measurement in &mut other.measurements {
+                for detector in &mut measurement.detectors {
+                    if let Some(new_name) = renamed.get(&detector.name) {
+                        detector.name.clone_from(new_name);
+                    }
+                }
+            }
+
+            other.initial_guesses = other
+                .initial_guesses
+                .into_iter()
+                .map(|(name, guesses)| (renamed.get(&name).cloned().unwrap_or(name), guesses))
+                .collect();
+        }
+
+        for (name, guesses) in other.initial_guesses {
+            self.initial_guesses.entry(name).or_insert(guesses);
+        }
+
+        for detector in other
+            .measurements
+            .iter_mut()
+            .flat_map(|measurement| measurement.detectors.iter_mut())
+        {
+            detector.sort_lines();
+        }
+
+        self.measurements.extend(other.measurements);
+        self.synchronize_detectors();
+    }
+
+    // lines that go into the fit of detector `name` with their source, in the order of the fit data
+    fn fit_lines(&self, name: &str) -> Vec<(&GammaSource, &DetectorLine)> {
+        let mut lines: Vec<(&GammaSource, &DetectorLine)> = self
+            .measurements
+            .iter()
+            .filter(|measurement| measurement.include_in_fits)
+            .flat_map(|measurement| {
+                measurement
+                    .detectors
+                    .iter()
+                    .filter(|detector| detector.name == name)
+                    .flat_map(|detector| detector.lines.iter())
+                    .filter(|line| line.is_fittable())
+                    .map(|line| (&measurement.gamma_source, line))
+            })
+            .collect();
+
+        // stable sort so the fit input is in ascending energy regardless of how lines were entered
+        lines.sort_by(|a, b| a.1.energy.total_cmp(&b.1.energy));
+
+        lines
+    }
+
     fn get_detector_data_from_measurements(&self, name: String) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
         let mut x_data: Vec<f64> = vec![];
         let mut y_data: Vec<f64> = vec![];
         let mut weights: Vec<f64> = vec![];
 
-        for measurement in &self.measurements {
-            for detector in &measurement.detectors {
-                if detector.name == name {
-                    for line in &detector.lines {
-                        x_data.push(line.energy);
-                        y_data.push(line.efficiency);
-                        weights.push(1.0 / line.efficiency_uncertainty);
+        for (_, line) in self.fit_lines(&name) {
+            x_data.push(line.energy);
+            y_data.push(line.efficiency);
+            weights.push(self.weighting.weight(line));
+        }
+
+        (x_data, y_data, weights)
+    }
+
+    // Maps each fit's weighted and studentized residuals back onto the detector lines they came
+    // from and flags the outliers. The fit data is collected in measurement -> detector -> line
+    // order and then stably sorted by energy, so the lines are put in the same order here
+    fn update_chi_squared_contributions(&mut self) {
+        let mut detector_lines: HashMap<String, Vec<&mut DetectorLine>> = HashMap::new();
+
+        for measurement in &mut self.measurements {
+            let include_in_fits = measurement.include_in_fits;
+
+            for detector in &mut measurement.detectors {
+                for line in &mut detector.lines {
+                    line.chi_squared = None;
+                    line.studentized_residual = None;
+                    line.outlier = false;
+                }
+
+                if include_in_fits {
+                    detector_lines
+                        .entry(detector.name.clone())
+                        .or_default()
+                        .extend(detector.lines.iter_mut());
+                }
+            }
+        }
+
+        for (name, mut lines) in detector_lines {
+            // excluded lines are not part of the fit data
+            lines.retain(|line| line.is_fittable());
+            lines.sort_by(|a, b| a.energy.total_cmp(&b.energy));
+
+            let fitter = self.measurement_exp_fits.get(&name);
+
+            // lines outside the fit range are not part of the fit either
+            if let Some(fitter) = fitter {
+                lines.retain(|line| fitter.in_fit_range(line.energy));
+            }
+
+            // skip stale fits whose data no longer matches the detector lines
+            let exp_fitter = fitter.and_then(|fitter| {
+                let exp_fitter = fitter.active()?;
+                let (x_data, y_data, _) = fitter.scaled_data();
+                if exp_fitter.x != x_data || exp_fitter.y != y_data {
+                    return None;
+                }
+                Some(exp_fitter)
+            });
+
+            let residuals = exp_fitter
+                .and_then(|exp_fitter| exp_fitter.fit_result.as_ref())
+                .map(|result| &result.weighted_residuals);
+            let studentized = exp_fitter
+                .map(|exp_fitter| exp_fitter.studentized_residuals())
+                .unwrap_or_default();
+
+            for (index, line) in lines.into_iter().enumerate() {
+                line.chi_squared = residuals
+                    .and_then(|residuals| residuals.get(index))
+                    .map(|residual| residual * residual);
+                line.studentized_residual = studentized.get(index).copied();
+                line.outlier = line
+                    .studentized_residual
+                    .is_some_and(|residual| residual.abs() > self.outlier_threshold);
+            }
+        }
+    }
+
+    // collects the fits that finished on the worker threads, repainting until every one is in
+    fn poll_fit_jobs(&mut self, ctx: &egui::Context) {
+        let mut running = self.global_fitter.poll_job(&mut self.measurement_exp_fits);
+        for fitter in self.measurement_exp_fits.values_mut() {
+            running |= fitter.poll_job();
+        }
+        running |= self.poll_summed_band();
+        running |= self.poll_cross_check();
+
+        if running {
+            ctx.request_repaint();
+        }
+    }
+
+    // runs the batch model on every detector, the fits finish on their worker threads
+    pub fn fit_all(&mut self) {
+        self.synchronize_detectors();
+
+        let mut names: Vec<String> = self.measurement_exp_fits.keys().cloned().collect();
+        names.sort();
+
+        let batch_model = self.batch_model;
+        self.batch = names
+            .into_iter()
+            .map(|name| {
+                let status = match self.measurement_exp_fits.get_mut(&name) {
+                    Some(fitter) => fitter.fit_batch(batch_model),
+                    None => Err("Detector not found".to_string()),
+                };
+                (name, status)
+            })
+            .collect();
+    }
+
+    // refits every detector one after another without a window, see `Fitter::refit_blocking`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn refit_all_blocking(&mut self, project: &str) -> BatchReport {
+        self.synchronize_detectors();
+
+        let mut names: Vec<String> = self.measurement_exp_fits.keys().cloned().collect();
+        names.sort();
+
+        let mut report = BatchReport::new(project);
+        for name in names {
+            let fitter = match self.measurement_exp_fits.get_mut(&name) {
+                Some(fitter) => fitter,
+                None => continue,
+            };
+
+            for (fit_name, status) in fitter.refit_blocking(self.batch_model) {
+                match (status, fitter.fits.get(&fit_name)) {
+                    (Ok(_), Some(fit)) => {
+                        report.add(&name, &fit_name, fit, fitter.low_energy_cutoff)
                     }
+                    (Ok(_), None) => report.fail(&name, &fit_name, "No result".to_string()),
+                    (Err(err), _) => report.fail(&name, &fit_name, err),
                 }
             }
         }
 
-        (x_data, y_data, weights)
+        self.update_chi_squared_contributions();
+
+        report
+    }
+
+    fn fit_all_button(&mut self, ui: &mut egui::Ui) {
+        if ui
+            .button("Fit All Detectors")
+            .on_hover_text("Run the selected model on every detector with its own initial guesses")
+            .clicked()
+        {
+            self.fit_all();
+        }
+    }
+
+    fn batch_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            self.batch_model.ui(ui);
+            self.fit_all_button(ui);
+
+            if !self.batch.is_empty() && ui.small_button("Clear").clicked() {
+                self.batch.clear();
+            }
+        });
+
+        if self.batch.is_empty() {
+            return;
+        }
+
+        ui.horizontal_wrapped(|ui| {
+            for (name, started) in &self.batch {
+                let status = match started {
+                    Ok(fit_name) => self
+                        .measurement_exp_fits
+                        .get(name)
+                        .and_then(|fitter| fitter.fit_status(fit_name)),
+                    Err(err) => Some(Err(err.clone())),
+                };
+
+                match status {
+                    None => {
+                        ui.spinner();
+                        ui.label(name);
+                    }
+                    Some(Ok(summary)) => {
+                        ui.label(format!("✔ {}: {}", name, summary));
+                    }
+                    Some(Err(err)) => {
+                        ui.colored_label(ui.visuals().error_fg_color, format!("✖ {}", name))
+                            .on_hover_text(err);
+                    }
+                }
+
+                ui.separator();
+            }
+        });
     }
 
     fn fit_detectors_ui(&mut self, ui: &mut egui::Ui) {
@@ -248,6 +991,13 @@ impl MeasurementHandler {
 
         ui.label("Fit Equation: y = a * exp[-x/b] + c * exp[-x/d]");
 
+        self.weighting.ui(ui);
+
+        self.global_fitter.ui(ui, &mut self.measurement_exp_fits);
+
+        self.batch_ui(ui);
+
+        let energy_unit = self.energy_unit;
         egui::ScrollArea::both().show(ui, |ui| {
             ui.separator();
 
@@ -270,13 +1020,191 @@ impl MeasurementHandler {
 
                     for (name, fitter) in &mut self.measurement_exp_fits {
                         fitter.name.clone_from(name);
-                        fitter.ui(ui);
+                        fitter.ui(ui, energy_unit);
                         ui.end_row();
                     }
                 });
         });
     }
 
+    // Fixed-width archival report: a header block per source followed by one row per detector line.
+    // Energies are always in keV so the layout does not depend on the display unit
+    pub fn calibration_report(&self) -> String {
+        let mut report = String::new();
+
+        let date = |date: Option<chrono::NaiveDateTime>| {
+            date.map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "---------- -----".to_string())
+        };
+
+        for measurement in &self.measurements {
+            let source = &measurement.gamma_source;
+
+            report.push_str(&format!("{:<16}{}\n", "SOURCE", source.name));
+            report.push_str(&format!(
+                "{:<16}{:<18}{:>12.3} ± {:.3} kBq\n",
+                "CALIBRATION",
+                date(source.source_activity_calibration.date),
+                source.source_activity_calibration.activity,
+                source.source_activity_calibration.activity
+                    * source.calibration_relative_uncertainty()
+            ));
+            report.push_str(&format!(
+                "{:<16}{:<18}{:>12.0} ± {:.0} Bq\n",
+                "MEASUREMENT",
+                date(source.source_activity_measurement.date),
+                source.source_activity_measurement.activity,
+                source.source_activity_measurement.activity
+                    * source.activity_relative_uncertainty()
+            ));
+            report.push_str(&format!(
+                "{:<16}{:>12.3} h\n",
+                "RUN TIME", source.measurement_time
+            ));
+            if let Some(pile_up) = measurement.pile_up {
+                report.push_str(&format!(
+                    "{:<16}{:>12.4} ± {:.4}\n",
+                    "PILE-UP", pile_up.factor, pile_up.uncertainty
+                ));
+            }
+            report.push_str(&format!(
+                "{:<16}{:<12}{:>12}{:>12}{:>12}{:>12}{:>18}\n",
+                "DETECTOR", "E [keV]", "EFF [%]", "DEFF [%]", "FIT [%]", "DFIT [%]", "SUMMING"
+            ));
+
+            for detector in &measurement.detectors {
+                let fitter = self
+                    .measurement_exp_fits
+                    .get(&detector.name)
+                    .filter(|fitter| fitter.active().is_some_and(|fit| fit.is_fitted()));
+
+                for line in &detector.lines {
+                    let (fit, fit_uncertainty) = match fitter {
+                        Some(fitter) => (
+                            format!("{:>12.5}", fitter.evaluate(line.energy)),
+                            format!("{:>12.5}", fitter.uncertainity(line.energy, 1.0)),
+                        ),
+                        None => (format!("{:>12}", "-"), format!("{:>12}", "-")),
+                    };
+
+                    // efficiencies entered directly have no correction applied here
+                    let summing = &line.summing_correction;
+                    let summing = if summing.is_identity() || detector.direct_efficiency {
+                        format!("{:>18}", "-")
+                    } else {
+                        format!(
+                            "{:>18}",
+                            format!("{:.4} ± {:.4}", summing.factor, summing.uncertainty)
+                        )
+                    };
+
+                    report.push_str(&format!(
+                        "{:<16.15}{:<12.3}{:>12.5}{:>12.5}{}{}{}\n",
+                        detector.name,
+                        line.energy,
+                        line.efficiency,
+                        line.efficiency_uncertainty,
+                        fit,
+                        fit_uncertainty,
+                        summing
+                    ));
+                }
+            }
+
+            report.push('\n');
+        }
+
+        let mut names: Vec<&String> = self.measurement_exp_fits.keys().collect();
+        names.sort();
+
+        report.push_str(&format!(
+            "{:<16}{:>12}{:>12}\n",
+            "FIT VALIDITY", "EMIN [keV]", "EMAX [keV]"
+        ));
+        for name in names {
+            if let Some(fit) = self.measurement_exp_fits[name].active() {
+                let (valid_min, valid_max) = fit.valid_range();
+                report.push_str(&format!(
+                    "{:<16.15}{:>12.3}{:>12.3}\n",
+                    name, valid_min, valid_max
+                ));
+            }
+        }
+
+        report
+    }
+
+    // plain LaTeX tabular of the active fit of every detector, ready to paste into a paper
+    pub fn latex_table(&self) -> String {
+        let mut names: Vec<&String> = self.measurement_exp_fits.keys().collect();
+        names.sort();
+
+        let fits: Vec<(&String, &Fitter, &ExpFitter)> = names
+            .into_iter()
+            .filter_map(|name| {
+                let fitter = &self.measurement_exp_fits[name];
+                fitter
+                    .active()
+                    .filter(|fit| fit.is_fitted())
+                    .map(|fit| (name, fitter, fit))
+            })
+            .collect();
+
+        // one column per parameter name of any of the models, in the order they first appear
+        let mut columns: Vec<String> = Vec::new();
+        for (_, _, fit) in &fits {
+            for (name, _, _) in fit.parameters() {
+                if !columns.contains(&name) {
+                    columns.push(name);
+                }
+            }
+        }
+
+        let mut table = String::new();
+
+        table.push_str(&format!(
+            "\\begin{{tabular}}{{ll{}c}}\n\\hline\n",
+            "c".repeat(columns.len())
+        ));
+        let header: Vec<String> = ["Detector".to_string(), "Model".to_string()]
+            .into_iter()
+            .chain(columns.iter().map(|name| latex_parameter(name)))
+            .chain(std::iter::once("$\\chi^2/\\nu$".to_string()))
+            .collect();
+        table.push_str(&header.join(" & "));
+        table.push_str(" \\\\\n\\hline\n");
+
+        for (name, fitter, fit) in fits {
+            let parameters = fit.parameters();
+
+            let mut model = latex_escape(&fitter.active_fit);
+            if fit.energy_times_efficiency {
+                model.push_str(" ($E \\cdot \\varepsilon$)");
+            }
+
+            let mut cells = vec![latex_escape(name), model];
+            for column in &columns {
+                cells.push(
+                    match parameters.iter().find(|(name, _, _)| name == column) {
+                        Some((_, value, uncertainty)) => latex_value(*value, *uncertainty),
+                        None => "--".to_string(),
+                    },
+                );
+            }
+            cells.push(match &fit.fit_result {
+                Some(result) => format!("{:.2}", result.reduced_chi_squared),
+                None => "--".to_string(),
+            });
+
+            table.push_str(&cells.join(" & "));
+            table.push_str(" \\\\\n");
+        }
+
+        table.push_str("\\hline\n\\end{tabular}\n");
+
+        table
+    }
+
     fn remove_measurement(&mut self, index: usize) {
         self.measurements.remove(index);
     }
@@ -287,22 +1215,98 @@ impl MeasurementHandler {
 
             self.plot_settings.menu_button(ui);
 
+            self.energy_unit.ui(ui);
+
+            self.uncertainty_display.ui(ui);
+
+            self.point_label.ui(ui);
+
+            ui.checkbox(&mut self.show_residuals, "Residuals")
+                .on_hover_text("Show the pulls (data - fit) / uncertainty of the active fits under the efficiency plot");
+
             ui.separator();
 
             ui.heading("Measurements");
             for measurement in self.measurements.iter_mut() {
-                measurement.menu_button(ui);
+                measurement.menu_button(ui, self.energy_unit);
+            }
+
+            if ui
+                .button("📋 Calibration Report")
+                .on_hover_text("Copy a fixed-width calibration report of every source and detector for archival")
+                .clicked()
+            {
+                let report = self.calibration_report();
+                ui.output_mut(|o| o.copied_text = report);
+            }
+
+            if ui
+                .button("📋 Copy as LaTeX")
+                .on_hover_text("Copy a LaTeX tabular of the active fit parameters and reduced χ² of every detector")
+                .clicked()
+            {
+                let table = self.latex_table();
+                ui.output_mut(|o| o.copied_text = table);
             }
 
             ui.separator();
 
             ui.heading("Fits");
+            ui.horizontal(|ui| {
+                self.batch_model.ui(ui);
+                self.fit_all_button(ui);
+            });
+            self.weighting.ui(ui);
+            self.low_energy_clamp.ui(ui);
+            ui.horizontal(|ui| {
+                ui.label("Outlier |r| >");
+                ui.add(
+                    egui::DragValue::new(&mut self.outlier_threshold)
+                        .speed(0.1)
+                        .clamp_range(0.0..=f64::INFINITY),
+                )
+                .on_hover_text("Flag lines whose studentized residual in the active fit is larger than this");
+            });
+            ui.checkbox(&mut self.detector_comparison.show, "Detector Comparison")
+                .on_hover_text("Show the efficiency of every detector at one energy");
+
+            if ui
+                .add_enabled(
+                    !self.exclusion_history.is_empty(),
+                    egui::Button::new("Undo Exclusion"),
+                )
+                .on_hover_text("Revert the last exclusion made on the plot (click or shift + drag)")
+                .clicked()
+            {
+                self.undo_exclusion();
+            }
             for (name, fitter) in self.measurement_exp_fits.iter_mut() {
                 ui.collapsing(format!("{} Fitter", name), |ui| {
-                    fitter.menu_button(ui);
+                    fitter.menu_button(ui, self.energy_unit);
                 });
             }
 
+            if !self.duplicate_detectors.is_empty() {
+                let warn_color = ui.visuals().warn_fg_color;
+                for (name, other_name) in &self.duplicate_detectors {
+                    ui.colored_label(
+                        warn_color,
+                        format!("⚠ {} and {} have identical data", name, other_name),
+                    )
+                    .on_hover_text("Identical detectors are counted twice in the summed efficiency. Check for a copy-paste error.");
+                }
+            }
+
+            ui.separator();
+
+            ui.heading("Counts Cross-Check");
+            self.cross_check_ui(ui);
+
+            ui.separator();
+
+            ui.heading("Efficiency Calculator");
+            self.efficiency_calculator_ui(ui);
+
             ui.separator();
 
             ui.heading("Summed Efficiency");
@@ -311,32 +1315,46 @@ impl MeasurementHandler {
             }
 
             if let Some(summed_efficiency) = &mut self.summed_efficiency {
-                if ui.button("Sum Efficiency Fits").clicked() {
-                    let max_range = summed_efficiency.max_energy;
-                    self.get_summed_efficiency(max_range);
-                }
+                let max_range = summed_efficiency.max_energy;
+                ui.horizontal(|ui| {
+                    let idle = self.summed_band_job.is_none();
+                    if ui
+                        .add_enabled(idle, egui::Button::new("Sum Efficiency Fits"))
+                        .clicked()
+                    {
+                        self.get_summed_efficiency(max_range);
+                    }
+                    if !idle {
+                        ui.spinner();
+                    }
+                });
             }
 
             if let Some(summed_efficiency) = &mut self.summed_efficiency {
-                ui.add(
-                    egui::DragValue::new(&mut summed_efficiency.max_energy)
-                        .speed(1.0)
-                        .clamp_range(0.0..=10000.0)
-                        .prefix("Max Energy: ")
-                        .suffix(" keV"),
-                );
+                ui.horizontal(|ui| {
+                    ui.label("Max Energy:");
+                    self.energy_unit
+                        .drag_value(ui, &mut summed_efficiency.max_energy);
+                });
+
+                ui.checkbox(&mut summed_efficiency.monte_carlo, "Monte Carlo Band")
+                    .on_hover_text(format!(
+                        "Sample every detector's fit parameters from its covariance ({} draws) and take the 15.9th and 84.1th percentiles of the sum, instead of adding the 1σ bands in quadrature. Keeps the parameter correlations and the asymmetry of the band",
+                        SUMMED_DRAWS
+                    ));
             }
 
             if let Some(summed_efficiency) = &mut self.summed_efficiency {
                 ui.horizontal(|ui| {
                     if ui
                         .button("📋")
-                        .on_hover_text(
-                            "Copy data to clipboard (CSV format)\nEnergy, Efficiency, Uncertainty",
-                        )
+                        .on_hover_text(format!(
+                            "Copy data to clipboard (CSV format)\nEnergy [{}], Efficiency, Uncertainty",
+                            self.energy_unit.label()
+                        ))
                         .clicked()
                     {
-                        let stat_str = summed_efficiency.csv_points();
+                        let stat_str = summed_efficiency.csv_points(self.energy_unit);
                         ui.output_mut(|o| o.copied_text = stat_str);
                     }
 
@@ -352,16 +1370,20 @@ impl MeasurementHandler {
 
     fn draw(&mut self, plot_ui: &mut egui_plot::PlotUi) {
         for measurement in self.measurements.iter_mut() {
-            measurement.draw(plot_ui);
+            measurement.draw(plot_ui, self.point_label, self.energy_unit);
         }
 
         for (name, fitter) in self.measurement_exp_fits.iter_mut() {
             fitter.name.clone_from(name);
-            fitter.draw(plot_ui);
+            fitter.draw(
+                plot_ui,
+                self.plot_settings.band_decimation,
+                self.low_energy_clamp.value(),
+            );
         }
 
         if let Some(summed_efficiency) = &mut self.summed_efficiency {
-            summed_efficiency.draw(plot_ui);
+            summed_efficiency.draw(plot_ui, self.plot_settings.band_decimation);
         }
     }
 
@@ -370,43 +1392,558 @@ impl MeasurementHandler {
             .min_size(egui::Vec2::new(400.0, 400.0))
             .auto_bounds(egui::Vec2b::new(true, true));
 
+        // the residuals share the energy axis, zooming or panning either plot moves both
+        let residual_height = ui.available_height() * 0.25;
+        if self.show_residuals {
+            plot = plot
+                .height((ui.available_height() - residual_height).max(400.0))
+                .link_axis("efficiency_energy_axis", true, false)
+                .link_cursor("efficiency_energy_axis", true, false);
+        }
+
         plot = self.plot_settings.apply_to_plot(plot);
 
+        // data is stored in keV, so the energy unit only changes the axis and hover labels
+        let energy_unit = self.energy_unit;
+        plot = plot
+            .x_axis_label(format!("Energy [{}]", energy_unit.label()))
+            .y_axis_label("Efficiency [%]")
+            .x_axis_formatter(move |mark, _chars, _range| {
+                let step = energy_unit.scale_from_kev(mark.step_size);
+                let decimals = (-step.log10().floor()).max(0.0) as usize;
+                format!("{:.*}", decimals, energy_unit.scale_from_kev(mark.value))
+            })
+            .label_formatter(move |name, value| {
+                let point = format!("E = {}\nε = {:.3}%", energy_unit.format(value.x), value.y);
+                if name.is_empty() {
+                    point
+                } else {
+                    format!("{}\n{}", name, point)
+                }
+            });
+
+        // shift + drag selects an energy range instead of panning
+        let brushing = ui.input(|i| i.modifiers.shift);
+        if brushing || self.brush.is_some() {
+            plot = plot.allow_drag(false).allow_boxed_zoom(false);
+        }
+
         plot.show(ui, |plot_ui| {
+            self.plot_settings.apply_bounds(plot_ui);
             self.draw(plot_ui);
+            self.exclusion_brush(plot_ui, brushing);
+            self.exclusion_click(plot_ui);
         })
         .response
         .context_menu(|ui| {
             self.context_menu(ui);
         });
+
+        if self.show_residuals {
+            self.residual_plot(ui, residual_height);
+        }
+    }
+
+    fn residual_plot(&self, ui: &mut egui::Ui, height: f32) {
+        let energy_unit = self.energy_unit;
+
+        let plot = Plot::new("Residuals")
+            .height(height.max(100.0))
+            .link_axis("efficiency_energy_axis", true, false)
+            .link_cursor("efficiency_energy_axis", true, false)
+            .allow_boxed_zoom(false)
+            .x_axis_label(format!("Energy [{}]", energy_unit.label()))
+            .y_axis_label("Pull [σ]")
+            .x_axis_formatter(move |mark, _chars, _range| {
+                let step = energy_unit.scale_from_kev(mark.step_size);
+                let decimals = (-step.log10().floor()).max(0.0) as usize;
+                format!("{:.*}", decimals, energy_unit.scale_from_kev(mark.value))
+            })
+            .label_formatter(move |name, value| {
+                let point = format!(
+                    "E = {}\npull = {:.2}σ",
+                    energy_unit.format(value.x),
+                    value.y
+                );
+                if name.is_empty() {
+                    point
+                } else {
+                    format!("{}\n{}", name, point)
+                }
+            });
+
+        let mut names: Vec<&String> = self.measurement_exp_fits.keys().collect();
+        names.sort();
+
+        plot.show(ui, |plot_ui| {
+            plot_ui.hline(egui_plot::HLine::new(0.0).color(egui::Color32::GRAY));
+
+            for name in names {
+                let active = match self.measurement_exp_fits[name].active() {
+                    Some(active) if active.is_fitted() => active,
+                    _ => continue,
+                };
+
+                plot_ui.points(
+                    egui_plot::Points::new(active.pulls())
+                        .color(active.fit_line.color)
+                        .radius(3.0)
+                        .name(name),
+                );
+            }
+        });
+    }
+
+    fn exclusion_brush(&mut self, plot_ui: &mut egui_plot::PlotUi, brushing: bool) {
+        let response = plot_ui.response().clone();
+        let pointer = plot_ui.pointer_coordinate();
+
+        if brushing && response.drag_started() {
+            if let Some(pointer) = pointer {
+                self.brush = Some((pointer.x, pointer.x));
+            }
+        }
+
+        let (start, mut end) = match self.brush {
+            Some(brush) => brush,
+            None => return,
+        };
+
+        if let Some(pointer) = pointer {
+            end = pointer.x;
+            self.brush = Some((start, end));
+        }
+
+        let bounds = plot_ui.plot_bounds();
+        let (y_min, y_max) = (bounds.min()[1], bounds.max()[1]);
+        let color = egui::Color32::from_rgba_unmultiplied(128, 128, 128, 40);
+
+        plot_ui.polygon(
+            egui_plot::Polygon::new(vec![
+                [start, y_min],
+                [end, y_min],
+                [end, y_max],
+                [start, y_max],
+            ])
+            .fill_color(color)
+            .stroke(egui::Stroke::new(1.0, egui::Color32::GRAY))
+            .highlight(false),
+        );
+
+        if response.drag_stopped() {
+            self.toggle_exclusion(start.min(end), start.max(end));
+            self.brush = None;
+        } else if !plot_ui.ctx().input(|i| i.pointer.primary_down()) {
+            // the drag ended somewhere the plot did not see it
+            self.brush = None;
+        }
+    }
+
+    // clicking a point toggles whether that one line is fitted
+    fn exclusion_click(&mut self, plot_ui: &mut egui_plot::PlotUi) {
+        if !plot_ui.response().clicked() {
+            return;
+        }
+
+        let pointer = match plot_ui.ctx().input(|i| i.pointer.interact_pos()) {
+            Some(pointer) => pointer,
+            None => return,
+        };
+
+        let mut nearest: Option<((usize, usize, usize), f32)> = None;
+        for (m, measurement) in self.measurements.iter().enumerate() {
+            for (d, detector) in measurement.detectors.iter().enumerate() {
+                if !detector.points.draw {
+                    continue;
+                }
+
+                for (l, line) in detector.lines.iter().enumerate() {
+                    let position = plot_ui
+                        .screen_from_plot(egui_plot::PlotPoint::new(line.energy, line.efficiency));
+                    let distance = position.distance(pointer);
+
+                    if distance <= CLICK_RADIUS
+                        && nearest.map_or(true, |(_, nearest)| distance < nearest)
+                    {
+                        nearest = Some(((m, d, l), distance));
+                    }
+                }
+            }
+        }
+
+        if let Some(((m, d, l), _)) = nearest {
+            self.exclusion_history.push(self.exclusion_flags());
+
+            let line = &mut self.measurements[m].detectors[d].lines[l];
+            line.exclude_from_fit = !line.exclude_from_fit;
+        }
+    }
+
+    // exclusion flag of every line, keyed so a snapshot survives lines being added or reordered
+    fn exclusion_flags(&self) -> HashMap<ExclusionKey, bool> {
+        let mut flags = HashMap::new();
+
+        for (m, measurement) in self.measurements.iter().enumerate() {
+            for detector in &measurement.detectors {
+                for line in &detector.lines {
+                    flags.insert(
+                        (m, detector.name.clone(), line.energy.to_bits()),
+                        line.exclude_from_fit,
+                    );
+                }
+            }
+        }
+
+        flags
+    }
+
+    // lines added since the snapshot keep their flag, removed ones are ignored
+    pub fn undo_exclusion(&mut self) {
+        let flags = match self.exclusion_history.pop() {
+            Some(flags) => flags,
+            None => return,
+        };
+
+        for (m, measurement) in self.measurements.iter_mut().enumerate() {
+            for detector in &mut measurement.detectors {
+                for line in &mut detector.lines {
+                    let key = (m, detector.name.clone(), line.energy.to_bits());
+                    if let Some(&excluded) = flags.get(&key) {
+                        line.exclude_from_fit = excluded;
+                    }
+                }
+            }
+        }
+    }
+
+    // flips `exclude_from_fit` for every line with an energy in [min, max] keV
+    fn toggle_exclusion(&mut self, min: f64, max: f64) {
+        self.exclusion_history.push(self.exclusion_flags());
+
+        for measurement in &mut self.measurements {
+            for detector in &mut measurement.detectors {
+                for line in &mut detector.lines {
+                    if line.energy >= min && line.energy <= max {
+                        line.exclude_from_fit = !line.exclude_from_fit;
+                    }
+                }
+            }
+        }
+    }
+
+    // Measurement whose fitted line is closest to each energy. The summed curve there is pinned
+    // by that source, so its activity is the one shared by every detector
+    fn closest_sources(&self, energies: &[f64]) -> Vec<Option<usize>> {
+        let lines: Vec<(f64, usize)> = self
+            .measurements
+            .iter()
+            .enumerate()
+            .filter(|(_, measurement)| measurement.include_in_fits)
+            .flat_map(|(index, measurement)| {
+                measurement
+                    .detectors
+                    .iter()
+                    .flat_map(|detector| detector.lines.iter())
+                    .filter(|line| line.is_fittable())
+                    .map(move |line| (line.energy, index))
+            })
+            .collect();
+
+        energies
+            .iter()
+            .map(|energy| {
+                lines
+                    .iter()
+                    .min_by(|a, b| (a.0 - energy).abs().total_cmp(&(b.0 - energy).abs()))
+                    .map(|&(_, index)| index)
+            })
+            .collect()
     }
 
-    pub fn total_efficiency(&mut self, energy: f64) -> (f64, f64) {
-        let mut efficiency = 0.0;
-        let mut uncertainty_values = Vec::new();
+    // relative activity uncertainty of the source closest to each energy, zero without lines
+    fn activity_relative_uncertainties(&self, energies: &[f64]) -> Vec<f64> {
+        self.closest_sources(energies)
+            .into_iter()
+            .map(|source| {
+                source.map_or(0.0, |index| {
+                    self.measurements[index]
+                        .gamma_source
+                        .activity_relative_uncertainty()
+                })
+            })
+            .collect()
+    }
+
+    // Sums the detector efficiencies. By default the detector uncertainties are independent and
+    // add in quadrature. With `correlated_activity` the part coming from the source activity is
+    // shared by every detector (they were all measured with the same source), so it is pulled out
+    // of each detector's uncertainty and added linearly before combining with the rest. The
+    // activity is the one of the source with the line closest to the energy.
+    pub fn total_efficiency(&mut self, energy: f64, sigma: f64) -> (f64, f64) {
+        self.total_efficiencies(&[energy], sigma)[0]
+    }
+
+    // summed (efficiency, uncertainty) of every detector at each energy
+    pub fn total_efficiencies(&self, energies: &[f64], sigma: f64) -> Vec<(f64, f64)> {
+        let activity_uncertainties: Vec<f64> = if self.calculator.correlated_activity {
+            self.activity_relative_uncertainties(energies)
+                .into_iter()
+                .map(|uncertainty| sigma * uncertainty)
+                .collect()
+        } else {
+            vec![0.0; energies.len()]
+        };
+
+        let detector_efficiencies: Vec<Vec<(f64, f64)>> = self
+            .measurement_exp_fits
+            .values()
+            .map(|fit| fit.efficiencies(energies, sigma))
+            .collect();
+
+        sum_efficiencies(&detector_efficiencies, &activity_uncertainties)
+    }
+
+    // every counted line of a source with a known activity, with its efficiency from the summed
+    // counts of all detectors
+    fn cross_check_lines(&self) -> Vec<CrossCheckLine> {
+        let mut lines = Vec::new();
+
+        for measurement in self.measurements.iter().filter(|m| m.include_in_fits) {
+            let source = &measurement.gamma_source;
+            // a relative only activity gives no number of decays to compare with
+            if source.relative_only {
+                continue;
+            }
 
-        for fit in self.measurement_exp_fits.values() {
-            if let Some(parameters) = &fit.exp_fitter.fit_params {
-                if parameters.len() == 1 {
-                    let a = parameters[0].0 .0;
-                    let b = parameters[0].1 .0;
-                    efficiency += a * (-energy / b).exp();
-                } else if parameters.len() == 2 {
-                    let a = parameters[0].0 .0;
-                    let b = parameters[0].1 .0;
-                    let c = parameters[1].0 .0;
-                    let d = parameters[1].1 .0;
-                    efficiency += a * (-energy / b).exp() + c * (-energy / d).exp();
+            let activity = source.source_activity_measurement.activity; // Bq
+            let activity_uncertainty = source.activity_relative_uncertainty();
+            let run_time = source.measurement_time * 3600.0; // seconds
+            let pile_up = measurement.pile_up_correction();
+
+            for gamma_line in &source.gamma_lines {
+                let mut counts = 0.0;
+                let mut counts_variance = 0.0;
+                let mut detectors: Vec<String> = Vec::new();
+
+                for detector in measurement
+                    .detectors
+                    .iter()
+                    .filter(|detector| !detector.direct_efficiency)
+                {
+                    // counts scaled up to the real time, as if the detector had been live throughout
+                    let live_fraction = detector.live_fraction(source.measurement_time);
+
+                    // a line whose background swallows the peak has no counts to add
+                    for line in detector.lines.iter().filter(|line| {
+                        line.energy == gamma_line.energy && !line.background_exceeds_counts()
+                    }) {
+                        counts += line.net_counts() / live_fraction;
+                        counts_variance += (line.uncertainty.powi(2)
+                            + line.background_uncertainty.powi(2))
+                            / live_fraction.powi(2);
+
+                        if !detectors.contains(&detector.name) {
+                            detectors.push(detector.name.clone());
+                        }
+                    }
+                }
+
+                let decays = activity
+                    * source.emitter_activity_ratio(&gamma_line.emitter)
+                    * run_time
+                    * gamma_line.intensity
+                    / 100.0;
+                if detectors.is_empty() || decays <= 0.0 || counts <= 0.0 {
+                    continue;
                 }
+
+                let direct = counts * pile_up.factor / decays * 100.0;
+                let direct_uncertainty = direct
+                    * (counts_variance / counts.powi(2)
+                        + (gamma_line.intensity_uncertainty / gamma_line.intensity).powi(2)
+                        + activity_uncertainty.powi(2)
+                        + pile_up.relative_uncertainty().powi(2))
+                    .sqrt();
+
+                lines.push(CrossCheckLine {
+                    source: source.name.clone(),
+                    energy: gamma_line.energy,
+                    direct: (direct, direct_uncertainty),
+                    detectors,
+                });
             }
+        }
+
+        lines
+    }
 
-            let uncertainity = fit.exp_fitter.uncertainity(energy, 1.0);
-            uncertainty_values.push(uncertainity);
+    // Refits every detector once per source without that source's lines on a worker thread,
+    // `poll_cross_check` stores the comparison
+    pub fn start_cross_check(&mut self) {
+        self.synchronize_detectors();
+
+        let lines = self.cross_check_lines();
+        let mut held_out: Vec<(String, String, Vec<bool>)> = Vec::new();
+        for line in &lines {
+            for detector in &line.detectors {
+                if held_out
+                    .iter()
+                    .any(|(source, name, _)| source == &line.source && name == detector)
+                {
+                    continue;
+                }
+
+                // the fit data is built from `fit_lines`, so the points line up with them
+                let keep = self
+                    .fit_lines(detector)
+                    .iter()
+                    .map(|(source, _)| source.name != line.source)
+                    .collect();
+                held_out.push((line.source.clone(), detector.clone(), keep));
+            }
         }
 
-        let total_uncertainty = (uncertainty_values.iter().map(|&x| x * x).sum::<f64>()).sqrt();
+        let fitters = self.measurement_exp_fits.clone();
+        let receiver = spawn_job(move || cross_check(&fitters, held_out, lines));
+        self.cross_check_job = Some(Arc::new(Mutex::new(receiver)));
+    }
 
-        (efficiency, total_uncertainty)
+    // stores the cross-check once it is done, returns true while it is still running
+    fn poll_cross_check(&mut self) -> bool {
+        let received = match &self.cross_check_job {
+            Some(job) => match job.lock() {
+                Ok(receiver) => receiver.try_recv(),
+                Err(_) => Err(TryRecvError::Disconnected),
+            },
+            None => return false,
+        };
+
+        match received {
+            Ok(checks) => {
+                self.cross_checks = checks;
+                self.cross_check_job = None;
+                false
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => {
+                self.cross_check_job = None;
+                log::error!("The counts cross-check was interrupted");
+                false
+            }
+        }
+    }
+
+    fn cross_check_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let idle = self.cross_check_job.is_none();
+            if ui
+                .add_enabled(idle, egui::Button::new("Run Cross-Check"))
+                .on_hover_text("Refit every detector without each source's lines and compare the fits with the efficiency from that source's summed counts")
+                .clicked()
+            {
+                self.start_cross_check();
+            }
+            if !idle {
+                ui.spinner();
+            }
+        });
+
+        if self.cross_checks.is_empty() {
+            return;
+        }
+
+        egui::Grid::new("counts_cross_check")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Source");
+                ui.label("Energy");
+                ui.label("Summed Counts")
+                    .on_hover_text("Efficiency of the detectors that saw the line, from their summed counts and the number of decays");
+                ui.label("Fits Without Source")
+                    .on_hover_text("Sum of the efficiencies of the same detectors, fitted to the lines of the other sources only");
+                ui.label("Δ/σ");
+                ui.end_row();
+
+                for check in &self.cross_checks {
+                    ui.label(&check.source);
+                    ui.label(self.energy_unit.format(check.energy));
+                    ui.label(self.uncertainty_display.format(check.direct.0, check.direct.1));
+
+                    let (fitted, deviation) = match (check.fitted, check.deviation()) {
+                        (Some(fitted), Some(deviation)) => (fitted, deviation),
+                        _ => {
+                            ui.label("–").on_hover_text(
+                                "A detector has too few lines from other sources to be fitted without this one",
+                            );
+                            ui.label("–");
+                            ui.end_row();
+                            continue;
+                        }
+                    };
+
+                    ui.label(self.uncertainty_display.format(fitted.0, fitted.1));
+
+                    let text = format!("{:.1}", deviation);
+                    if deviation.abs() > 3.0 {
+                        ui.colored_label(ui.visuals().warn_fg_color, text).on_hover_text(
+                            "More than 3σ apart. Check the activity, intensity and counts entered for this line",
+                        );
+                    } else {
+                        ui.label(text);
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn efficiency_calculator_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Energy:");
+            self.energy_unit.drag_value(ui, &mut self.calculator.energy);
+        });
+
+        self.calculator.sigma_ui(ui);
+
+        ui.checkbox(
+            &mut self.calculator.correlated_activity,
+            "Correlated Source Activity",
+        )
+        .on_hover_text(format!(
+            "Treat the source activity uncertainty ({:.1}% here) as fully correlated between detectors, since they share the source. It is summed linearly instead of in quadrature. Each energy uses the source with the closest line. Also applies to the summed efficiency line.",
+            self.activity_relative_uncertainties(&[self.calculator.energy])[0] * 100.0
+        ));
+
+        let (efficiency, uncertainty) =
+            self.total_efficiency(self.calculator.energy, self.calculator.sigma);
+
+        ui.label(format!(
+            "Summed Efficiency: {} ({:.2}σ, {:.1}% CL)",
+            self.uncertainty_display.format(efficiency, uncertainty),
+            self.calculator.sigma,
+            self.calculator.confidence_level()
+        ));
+    }
+
+    // the measurement each energy takes its shared activity from and that activity's relative
+    // uncertainty, none unless the calculator correlates the activity
+    fn band_sources(&self, energies: &[f64]) -> (Vec<Option<usize>>, Vec<f64>) {
+        let sources = if self.calculator.correlated_activity {
+            self.closest_sources(energies)
+        } else {
+            vec![None; energies.len()]
+        };
+        let activity_uncertainties: Vec<f64> = sources
+            .iter()
+            .map(|source| {
+                source.map_or(0.0, |index| {
+                    self.measurements[index]
+                        .gamma_source
+                        .activity_relative_uncertainty()
+                })
+            })
+            .collect();
+
+        (sources, activity_uncertainties)
     }
 
     pub fn get_summed_efficiency(&mut self, max_x: f64) {
@@ -415,27 +1952,100 @@ impl MeasurementHandler {
             self.summed_efficiency = Some(SummedEfficiency::new());
         }
 
-        // Collect efficiency and uncertainty values before mutably borrowing `summed_efficiency`
         let num_points = 1000;
         let start = 0.0;
         let step = (max_x - start) / num_points as f64;
 
+        let energies: Vec<f64> = (0..num_points).map(|i| start + i as f64 * step).collect();
+        let efficiencies = self.total_efficiencies(&energies, 1.0);
+
+        let monte_carlo = self
+            .summed_efficiency
+            .as_ref()
+            .is_some_and(|summed| summed.monte_carlo);
+
+        if !monte_carlo {
+            self.summed_band_job = None;
+            self.store_summed_efficiency(&energies, efficiencies, None);
+            return;
+        }
+
+        // the sampled band takes a while, it is stored by `poll_summed_band`
+        let (sources, activity_uncertainties) = self.band_sources(&energies);
+        let fitters = self.measurement_exp_fits.clone();
+        let n_sources = self.measurements.len();
+        let receiver = spawn_job(move || {
+            let band = summed_monte_carlo_band(
+                &fitters,
+                &energies,
+                &sources,
+                &activity_uncertainties,
+                n_sources,
+            );
+            (energies, band)
+        });
+
+        self.summed_band_job = Some(SummedBandJob {
+            efficiencies,
+            receiver: Arc::new(Mutex::new(receiver)),
+        });
+    }
+
+    // stores the summed band once it is sampled, returns true while it is still running
+    fn poll_summed_band(&mut self) -> bool {
+        let job = match &self.summed_band_job {
+            Some(job) => job.clone(),
+            None => return false,
+        };
+
+        let received = match job.receiver.lock() {
+            Ok(receiver) => receiver.try_recv(),
+            Err(_) => Err(TryRecvError::Disconnected),
+        };
+
+        match received {
+            Ok((energies, band)) => {
+                self.summed_band_job = None;
+                self.store_summed_efficiency(&energies, job.efficiencies, Some(band));
+                false
+            }
+            Err(TryRecvError::Empty) => true,
+            Err(TryRecvError::Disconnected) => {
+                self.summed_band_job = None;
+                log::error!("Sampling the summed band was interrupted");
+                false
+            }
+        }
+    }
+
+    // the summed curve with the sampled `band` when there is one, the quadrature sum otherwise
+    fn store_summed_efficiency(
+        &mut self,
+        energies: &[f64],
+        efficiencies: Vec<(f64, f64)>,
+        band: Option<Vec<[f64; 3]>>,
+    ) {
         let mut line_points: Vec<[f64; 2]> = Vec::new();
         let mut uncertainity_values: Vec<f64> = Vec::new();
         let mut uncertainty_lower_points: Vec<[f64; 2]> = Vec::new();
         let mut uncertainty_upper_points: Vec<[f64; 2]> = Vec::new();
 
-        for i in 0..num_points {
-            let x = start + i as f64 * step;
-            let (efficiency, uncertainty) = self.total_efficiency(x);
-
-            line_points.push([x, efficiency]);
-            uncertainity_values.push(uncertainty);
-            uncertainty_lower_points.push([x, efficiency - uncertainty]);
-            uncertainty_upper_points.push([x, efficiency + uncertainty]);
+        if let Some(band) = band {
+            for ((efficiency, _), [x, lower, upper]) in efficiencies.into_iter().zip(band) {
+                line_points.push([x, efficiency]);
+                uncertainity_values.push(0.5 * (upper - lower));
+                uncertainty_lower_points.push([x, lower]);
+                uncertainty_upper_points.push([x, upper]);
+            }
+        } else {
+            for (&x, (efficiency, uncertainty)) in energies.iter().zip(efficiencies) {
+                line_points.push([x, efficiency]);
+                uncertainity_values.push(uncertainty);
+                uncertainty_lower_points.push([x, efficiency - uncertainty]);
+                uncertainty_upper_points.push([x, efficiency + uncertainty]);
+            }
         }
 
-        // Now update `summed_efficiency` with the collected data
         if let Some(summed_efficiency) = &mut self.summed_efficiency {
             summed_efficiency.line.points = line_points;
             summed_efficiency.uncertainty = uncertainity_values;
@@ -444,13 +2054,28 @@ impl MeasurementHandler {
         }
     }
 
-    pub fn ui(&mut self, ui: &mut egui::Ui, show_bottom_panel: bool, show_left_panel: bool) {
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        show_bottom_panel: bool,
+        show_left_panel: bool,
+        library: &mut SourceLibrary,
+    ) {
+        self.poll_fit_jobs(ui.ctx());
+
+        for fitter in self.measurement_exp_fits.values_mut() {
+            fitter.correlation_window(ui.ctx());
+            fitter.profile_window(ui.ctx());
+        }
+
         egui::TopBottomPanel::bottom("efficiency_bottom")
             .resizable(true)
             .show_animated_inside(ui, show_bottom_panel, |ui| {
                 self.fit_detectors_ui(ui);
             });
 
+        self.update_chi_squared_contributions();
+
         egui::SidePanel::left("cebra_efficiency_left_side_panel").show_animated_inside(
             ui,
             show_left_panel,
@@ -462,7 +2087,13 @@ impl MeasurementHandler {
                         .default_open(true)
                         .show(ui, |ui| {
                             for (index, measurement) in self.measurements.iter_mut().enumerate() {
-                                measurement.update_ui(ui, index);
+                                measurement.update_ui(
+                                    ui,
+                                    index,
+                                    self.energy_unit,
+                                    self.uncertainty_display,
+                                    library,
+                                );
 
                                 if ui.button("Remove Source").clicked() {
                                     index_to_remove = Some(index);
@@ -485,6 +2116,498 @@ impl MeasurementHandler {
             },
         );
 
+        egui::SidePanel::right("cebra_efficiency_detector_comparison_panel")
+            .resizable(true)
+            .default_width(300.0)
+            .show_animated_inside(ui, self.detector_comparison.show, |ui| {
+                self.detector_comparison
+                    .ui(ui, &self.measurement_exp_fits, self.energy_unit);
+            });
+
         self.plot(ui);
     }
 }
+
+// Percentile band of the summed efficiency with every detector's parameters drawn from its
+// covariance, so the correlations within each fit are kept. The detectors are independent
+// except for a shared activity, which is split off and drawn once per sum like in
+// `total_efficiencies`. `sources` and `activity_uncertainties` come from `band_sources`
+fn summed_monte_carlo_band(
+    fitters: &HashMap<String, Fitter>,
+    energies: &[f64],
+    sources: &[Option<usize>],
+    activity_uncertainties: &[f64],
+    n_sources: usize,
+) -> Vec<[f64; 3]> {
+    let mut sums = vec![vec![0.0; energies.len()]; SUMMED_DRAWS];
+
+    for fitter in fitters.values() {
+        let central = fitter.efficiencies(energies, 1.0);
+
+        // fits without a covariance add their curve without spread
+        let draws = match fitter.sampled_efficiencies(energies, SUMMED_DRAWS) {
+            Some(draws) => draws,
+            None => {
+                for sum in &mut sums {
+                    for (value, (efficiency, _)) in sum.iter_mut().zip(&central) {
+                        *value += efficiency;
+                    }
+                }
+                continue;
+            }
+        };
+
+        for (sum, draw) in sums.iter_mut().zip(draws) {
+            for (((value, sample), &(efficiency, uncertainty)), activity_uncertainty) in sum
+                .iter_mut()
+                .zip(draw)
+                .zip(&central)
+                .zip(activity_uncertainties)
+            {
+                // shrink the spread to the independent part, the activity is drawn below
+                let correlated = activity_uncertainty * efficiency;
+                let shrink = if uncertainty > 0.0 {
+                    (uncertainty.powi(2) - correlated.powi(2)).max(0.0).sqrt() / uncertainty
+                } else {
+                    1.0
+                };
+
+                *value += efficiency + shrink * (sample - efficiency);
+            }
+        }
+    }
+
+    // one activity draw per source and sum, shared by the energies closest to that source
+    if sources.iter().any(Option::is_some) {
+        if let Ok(normal) = statrs::distribution::Normal::new(0.0, 1.0) {
+            let mut rng = rand::thread_rng();
+
+            for sum in &mut sums {
+                let draws: Vec<f64> = (0..n_sources).map(|_| normal.sample(&mut rng)).collect();
+
+                for ((value, source), activity_uncertainty) in
+                    sum.iter_mut().zip(sources).zip(activity_uncertainties)
+                {
+                    if let Some(index) = source {
+                        *value *= 1.0 + draws[*index] * activity_uncertainty;
+                    }
+                }
+            }
+        }
+    }
+
+    percentile_band(energies, &sums)
+}
+
+// Sums the efficiencies of the fits without each line's source. `held_out` is the (source,
+// detector, points to keep) of every fit to make
+fn cross_check(
+    fitters: &HashMap<String, Fitter>,
+    held_out: Vec<(String, String, Vec<bool>)>,
+    lines: Vec<CrossCheckLine>,
+) -> Vec<CrossCheck> {
+    let fits: HashMap<(String, String), ExpFitter> = held_out
+        .into_iter()
+        .filter_map(|(source, detector, keep)| {
+            let fit = fitters.get(&detector)?.refit_without(&keep)?;
+            Some(((source, detector), fit))
+        })
+        .collect();
+
+    lines
+        .into_iter()
+        .map(|line| {
+            let mut fitted = Some((0.0, 0.0));
+            for detector in &line.detectors {
+                fitted = match (fitted, fits.get(&(line.source.clone(), detector.clone()))) {
+                    (Some((sum, variance)), Some(fit)) => Some((
+                        sum + fit.evaluate(line.energy),
+                        variance + fit.uncertainity(line.energy, 1.0).powi(2),
+                    )),
+                    _ => None,
+                };
+            }
+
+            CrossCheck {
+                source: line.source,
+                energy: line.energy,
+                direct: line.direct,
+                fitted: fitted.map(|(sum, variance)| (sum, variance.sqrt())),
+            }
+        })
+        .collect()
+}
+
+// Sum of the (efficiency, uncertainty) of every detector at each energy. The relative activity
+// uncertainty at an energy is taken out of each detector and added linearly, the rest is
+// independent and adds in quadrature
+fn sum_efficiencies(
+    detectors: &[Vec<(f64, f64)>],
+    activity_uncertainties: &[f64],
+) -> Vec<(f64, f64)> {
+    let mut efficiencies = vec![0.0; activity_uncertainties.len()];
+    let mut independent_variances = vec![0.0; activity_uncertainties.len()];
+
+    for detector_efficiencies in detectors {
+        for (index, &(detector_efficiency, uncertainity)) in
+            detector_efficiencies.iter().enumerate()
+        {
+            efficiencies[index] += detector_efficiency;
+
+            // remaining independent part, clamped in case the fit band is narrower than the activity term
+            let correlated = activity_uncertainties[index] * detector_efficiency;
+            independent_variances[index] += (uncertainity.powi(2) - correlated.powi(2)).max(0.0);
+        }
+    }
+
+    efficiencies
+        .into_iter()
+        .zip(independent_variances)
+        .zip(activity_uncertainties)
+        .map(
+            |((efficiency, independent_variance), activity_uncertainty)| {
+                let correlated_uncertainty = activity_uncertainty * efficiency;
+                (
+                    efficiency,
+                    (independent_variance + correlated_uncertainty.powi(2)).sqrt(),
+                )
+            },
+        )
+        .collect()
+}
+
+// "$(1.23 \pm 0.05) \times 10^{3}$", the uncertainty is kept to two significant figures and the
+// value is rounded to match
+fn latex_value(value: f64, uncertainty: f64) -> String {
+    if !value.is_finite() || !uncertainty.is_finite() {
+        return "--".to_string();
+    }
+
+    let exponent = if value != 0.0 {
+        value.abs().log10().floor() as i32
+    } else {
+        0
+    };
+    let exponent = if exponent.abs() >= 3 { exponent } else { 0 };
+
+    let scale = 10f64.powi(exponent);
+    let (value, uncertainty) = (value / scale, uncertainty / scale);
+
+    let decimals = if uncertainty > 0.0 {
+        (1 - uncertainty.log10().floor() as i32).max(0) as usize
+    } else {
+        3
+    };
+
+    if exponent == 0 {
+        format!("${:.*} \\pm {:.*}$", decimals, value, decimals, uncertainty)
+    } else {
+        format!(
+            "$({:.*} \\pm {:.*}) \\times 10^{{{}}}$",
+            decimals, value, decimals, uncertainty, exponent
+        )
+    }
+}
+
+// "$a_{0}$" for the numbered parameters of the polynomial and spline models
+fn latex_parameter(name: &str) -> String {
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (symbol, index) = name.split_at(name.len() - digits);
+
+    if index.is_empty() || symbol.is_empty() {
+        format!("${}$", latex_escape(name))
+    } else {
+        format!("${}_{{{}}}$", latex_escape(symbol), index)
+    }
+}
+
+fn latex_escape(text: &str) -> String {
+    text.replace('\\', "\\textbackslash{}")
+        .replace('&', "\\&")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+        .replace('#', "\\#")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::exp_fitter::FitResult;
+    use super::super::fit_model::{Exponential, LogPolynomial, Model};
+    use super::*;
+
+    // one measurement with a detector "A" holding lines at `energies`, efficiency falling with energy
+    fn handler(energies: &[f64]) -> MeasurementHandler {
+        let mut detector = Detector {
+            name: "A".to_string(),
+            ..Default::default()
+        };
+        detector.lines = energies
+            .iter()
+            .map(|&energy| DetectorLine {
+                energy,
+                efficiency: 1000.0 / energy,
+                efficiency_uncertainty: 10.0 / energy,
+                ..Default::default()
+            })
+            .collect();
+
+        let mut measurement = Measurement::new(None);
+        measurement.detectors.push(detector);
+
+        let mut handler = MeasurementHandler::new();
+        handler.measurements.push(measurement);
+        handler
+    }
+
+    #[test]
+    fn fit_input_does_not_depend_on_line_order() {
+        let sorted = handler(&[121.78, 244.7, 344.28, 1408.0]);
+        let shuffled = handler(&[1408.0, 121.78, 344.28, 244.7]);
+
+        let data = sorted.get_detector_data_from_measurements("A".to_string());
+        assert_eq!(
+            data,
+            shuffled.get_detector_data_from_measurements("A".to_string())
+        );
+        assert_eq!(data.0, vec![121.78, 244.7, 344.28, 1408.0]);
+    }
+
+    #[test]
+    fn undo_exclusion_follows_the_line_energy() {
+        let mut handler = handler(&[121.78, 344.28, 1408.0]);
+        handler.toggle_exclusion(300.0, 400.0);
+
+        // same number of lines, but 344.28 keV moved to another position
+        let lines = &mut handler.measurements[0].detectors[0].lines;
+        lines.pop();
+        lines.insert(
+            1,
+            DetectorLine {
+                energy: 200.0,
+                exclude_from_fit: true,
+                ..Default::default()
+            },
+        );
+
+        handler.undo_exclusion();
+
+        let excluded: Vec<(f64, bool)> = handler.measurements[0].detectors[0]
+            .lines
+            .iter()
+            .map(|line| (line.energy, line.exclude_from_fit))
+            .collect();
+        assert_eq!(
+            excluded,
+            vec![(121.78, false), (200.0, true), (344.28, false)]
+        );
+    }
+
+    #[test]
+    fn correlated_activity_widens_the_sum() {
+        // two detectors at 10 ± 1 %, 0.5 % of which comes from a 5 % activity uncertainty
+        let detectors = vec![vec![(10.0, 1.0)], vec![(10.0, 1.0)]];
+
+        let (efficiency, uncorrelated) = sum_efficiencies(&detectors, &[0.0])[0];
+        assert_eq!(efficiency, 20.0);
+        assert!((uncorrelated - 2.0f64.sqrt()).abs() < 1e-12);
+
+        // 2 × (1 - 0.25) independent plus (0.05 × 20)² shared
+        let (_, correlated) = sum_efficiencies(&detectors, &[0.05])[0];
+        assert!((correlated - 2.5f64.sqrt()).abs() < 1e-12);
+        assert!(correlated > uncorrelated);
+    }
+
+    #[test]
+    fn activity_uncertainty_of_the_closest_source() {
+        let mut handler = handler(&[121.78, 344.28]);
+        let mut other = self::handler(&[1408.0]);
+        handler.measurements[0]
+            .gamma_source
+            .source_activity_uncertainty = 2.0;
+        other.measurements[0]
+            .gamma_source
+            .source_activity_uncertainty = 6.0;
+        handler.merge(other, false);
+
+        let uncertainties = handler.activity_relative_uncertainties(&[100.0, 500.0, 1000.0]);
+        assert!((uncertainties[0] - 0.02).abs() < 1e-12);
+        assert!((uncertainties[1] - 0.02).abs() < 1e-12);
+        assert!((uncertainties[2] - 0.06).abs() < 1e-12);
+    }
+
+    #[test]
+    fn synchronize_keeps_guesses_and_prunes_removed_detectors() {
+        let mut handler = handler(&[121.78, 344.28]);
+        handler.synchronize_detectors();
+
+        let fitter = handler.measurement_exp_fits.get_mut("A").unwrap();
+        fitter.initial_b_guess = 123.0;
+        fitter.initial_d_guess = 4567.0;
+
+        handler.synchronize_detectors();
+        assert_eq!(handler.initial_guesses["A"], (123.0, 4567.0));
+        assert_eq!(handler.measurement_exp_fits["A"].initial_b_guess, 123.0);
+
+        // a fresh fitter for the same detector starts from the remembered guesses
+        handler.measurement_exp_fits.clear();
+        handler.synchronize_detectors();
+        assert_eq!(handler.measurement_exp_fits["A"].initial_d_guess, 4567.0);
+
+        handler.measurements[0].detectors[0].name = "B".to_string();
+        handler.synchronize_detectors();
+        assert!(!handler.initial_guesses.contains_key("A"));
+        assert!(handler.initial_guesses.contains_key("B"));
+    }
+
+    fn fitted(model: Model, parameters: Vec<f64>) -> ExpFitter {
+        let n = parameters.len();
+        let mut fit = ExpFitter::new(vec![100.0, 200.0, 300.0], vec![1.0; 3], vec![1.0; 3]);
+        fit.model = Some(model);
+        fit.fit_result = Some(FitResult {
+            linear_parameters: parameters,
+            linear_variances: vec![0.01; n],
+            covariance_matrix: vec![0.0; n * n],
+            reduced_chi_squared: 1.25,
+            ..Default::default()
+        });
+        fit
+    }
+
+    #[test]
+    fn latex_columns_follow_the_models() {
+        let mut handler = MeasurementHandler::new();
+
+        let mut exponential = Fitter::default();
+        exponential.insert_fit(
+            "Single",
+            fitted(
+                Model::Exponential(Exponential::new(vec![400.0])),
+                vec![10.0, 400.0],
+            ),
+        );
+        handler
+            .measurement_exp_fits
+            .insert("A".to_string(), exponential);
+
+        let mut polynomial = Fitter::default();
+        polynomial.insert_fit(
+            "Log Polynomial",
+            fitted(
+                Model::LogPolynomial(LogPolynomial {
+                    order: 2,
+                    reference_energy: 1000.0,
+                }),
+                vec![0.5, -0.7, -0.1],
+            ),
+        );
+        handler
+            .measurement_exp_fits
+            .insert("B".to_string(), polynomial);
+
+        let table = handler.latex_table();
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines[0], "\\begin{tabular}{llcccccc}");
+        assert_eq!(
+            lines[2],
+            "Detector & Model & $a$ & $b$ & $a_{0}$ & $a_{1}$ & $a_{2}$ & $\\chi^2/\\nu$ \\\\"
+        );
+        // each row has a cell for every column, "--" where its model has no such parameter
+        for row in &lines[4..6] {
+            assert_eq!(row.split(" & ").count(), 8, "{}", row);
+        }
+        assert!(lines[4].starts_with("A & Single & $"));
+        assert!(lines[4].contains("& -- & -- & --"));
+        assert!(lines[5].starts_with("B & Log Polynomial & -- & -- & $"));
+        assert!(lines[5].ends_with("& 1.25 \\\\"));
+    }
+
+    #[test]
+    fn merged_lines_are_sorted() {
+        let mut handler = handler(&[121.78]);
+        handler.merge(self::handler(&[1408.0, 344.28]), true);
+
+        let energies: Vec<f64> = handler.measurements[1].detectors[0]
+            .lines
+            .iter()
+            .map(|line| line.energy)
+            .collect();
+        assert_eq!(energies, vec![344.28, 1408.0]);
+    }
+
+    #[test]
+    fn cross_check_fits_leave_the_source_out() {
+        // ln(eff) quadratic in ln(E / 1 MeV), the 661.7 keV line of the checked source entered 20 % high
+        let truth = |energy: f64| {
+            let u = (energy / 1000.0).ln();
+            (0.5 - 0.7 * u - 0.1 * u * u).exp()
+        };
+        let x = vec![121.8, 244.7, 344.3, 661.7, 778.9, 964.1, 1408.0];
+        let y: Vec<f64> = x
+            .iter()
+            .map(|&energy| {
+                if energy == 661.7 {
+                    1.2 * truth(energy)
+                } else {
+                    truth(energy)
+                }
+            })
+            .collect();
+        let weights: Vec<f64> = y
+            .iter()
+            .map(|efficiency| 1.0 / (0.02 * efficiency))
+            .collect();
+
+        let mut fit = ExpFitter::new(x.clone(), y.clone(), weights.clone());
+        fit.fit(
+            Model::LogPolynomial(LogPolynomial {
+                order: 2,
+                reference_energy: 1000.0,
+            }),
+            &[],
+        );
+        let mut fitter = Fitter::default();
+        fitter.data = (x.clone(), y, weights);
+        fitter.insert_fit("ln-Poly", fit);
+        let fitters = HashMap::from([("A".to_string(), fitter)]);
+
+        let keep: Vec<bool> = x.iter().map(|&energy| energy != 661.7).collect();
+        let line = CrossCheckLine {
+            source: "Cs137".to_string(),
+            energy: 661.7,
+            direct: (1.2 * truth(661.7), 0.01),
+            detectors: vec!["A".to_string()],
+        };
+        let checks = cross_check(
+            &fitters,
+            vec![("Cs137".to_string(), "A".to_string(), keep)],
+            vec![line],
+        );
+
+        // the other lines alone give the true efficiency, so the entry error stands out
+        let (fitted, _) = checks[0].fitted.unwrap();
+        assert!((fitted - truth(661.7)).abs() < 1e-6);
+        assert!(checks[0].deviation().unwrap() > 3.0);
+    }
+
+    #[test]
+    fn poisson_weight_counts_the_background() {
+        let mut line = DetectorLine {
+            count: 400.0,
+            efficiency: 2.0,
+            efficiency_uncertainty: 1.0,
+            ..Default::default()
+        };
+        // σ = ε / √N without a background
+        assert!((WeightingScheme::Poisson.weight(&line) - 10.0).abs() < 1e-12);
+
+        // 300 net counts with an error of √500
+        line.background = 100.0;
+        let expected = 300.0 / (2.0 * 500.0f64.sqrt());
+        assert!((WeightingScheme::Poisson.weight(&line) - expected).abs() < 1e-12);
+
+        // nothing left above the background, back to 1/σ
+        line.background = 400.0;
+        assert_eq!(WeightingScheme::Poisson.weight(&line), 1.0);
+    }
+}
@@ -1,4 +1,14 @@
+pub mod code_export;
+pub mod coincidence_summing;
+pub mod decay_data;
 pub mod detector;
+pub mod detector_comparison;
 pub mod exp_fitter;
+pub mod fit_model;
+pub mod fit_report;
 pub mod gamma_source;
+pub mod global_fitter;
 pub mod measurements;
+pub mod pile_up;
+pub mod source_library;
+pub mod units;
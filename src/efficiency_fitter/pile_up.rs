@@ -0,0 +1,57 @@
+// Pile-up correction of a high-rate run, true over observed full-energy peak counts. One factor
+// for the run, every detector line of the measurement is multiplied by it
+#[derive(Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PileUpCorrection {
+    pub factor: f64,
+    pub uncertainty: f64,
+}
+
+impl Default for PileUpCorrection {
+    fn default() -> Self {
+        Self {
+            factor: 1.0,
+            uncertainty: 0.0,
+        }
+    }
+}
+
+impl PileUpCorrection {
+    pub fn relative_uncertainty(&self) -> f64 {
+        if self.factor > 0.0 {
+            self.uncertainty / self.factor
+        } else {
+            0.0
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::DragValue::new(&mut self.factor)
+                .speed(0.001)
+                .clamp_range(0.01..=100.0)
+                .prefix("×"),
+        );
+        ui.add(
+            egui::DragValue::new(&mut self.uncertainty)
+                .speed(0.001)
+                .clamp_range(0.0..=f64::INFINITY)
+                .prefix("± "),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_uncertainty() {
+        let correction = PileUpCorrection {
+            factor: 1.2,
+            uncertainty: 0.06,
+        };
+
+        assert!((correction.relative_uncertainty() - 0.05).abs() < 1e-12);
+        assert_eq!(PileUpCorrection::default().relative_uncertainty(), 0.0);
+    }
+}
@@ -0,0 +1,119 @@
+use super::gamma_source::{GammaSource, SourceActivity};
+
+// storage key of the library, saved apart from the project so it survives loading other files
+pub const SOURCE_LIBRARY_KEY: &str = "source_library";
+
+// Sources the user saved (lines, half-life and calibration certificate), kept with the app settings
+// instead of a project so any new measurement can start from them
+#[derive(Default, Clone, serde::Deserialize, serde::Serialize)]
+pub struct SourceLibrary {
+    pub sources: Vec<GammaSource>,
+}
+
+impl SourceLibrary {
+    // replaces a saved source of the same name. The measurement date, activity and run time belong
+    // to one measurement and are not kept
+    pub fn save(&mut self, source: &GammaSource) {
+        let mut entry = source.clone();
+        entry.source_activity_measurement = SourceActivity::default();
+        entry.measurement_time = 0.0;
+
+        match self
+            .sources
+            .iter_mut()
+            .find(|saved| saved.name == entry.name)
+        {
+            Some(saved) => *saved = entry,
+            None => {
+                self.sources.push(entry);
+                self.sources.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.sources.retain(|saved| saved.name != name);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sources.iter().any(|saved| saved.name == name)
+    }
+
+    // copies the saved source `name` into `source`, keeping its measurement
+    pub fn load(&self, name: &str, source: &mut GammaSource) {
+        let saved = match self.sources.iter().find(|saved| saved.name == name) {
+            Some(saved) => saved,
+            None => return,
+        };
+
+        let measurement = source.source_activity_measurement.clone();
+        let measurement_time = source.measurement_time;
+
+        *source = saved.clone();
+        source.source_activity_measurement = measurement;
+        source.measurement_time = measurement_time;
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, source: &mut GammaSource) {
+        ui.horizontal(|ui| {
+            ui.label("Library:");
+
+            let mut selected: Option<String> = None;
+            egui::ComboBox::from_id_source("source_library")
+                .selected_text("Load")
+                .show_ui(ui, |ui| {
+                    if self.sources.is_empty() {
+                        ui.label("No saved sources");
+                    }
+
+                    for saved in &self.sources {
+                        if ui
+                            .selectable_label(false, &saved.name)
+                            .on_hover_text(format!(
+                                "{} lines, {} on {}",
+                                saved.gamma_lines.len(),
+                                saved.source_activity_calibration.unit.format(
+                                    saved.source_activity_calibration.activity,
+                                    saved.source_activity_calibration.uncertainty
+                                ),
+                                saved
+                                    .source_activity_calibration
+                                    .date
+                                    .map_or("no date".to_string(), |date| date.to_string())
+                            ))
+                            .clicked()
+                        {
+                            selected = Some(saved.name.clone());
+                        }
+                    }
+                });
+
+            if let Some(name) = selected {
+                self.load(&name, source);
+            }
+
+            let exists = self.contains(&source.name);
+
+            if ui
+                .add_enabled(!source.name.is_empty(), egui::Button::new("Save"))
+                .on_hover_text(if exists {
+                    "Replace the saved source of this name with the lines, half-life and calibration here"
+                } else {
+                    "Save the lines, half-life and calibration of this source for later measurements"
+                })
+                .clicked()
+            {
+                self.save(source);
+            }
+
+            if exists
+                && ui
+                    .button("🗑")
+                    .on_hover_text("Remove this source from the library")
+                    .clicked()
+            {
+                self.remove(&source.name);
+            }
+        });
+    }
+}
@@ -0,0 +1,224 @@
+// Energies are always stored in keV, the unit here only changes how they are displayed and exported
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum EnergyUnit {
+    #[default]
+    KeV,
+    MeV,
+}
+
+impl EnergyUnit {
+    pub fn label(self) -> &'static str {
+        match self {
+            EnergyUnit::KeV => "keV",
+            EnergyUnit::MeV => "MeV",
+        }
+    }
+
+    pub fn suffix(self) -> String {
+        format!(" {}", self.label())
+    }
+
+    pub fn scale_from_kev(self, energy: f64) -> f64 {
+        match self {
+            EnergyUnit::KeV => energy,
+            EnergyUnit::MeV => energy / 1000.0,
+        }
+    }
+
+    pub fn scale_to_kev(self, energy: f64) -> f64 {
+        match self {
+            EnergyUnit::KeV => energy,
+            EnergyUnit::MeV => energy * 1000.0,
+        }
+    }
+
+    pub fn format(self, energy: f64) -> String {
+        match self {
+            EnergyUnit::KeV => format!("{:.1} keV", energy),
+            EnergyUnit::MeV => format!("{:.4} MeV", self.scale_from_kev(energy)),
+        }
+    }
+
+    pub fn drag_speed(self) -> f64 {
+        match self {
+            EnergyUnit::KeV => 1.0,
+            EnergyUnit::MeV => 0.001,
+        }
+    }
+
+    // DragValue for a value stored in keV but edited in the display unit
+    pub fn drag_value(self, ui: &mut egui::Ui, energy: &mut f64) -> egui::Response {
+        let mut display_energy = self.scale_from_kev(*energy);
+
+        let response = ui.add(
+            egui::DragValue::new(&mut display_energy)
+                .speed(self.drag_speed())
+                .clamp_range(0.0..=f64::INFINITY)
+                .suffix(self.suffix()),
+        );
+
+        if response.changed() {
+            *energy = self.scale_to_kev(display_energy);
+        }
+
+        response
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Energy Unit:");
+            ui.radio_value(self, EnergyUnit::KeV, "keV");
+            ui.radio_value(self, EnergyUnit::MeV, "MeV");
+        });
+    }
+}
+
+// Activities are stored in kBq, the unit is what the certificate quotes (older ones are in µCi)
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum ActivityUnit {
+    Bq,
+    #[default]
+    KBq,
+    MBq,
+    MicroCi,
+    MilliCi,
+}
+
+impl ActivityUnit {
+    pub const ALL: [ActivityUnit; 5] = [
+        ActivityUnit::Bq,
+        ActivityUnit::KBq,
+        ActivityUnit::MBq,
+        ActivityUnit::MicroCi,
+        ActivityUnit::MilliCi,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ActivityUnit::Bq => "Bq",
+            ActivityUnit::KBq => "kBq",
+            ActivityUnit::MBq => "MBq",
+            ActivityUnit::MicroCi => "µCi",
+            ActivityUnit::MilliCi => "mCi",
+        }
+    }
+
+    pub fn suffix(self) -> String {
+        format!(" {}", self.label())
+    }
+
+    // kBq in one of this unit, 1 Ci = 3.7e10 Bq
+    fn kbq(self) -> f64 {
+        match self {
+            ActivityUnit::Bq => 0.001,
+            ActivityUnit::KBq => 1.0,
+            ActivityUnit::MBq => 1000.0,
+            ActivityUnit::MicroCi => 37.0,
+            ActivityUnit::MilliCi => 37000.0,
+        }
+    }
+
+    pub fn scale_from_kbq(self, activity: f64) -> f64 {
+        activity / self.kbq()
+    }
+
+    pub fn scale_to_kbq(self, activity: f64) -> f64 {
+        activity * self.kbq()
+    }
+
+    fn decimals(self) -> usize {
+        match self {
+            ActivityUnit::Bq => 0,
+            ActivityUnit::KBq | ActivityUnit::MicroCi => 3,
+            ActivityUnit::MBq | ActivityUnit::MilliCi => 6,
+        }
+    }
+
+    // activity and uncertainty in kBq
+    pub fn format(self, activity: f64, uncertainty: f64) -> String {
+        format!(
+            "{:.*} ± {:.*} {}",
+            self.decimals(),
+            self.scale_from_kbq(activity),
+            self.decimals(),
+            self.scale_from_kbq(uncertainty),
+            self.label()
+        )
+    }
+
+    pub fn drag_speed(self) -> f64 {
+        match self {
+            ActivityUnit::Bq => 100.0,
+            ActivityUnit::KBq => 1.0,
+            ActivityUnit::MBq => 0.001,
+            ActivityUnit::MicroCi => 0.01,
+            ActivityUnit::MilliCi => 0.00001,
+        }
+    }
+
+    // DragValue for a value stored in kBq but edited in this unit
+    pub fn drag_value(self, ui: &mut egui::Ui, activity: &mut f64, prefix: &str) -> egui::Response {
+        let mut display_activity = self.scale_from_kbq(*activity);
+
+        let response = ui.add(
+            egui::DragValue::new(&mut display_activity)
+                .speed(self.drag_speed())
+                .clamp_range(0.0..=f64::INFINITY)
+                .prefix(prefix)
+                .suffix(self.suffix()),
+        );
+
+        if response.changed() {
+            *activity = self.scale_to_kbq(display_activity);
+        }
+
+        response
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui, id_source: impl std::hash::Hash) {
+        egui::ComboBox::from_id_source(id_source)
+            .selected_text(self.label())
+            .width(60.0)
+            .show_ui(ui, |ui| {
+                for unit in ActivityUnit::ALL {
+                    ui.selectable_value(self, unit, unit.label());
+                }
+            });
+    }
+}
+
+// Efficiencies are already percentages, so a bare "± x%" reads as either percentage points or a
+// fraction of the efficiency. Absolute shows percentage points, relative a percent of the value
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum UncertaintyDisplay {
+    #[default]
+    Absolute,
+    Relative,
+}
+
+impl UncertaintyDisplay {
+    // efficiency and uncertainty are both in percent (absolute)
+    pub fn format(self, efficiency: f64, uncertainty: f64) -> String {
+        match self {
+            UncertaintyDisplay::Absolute => format!("({:.3} ± {:.3})%", efficiency, uncertainty),
+            UncertaintyDisplay::Relative => {
+                let relative = if efficiency != 0.0 {
+                    100.0 * uncertainty / efficiency.abs()
+                } else {
+                    0.0
+                };
+                format!("{:.3}% ± {:.1}% rel.", efficiency, relative)
+            }
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Uncertainty:");
+            ui.radio_value(self, UncertaintyDisplay::Absolute, "Absolute")
+                .on_hover_text("Percentage points of efficiency, (ε ± σ)%");
+            ui.radio_value(self, UncertaintyDisplay::Relative, "Relative")
+                .on_hover_text("Percent of the efficiency value, ε% ± (σ/ε)%");
+        });
+    }
+}
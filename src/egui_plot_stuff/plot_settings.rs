@@ -1,6 +1,79 @@
+// egui_plot's Corner and TextStyle are not serializable, so mirror the options needed here
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LegendPosition {
+    LeftTop,
+    #[default]
+    RightTop,
+    LeftBottom,
+    RightBottom,
+}
+
+impl LegendPosition {
+    pub const ALL: [LegendPosition; 4] = [
+        LegendPosition::LeftTop,
+        LegendPosition::RightTop,
+        LegendPosition::LeftBottom,
+        LegendPosition::RightBottom,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LegendPosition::LeftTop => "Top Left",
+            LegendPosition::RightTop => "Top Right",
+            LegendPosition::LeftBottom => "Bottom Left",
+            LegendPosition::RightBottom => "Bottom Right",
+        }
+    }
+
+    pub fn corner(&self) -> egui_plot::Corner {
+        match self {
+            LegendPosition::LeftTop => egui_plot::Corner::LeftTop,
+            LegendPosition::RightTop => egui_plot::Corner::RightTop,
+            LegendPosition::LeftBottom => egui_plot::Corner::LeftBottom,
+            LegendPosition::RightBottom => egui_plot::Corner::RightBottom,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LegendTextSize {
+    Small,
+    #[default]
+    Body,
+    Heading,
+}
+
+impl LegendTextSize {
+    pub const ALL: [LegendTextSize; 3] = [
+        LegendTextSize::Small,
+        LegendTextSize::Body,
+        LegendTextSize::Heading,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LegendTextSize::Small => "Small",
+            LegendTextSize::Body => "Body",
+            LegendTextSize::Heading => "Heading",
+        }
+    }
+
+    pub fn text_style(&self) -> egui::TextStyle {
+        match self {
+            LegendTextSize::Small => egui::TextStyle::Small,
+            LegendTextSize::Body => egui::TextStyle::Body,
+            LegendTextSize::Heading => egui::TextStyle::Heading,
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct EguiPlotSettings {
     pub legend: bool,
+    #[serde(default)]
+    pub legend_position: LegendPosition,
+    #[serde(default)]
+    pub legend_text_size: LegendTextSize,
     pub show_x_value: bool,
     pub show_y_value: bool,
     pub center_x_axis: bool,
@@ -13,12 +86,45 @@ pub struct EguiPlotSettings {
     pub show_grid: bool,
     pub sharp_grid_lines: bool,
     pub show_background: bool,
+    #[serde(default)]
+    pub x_min: f64,
+    #[serde(default)]
+    pub x_max: f64,
+    #[serde(default)]
+    pub y_min: f64,
+    #[serde(default)]
+    pub y_max: f64,
+    #[serde(default)]
+    pub manual_bounds: bool,
+    #[serde(default = "default_band_decimation")]
+    pub band_decimation: usize,
+    #[serde(skip)]
+    pub apply_bounds: bool,
+}
+
+fn default_band_decimation() -> usize {
+    1
+}
+
+// keeps every `step`th point plus the last one so a band still spans the full range
+pub fn decimate<T: Copy>(points: &[T], step: usize) -> Vec<T> {
+    let mut decimated: Vec<T> = points.iter().step_by(step.max(1)).copied().collect();
+
+    if let Some(last) = points.last() {
+        if (points.len() - 1) % step.max(1) != 0 {
+            decimated.push(*last);
+        }
+    }
+
+    decimated
 }
 
 impl Default for EguiPlotSettings {
     fn default() -> Self {
         EguiPlotSettings {
             legend: true,
+            legend_position: LegendPosition::default(),
+            legend_text_size: LegendTextSize::default(),
             show_x_value: true,
             show_y_value: true,
             center_x_axis: false,
@@ -31,6 +137,13 @@ impl Default for EguiPlotSettings {
             show_grid: true,
             sharp_grid_lines: true,
             show_background: true,
+            x_min: 0.0,
+            x_max: 0.0,
+            y_min: 0.0,
+            y_max: 0.0,
+            manual_bounds: false,
+            apply_bounds: false,
+            band_decimation: 1,
         }
     }
 }
@@ -40,6 +153,36 @@ impl EguiPlotSettings {
         ui.menu_button("egui Plot Settings", |ui| {
             ui.vertical(|ui| {
                 ui.checkbox(&mut self.legend, "Legend");
+                if self.legend {
+                    ui.horizontal(|ui| {
+                        ui.label("Position:");
+                        egui::ComboBox::from_id_source("legend_position")
+                            .selected_text(self.legend_position.label())
+                            .show_ui(ui, |ui| {
+                                for position in LegendPosition::ALL {
+                                    ui.selectable_value(
+                                        &mut self.legend_position,
+                                        position,
+                                        position.label(),
+                                    );
+                                }
+                            });
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Text Size:");
+                        egui::ComboBox::from_id_source("legend_text_size")
+                            .selected_text(self.legend_text_size.label())
+                            .show_ui(ui, |ui| {
+                                for size in LegendTextSize::ALL {
+                                    ui.selectable_value(
+                                        &mut self.legend_text_size,
+                                        size,
+                                        size.label(),
+                                    );
+                                }
+                            });
+                    });
+                }
                 ui.checkbox(&mut self.show_x_value, "Show X Value");
                 ui.checkbox(&mut self.show_y_value, "Show Y Value");
                 ui.checkbox(&mut self.center_x_axis, "Center X Axis");
@@ -55,6 +198,67 @@ impl EguiPlotSettings {
 
                 ui.separator();
 
+                ui.horizontal(|ui| {
+                    ui.label("Band Decimation:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.band_decimation)
+                            .speed(1)
+                            .clamp_range(1..=100),
+                    )
+                    .on_hover_text("Draw the confidence bands with every Nth point. Fit lines keep their full resolution");
+                });
+
+                ui.separator();
+
+                ui.label("Axis Limits");
+                egui::Grid::new("plot_axis_limits").show(ui, |ui| {
+                    ui.label("X:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.x_min)
+                            .speed(1.0)
+                            .prefix("min: "),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut self.x_max)
+                            .speed(1.0)
+                            .prefix("max: "),
+                    );
+                    ui.end_row();
+
+                    ui.label("Y:");
+                    ui.add(
+                        egui::DragValue::new(&mut self.y_min)
+                            .speed(0.01)
+                            .prefix("min: "),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut self.y_max)
+                            .speed(0.01)
+                            .prefix("max: "),
+                    );
+                    ui.end_row();
+                });
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .button("Apply")
+                        .on_hover_text("Set the plot bounds and turn off auto-bounds")
+                        .clicked()
+                    {
+                        self.manual_bounds = true;
+                        self.apply_bounds = true;
+                    }
+
+                    if ui
+                        .add_enabled(self.manual_bounds, egui::Button::new("Auto Bounds"))
+                        .clicked()
+                    {
+                        self.manual_bounds = false;
+                    }
+                });
+
+                ui.separator();
+
                 if ui.button("Reset").clicked() {
                     *self = EguiPlotSettings::default();
                 }
@@ -62,6 +266,24 @@ impl EguiPlotSettings {
         });
     }
 
+    // call inside the plot closure, bounds can only be set through the PlotUi
+    pub fn apply_bounds(&mut self, plot_ui: &mut egui_plot::PlotUi) {
+        if !self.apply_bounds {
+            return;
+        }
+
+        self.apply_bounds = false;
+
+        if self.x_min < self.x_max && self.y_min < self.y_max {
+            plot_ui.set_plot_bounds(egui_plot::PlotBounds::from_min_max(
+                [self.x_min, self.y_min],
+                [self.x_max, self.y_max],
+            ));
+        } else {
+            log::error!("Axis limits must have min < max");
+        }
+    }
+
     // some function i can call that adds the settings to the plot
     pub fn apply_to_plot(&self, plot: egui_plot::Plot) -> egui_plot::Plot {
         let plot = plot
@@ -77,10 +299,14 @@ impl EguiPlotSettings {
             .show_grid(self.show_grid)
             .sharp_grid_lines(self.sharp_grid_lines)
             .show_background(self.show_background)
-            .auto_bounds(egui::Vec2b::new(true, true));
+            .auto_bounds(egui::Vec2b::new(!self.manual_bounds, !self.manual_bounds));
 
         if self.legend {
-            plot.legend(egui_plot::Legend::default())
+            plot.legend(
+                egui_plot::Legend::default()
+                    .position(self.legend_position.corner())
+                    .text_style(self.legend_text_size.text_style()),
+            )
         } else {
             plot
         }
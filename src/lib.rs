@@ -1,7 +1,14 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
+pub mod curve;
+pub use curve::EfficiencyCurve;
+
+#[cfg(feature = "gui")]
 mod app;
+#[cfg(feature = "gui")]
 pub use app::CeBrAEfficiencyApp;
 
+#[cfg(feature = "gui")]
 mod efficiency_fitter;
+#[cfg(feature = "gui")]
 mod egui_plot_stuff;
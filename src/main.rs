@@ -6,6 +6,12 @@
 fn main() -> eframe::Result<()> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).  command for windows: $env:RUST_LOG="info"; cargo run
 
+    // `cebra_efficiency fit project.yaml --out results.json` refits a saved project without a window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("fit") {
+        std::process::exit(fit_command(&args[1..]));
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([425.0, 250.0])
@@ -19,6 +25,63 @@ fn main() -> eframe::Result<()> {
     )
 }
 
+// Exit code 0 when every fit finished, 1 when any failed and 2 for bad arguments. The results go
+// next to the project when --out is left out
+#[cfg(not(target_arch = "wasm32"))]
+fn fit_command(args: &[String]) -> i32 {
+    const USAGE: &str = "usage: cebra_efficiency fit <project.yaml> [--out <results.json>]";
+
+    let mut project: Option<std::path::PathBuf> = None;
+    let mut out: Option<std::path::PathBuf> = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" | "-o" => match args.next() {
+                Some(path) => out = Some(path.into()),
+                None => {
+                    eprintln!("--out needs a file name\n{}", USAGE);
+                    return 2;
+                }
+            },
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return 0;
+            }
+            _ if project.is_none() => project = Some(arg.into()),
+            _ => {
+                eprintln!("Unexpected argument {}\n{}", arg, USAGE);
+                return 2;
+            }
+        }
+    }
+
+    let project = match project {
+        Some(project) => project,
+        None => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let out = out.unwrap_or_else(|| project.with_extension("json"));
+
+    match cebra_efficiency::CeBrAEfficiencyApp::fit_headless(&project, &out) {
+        Ok((fitted, failed)) => {
+            println!(
+                "{} fits written to {}, {} failed",
+                fitted,
+                out.display(),
+                failed
+            );
+            i32::from(failed > 0)
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
 // When compiling to web using trunk:
 #[cfg(target_arch = "wasm32")]
 fn main() {